@@ -39,15 +39,19 @@ pub const  MB:usize=1024*1024;
 pub const  PAGE_SIZE:usize=4096;//每个页面大小4kb
 
 
-pub const MEMORY_SIZE:usize=128*MB;//总可用空闲物理内存大小
-pub const KERNEL_HEAP_SIZE:usize=64*MB;//内核堆大小，64mb足以了，后期可以调整，主要是init程序加载时的vec比较大。
+pub const MEMORY_SIZE:usize=128*MB;//总可用空闲物理内存大小，设备树没有 /memory 时使用
+pub const KERNEL_HEAP_SIZE:usize=16*MB;//内核堆大小，ELF 按需分页后不再整个读进 vec，16mb 够 ramfs 和读写缓冲用；堆在 .bss 里，太大 -m 64M 起不来
 
 
 pub const KERNEL_STACK_SIZE:usize=PAGE_SIZE*4;//应用内核栈有四个页面的大小
+///支持的最多 hart 数，hartid 必须小于它，entry.asm 按它分配启动栈
+pub const MAX_HARTS:usize=8;
+///每个 hart 的启动栈（idle 循环也跑在上面）
+pub const BOOT_STACK_SIZE:usize=PAGE_SIZE*16;
 pub static mut KERNEL_HEADP:[u8;KERNEL_HEAP_SIZE]=[0;KERNEL_HEAP_SIZE];//内核堆实例
 pub const  PAGE_SIZE_BITS:usize=12;//2^12=4096 4kb
 
-pub const CPU_CIRCLE:usize=12_500_000; // qemu时钟频率
+pub const CPU_CIRCLE:usize=12_500_000; // qemu时钟频率，设备树没有 timebase-frequency 时使用
 
 ///使用虚拟高地址并且刚好留够一个页面,代表开始的第一个地址
 pub const TRAP_BOTTOM_ADDR:usize=usize::MAX-PAGE_SIZE+1;
@@ -55,6 +59,12 @@ pub const TRAP_BOTTOM_ADDR:usize=usize::MAX-PAGE_SIZE+1;
 pub const TRAP_CONTEXT_ADDR:usize=TRAP_BOTTOM_ADDR-PAGE_SIZE;
 ///用户start函数在用户地址空间的起始映射地址，不携带页帧，直接操作页表映射 D
 pub const USERLIB_START_RETURN_HIGNADDR:usize=TRAP_CONTEXT_ADDR-PAGE_SIZE;
+///信号返回跳板页，用户态处理函数返回到这里
+pub const SIGRETURN_TRAMPOLINE_ADDR:usize=USERLIB_START_RETURN_HIGNADDR;
+///一个地址空间里除主线程外最多的线程数，每个线程在 USERLIB_START_RETURN_HIGNADDR 下面占一页陷阱上下文
+pub const MAX_THREAD_TRAP_SLOTS:usize=64;
+///线程陷阱上下文区的最低地址，mmap/mremap 只在它下面找空闲区
+pub const THREAD_TRAP_CONTEXT_BOTTOM:usize=USERLIB_START_RETURN_HIGNADDR-MAX_THREAD_TRAP_SLOTS*PAGE_SIZE;
 pub const HIGNADDRESS_MASK:usize=0xFFFFFFE000000000;//0xFFFFFFFFFFFFF000 hb *0xfffffffffffff070
 ///每秒多少次时钟中断
 pub const TIME_FREQUENT:usize=100;
@@ -70,10 +80,10 @@ pub const BIG_INT:usize=1_000_000;
 pub const BLOCKSIZE:usize = 4096;
 
 use lazy_static::lazy_static;
-use crate::{MapSet, sync::UPSafeCell};
+use crate::{MapSet, sync::SpinLock};
 lazy_static!{
-        pub static ref KERNEL_SPACE:UPSafeCell<MapSet> =unsafe {
-            UPSafeCell::new( MapSet::new_kernel())//内核地址空间，必须持有,从来不会丢弃
+        pub static ref KERNEL_SPACE:SpinLock<MapSet> =unsafe {
+            SpinLock::new( MapSet::new_kernel())//内核地址空间，必须持有,从来不会丢弃
         };
 }
 
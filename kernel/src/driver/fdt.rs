@@ -0,0 +1,265 @@
+//! 设备树(FDT)解析 big-endian
+//! SBI 启动时 a1 传入 dtb 物理地址，开分页前解析一遍，把内存大小、时钟频率和设备 mmio 地址存下来
+//! 之后不再访问 dtb（它可能落在会被分配出去的物理页帧里）
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use log::{debug, warn};
+
+use crate::config::{CPU_CIRCLE, MEMORY_SIZE, PAGE_SIZE, ekernel};
+use crate::sync::SpinLock;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// 设备 mmio 区间和中断号
+#[derive(Clone, Copy, Debug)]
+pub struct MmioDevice {
+    pub base: usize,
+    pub size: usize,
+    /// interrupts 属性第一个 cell，没有为 None
+    pub irq: Option<u32>,
+}
+
+/// 从设备树里拿到的启动信息
+#[derive(Default)]
+pub struct BootInfo {
+    /// 物理内存 [base, base+size)
+    pub memory: Option<(usize, usize)>,
+    /// dtb 自身占用的物理区间
+    pub fdt: Option<(usize, usize)>,
+    /// 所有 virtio,mmio 节点，按 mmio 地址升序
+    pub virtio_mmio: Vec<MmioDevice>,
+    pub plic: Option<MmioDevice>,
+    /// PLIC 支持的中断源数量 riscv,ndev
+    pub plic_ndev: u32,
+    pub uart: Option<MmioDevice>,
+    /// UART 输入时钟 clock-frequency
+    pub uart_clock: u32,
+    /// /cpus 下每个 cpu 的 hartid
+    pub harts: Vec<usize>,
+}
+
+lazy_static! {
+    pub static ref BOOT_INFO: SpinLock<BootInfo> = SpinLock::new(BootInfo::default());
+}
+
+/// 时钟频率和内存结束地址在中断里也会读，单独放原子量
+static TIMEBASE_FREQ: AtomicUsize = AtomicUsize::new(CPU_CIRCLE);
+static MEMORY_END: AtomicUsize = AtomicUsize::new(0);
+
+///time csr 每秒 tick 数，设备树没有给出时为 CPU_CIRCLE
+pub fn timebase_frequency() -> usize {
+    TIMEBASE_FREQ.load(Ordering::Relaxed)
+}
+
+///可以交给页帧分配器的物理内存结束地址（不含 dtb），设备树没有给出时为 ekernel+MEMORY_SIZE
+pub fn phys_mem_end() -> usize {
+    match MEMORY_END.load(Ordering::Relaxed) {
+        0 => ekernel as usize + MEMORY_SIZE,
+        end => end,
+    }
+}
+
+fn be32(data: &[u8], off: usize) -> Option<u32> {
+    let b = data.get(off..off + 4)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+///按 cells 个 u32 读一个大端整数
+fn read_cells(data: &[u8], off: usize, cells: usize) -> Option<usize> {
+    let mut v = 0usize;
+    for i in 0..cells {
+        v = (v << 32) | be32(data, off + i * 4)? as usize;
+    }
+    Some(v)
+}
+
+fn cstr(data: &[u8], off: usize) -> Option<&str> {
+    let s = data.get(off..)?;
+    let len = s.iter().position(|&c| c == 0)?;
+    core::str::from_utf8(&s[..len]).ok()
+}
+
+/// 解析中的节点，属性都在子节点前面，END_NODE 时处理
+#[derive(Default)]
+struct FdtNode<'a> {
+    name: &'a str,
+    compatible: &'a [u8],
+    device_type: &'a [u8],
+    status: &'a [u8],
+    reg: &'a [u8],
+    interrupts: &'a [u8],
+    /// 给子节点用的 #address-cells/#size-cells
+    address_cells: usize,
+    size_cells: usize,
+    timebase: Option<usize>,
+    clock: Option<u32>,
+    ndev: Option<u32>,
+}
+
+impl<'a> FdtNode<'a> {
+    fn is_compatible(&self, name: &str) -> bool {
+        self.compatible
+            .split(|&c| c == 0)
+            .any(|s| s == name.as_bytes())
+    }
+
+    fn is_okay(&self) -> bool {
+        self.status.is_empty() || self.status.starts_with(b"okay") || self.status.starts_with(b"ok\0")
+    }
+
+    ///reg 的第一个 (addr,size)，cells 由父节点决定
+    fn first_reg(&self, address_cells: usize, size_cells: usize) -> Option<(usize, usize)> {
+        let base = read_cells(self.reg, 0, address_cells)?;
+        let size = read_cells(self.reg, address_cells * 4, size_cells).unwrap_or(0);
+        Some((base, size))
+    }
+
+    fn mmio(&self, address_cells: usize, size_cells: usize) -> Option<MmioDevice> {
+        let (base, size) = self.first_reg(address_cells, size_cells)?;
+        Some(MmioDevice {
+            base,
+            size,
+            irq: be32(self.interrupts, 0),
+        })
+    }
+}
+
+///解析 dtb，失败时保留默认值（MEMORY_SIZE/CPU_CIRCLE/硬编码设备地址）
+/// 必须在页帧分配器初始化之前调用，此时还是物理地址直接访问
+pub fn init_fdt(dtb: usize) {
+    if dtb == 0 || dtb % 4 != 0 {
+        warn!("fdt: invalid dtb pointer {:#x}, use default config", dtb);
+        return;
+    }
+    let header = unsafe { core::slice::from_raw_parts(dtb as *const u8, 40) };
+    if be32(header, 0) != Some(FDT_MAGIC) {
+        warn!("fdt: bad magic at {:#x}, use default config", dtb);
+        return;
+    }
+    let total = be32(header, 4).unwrap_or(0) as usize;
+    let data = unsafe { core::slice::from_raw_parts(dtb as *const u8, total) };
+    let mut info = BootInfo::default();
+    info.fdt = Some((dtb, total));
+    if parse(data, &mut info).is_none() {
+        warn!("fdt: malformed device tree, use default config");
+        return;
+    }
+    info.virtio_mmio.sort_by_key(|dev| dev.base);
+
+    if let Some((base, size)) = info.memory {
+        let mut end = base + size;
+        // dtb 在内存末尾时不能交给页帧分配器
+        let fdt_start = dtb & !(PAGE_SIZE - 1);
+        if fdt_start > ekernel as usize && fdt_start < end {
+            end = fdt_start;
+        }
+        MEMORY_END.store(end, Ordering::Relaxed);
+    }
+    debug!(
+        "fdt: memory {:x?} timebase {} harts {:?} virtio {} plic {:x?} uart {:x?}",
+        info.memory,
+        timebase_frequency(),
+        info.harts,
+        info.virtio_mmio.len(),
+        info.plic.map(|d| d.base),
+        info.uart.map(|d| d.base)
+    );
+    *BOOT_INFO.lock() = info;
+}
+
+fn parse(data: &[u8], info: &mut BootInfo) -> Option<()> {
+    let off_struct = be32(data, 8)? as usize;
+    let off_strings = be32(data, 12)? as usize;
+    let strings = data.get(off_strings..)?;
+
+    // 节点栈，根节点的父节点默认 #address-cells=2 #size-cells=1
+    let mut stack: Vec<FdtNode> = Vec::new();
+    let mut off = off_struct;
+    loop {
+        let token = be32(data, off)?;
+        off += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = cstr(data, off)?;
+                off = (off + name.len() + 1 + 3) & !3;
+                stack.push(FdtNode {
+                    name,
+                    address_cells: 2,
+                    size_cells: 1,
+                    ..Default::default()
+                });
+            }
+            FDT_END_NODE => {
+                let node = stack.pop()?;
+                let (ac, sc) = stack.last().map_or((2, 1), |p| (p.address_cells, p.size_cells));
+                handle_node(&node, stack.last().map(|p| p.name), ac, sc, info);
+            }
+            FDT_PROP => {
+                let len = be32(data, off)? as usize;
+                let nameoff = be32(data, off + 4)? as usize;
+                let value = data.get(off + 8..off + 8 + len)?;
+                off = (off + 8 + len + 3) & !3;
+                let name = cstr(strings, nameoff)?;
+                let node = stack.last_mut()?;
+                match name {
+                    "compatible" => node.compatible = value,
+                    "device_type" => node.device_type = value,
+                    "status" => node.status = value,
+                    "reg" => node.reg = value,
+                    "interrupts" => node.interrupts = value,
+                    "#address-cells" => node.address_cells = be32(value, 0)? as usize,
+                    "#size-cells" => node.size_cells = be32(value, 0)? as usize,
+                    "timebase-frequency" => node.timebase = read_cells(value, 0, len / 4),
+                    "clock-frequency" => node.clock = be32(value, 0),
+                    "riscv,ndev" => node.ndev = be32(value, 0),
+                    _ => {}
+                }
+            }
+            FDT_NOP => {}
+            FDT_END => break,
+            _ => return None,
+        }
+    }
+    Some(())
+}
+
+fn handle_node(node: &FdtNode, parent: Option<&str>, ac: usize, sc: usize, info: &mut BootInfo) {
+    if !node.is_okay() {
+        return;
+    }
+    // cpus 节点的 timebase-frequency 也可能写在单个 cpu 节点上
+    if let Some(freq) = node.timebase {
+        if freq != 0 {
+            TIMEBASE_FREQ.store(freq, Ordering::Relaxed);
+        }
+    }
+    if node.device_type.starts_with(b"memory") {
+        if let Some((base, size)) = node.first_reg(ac, sc) {
+            // 只用内核所在的那一段
+            if base <= ekernel as usize && (ekernel as usize) < base + size {
+                info.memory = Some((base, size));
+            }
+        }
+    } else if node.device_type.starts_with(b"cpu") && parent == Some("cpus") {
+        if let Some(hartid) = read_cells(node.reg, 0, ac) {
+            info.harts.push(hartid);
+        }
+    } else if node.is_compatible("virtio,mmio") {
+        if let Some(dev) = node.mmio(ac, sc) {
+            info.virtio_mmio.push(dev);
+        }
+    } else if node.is_compatible("riscv,plic0") || node.is_compatible("sifive,plic-1.0.0") {
+        info.plic = node.mmio(ac, sc);
+        info.plic_ndev = node.ndev.unwrap_or(0);
+    } else if node.is_compatible("ns16550a") && info.uart.is_none() {
+        info.uart = node.mmio(ac, sc);
+        info.uart_clock = node.clock.unwrap_or(0);
+    }
+}
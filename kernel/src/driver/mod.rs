@@ -1,2 +1,40 @@
+pub mod fdt;
+pub mod plic;
+mod uart;
 mod virtio_blk;
-pub use self::virtio_blk::*;
\ No newline at end of file
+pub use self::uart::*;
+pub use self::virtio_blk::*;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::warn;
+
+use crate::fs::component::tty::tty_receive_input;
+use crate::task::hart_id;
+
+/// 接收外部中断的 hart，设备中断只路由到它（启动 hart）
+static IRQ_HART: AtomicUsize = AtomicUsize::new(0);
+
+///初始化 PLIC 并让 hartid 接收外部中断，需要在创建设备之前调用
+pub fn init_external_interrupt(hartid: usize) {
+    IRQ_HART.store(hartid, Ordering::Relaxed);
+    plic::init_plic();
+    plic::init_hart(hartid);
+}
+
+///在 PLIC 上使能一个设备中断
+pub fn enable_device_irq(irq: u32) {
+    plic::enable_irq(IRQ_HART.load(Ordering::Relaxed), irq);
+}
+
+///外部中断处理：从 PLIC 领取本 hart 所有待处理中断，分发给设备后 complete
+pub fn handle_external_interrupt() {
+    let hart = hart_id();
+    while let Some(irq) = plic::claim(hart) {
+        if is_uart_irq(irq) {
+            tty_receive_input();
+        } else if !virtio_blk_irq(irq) {
+            warn!("unhandled external interrupt irq={}", irq);
+        }
+        plic::complete(hart, irq);
+    }
+}
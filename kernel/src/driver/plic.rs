@@ -0,0 +1,83 @@
+//! PLIC 平台级中断控制器
+//! 每个中断源有一个优先级，每个 hart 的 M/S 模式各是一个 context，context 有自己的使能位、阈值和 claim/complete 寄存器
+//! qemu virt 上 hart N 的 S 模式 context 是 2N+1
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::{debug, warn};
+
+use crate::driver::fdt::BOOT_INFO;
+
+/// 设备树没有给出时用 qemu virt 的地址
+const PLIC_BASE_DEFAULT: usize = 0x0c00_0000;
+/// qemu virt 的中断源数量
+const PLIC_NDEV_DEFAULT: u32 = 95;
+
+const PRIORITY_OFFSET: usize = 0x0;
+const ENABLE_OFFSET: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_OFFSET: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const THRESHOLD: usize = 0x0;
+const CLAIM_COMPLETE: usize = 0x4;
+
+static PLIC_BASE: AtomicUsize = AtomicUsize::new(PLIC_BASE_DEFAULT);
+static PLIC_NDEV: AtomicUsize = AtomicUsize::new(PLIC_NDEV_DEFAULT as usize);
+
+fn reg(offset: usize) -> *mut u32 {
+    (PLIC_BASE.load(Ordering::Relaxed) + offset) as *mut u32
+}
+
+///hart 的 S 模式 context 号
+fn s_context(hartid: usize) -> usize {
+    hartid * 2 + 1
+}
+
+///初始化 PLIC：地址来自设备树，hart 的 S 模式阈值设为0（接收所有优先级>0的中断）
+pub fn init_plic() {
+    let (base, ndev) = {
+        let info = BOOT_INFO.lock();
+        match info.plic {
+            Some(dev) => (dev.base, if info.plic_ndev == 0 { PLIC_NDEV_DEFAULT } else { info.plic_ndev }),
+            None => (PLIC_BASE_DEFAULT, PLIC_NDEV_DEFAULT),
+        }
+    };
+    PLIC_BASE.store(base, Ordering::Relaxed);
+    PLIC_NDEV.store(ndev as usize, Ordering::Relaxed);
+    debug!("plic: base {:#x} ndev {}", base, ndev);
+}
+
+///打开 hart 的 S 模式 context：阈值清零
+pub fn init_hart(hartid: usize) {
+    unsafe {
+        reg(CONTEXT_OFFSET + s_context(hartid) * CONTEXT_STRIDE + THRESHOLD).write_volatile(0);
+    }
+}
+
+///使能中断源 irq 到 hart 的 S 模式，优先级设为1
+pub fn enable_irq(hartid: usize, irq: u32) {
+    if irq == 0 || irq as usize > PLIC_NDEV.load(Ordering::Relaxed) {
+        warn!("plic: irq {} out of range", irq);
+        return;
+    }
+    let ctx = s_context(hartid);
+    unsafe {
+        reg(PRIORITY_OFFSET + irq as usize * 4).write_volatile(1);
+        let enable = reg(ENABLE_OFFSET + ctx * ENABLE_STRIDE + (irq as usize / 32) * 4);
+        enable.write_volatile(enable.read_volatile() | 1 << (irq % 32));
+    }
+}
+
+///领取一个待处理的中断，没有时返回 None
+pub fn claim(hartid: usize) -> Option<u32> {
+    let irq = unsafe {
+        reg(CONTEXT_OFFSET + s_context(hartid) * CONTEXT_STRIDE + CLAIM_COMPLETE).read_volatile()
+    };
+    if irq == 0 { None } else { Some(irq) }
+}
+
+///处理完 irq 后通知 PLIC，之后同一个源才能再次触发
+pub fn complete(hartid: usize, irq: u32) {
+    unsafe {
+        reg(CONTEXT_OFFSET + s_context(hartid) * CONTEXT_STRIDE + CLAIM_COMPLETE).write_volatile(irq);
+    }
+}
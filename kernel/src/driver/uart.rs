@@ -0,0 +1,106 @@
+//! ns16550a 串口驱动 mmio
+//! 初始化前（以及设备树里没有串口时）输出走 SBI putc，之后直接读写寄存器，收到字符由 PLIC 中断通知
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use log::debug;
+
+use crate::driver::enable_device_irq;
+use crate::driver::fdt::BOOT_INFO;
+use crate::sbi;
+
+/// qemu virt 的 UART0
+const UART_BASE_DEFAULT: usize = 0x1000_0000;
+const UART_IRQ_DEFAULT: u32 = 10;
+const UART_BAUD: u32 = 115200;
+
+/// 寄存器偏移（reg-shift = 0）
+const RBR: usize = 0; // 读：接收缓冲
+const THR: usize = 0; // 写：发送保持
+const DLL: usize = 0; // DLAB=1 时：除数低字节
+const IER: usize = 1; // 中断使能
+const DLM: usize = 1; // DLAB=1 时：除数高字节
+const FCR: usize = 2; // 写：FIFO 控制
+const LCR: usize = 3; // 线路控制
+const MCR: usize = 4; // modem 控制
+const LSR: usize = 5; // 线路状态
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const FCR_ENABLE_CLEAR: u8 = 0b111;
+const LCR_8N1: u8 = 0b11;
+const LCR_DLAB: u8 = 1 << 7;
+/// DTR | RTS | OUT2，OUT2 在真实 16550 上控制中断输出
+const MCR_DTR_RTS_OUT2: u8 = 0b1011;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+
+static UART_BASE: AtomicUsize = AtomicUsize::new(UART_BASE_DEFAULT);
+static UART_READY: AtomicBool = AtomicBool::new(false);
+static UART_IRQ: AtomicUsize = AtomicUsize::new(0);
+
+fn read_reg(off: usize) -> u8 {
+    unsafe { ((UART_BASE.load(Ordering::Relaxed) + off) as *const u8).read_volatile() }
+}
+
+fn write_reg(off: usize, val: u8) {
+    unsafe { ((UART_BASE.load(Ordering::Relaxed) + off) as *mut u8).write_volatile(val) }
+}
+
+///初始化串口：8N1，打开 FIFO 和接收中断。需要在 init_external_interrupt 之后调用
+pub fn init_uart() {
+    let (base, irq, clock) = {
+        let info = BOOT_INFO.lock();
+        match info.uart {
+            Some(dev) => (dev.base, dev.irq.unwrap_or(UART_IRQ_DEFAULT), info.uart_clock),
+            None => (UART_BASE_DEFAULT, UART_IRQ_DEFAULT, 0),
+        }
+    };
+    UART_BASE.store(base, Ordering::Relaxed);
+
+    write_reg(IER, 0);
+    // 设备树给了输入时钟才设置波特率，qemu 不关心除数
+    if clock != 0 {
+        let divisor = clock / (16 * UART_BAUD);
+        write_reg(LCR, LCR_DLAB);
+        write_reg(DLL, divisor as u8);
+        write_reg(DLM, (divisor >> 8) as u8);
+    }
+    write_reg(LCR, LCR_8N1);
+    write_reg(FCR, FCR_ENABLE_CLEAR);
+    write_reg(MCR, MCR_DTR_RTS_OUT2);
+    write_reg(IER, IER_RX_AVAILABLE);
+    UART_READY.store(true, Ordering::Relaxed);
+    enable_device_irq(irq);
+    UART_IRQ.store(irq as usize, Ordering::Relaxed);
+    debug!("uart: ns16550a at {:#x} irq {}", base, irq);
+}
+
+///irq 是不是串口的中断
+pub fn is_uart_irq(irq: u32) -> bool {
+    UART_READY.load(Ordering::Relaxed) && UART_IRQ.load(Ordering::Relaxed) == irq as usize
+}
+
+///输出一个字节，不加锁，panic 和日志里也能用
+pub fn uart_putc(c: u8) {
+    if !UART_READY.load(Ordering::Relaxed) {
+        sbi::putc(c as usize);
+        return;
+    }
+    while read_reg(LSR) & LSR_THR_EMPTY == 0 {
+        core::hint::spin_loop();
+    }
+    write_reg(THR, c);
+}
+
+///读一个字节，没有数据时返回 None
+pub fn uart_getc() -> Option<u8> {
+    if !UART_READY.load(Ordering::Relaxed) {
+        return match sbi::get_char() {
+            c if c > 0 => Some(c as u8),
+            _ => None,
+        };
+    }
+    if read_reg(LSR) & LSR_DATA_READY == 0 {
+        return None;
+    }
+    Some(read_reg(RBR))
+}
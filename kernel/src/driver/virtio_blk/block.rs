@@ -1,45 +1,235 @@
-use spin::Mutex;
-use virtio_drivers::{Hal, VirtIOBlk, VirtIOHeader};
+use core::hint::spin_loop;
+use core::sync::atomic::{fence, Ordering};
+use virtio_drivers::{BlkResp, Error as VirtioError, Hal, RespStatus, Result as VirtioResult, VirtIOBlk, VirtIOHeader};
 use lazy_static::*;
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
 use crate::{memory::*};
-use crate::sync::UPSafeCell;
+use crate::sync::{SpinLock, hart_holds_lock};
+use crate::driver::enable_device_irq;
+use crate::driver::fdt::{BOOT_INFO, MmioDevice};
+use crate::task::{TASK_MANAER, TASK_MANAGER_INIT};
 
+/// 设备树不可用时按 qemu virt 的布局探测 virtio-mmio 槽位
 const VIRTIO0: usize = 0x10001000;
+const VIRTIO_MMIO_SLOTS: usize = 8;
+const VIRTIO_MMIO_STRIDE: usize = 0x1000;
+/// virtio-mmio MagicValue "virt"
+const VIRTIO_MMIO_MAGIC: u32 = 0x7472_6976;
+const VIRTIO_MMIO_DEVICE_ID: usize = 0x008;
+const VIRTIO_DEVICE_ID_BLOCK: u32 = 2;
 
 lazy_static!{
-    static ref QUEUE_FRAMES:UPSafeCell<Vec<(virtio_drivers::PhysAddr, Vec<FramTracker>)>> =
-        UPSafeCell::new(Vec::new());
+    static ref QUEUE_FRAMES:SpinLock<Vec<(virtio_drivers::PhysAddr, Vec<FramTracker>)>> =
+        SpinLock::new(Vec::new());
 }
 
 
 
 
 
-pub struct VirtBlk(pub UPSafeCell<VirtIOBlk<'static,VirtioHal>>, u64);
+/// 一个已提交请求的状态，完成由中断（或轮询）在 handle_irq 里标记
+struct BlkRequest {
+    /// 睡眠等待的任务，轮询等待时为 None
+    pid: Option<i32>,
+    done: bool,
+}
 
+pub struct VirtBlk {
+    blk: SpinLock<VirtIOBlk<'static,VirtioHal>>,
+    capacity: u64,
+    /// PLIC 中断号，没有时只能轮询
+    pub irq: Option<u32>,
+    /// token -> 请求状态，请求方取走结果后删除
+    requests: SpinLock<BTreeMap<u16, BlkRequest>>,
+}
 
+lazy_static!{
+    /// 所有已打开的块设备，外部中断按 irq 分发
+    static ref VIRTIO_BLKS:SpinLock<Vec<Arc<VirtBlk>>> = SpinLock::new(Vec::new());
+}
 
 impl VirtBlk {
-    /// TODO:扫描mmio遍历设备
-    /// 添加到全局块设备里面
-    pub fn new()->Self{
+    ///打开 mmio 上的 virtio 块设备，dev 需要先经过 probe_virtio_blk 确认
+    pub fn new(dev:&MmioDevice)->Self{
         unsafe {
-            let header = &mut *(VIRTIO0 as *mut VirtIOHeader);
+            let header = &mut *(dev.base as *mut VirtIOHeader);
             let capacity_in_sectors = core::ptr::read_volatile(header.config_space() as *const u64);
-            let blk = VirtBlk(
-                UPSafeCell::new(
+            VirtBlk {
+                blk: SpinLock::new(
                     VirtIOBlk::new(header).expect("failed new blk device")
                 ),
-                capacity_in_sectors,
-            );
-            blk
+                capacity: capacity_in_sectors,
+                irq: dev.irq,
+                requests: SpinLock::new(BTreeMap::new()),
+            }
         }
     }
 
     pub fn capacity_in_sectors(&self) -> u64 {
-        self.1
+        self.capacity
+    }
+
+    ///读一个扇区，提交后等待完成
+    pub fn read_block(&self, block_id: usize, buf: &mut [u8]) -> VirtioResult {
+        let mut resp = BlkResp::default();
+        let token = loop {
+            // 请求完成前 buf 和 resp 都在这个栈帧上，不会失效
+            // 先放掉设备借用再处理结果，handle_irq 还要借
+            let ret = unsafe { self.blk.lock().read_block_nb(block_id, buf, &mut resp) };
+            match ret {
+                Ok(token) => break token,
+                // 队列满，先收掉已完成的请求再重试
+                Err(VirtioError::BufferTooSmall) => self.handle_irq(),
+                Err(e) => return Err(e),
+            }
+        };
+        self.wait_for(token);
+        Self::resp_result(&resp)
+    }
+
+    ///写一个扇区，提交后等待完成
+    pub fn write_block(&self, block_id: usize, buf: &[u8]) -> VirtioResult {
+        let mut resp = BlkResp::default();
+        let token = loop {
+            let ret = unsafe { self.blk.lock().write_block_nb(block_id, buf, &mut resp) };
+            match ret {
+                Ok(token) => break token,
+                Err(VirtioError::BufferTooSmall) => self.handle_irq(),
+                Err(e) => return Err(e),
+            }
+        };
+        self.wait_for(token);
+        Self::resp_result(&resp)
+    }
+
+    fn resp_result(resp: &BlkResp) -> VirtioResult {
+        // 设备通过 DMA 写 resp，读之前加内存屏障
+        fence(Ordering::SeqCst);
+        match resp.status() {
+            RespStatus::Ok => Ok(()),
+            _ => Err(VirtioError::IoError),
+        }
+    }
+
+    ///等待 token 完成：阻塞当前任务等中断唤醒
+    /// 文件系统和页缓存的锁是 SleepLock，持有它们也能睡眠；
+    /// 只有持有 SpinLock（换入换出时拿着地址空间的锁）时切走会让别的任务自旋，才轮询 used ring
+    fn wait_for(&self, token: u16) {
+        let pid = if unsafe { TASK_MANAGER_INIT } && !hart_holds_lock() {
+            TASK_MANAER.get_current_pid()
+        } else {
+            None
+        };
+        // 别的 hart 可能在提交和登记之间已经收走了完成通知
+        self.requests
+            .lock()
+            .entry(token)
+            .and_modify(|r| r.pid = pid)
+            .or_insert(BlkRequest { pid, done: false });
+        loop {
+            self.handle_irq();
+            {
+                let mut requests = self.requests.lock();
+                if requests.get(&token).map_or(true, |r| r.done) {
+                    requests.remove(&token);
+                    return;
+                }
+            }
+            if pid.is_some() {
+                TASK_MANAER.blocking_current_task_and_run_next();
+            } else {
+                spin_loop();
+            }
+        }
+    }
+
+    ///收取 used ring 里所有完成的请求并唤醒等待的任务
+    /// 中断和轮询都走这里；本 hart 持有锁时（可能是要唤醒的任务的 TCB）先不唤醒，下次调用再补上
+    pub fn handle_irq(&self) {
+        let wake: Vec<i32> = {
+            let mut blk = self.blk.lock();
+            let mut requests = self.requests.lock();
+            blk.ack_interrupt();
+            while let Ok(token) = blk.pop_used() {
+                match requests.get_mut(&token) {
+                    Some(req) => req.done = true,
+                    // 提交之后还没登记，先记下完成，登记时保留
+                    None => {
+                        requests.insert(token, BlkRequest { pid: None, done: true });
+                    }
+                }
+            }
+            requests.values().filter(|r| r.done).filter_map(|r| r.pid).collect()
+        };
+        if wake.is_empty() || hart_holds_lock() {
+            return;
+        }
+        for pid in wake {
+            TASK_MANAER.wake_task_from_blocking(pid);
+        }
+    }
+}
+
+///把块设备登记到中断分发表并在 PLIC 上使能它的中断
+pub fn register_virtio_blk(blk: Arc<VirtBlk>) {
+    if let Some(irq) = blk.irq {
+        enable_device_irq(irq);
+    }
+    VIRTIO_BLKS.lock().push(blk);
+}
+
+///外部中断分发：irq 属于某个块设备时处理它的完成队列
+pub fn virtio_blk_irq(irq: u32) -> bool {
+    let blks: Vec<Arc<VirtBlk>> = VIRTIO_BLKS
+        .lock()
+        .iter()
+        .filter(|b| b.irq == Some(irq))
+        .cloned()
+        .collect();
+    for blk in blks.iter() {
+        blk.handle_irq();
+    }
+    !blks.is_empty()
+}
+
+///不依赖中断收取所有块设备的完成请求，调度时调用，补上被推迟的唤醒
+pub fn virtio_blk_poll() {
+    if VIRTIO_BLKS.held_by_current_hart() {
+        return;
+    }
+    let blks: Vec<Arc<VirtBlk>> = VIRTIO_BLKS.lock().clone();
+    for blk in blks.iter() {
+        // 本 hart 正在提交请求的上下文里不收
+        if blk.blk.held_by_current_hart() || blk.requests.held_by_current_hart() {
+            continue;
+        }
+        blk.handle_irq();
+    }
+}
+
+///base 上的 virtio-mmio 槽位是否插着块设备（空槽位 DeviceID 为 0）
+fn is_virtio_blk(base:usize)->bool{
+    unsafe {
+        let magic = core::ptr::read_volatile(base as *const u32);
+        let device_id = core::ptr::read_volatile((base + VIRTIO_MMIO_DEVICE_ID) as *const u32);
+        magic == VIRTIO_MMIO_MAGIC && device_id == VIRTIO_DEVICE_ID_BLOCK
+    }
+}
+
+///探测所有 virtio-mmio 槽位，返回块设备的 mmio 描述，按地址升序（bus.0 在最前面）
+/// 设备树里没有 virtio,mmio 节点时探测 VIRTIO0 开始的 8 个槽位
+pub fn probe_virtio_blk()->Vec<MmioDevice>{
+    let mut slots = BOOT_INFO.lock().virtio_mmio.clone();
+    if slots.is_empty() {
+        slots = (0..VIRTIO_MMIO_SLOTS)
+            .map(|i| MmioDevice {
+                base: VIRTIO0 + i * VIRTIO_MMIO_STRIDE,
+                size: VIRTIO_MMIO_STRIDE,
+                irq: Some(i as u32 + 1),
+            })
+            .collect();
     }
+    slots.into_iter().filter(|dev| is_virtio_blk(dev.base)).collect()
 }
 
 pub struct VirtioHal;
@@ -1,11 +1,28 @@
     .section .text.entry
     .globl _blue_start
+    .globl _blue_secondary_start
+# a0:hartid a1:设备树(主 hart)/opaque(从 hart)
+# tp 在内核里一直保存 hartid
+# 每个 hart 一个启动栈 sp = kernel_stack_top - hartid * BOOT_STACK_SIZE
 _blue_start:
+    mv tp, a0
     la sp, kernel_stack_top
+    li t0, 4096 * 16
+    mul t0, t0, a0
+    sub sp, sp, t0
     la t0,kernel_trap_stack_top
     csrrw t0,sscratch,t0
     call blue_main
 
+# 其它 hart 由主 hart 通过 SBI HSM hart_start 从这里启动，此时还没开分页
+_blue_secondary_start:
+    mv tp, a0
+    la sp, kernel_stack_top
+    li t0, 4096 * 16
+    mul t0, t0, a0
+    sub sp, sp, t0
+    call blue_secondary_main
+
 
 
 
@@ -14,7 +31,8 @@ _blue_start:
 .section .bss.stack
     .globl kernel_stack_lower_bound
 kernel_stack_lower_bound:
-    .space 4096 * 16
+    # MAX_HARTS(8) 个 BOOT_STACK_SIZE(64KB)
+    .space 4096 * 16 * 8
     .globl kernel_stack_top
 kernel_stack_top:
 .global kernel_trap_run_stack_bottom
@@ -33,4 +51,4 @@ kernel_trap_stack_top:
 
 
 
-#ld:从内存加载64到寄存器 la 将符号地址赋值给寄存器
\ No newline at end of file
+#ld:从内存加载64到寄存器 la 将符号地址赋值给寄存器
@@ -1,2 +1,3 @@
 pub mod pipe;
-pub mod stdio;
\ No newline at end of file
+pub mod stdio;
+pub mod tty;
\ No newline at end of file
@@ -1,16 +1,20 @@
 use alloc::sync::{Arc, Weak};
 
-use crate::sync::UPSafeCell;
+use crate::sync::SpinLock;
 use crate::fs::vfs::{File, VfsFsError};
-use crate::task::TASK_MANAER;
+use crate::task::{WaitQueue, TASK_MANAER};
 
 pub const RINGBUFFERSIZE:usize = 512;
 
-///pipe模型
+///pipe模型 两端共享缓冲区和等待队列，端口本身不可变
 pub struct Pipe{
     readble:bool,
     writeble:bool,
-    ringbuffer:Arc<UPSafeCell<PipeRingBuffer>>,
+    ringbuffer:Arc<SpinLock<PipeRingBuffer>>,
+    ///等数据（或写端关闭）的读者
+    readers:Arc<WaitQueue>,
+    ///等空间（或读端关闭）的写者
+    writers:Arc<WaitQueue>,
 }
 
 ///pipe环形缓冲区模型
@@ -19,8 +23,8 @@ pub struct PipeRingBuffer{
     status:PipeRingBufferStatus,
     head:usize,
     tail:usize,
-    write_point:Weak<UPSafeCell<Pipe>>, //写段弱引用计数,检测写段是否关闭
-    read_point:Weak<UPSafeCell<Pipe>>, //读端弱引用计数，检测读端是否关闭
+    write_point:Weak<Pipe>, //写段弱引用计数,检测写段是否关闭
+    read_point:Weak<Pipe>, //读端弱引用计数，检测读端是否关闭
 }
 
 ///pipe环形缓冲区状态
@@ -42,10 +46,10 @@ impl PipeRingBuffer {
         }
     }
 
-    pub fn set_write_point(&mut self, w: Weak<UPSafeCell<Pipe>>) {
+    pub fn set_write_point(&mut self, w: Weak<Pipe>) {
         self.write_point = w;
     }
-    pub fn set_read_point(&mut self, r: Weak<UPSafeCell<Pipe>>) {
+    pub fn set_read_point(&mut self, r: Weak<Pipe>) {
         self.read_point = r;
     }
 
@@ -149,73 +153,122 @@ impl PipeRingBuffer {
 }
 
 impl Pipe {
-    pub fn new(readble: bool, writeble: bool, ringbuffer: Arc<UPSafeCell<PipeRingBuffer>>) -> Self {
+    pub fn new(
+        readble: bool,
+        writeble: bool,
+        ringbuffer: Arc<SpinLock<PipeRingBuffer>>,
+        readers: Arc<WaitQueue>,
+        writers: Arc<WaitQueue>,
+    ) -> Self {
         Self {
             readble,
             writeble,
             ringbuffer,
+            readers,
+            writers,
         }
     }
 
+    ///没有数据时睡在 readers 上，写端全部关闭后返回0
     pub fn read(&self, buf: &mut [u8]) -> Result<usize, VfsFsError> {
-        if !self.readble {
+        if !self.readble || buf.is_empty() {
             return Ok(0);
         }
         loop {
-            let (can_read, write_closed) = {
-                let ring = self.ringbuffer.lock();
-                (ring.can_read(), ring.is_write_end_closed())
-            };
-            if can_read {
+            {
                 let mut ring = self.ringbuffer.lock();
-                return Ok(ring.read(buf));
+                if ring.can_read() {
+                    let n = ring.read(buf);
+                    drop(ring);
+                    self.writers.wake_all();
+                    return Ok(n);
+                }
+                if ring.is_write_end_closed() {
+                    return Ok(0);
+                }
             }
-            if write_closed {
-                return Ok(0);
+            if TASK_MANAER.current_has_signal() {
+                return Err(VfsFsError::Interrupted);
             }
-            TASK_MANAER.suspend_and_run_task();
+            self.readers.sleep_unless(|| {
+                let ring = self.ringbuffer.lock();
+                ring.can_read() || ring.is_write_end_closed()
+            });
         }
     }
 
+    ///缓冲区满时睡在 writers 上直到全部写完，读端关闭返回 BrokenPipe
     pub fn write(&self, buf: &[u8]) -> Result<usize, VfsFsError> {
         if !self.writeble {
             return Ok(0);
         }
-        let mut ring = self.ringbuffer.lock();
-        if !ring.can_write() {
-            return Ok(0);
+        let mut written = 0usize;
+        while written < buf.len() {
+            {
+                let mut ring = self.ringbuffer.lock();
+                if ring.can_write() || ring.is_read_end_closed() {
+                    let n = match ring.write(&buf[written..]) {
+                        Ok(n) => n,
+                        Err(e) if written == 0 => return Err(e),
+                        Err(_) => return Ok(written),
+                    };
+                    drop(ring);
+                    written += n;
+                    self.readers.wake_all();
+                    continue;
+                }
+            }
+            if TASK_MANAER.current_has_signal() {
+                return if written == 0 { Err(VfsFsError::Interrupted) } else { Ok(written) };
+            }
+            self.writers.sleep_unless(|| {
+                let ring = self.ringbuffer.lock();
+                ring.can_write() || ring.is_read_end_closed()
+            });
+        }
+        Ok(written)
+    }
+}
+
+impl Drop for Pipe {
+    ///一端关闭时唤醒另一端，让它们看到 EOF/BrokenPipe
+    fn drop(&mut self) {
+        if self.writeble {
+            self.readers.wake_all();
+        }
+        if self.readble {
+            self.writers.wake_all();
         }
-        ring.write(buf)
     }
 }
 
-pub fn make_pipe() -> (Arc<UPSafeCell<Pipe>>, Arc<UPSafeCell<Pipe>>) {
-    let ring = Arc::new(UPSafeCell::new(PipeRingBuffer::new()));
-    let read_end = Arc::new(UPSafeCell::new(Pipe::new(true, false, ring.clone())));
-    let write_end = Arc::new(UPSafeCell::new(Pipe::new(false, true, ring.clone())));
+pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
+    let ring = Arc::new(SpinLock::new(PipeRingBuffer::new()));
+    let readers = Arc::new(WaitQueue::new());
+    let writers = Arc::new(WaitQueue::new());
+    let read_end = Arc::new(Pipe::new(true, false, ring.clone(), readers.clone(), writers.clone()));
+    let write_end = Arc::new(Pipe::new(false, true, ring.clone(), readers, writers));
     ring.lock().set_write_point(Arc::downgrade(&write_end));
     ring.lock().set_read_point(Arc::downgrade(&read_end));
     (read_end, write_end)
 }
 
 pub struct PipeHandle {
-    end: Arc<UPSafeCell<Pipe>>,
+    end: Arc<Pipe>,
 }
 
 impl PipeHandle {
-    pub fn new(end: Arc<UPSafeCell<Pipe>>) -> Self {
+    pub fn new(end: Arc<Pipe>) -> Self {
         Self { end }
     }
 }
 
 impl File for PipeHandle {
     fn read(&self, buf: &mut [u8]) -> Result<usize, VfsFsError> {
-        let pipe = self.end.lock();
-        pipe.read(buf)
+        self.end.read(buf)
     }
 
     fn write(&self, buf: &[u8]) -> Result<usize, VfsFsError> {
-        let pipe = self.end.lock();
-        pipe.write(buf)
+        self.end.write(buf)
     }
 }
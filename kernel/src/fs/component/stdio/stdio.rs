@@ -1,6 +1,6 @@
 use core::fmt::{self, Write};
 use alloc::sync::Arc;
-use crate::{sbi, task::TASK_MANAER};
+use crate::fs::component::tty::{tty_ioctl, tty_check_write, tty_read, tty_write};
 use crate::fs::vfs::{File, OpenFlags, VfsFsError};
 
 
@@ -18,56 +18,35 @@ pub struct Stdin;
 pub struct Stderr;
 
 
-impl Stdin {
-    ///调用栈顶必须为traphandler！！！，因为其中有TASK_MANAER.suspend_and_run_task();
-    pub fn get_char() -> u8 {
-        //直接调用sbi接口，返回一个字符，没有字符就挂起
-        let cha = sbi::get_char() as u8;
-
-        if cha == 0 {
-            TASK_MANAER.suspend_and_run_task();//没有字符就切换任务
-        }
-        
-        cha
-    }
-}
-
 impl File for Stdout {
     fn read(&self, _buf: &mut [u8]) -> Result<usize, VfsFsError> {
         Err(VfsFsError::NotSupported)
     }
 
     fn write(&self, buf: &[u8]) -> Result<usize, VfsFsError> {
-        for &byte in buf {
-            sbi::putc(byte as usize);
-        }
+        tty_check_write()?;
+        tty_write(buf);
         Ok(buf.len())
     }
+
+    fn ioctl(&self, cmd: usize, data: &mut [u8]) -> Result<usize, VfsFsError> {
+        tty_ioctl(cmd, data)
+    }
 }
 
 impl File for Stdin {
+    ///经过 TTY 行规程，cooked 模式一次返回一行
     fn read(&self, buf: &mut [u8]) -> Result<usize, VfsFsError> {
-        let mut read_count = 0usize;
-        buf.iter_mut().for_each(|b| *b = 0);
-
-        for slot in buf.iter_mut() {
-            let mut cha = sbi::get_char();
-            while cha == 0 {
-                TASK_MANAER.suspend_and_run_task();
-                cha = sbi::get_char();
-            }
-            *slot = cha as u8;
-            read_count += 1;
-            if *slot == 13 {
-                break;
-            }
-        }
-        Ok(read_count)
+        tty_read(buf)
     }
 
     fn write(&self, _buf: &[u8]) -> Result<usize, VfsFsError> {
         Err(VfsFsError::NotSupported)
     }
+
+    fn ioctl(&self, cmd: usize, data: &mut [u8]) -> Result<usize, VfsFsError> {
+        tty_ioctl(cmd, data)
+    }
 }
 
 impl File for Stderr {
@@ -76,21 +55,20 @@ impl File for Stderr {
     }
 
     fn write(&self, buf: &[u8]) -> Result<usize, VfsFsError> {
-        for &b in b"<3>" {
-            sbi::putc(b as usize);
-        }
-        for &byte in buf {
-            sbi::putc(byte as usize);
-        }
+        tty_check_write()?;
+        tty_write(b"<3>");
+        tty_write(buf);
         Ok(buf.len())
     }
+
+    fn ioctl(&self, cmd: usize, data: &mut [u8]) -> Result<usize, VfsFsError> {
+        tty_ioctl(cmd, data)
+    }
 }
 
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        for cha in s.chars() {
-            sbi::putc(cha as usize);
-        }
+        tty_write(s.as_bytes());
         Ok(())
     }
 }
@@ -0,0 +1,2 @@
+mod tty;
+pub use tty::*;
\ No newline at end of file
@@ -0,0 +1,498 @@
+//! 控制台 TTY：ns16550a 串口之上的行规程
+//! cooked 模式（ICANON）按行缓冲：回显、退格、^U 删行、^D 文件结束、^C 发 SIGINT、^Z 发 SIGTSTP
+//! raw 模式字符直接交给 read，通过 ioctl(TCGETS/TCSETS) 切换
+//! 控制台是一个会话的控制终端，前台进程组收终端信号；后台进程组读终端收 SIGTTIN，设置了 TOSTOP 时写终端收 SIGTTOU
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+use lazy_static::lazy_static;
+
+use crate::driver::{uart_getc, uart_putc};
+use crate::fs::vfs::VfsFsError;
+use crate::sync::SpinLock;
+use crate::task::{current_ignores_or_blocks, Signal, WaitQueue, INIT_PID, TASK_MANAER};
+
+/// ioctl 请求号 asm-generic
+pub const TCGETS: usize = 0x5401;
+pub const TCSETS: usize = 0x5402;
+pub const TCSETSW: usize = 0x5403;
+pub const TCSETSF: usize = 0x5404;
+pub const TIOCSCTTY: usize = 0x540E;
+pub const TIOCGPGRP: usize = 0x540F;
+pub const TIOCSPGRP: usize = 0x5410;
+pub const TIOCNOTTY: usize = 0x5422;
+pub const TIOCGSID: usize = 0x5429;
+
+/// c_iflag
+pub const INLCR: u32 = 0o100;
+pub const IGNCR: u32 = 0o200;
+pub const ICRNL: u32 = 0o400;
+/// c_oflag
+pub const OPOST: u32 = 0o1;
+pub const ONLCR: u32 = 0o4;
+/// c_lflag
+pub const ISIG: u32 = 0o1;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+pub const ECHOE: u32 = 0o20;
+pub const ECHOK: u32 = 0o40;
+pub const TOSTOP: u32 = 0o400;
+pub const ECHOCTL: u32 = 0o1000;
+pub const IEXTEN: u32 = 0o100000;
+
+/// c_cc 下标
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VTIME: usize = 5;
+pub const VMIN: usize = 6;
+pub const VSUSP: usize = 10;
+pub const NCCS: usize = 19;
+
+/// cooked 模式一行最多这么长，多出来的字符丢掉
+const MAX_LINE: usize = 4096;
+
+/// 内核 ABI 的 struct termios（36字节），libc 的 termios 以它为前缀
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Termios {
+    pub c_iflag: u32,
+    pub c_oflag: u32,
+    pub c_cflag: u32,
+    pub c_lflag: u32,
+    pub c_line: u8,
+    pub c_cc: [u8; NCCS],
+}
+
+impl Default for Termios {
+    ///和 Linux 控制台的默认值一致
+    fn default() -> Self {
+        let mut c_cc = [0u8; NCCS];
+        c_cc[VINTR] = 0x03; // ^C
+        c_cc[VQUIT] = 0x1c; // ^\
+        c_cc[VERASE] = 0x7f; // DEL
+        c_cc[VKILL] = 0x15; // ^U
+        c_cc[VEOF] = 0x04; // ^D
+        c_cc[VTIME] = 0;
+        c_cc[VMIN] = 1;
+        c_cc[VSUSP] = 0x1a; // ^Z
+        Termios {
+            c_iflag: ICRNL,
+            c_oflag: OPOST | ONLCR,
+            c_cflag: 0o2277, // B38400 | CS8 | CREAD | HUPCL
+            c_lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | IEXTEN,
+            c_line: 0,
+            c_cc,
+        }
+    }
+}
+
+/// 收到一个字符后需要在 TTY 借用之外做的事
+enum TtyAction {
+    None,
+    /// 有新数据可读，唤醒读者
+    Wake,
+    /// 给前台任务发信号
+    Signal(Signal),
+}
+
+pub struct Tty {
+    termios: Termios,
+    /// cooked 模式已经提交的行，空行表示 ^D 文件结束
+    lines: VecDeque<Vec<u8>>,
+    /// cooked 模式正在编辑的行
+    line: Vec<u8>,
+    /// raw 模式收到的字符
+    raw: VecDeque<u8>,
+    /// 以它为控制终端的会话，0 表示没有
+    session: i32,
+    /// 前台进程组
+    pgrp: i32,
+}
+
+lazy_static! {
+    pub static ref TTY: SpinLock<Tty> = SpinLock::new(Tty::new());
+}
+
+/// 睡眠等待输入的任务
+static TTY_READERS: WaitQueue = WaitQueue::new();
+
+/// 输出处理标志单独放原子量：内核日志和 panic 输出不能借 TTY
+static OFLAG: AtomicU32 = AtomicU32::new(OPOST | ONLCR);
+
+///按 c_oflag 输出一个字节
+pub fn tty_putc(c: u8) {
+    let oflag = OFLAG.load(Ordering::Relaxed);
+    if c == b'\n' && oflag & OPOST != 0 && oflag & ONLCR != 0 {
+        uart_putc(b'\r');
+    }
+    uart_putc(c);
+}
+
+pub fn tty_write(buf: &[u8]) {
+    for &c in buf {
+        tty_putc(c);
+    }
+}
+
+impl Tty {
+    fn new() -> Self {
+        Tty {
+            termios: Termios::default(),
+            lines: VecDeque::new(),
+            line: Vec::new(),
+            raw: VecDeque::new(),
+            // 开机时控制台就是 init 会话的控制终端
+            session: INIT_PID,
+            pgrp: INIT_PID,
+        }
+    }
+
+    fn lflag(&self, flag: u32) -> bool {
+        self.termios.c_lflag & flag != 0
+    }
+
+    fn echo(&self, c: u8) {
+        if !self.lflag(ECHO) {
+            return;
+        }
+        // 控制字符回显成 ^X
+        if self.lflag(ECHOCTL) && c < 0x20 && c != b'\n' && c != b'\t' {
+            tty_putc(b'^');
+            tty_putc(c + 0x40);
+        } else {
+            tty_putc(c);
+        }
+    }
+
+    ///退格删掉一个字符（^X 形式回显的控制字符占两格）
+    fn erase_one(&mut self) {
+        if let Some(c) = self.line.pop() {
+            if self.lflag(ECHO) && self.lflag(ECHOE) {
+                let width = if self.lflag(ECHOCTL) && c < 0x20 && c != b'\t' { 2 } else { 1 };
+                for _ in 0..width {
+                    tty_write(b"\x08 \x08");
+                }
+            }
+        }
+    }
+
+    ///行规程处理一个输入字符
+    fn receive(&mut self, mut c: u8) -> TtyAction {
+        let iflag = self.termios.c_iflag;
+        if c == b'\r' {
+            if iflag & IGNCR != 0 {
+                return TtyAction::None;
+            }
+            if iflag & ICRNL != 0 {
+                c = b'\n';
+            }
+        } else if c == b'\n' && iflag & INLCR != 0 {
+            c = b'\r';
+        }
+
+        let cc = self.termios.c_cc;
+        if self.lflag(ISIG) && (c == cc[VINTR] || c == cc[VQUIT] || c == cc[VSUSP]) {
+            self.line.clear();
+            self.echo(c);
+            if self.lflag(ECHO) {
+                tty_putc(b'\n');
+            }
+            let sig = if c == cc[VINTR] {
+                Signal::SIGINT
+            } else if c == cc[VQUIT] {
+                Signal::SIGQUIT
+            } else {
+                Signal::SIGTSTP
+            };
+            return TtyAction::Signal(sig);
+        }
+
+        if !self.lflag(ICANON) {
+            self.raw.push_back(c);
+            self.echo(c);
+            return TtyAction::Wake;
+        }
+
+        // 退格键有的终端发 DEL 有的发 BS，都当成擦除
+        if c == cc[VERASE] || c == 0x08 {
+            self.erase_one();
+            return TtyAction::None;
+        }
+        if c == cc[VKILL] {
+            while !self.line.is_empty() {
+                self.erase_one();
+            }
+            return TtyAction::None;
+        }
+        if c == cc[VEOF] {
+            // 行首的 ^D 提交一个空行，read 返回0
+            let line = core::mem::take(&mut self.line);
+            self.lines.push_back(line);
+            return TtyAction::Wake;
+        }
+        if c == b'\n' {
+            self.line.push(c);
+            self.echo(c);
+            let line = core::mem::take(&mut self.line);
+            self.lines.push_back(line);
+            return TtyAction::Wake;
+        }
+        if self.line.len() < MAX_LINE {
+            self.line.push(c);
+            self.echo(c);
+        }
+        TtyAction::None
+    }
+
+    ///有没有能读的数据
+    fn has_input(&self) -> bool {
+        if self.lflag(ICANON) {
+            !self.lines.is_empty()
+        } else {
+            !self.raw.is_empty()
+        }
+    }
+
+    ///有数据时读出来，没有数据返回 None
+    /// cooked 模式一次最多读一行
+    fn try_read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if !self.lflag(ICANON) {
+            if self.raw.is_empty() {
+                return None;
+            }
+            let n = core::cmp::min(buf.len(), self.raw.len());
+            for (dst, src) in buf.iter_mut().zip(self.raw.drain(..n)) {
+                *dst = src;
+            }
+            return Some(n);
+        }
+        let line = self.lines.front_mut()?;
+        if line.is_empty() {
+            self.lines.pop_front();
+            return Some(0);
+        }
+        let n = core::cmp::min(buf.len(), line.len());
+        buf[..n].copy_from_slice(&line[..n]);
+        line.drain(..n);
+        if line.is_empty() {
+            self.lines.pop_front();
+        }
+        Some(n)
+    }
+
+    ///切换模式时把已有输入搬到新模式的缓冲里
+    fn set_termios(&mut self, termios: Termios, flush: bool) {
+        let was_canon = self.lflag(ICANON);
+        self.termios = termios;
+        OFLAG.store(termios.c_oflag, Ordering::Relaxed);
+        if flush {
+            self.lines.clear();
+            self.line.clear();
+            self.raw.clear();
+            return;
+        }
+        let now_canon = self.lflag(ICANON);
+        if was_canon && !now_canon {
+            for line in self.lines.drain(..) {
+                self.raw.extend(line);
+            }
+            self.raw.extend(self.line.drain(..));
+        } else if !was_canon && now_canon {
+            self.line.extend(self.raw.drain(..));
+        }
+    }
+}
+
+///把串口里收到的字符全部交给行规程，串口中断和读者轮询都走这里
+/// 收取时一直持有 TTY 锁，多个 hart 同时收取时字符不会乱序
+pub fn tty_receive_input() {
+    let mut wake = false;
+    let mut signals: Vec<Signal> = Vec::new();
+    let pgrp = {
+        let mut tty = TTY.lock();
+        while let Some(c) = uart_getc() {
+            match tty.receive(c) {
+                TtyAction::None => {}
+                TtyAction::Wake => wake = true,
+                TtyAction::Signal(sig) => {
+                    signals.push(sig);
+                    wake = true;
+                }
+            }
+        }
+        tty.pgrp
+    };
+    for sig in signals {
+        TASK_MANAER.signal_process_group(pgrp, sig);
+    }
+    if wake {
+        TTY_READERS.wake_all();
+    }
+}
+
+///当前任务是不是控制终端会话里的后台进程，是的话返回它的进程组
+fn background_pgrp() -> Option<i32> {
+    let task = TASK_MANAER.current_task()?;
+    let (pgid, sid) = {
+        let t = task.lock();
+        (t.pgid, t.sid)
+    };
+    let tty = TTY.lock();
+    (tty.session == sid && tty.pgrp != pgid).then_some(pgid)
+}
+
+///后台进程访问终端：给它的进程组发 sig 并返回 Interrupted，停下再继续后系统调用重新执行
+/// 信号被忽略或屏蔽时 SIGTTIN 返回 IO 错误，SIGTTOU 直接放行
+fn job_control_check(sig: Signal) -> Result<(), VfsFsError> {
+    let Some(pgid) = background_pgrp() else {
+        return Ok(());
+    };
+    if current_ignores_or_blocks(sig) {
+        return if sig == Signal::SIGTTIN { Err(VfsFsError::IO) } else { Ok(()) };
+    }
+    TASK_MANAER.signal_process_group(pgid, sig);
+    Err(VfsFsError::Interrupted)
+}
+
+///用户程序写终端之前检查，TOSTOP 打开时后台进程会收到 SIGTTOU
+pub fn tty_check_write() -> Result<(), VfsFsError> {
+    let tostop = TTY.lock().lflag(TOSTOP);
+    if tostop {
+        job_control_check(Signal::SIGTTOU)?;
+    }
+    Ok(())
+}
+
+///会话首进程退出或者放弃控制终端：终端不再属于这个会话，前台进程组收到 SIGHUP 和 SIGCONT
+pub fn tty_release_session(sid: i32) {
+    let pgrp = {
+        let mut tty = TTY.lock();
+        if tty.session != sid {
+            return;
+        }
+        tty.session = 0;
+        core::mem::replace(&mut tty.pgrp, 0)
+    };
+    TASK_MANAER.signal_process_group(pgrp, Signal::SIGHUP);
+    TASK_MANAER.signal_process_group(pgrp, Signal::SIGCONT);
+}
+
+///从 TTY 读，没有数据时阻塞（不能睡眠时让出 CPU 轮询）
+pub fn tty_read(buf: &mut [u8]) -> Result<usize, VfsFsError> {
+    if buf.is_empty() {
+        return Ok(0);
+    }
+    job_control_check(Signal::SIGTTIN)?;
+    loop {
+        // 内核态不响应串口中断，先把没处理的字符收进来
+        tty_receive_input();
+        if let Some(n) = TTY.lock().try_read(buf) {
+            return Ok(n);
+        }
+        if TASK_MANAER.current_has_signal() {
+            return Err(VfsFsError::Interrupted);
+        }
+        TTY_READERS.sleep_unless(|| TTY.lock().has_input());
+    }
+}
+
+///控制终端相关的 ioctl：TIOCSCTTY/TIOCNOTTY/TIOC[GS]PGRP/TIOCGSID
+fn tty_ioctl_session(cmd: usize, data: &mut [u8]) -> Result<usize, VfsFsError> {
+    let task = TASK_MANAER.current_task().ok_or(VfsFsError::Invalid)?;
+    let (tgid, pgid, sid) = {
+        let t = task.lock();
+        (t.tgid, t.pgid, t.sid)
+    };
+    drop(task);
+    match cmd {
+        TIOCSCTTY => {
+            // 只有没有控制终端的会话首进程能拿，终端已经属于别的会话时失败
+            let mut tty = TTY.lock();
+            if tgid != sid || (tty.session != 0 && tty.session != sid) {
+                return Err(VfsFsError::PermissionDenied);
+            }
+            tty.session = sid;
+            tty.pgrp = pgid;
+            Ok(0)
+        }
+        TIOCNOTTY => {
+            if TTY.lock().session != sid {
+                return Err(VfsFsError::NoDevice);
+            }
+            if tgid == sid {
+                tty_release_session(sid);
+            }
+            Ok(0)
+        }
+        TIOCGPGRP | TIOCGSID => {
+            let tty = TTY.lock();
+            if tty.session != sid {
+                return Err(VfsFsError::NoDevice);
+            }
+            let value = if cmd == TIOCGPGRP { tty.pgrp } else { tty.session };
+            data[..4].copy_from_slice(&value.to_le_bytes());
+            Ok(0)
+        }
+        TIOCSPGRP => {
+            let new_pgrp = i32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            if TTY.lock().session != sid {
+                return Err(VfsFsError::NoDevice);
+            }
+            job_control_check(Signal::SIGTTOU)?;
+            // 只能设成本会话里已有的进程组
+            if new_pgrp <= 0 || TASK_MANAER.find_tasks(|t| t.pgid == new_pgrp && t.sid == sid).is_empty() {
+                return Err(VfsFsError::PermissionDenied);
+            }
+            TTY.lock().pgrp = new_pgrp;
+            Ok(0)
+        }
+        _ => Err(VfsFsError::NotSupported),
+    }
+}
+
+///TTY 的 ioctl，data 是已经从用户态拷进来/要拷回去的参数
+pub fn tty_ioctl(cmd: usize, data: &mut [u8]) -> Result<usize, VfsFsError> {
+    const SIZE: usize = core::mem::size_of::<Termios>();
+    if matches!(cmd, TIOCSCTTY | TIOCNOTTY | TIOCGPGRP | TIOCSPGRP | TIOCGSID) {
+        return tty_ioctl_session(cmd, data);
+    }
+    if data.len() < SIZE {
+        return Err(VfsFsError::Invalid);
+    }
+    match cmd {
+        TCGETS => {
+            let termios = TTY.lock().termios;
+            let bytes = unsafe {
+                core::slice::from_raw_parts(&termios as *const Termios as *const u8, SIZE)
+            };
+            data[..SIZE].copy_from_slice(bytes);
+            Ok(0)
+        }
+        TCSETS | TCSETSW | TCSETSF => {
+            job_control_check(Signal::SIGTTOU)?;
+            let termios = unsafe { core::ptr::read_unaligned(data.as_ptr() as *const Termios) };
+            // 输出是同步的，TCSETSW 不需要等待
+            TTY.lock().set_termios(termios, cmd == TCSETSF);
+            Ok(0)
+        }
+        _ => Err(VfsFsError::NotSupported),
+    }
+}
+
+///ioctl 参数在用户态的大小，syscall 按它拷贝；不认识的请求返回 None
+pub fn tty_ioctl_arg_size(cmd: usize) -> Option<(usize, bool)> {
+    match cmd {
+        // (大小, 是否需要拷回用户态)
+        TCGETS => Some((core::mem::size_of::<Termios>(), true)),
+        TCSETS | TCSETSW | TCSETSF => Some((core::mem::size_of::<Termios>(), false)),
+        TIOCGPGRP | TIOCGSID => Some((core::mem::size_of::<i32>(), true)),
+        TIOCSPGRP => Some((core::mem::size_of::<i32>(), false)),
+        // 参数是整数值或者没有参数，不用拷贝
+        TIOCSCTTY | TIOCNOTTY => Some((0, false)),
+        _ => None,
+    }
+}
@@ -1,9 +1,9 @@
 use crate::fs::vfs::*;
-use alloc::string::ToString;
+use alloc::string::{String, ToString};
 use rsext4::{Jbd2Dev, ext4_backend::ext4::Ext4FileSystem, fs_mount, fs_umount, mkfs};
 
 use alloc::format;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use rsext4::{
     OpenFile,
     lseek as ext4_lseek,
@@ -18,33 +18,51 @@ use rsext4::{
 };
 use rsext4::ext4_backend::dir::get_inode_with_num;
 use rsext4::ext4_backend::entries::DirEntryIterator;
-use rsext4::ext4_backend::file::unlink as ext4_unlink;
+use rsext4::ext4_backend::file::{create_symbol_link as ext4_symlink, link as ext4_link, unlink as ext4_unlink};
 use rsext4::ext4_backend::loopfile::resolve_inode_block_allextend;
 use rsext4::ext4_backend::config::BLOCK_SIZE;
 use alloc::vec::Vec;
 use super::Ext4BlockDevice;
+use crate::memory::FramTracker;
+use crate::sync::SleepLock;
 
 pub struct Ext4Fs {
     pub dev: Jbd2Dev<Ext4BlockDevice>,
     pub fs: Option<Ext4FileSystem>,
 }
 
+/// 目标不到 60 字节的符号链接存在 inode 的 i_block 里，不占数据块
+const FAST_SYMLINK_MAX: usize = 60;
+
 fn align_up(x: usize, align: usize) -> usize {
     (x + align - 1) & !(align - 1)
 }
 
 pub struct Ext4File {
     mount: MountFs,
-    of: spin::Mutex<OpenFile>,
+    of: SleepLock<OpenFile>,
     flags: OpenFlags,
+    /// 自身弱引用，写页缓存时登记为回写者
+    this: Weak<Ext4File>,
 }
 
 impl Ext4File {
-    pub fn new(mount: MountFs, of: OpenFile,flags: OpenFlags) -> Self {
-        Self {
+    pub fn new(mount: MountFs, of: OpenFile,flags: OpenFlags) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
             mount,
-            of: spin::Mutex::new(of),
+            of: SleepLock::new(of),
             flags,
+            this: this.clone(),
+        })
+    }
+
+    ///普通文件走页缓存，目录不缓存
+    fn cache_key(&self) -> Option<FileCacheKey> {
+        let of = self.of.lock();
+        if of.inode.is_file() {
+            Some(FileCacheKey::new(&self.mount, of.inode_num as u64))
+        } else {
+            None
         }
     }
 
@@ -58,14 +76,12 @@ impl Ext4File {
     }
 }
 
-impl File for Ext4File {
-    fn read(&self, buf: &mut [u8]) -> Result<usize, VfsFsError> {
-        if !self.flags.readable() {
-            return Err(VfsFsError::PermissionDenied);
-        }
+impl PageBacking for Ext4File {
+    fn backing_read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, VfsFsError> {
         let data = self.with_ext4_mut(|ext4| {
             let fs_inner = ext4.fs.as_mut().ok_or(VfsFsError::IO)?;
             let mut of = self.of.lock();
+            ext4_lseek(&mut *of, offset as u64);
             ext4_read_at(&mut ext4.dev, fs_inner, &mut *of, buf.len()).map_err(|_| VfsFsError::IO)
         })?;
         let n = core::cmp::min(buf.len(), data.len());
@@ -73,48 +89,82 @@ impl File for Ext4File {
         Ok(n)
     }
 
-    fn write(&self, buf: &[u8]) -> Result<usize, VfsFsError> {
-        if !self.flags.writable() {
-            return Err(VfsFsError::PermissionDenied);
-        }
+    fn backing_write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, VfsFsError> {
         self.with_ext4_mut(|ext4| {
             let fs_inner = ext4.fs.as_mut().ok_or(VfsFsError::IO)?;
             let mut of = self.of.lock();
-            if self.flags.contains(OpenFlags::APPEND) {
-                let end = of.inode.size() as u64;
-                ext4_lseek(&mut *of, end);
-            }
+            ext4_lseek(&mut *of, offset as u64);
             ext4_write_at(&mut ext4.dev, fs_inner, &mut *of, buf).map_err(|_| VfsFsError::IO)?;
             Ok(buf.len())
         })
     }
 
+    fn backing_size(&self) -> usize {
+        self.of.lock().inode.size() as usize
+    }
+}
+
+impl File for Ext4File {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, VfsFsError> {
+        let off = self.of.lock().offset as usize;
+        let n = self.read_at(off, buf)?;
+        ext4_lseek(&mut *self.of.lock(), (off + n) as u64);
+        Ok(n)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, VfsFsError> {
+        let off = {
+            let of = self.of.lock();
+            if self.flags.contains(OpenFlags::APPEND) {
+                of.inode.size() as usize
+            } else {
+                of.offset as usize
+            }
+        };
+        let n = self.write_at(off, buf)?;
+        ext4_lseek(&mut *self.of.lock(), (off + n) as u64);
+        Ok(n)
+    }
+
     fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, VfsFsError> {
         if !self.flags.readable() {
             return Err(VfsFsError::PermissionDenied);
         }
-        let data = self.with_ext4_mut(|ext4| {
-            let fs_inner = ext4.fs.as_mut().ok_or(VfsFsError::IO)?;
-            let mut of = self.of.lock();
-            ext4_lseek(&mut *of, offset as u64);
-            ext4_read_at(&mut ext4.dev, fs_inner, &mut *of, buf.len()).map_err(|_| VfsFsError::IO)
-        })?;
-        let n = core::cmp::min(buf.len(), data.len());
-        buf[..n].copy_from_slice(&data[..n]);
-        Ok(n)
+        match self.cache_key() {
+            Some(key) => FILE_CACHE.lock().read_at(key, self, offset, buf),
+            None => self.backing_read_at(offset, buf),
+        }
     }
 
     fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, VfsFsError> {
         if !self.flags.writable() {
             return Err(VfsFsError::PermissionDenied);
         }
-        self.with_ext4_mut(|ext4| {
-            let fs_inner = ext4.fs.as_mut().ok_or(VfsFsError::IO)?;
-            let mut of = self.of.lock();
-            ext4_lseek(&mut *of, offset as u64);
-            ext4_write_at(&mut ext4.dev, fs_inner, &mut *of, buf).map_err(|_| VfsFsError::IO)?;
-            Ok(buf.len())
-        })
+        match self.cache_key() {
+            Some(key) => FILE_CACHE.lock().write_at(key, self, self.this.upgrade().expect("Kernel error"), offset, buf),
+            None => self.backing_write_at(offset, buf),
+        }
+    }
+
+    fn flush(&self) -> Result<(), VfsFsError> {
+        match self.cache_key() {
+            Some(key) => FILE_CACHE.lock().flush_file(key, self),
+            None => Ok(()),
+        }
+    }
+
+    fn cache_frame(&self, page: usize) -> Result<Option<Arc<FramTracker>>, VfsFsError> {
+        match self.cache_key() {
+            Some(key) => Ok(Some(FILE_CACHE.lock().get_frame(key, self, page)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn cache_mark_dirty(&self, page: usize, frame: &Arc<FramTracker>) -> bool {
+        match self.cache_key() {
+            Some(key) => FILE_CACHE.lock().mark_dirty(key, page, frame, self.this.upgrade().expect("Kernel error")),
+            None => false,
+        }
     }
 
     fn lseek(&self, offset: isize, whence: usize) -> Result<usize, VfsFsError> {
@@ -143,6 +193,8 @@ impl File for Ext4File {
             VFS_DT_DIR
         } else if of.inode.is_file() {
             VFS_DT_REG
+        } else if of.inode.is_symlink() {
+            VFS_DT_LNK
         } else {
             VFS_DT_UNKNOWN
         };
@@ -151,6 +203,7 @@ impl File for Ext4File {
             size: of.inode.size(),
             mode: 0,
             file_type,
+            nlink: of.inode.i_links_count as u32,
         })
     }
 
@@ -290,7 +343,10 @@ impl VfsFs for Ext4Fs {
                 return Err(VfsFsError::PermissionDenied);
             }
         }
-        Ok(Arc::new(Ext4File::new(mount_fs, of, flags)))
+        if flags.contains(OpenFlags::TRUNC) {
+            FILE_CACHE.lock().invalidate(FileCacheKey::new(&mount_fs, of.inode_num as u64));
+        }
+        Ok(Ext4File::new(mount_fs, of, flags))
     }
 
     fn name(&self) -> Result<alloc::string::String, VfsFsError> {
@@ -328,6 +384,34 @@ impl VfsFs for Ext4Fs {
         ext4_mv(fs_inner, &mut self.dev, src, dest).map_err(|_| VfsFsError::IO)
     }
 
+    /// rsext4 没有原地换目录项的接口：EXCHANGE 不支持；
+    /// 替换时先把 dest 删掉再挪，删之前把能检查的都检查完
+    fn rename2(&mut self, src: &str, dest: &str, flags: RenameFlags) -> Result<(), VfsFsError> {
+        if flags.contains(RenameFlags::EXCHANGE) {
+            return Err(VfsFsError::NotSupported);
+        }
+        let fs_inner = self.fs.as_mut().ok_or(VfsFsError::IO)?;
+        let (src_ino, src_inode) = get_inode_with_num(fs_inner, &mut self.dev, src)
+            .map_err(|_| VfsFsError::IO)?
+            .ok_or(VfsFsError::NotFound)?;
+        let dest_got = get_inode_with_num(fs_inner, &mut self.dev, dest).map_err(|_| VfsFsError::IO)?;
+        if let Some((dest_ino, dest_inode)) = dest_got {
+            if dest_ino == src_ino {
+                return Ok(());
+            }
+            if flags.contains(RenameFlags::NOREPLACE) {
+                return Err(VfsFsError::AlreadyExists);
+            }
+            match (src_inode.is_dir(), dest_inode.is_dir()) {
+                (true, false) => return Err(VfsFsError::NotDir),
+                (false, true) => return Err(VfsFsError::IsDir),
+                (true, true) => self.rmdir(dest)?,
+                (false, false) => self.unlink(dest)?,
+            }
+        }
+        self.mv(src, dest)
+    }
+
     fn rename(&mut self, path: &str, new_name: &str) -> Result<(), VfsFsError> {
         let fs_inner = self.fs.as_mut().ok_or(VfsFsError::IO)?;
         let new_path = if let Some(pos) = path.rfind('/') {
@@ -361,6 +445,32 @@ impl VfsFs for Ext4Fs {
         Ok(())
     }
 
+    fn rmdir(&mut self, path: &str) -> Result<(), VfsFsError> {
+        let fs_inner = self.fs.as_mut().ok_or(VfsFsError::IO)?;
+        let (_ino, mut inode) = get_inode_with_num(fs_inner, &mut self.dev, path)
+            .map_err(|_| VfsFsError::IO)?
+            .ok_or(VfsFsError::NotFound)?;
+        if !inode.is_dir() {
+            return Err(VfsFsError::NotDir);
+        }
+        // 除了 "." 和 ".." 还有目录项就不能删
+        let blocks = resolve_inode_block_allextend(fs_inner, &mut self.dev, &mut inode)
+            .map_err(|_| VfsFsError::IO)?;
+        for &phys in blocks.values() {
+            let cached = fs_inner
+                .datablock_cache
+                .get_or_load(&mut self.dev, phys)
+                .map_err(|_| VfsFsError::IO)?;
+            let busy = DirEntryIterator::new(&cached.data[..BLOCK_SIZE])
+                .any(|(entry, _)| entry.inode != 0 && entry.name != b"." && entry.name != b"..");
+            if busy {
+                return Err(VfsFsError::NotEmpty);
+            }
+        }
+        ext4_unlink(fs_inner, &mut self.dev, path);
+        Ok(())
+    }
+
     fn stat(&mut self, path: &str) -> Result<VfsStat, VfsFsError> {
         let fs_inner = self.fs.as_mut().ok_or(VfsFsError::IO)?;
         let got = get_inode_with_num(fs_inner, &mut self.dev, path)
@@ -371,6 +481,8 @@ impl VfsFs for Ext4Fs {
             VFS_DT_DIR
         } else if inode.is_file() {
             VFS_DT_REG
+        } else if inode.is_symlink() {
+            VFS_DT_LNK
         } else {
             VFS_DT_UNKNOWN
         };
@@ -379,9 +491,64 @@ impl VfsFs for Ext4Fs {
             size: inode.size(),
             mode: 0,
             file_type,
+            nlink: inode.i_links_count as u32,
         })
     }
 
+    fn link(&mut self, src: &str, dest: &str) -> Result<(), VfsFsError> {
+        let fs_inner = self.fs.as_mut().ok_or(VfsFsError::IO)?;
+        let (_ino, inode) = get_inode_with_num(fs_inner, &mut self.dev, src)
+            .map_err(|_| VfsFsError::IO)?
+            .ok_or(VfsFsError::NotFound)?;
+        if inode.is_dir() {
+            return Err(VfsFsError::NotPermitted);
+        }
+        let exists = get_inode_with_num(fs_inner, &mut self.dev, dest)
+            .map_err(|_| VfsFsError::IO)?
+            .is_some();
+        if exists {
+            return Err(VfsFsError::AlreadyExists);
+        }
+        ext4_link(fs_inner, &mut self.dev, dest, src);
+        Ok(())
+    }
+
+    fn symlink(&mut self, target: &str, path: &str) -> Result<(), VfsFsError> {
+        let fs_inner = self.fs.as_mut().ok_or(VfsFsError::IO)?;
+        let exists = get_inode_with_num(fs_inner, &mut self.dev, path)
+            .map_err(|_| VfsFsError::IO)?
+            .is_some();
+        if exists {
+            return Err(VfsFsError::AlreadyExists);
+        }
+        ext4_symlink(&mut self.dev, fs_inner, target, path).map_err(|_| VfsFsError::IO)
+    }
+
+    fn readlink(&mut self, path: &str) -> Result<String, VfsFsError> {
+        let fs_inner = self.fs.as_mut().ok_or(VfsFsError::IO)?;
+        let (_ino, mut inode) = get_inode_with_num(fs_inner, &mut self.dev, path)
+            .map_err(|_| VfsFsError::IO)?
+            .ok_or(VfsFsError::NotFound)?;
+        if !inode.is_symlink() {
+            return Err(VfsFsError::Invalid);
+        }
+        let len = inode.size() as usize;
+        let raw: Vec<u8> = if len < FAST_SYMLINK_MAX {
+            // 短链接直接存在 i_block 里
+            inode.i_block.iter().flat_map(|w| w.to_le_bytes()).take(len).collect()
+        } else {
+            let blocks = resolve_inode_block_allextend(fs_inner, &mut self.dev, &mut inode)
+                .map_err(|_| VfsFsError::IO)?;
+            let phys = *blocks.values().next().ok_or(VfsFsError::IO)?;
+            let cached = fs_inner
+                .datablock_cache
+                .get_or_load(&mut self.dev, phys)
+                .map_err(|_| VfsFsError::IO)?;
+            cached.data[..core::cmp::min(len, BLOCK_SIZE)].to_vec()
+        };
+        String::from_utf8(raw).map_err(|_| VfsFsError::IO)
+    }
+
     fn as_any(&self) -> &dyn core::any::Any {
         self
     }
@@ -4,13 +4,14 @@
 
 use alloc::string::String;
 use crate::alloc::string::ToString;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
 use core::any::Any;
 use alloc::vec;
-use spin::Mutex;
+use crate::sync::SleepLock;
 use alloc::format;
-use crate::fs::vfs::{File, LinuxDirent64, MountFs, OpenFlags, VfsFs, VfsFsError, VfsStat, VFS_DT_DIR, VFS_DT_REG};
+use crate::fs::vfs::{File, FileCacheKey, LinuxDirent64, MountFs, OpenFlags, PageBacking, RenameFlags, VfsFs, VfsFsError, VfsStat, FILE_CACHE, VFS_DT_DIR, VFS_DT_REG};
+use crate::memory::FramTracker;
 
 fn le16(b: &[u8]) -> u16 {
     u16::from_le_bytes([b[0], b[1]])
@@ -945,6 +946,102 @@ impl Fat32Fs {
         }
     }
 
+    /// 把 ent 的 SFN 目录项和它前面的 LFN 目录项标记为已删除，不动数据簇
+    fn delete_dirent(&self, ent: &DirEnt) -> Result<(), VfsFsError> {
+        // Mark SFN entry deleted.
+        let mut buf = vec![0u8; self.info.clus_bytes as usize];
+        self.read_cluster(ent.dirent_clus, &mut buf)?;
+        if ent.dirent_off + DIR_ENTRY_SIZE > buf.len() {
+            return Err(VfsFsError::Invalid);
+        }
+        let raw = &buf[ent.dirent_off..ent.dirent_off + DIR_ENTRY_SIZE];
+        let sfn = Fat32SfnEntry::from_raw(raw)?;
+        let sfn11 = sfn.name11();
+        let ck = lfn_checksum(&sfn11);
+
+        buf[ent.dirent_off] = 0xE5;
+        self.write_cluster(ent.dirent_clus, &buf)?;
+
+        // Mark preceding LFN entries deleted (minimal: only within same cluster).
+        let mut off = ent.dirent_off;
+        while off >= DIR_ENTRY_SIZE {
+            let prev = off - DIR_ENTRY_SIZE;
+            let e = &buf[prev..prev + DIR_ENTRY_SIZE];
+            if e[0] == 0x00 {
+                break;
+            }
+            // LFN entry must have attr 0x0F and same checksum.
+            if e[11] != ATTR_LONG_NAME || e[13] != ck {
+                break;
+            }
+            buf[prev] = 0xE5;
+            off = prev;
+        }
+        self.write_cluster(ent.dirent_clus, &buf)
+    }
+
+    fn read_dirent_raw(&self, dirent_clus: u32, dirent_off: usize) -> Result<[u8; DIR_ENTRY_SIZE], VfsFsError> {
+        let mut buf = vec![0u8; self.info.clus_bytes as usize];
+        self.read_cluster(dirent_clus, &mut buf)?;
+        if dirent_off + DIR_ENTRY_SIZE > buf.len() {
+            return Err(VfsFsError::Invalid);
+        }
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        raw.copy_from_slice(&buf[dirent_off..dirent_off + DIR_ENTRY_SIZE]);
+        Ok(raw)
+    }
+
+    fn write_dirent_raw(&self, dirent_clus: u32, dirent_off: usize, raw: &[u8; DIR_ENTRY_SIZE]) -> Result<(), VfsFsError> {
+        let mut buf = vec![0u8; self.info.clus_bytes as usize];
+        self.read_cluster(dirent_clus, &mut buf)?;
+        if dirent_off + DIR_ENTRY_SIZE > buf.len() {
+            return Err(VfsFsError::Invalid);
+        }
+        buf[dirent_off..dirent_off + DIR_ENTRY_SIZE].copy_from_slice(raw);
+        self.write_cluster(dirent_clus, &buf)
+    }
+
+    /// 目录里除了 "." 和 ".." 之外没有别的有效目录项
+    fn dir_is_empty(&self, dir_first: u32) -> Result<bool, VfsFsError> {
+        let mut clus = dir_first;
+        let mut buf = vec![0u8; self.info.clus_bytes as usize];
+        loop {
+            self.read_cluster(clus, &mut buf)?;
+            for off in (0..buf.len()).step_by(DIR_ENTRY_SIZE) {
+                let e = &buf[off..off + DIR_ENTRY_SIZE];
+                match e[0] {
+                    0x00 => return Ok(true),
+                    0xE5 => continue,
+                    _ => {}
+                }
+                if e[11] == ATTR_LONG_NAME || (e[11] & ATTR_VOLUME_ID) != 0 {
+                    continue;
+                }
+                if &e[0..11] == b".          " || &e[0..11] == b"..         " {
+                    continue;
+                }
+                return Ok(false);
+            }
+            match self.next_cluster(clus)? {
+                Some(n) => clus = n,
+                None => return Ok(true),
+            }
+        }
+    }
+
+    /// 目录换了父目录之后改写它的 ".." 目录项（第二个目录项）
+    fn set_dotdot(&self, dir_first: u32, parent_clus: u32) -> Result<(), VfsFsError> {
+        let mut buf = vec![0u8; self.info.clus_bytes as usize];
+        self.read_cluster(dir_first, &mut buf)?;
+        let e = &mut buf[DIR_ENTRY_SIZE..2 * DIR_ENTRY_SIZE];
+        if &e[0..11] != b"..         " {
+            return Err(VfsFsError::IO);
+        }
+        e[20..22].copy_from_slice(&((parent_clus >> 16) as u16).to_le_bytes());
+        e[26..28].copy_from_slice(&((parent_clus & 0xFFFF) as u16).to_le_bytes());
+        self.write_cluster(dir_first, &buf)
+    }
+
     fn open_path(&self, path: &str) -> Result<(u32, bool, u32), VfsFsError> {
         // returns (first_cluster, is_dir, size)
         if path == "/" || path.is_empty() {
@@ -1161,12 +1258,14 @@ impl Fat32Fs {
 
 pub struct Fat32File {
     mount_fs: MountFs,
-    first_clus: Mutex<u32>,
-    size: Mutex<u32>,
+    first_clus: SleepLock<u32>,
+    size: SleepLock<u32>,
     is_dir: bool,
-    offset: Mutex<usize>,
+    offset: SleepLock<usize>,
     dirent_loc: Option<(u32, usize)>,
     sfn11: Option<[u8; 11]>,
+    /// 自身弱引用，写页缓存时登记为回写者
+    this: Weak<Fat32File>,
 }
 
 impl Fat32File {
@@ -1175,6 +1274,34 @@ impl Fat32File {
         let fs = guard.as_any().downcast_ref::<Fat32Fs>().ok_or(VfsFsError::NotSupported)?;
         f(fs)
     }
+
+    ///页缓存键，inode 取首簇号（与 stat 一致）；还没有簇的空文件不缓存
+    fn cache_key(&self) -> Option<FileCacheKey> {
+        let first = *self.first_clus.lock();
+        if self.is_dir || first == 0 {
+            None
+        } else {
+            Some(FileCacheKey::new(&self.mount_fs, first as u64))
+        }
+    }
+}
+
+impl PageBacking for Fat32File {
+    fn backing_read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, VfsFsError> {
+        let first = *self.first_clus.lock();
+        let size = *self.size.lock();
+        self.with_fs(|fs| fs.read_file_at(first, size, offset, buf))
+    }
+
+    fn backing_write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, VfsFsError> {
+        let mut first = self.first_clus.lock();
+        let mut size = self.size.lock();
+        self.with_fs(|fs| fs.write_file_at(self.dirent_loc, self.sfn11, &mut *first, &mut *size, offset, buf))
+    }
+
+    fn backing_size(&self) -> usize {
+        *self.size.lock() as usize
+    }
 }
 
 impl File for Fat32File {
@@ -1198,18 +1325,41 @@ impl File for Fat32File {
         if self.is_dir {
             return Err(VfsFsError::IsDir);
         }
-        let first = *self.first_clus.lock();
-        let size = *self.size.lock();
-        self.with_fs(|fs| fs.read_file_at(first, size, offset, buf))
+        match self.cache_key() {
+            Some(key) => FILE_CACHE.lock().read_at(key, self, offset, buf),
+            None => self.backing_read_at(offset, buf),
+        }
     }
 
     fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, VfsFsError> {
         if self.is_dir {
             return Err(VfsFsError::IsDir);
         }
-        let mut first = self.first_clus.lock();
-        let mut size = self.size.lock();
-        self.with_fs(|fs| fs.write_file_at(self.dirent_loc, self.sfn11, &mut *first, &mut *size, offset, buf))
+        match self.cache_key() {
+            Some(key) => FILE_CACHE.lock().write_at(key, self, self.this.upgrade().expect("Kernel error"), offset, buf),
+            None => self.backing_write_at(offset, buf),
+        }
+    }
+
+    fn flush(&self) -> Result<(), VfsFsError> {
+        match self.cache_key() {
+            Some(key) => FILE_CACHE.lock().flush_file(key, self),
+            None => Ok(()),
+        }
+    }
+
+    fn cache_frame(&self, page: usize) -> Result<Option<Arc<FramTracker>>, VfsFsError> {
+        match self.cache_key() {
+            Some(key) => Ok(Some(FILE_CACHE.lock().get_frame(key, self, page)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn cache_mark_dirty(&self, page: usize, frame: &Arc<FramTracker>) -> bool {
+        match self.cache_key() {
+            Some(key) => FILE_CACHE.lock().mark_dirty(key, page, frame, self.this.upgrade().expect("Kernel error")),
+            None => false,
+        }
     }
 
     fn lseek(&self, offset: isize, whence: usize) -> Result<usize, VfsFsError> {
@@ -1246,6 +1396,7 @@ impl File for Fat32File {
             size: if self.is_dir { 0 } else { *self.size.lock() as u64 },
             mode: 0,
             file_type: if self.is_dir { VFS_DT_DIR } else { VFS_DT_REG },
+            nlink: 1,
         })
     }
 }
@@ -1271,36 +1422,7 @@ impl VfsFs for Fat32Fs {
             return Err(VfsFsError::IsDir);
         }
 
-        // Mark SFN entry deleted.
-        let mut buf = vec![0u8; self.info.clus_bytes as usize];
-        self.read_cluster(ent.dirent_clus, &mut buf)?;
-        if ent.dirent_off + DIR_ENTRY_SIZE > buf.len() {
-            return Err(VfsFsError::Invalid);
-        }
-        let raw = &buf[ent.dirent_off..ent.dirent_off + DIR_ENTRY_SIZE];
-        let sfn = Fat32SfnEntry::from_raw(raw)?;
-        let sfn11 = sfn.name11();
-        let ck = lfn_checksum(&sfn11);
-
-        buf[ent.dirent_off] = 0xE5;
-        self.write_cluster(ent.dirent_clus, &buf)?;
-
-        // Mark preceding LFN entries deleted (minimal: only within same cluster).
-        let mut off = ent.dirent_off;
-        while off >= DIR_ENTRY_SIZE {
-            let prev = off - DIR_ENTRY_SIZE;
-            let e = &buf[prev..prev + DIR_ENTRY_SIZE];
-            if e[0] == 0x00 {
-                break;
-            }
-            // LFN entry must have attr 0x0F and same checksum.
-            if e[11] != ATTR_LONG_NAME || e[13] != ck {
-                break;
-            }
-            buf[prev] = 0xE5;
-            off = prev;
-        }
-        self.write_cluster(ent.dirent_clus, &buf)?;
+        self.delete_dirent(&ent)?;
 
         // Free data clusters.
         self.free_cluster_chain(ent.first_clus)?;
@@ -1472,14 +1594,15 @@ impl VfsFs for Fat32Fs {
         };
 
         let init_off = if flags.contains(OpenFlags::APPEND) { size as usize } else { 0 };
-        Ok(Arc::new(Fat32File {
+        Ok(Arc::new_cyclic(|this| Fat32File {
             mount_fs,
-            first_clus: Mutex::new(first_clus),
-            size: Mutex::new(size),
+            first_clus: SleepLock::new(first_clus),
+            size: SleepLock::new(size),
             is_dir,
-            offset: Mutex::new(init_off),
+            offset: SleepLock::new(init_off),
             dirent_loc: loc,
             sfn11,
+            this: this.clone(),
         }))
     }
 
@@ -1493,9 +1616,145 @@ impl VfsFs for Fat32Fs {
             size: if is_dir { 0 } else { size as u64 },
             mode: 0,
             file_type: if is_dir { VFS_DT_DIR } else { VFS_DT_REG },
+            nlink: 1,
         })
     }
 
+    /// 删除空目录：只剩 "." 和 ".." 时删掉目录项并释放簇链
+    fn rmdir(&mut self, path: &str) -> Result<(), VfsFsError> {
+        if !self.mounted {
+            return Err(VfsFsError::Unmounted);
+        }
+        if path == "/" || path.is_empty() {
+            return Err(VfsFsError::Invalid);
+        }
+        let (parent_path, name) = split_parent(path)?;
+        let (pclus, is_dir, _, _, _) = self.open_path_with_loc(&parent_path)?;
+        if !is_dir {
+            return Err(VfsFsError::NotDir);
+        }
+        let ent = self.walk_dir_find(pclus, &name)?;
+        if !ent.is_dir() {
+            return Err(VfsFsError::NotDir);
+        }
+        if !self.dir_is_empty(ent.first_clus)? {
+            return Err(VfsFsError::NotEmpty);
+        }
+        self.delete_dirent(&ent)?;
+        self.free_cluster_chain(ent.first_clus)?;
+        Ok(())
+    }
+
+    fn mv(&mut self, src: &str, dest: &str) -> Result<(), VfsFsError> {
+        self.rename2(src, dest, RenameFlags::NOREPLACE)
+    }
+
+    /// 数据簇不动，只改目录项：SFN 的前 11 字节是名字，后面的属性、时间、首簇、大小跟着文件走
+    /// - dest 不存在：在 dest 的父目录写一组新的 LFN+SFN，再删掉旧的
+    /// - 替换：dest 的 SFN 原地换成 src 的内容，再删 src 的目录项、释放 dest 原来的簇链
+    /// - 交换：两个 SFN 原地互换名字后面的部分
+    /// 目录换了父目录要改它的 ".."
+    fn rename2(&mut self, src: &str, dest: &str, flags: RenameFlags) -> Result<(), VfsFsError> {
+        if !self.mounted {
+            return Err(VfsFsError::Unmounted);
+        }
+        if src == "/" || src.is_empty() || dest == "/" || dest.is_empty() {
+            return Err(VfsFsError::Invalid);
+        }
+        let (src_parent, src_name) = split_parent(src)?;
+        let (spclus, is_dir, _, _, _) = self.open_path_with_loc(&src_parent)?;
+        if !is_dir {
+            return Err(VfsFsError::NotDir);
+        }
+        let sent = self.walk_dir_find(spclus, &src_name)?;
+
+        let (dest_parent, dest_name) = split_parent(dest)?;
+        let (dpclus, is_dir, _, _, _) = self.open_path_with_loc(&dest_parent)?;
+        if !is_dir {
+            return Err(VfsFsError::NotDir);
+        }
+        let dent = match self.walk_dir_find(dpclus, &dest_name) {
+            Ok(e) => Some(e),
+            Err(VfsFsError::NotFound) => None,
+            Err(e) => return Err(e),
+        };
+        if let Some(d) = dent {
+            if d.dirent_clus == sent.dirent_clus && d.dirent_off == sent.dirent_off {
+                return Ok(());
+            }
+        }
+        let sraw = self.read_dirent_raw(sent.dirent_clus, sent.dirent_off)?;
+
+        if flags.contains(RenameFlags::EXCHANGE) {
+            let dent = dent.ok_or(VfsFsError::NotFound)?;
+            let draw = self.read_dirent_raw(dent.dirent_clus, dent.dirent_off)?;
+            let mut new_s = draw;
+            new_s[0..11].copy_from_slice(&sraw[0..11]);
+            let mut new_d = sraw;
+            new_d[0..11].copy_from_slice(&draw[0..11]);
+            self.write_dirent_raw(sent.dirent_clus, sent.dirent_off, &new_s)?;
+            self.write_dirent_raw(dent.dirent_clus, dent.dirent_off, &new_d)?;
+            if spclus != dpclus {
+                if sent.is_dir() {
+                    self.set_dotdot(sent.first_clus, dpclus)?;
+                }
+                if dent.is_dir() {
+                    self.set_dotdot(dent.first_clus, spclus)?;
+                }
+            }
+            return Ok(());
+        }
+
+        match dent {
+            Some(dent) => {
+                if flags.contains(RenameFlags::NOREPLACE) {
+                    return Err(VfsFsError::AlreadyExists);
+                }
+                match (sent.is_dir(), dent.is_dir()) {
+                    (true, false) => return Err(VfsFsError::NotDir),
+                    (false, true) => return Err(VfsFsError::IsDir),
+                    (true, true) if !self.dir_is_empty(dent.first_clus)? => return Err(VfsFsError::NotEmpty),
+                    _ => {}
+                }
+                let mut new_d = sraw;
+                new_d[0..11].copy_from_slice(&self.read_dirent_raw(dent.dirent_clus, dent.dirent_off)?[0..11]);
+                self.write_dirent_raw(dent.dirent_clus, dent.dirent_off, &new_d)?;
+                self.delete_dirent(&sent)?;
+                self.free_cluster_chain(dent.first_clus)?;
+            }
+            None => {
+                let (clus, off, name11) = self.write_name_dirents(dpclus, &dest_name, sent.attr, sent.first_clus, sent.size)?;
+                let mut new_d = sraw;
+                new_d[0..11].copy_from_slice(&name11);
+                self.write_dirent_raw(clus, off, &new_d)?;
+                self.delete_dirent(&sent)?;
+            }
+        }
+        if sent.is_dir() && spclus != dpclus {
+            self.set_dotdot(sent.first_clus, dpclus)?;
+        }
+        Ok(())
+    }
+
+    fn rename(&mut self, path: &str, new_name: &str) -> Result<(), VfsFsError> {
+        let (parent_path, _) = split_parent(path)?;
+        let new_path = if parent_path == "/" || parent_path.is_empty() {
+            format!("/{new_name}")
+        } else {
+            format!("{parent_path}/{new_name}")
+        };
+        self.mv(path, &new_path)
+    }
+
+    /// FAT32 没有 inode，不支持硬链接和符号链接
+    fn link(&mut self, _src: &str, _dest: &str) -> Result<(), VfsFsError> {
+        Err(VfsFsError::NotPermitted)
+    }
+
+    fn symlink(&mut self, _target: &str, _path: &str) -> Result<(), VfsFsError> {
+        Err(VfsFsError::NotPermitted)
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -1,4 +1,5 @@
 use alloc::collections::btree_map::BTreeMap;
+use alloc::format;
 use alloc::string::String;
 use alloc::string::ToString;
 use alloc::sync::Arc;
@@ -7,7 +8,7 @@ use core::any::Any;
 use spin::Mutex;
 
 use crate::fs::vfs::{
-    File, LinuxDirent64, MountFs, OpenFlags, VfsFs, VfsFsError, VfsStat, VFS_DT_DIR, VFS_DT_REG,
+    File, LinuxDirent64, MountFs, OpenFlags, RenameFlags, VfsFs, VfsFsError, VfsStat, VFS_DT_DIR, VFS_DT_LNK, VFS_DT_REG,
 };
 
 const ROOT_INODE: u32 = 1;
@@ -15,12 +16,14 @@ const ROOT_INODE: u32 = 1;
 #[derive(Clone)]
 struct NodeMeta {
     inode: u32,
+    nlink: u32, //指向它的目录项个数，减到 0 才删掉
 }
 
 enum NodeKind {
     Dir { entries: BTreeMap<String, u32> },
     File { data: Vec<u8> },
     Device { file: Arc<dyn File> },
+    Symlink { target: String },
 }
 
 struct Node {
@@ -42,7 +45,7 @@ impl RamFs {
         nodes.insert(
             ROOT_INODE,
             Node {
-                meta: NodeMeta { inode: ROOT_INODE },
+                meta: NodeMeta { inode: ROOT_INODE, nlink: 1 },
                 kind: NodeKind::Dir {
                     entries: BTreeMap::new(),
                 },
@@ -132,13 +135,35 @@ impl RamFs {
         self.nodes.insert(
             ino,
             Node {
-                meta: NodeMeta { inode: ino },
+                meta: NodeMeta { inode: ino, nlink: 1 },
                 kind,
             },
         );
         Ok(ino)
     }
 
+    /// 去掉一个指向 ino 的目录项，最后一个名字没了才真正删掉节点
+    fn drop_link(&mut self, ino: u32) {
+        let Some(node) = self.nodes.get_mut(&ino) else {
+            return;
+        };
+        node.meta.nlink = node.meta.nlink.saturating_sub(1);
+        if node.meta.nlink > 0 {
+            // 还有别的硬链接
+            return;
+        }
+        if let Some(Node { kind: NodeKind::File { data }, .. }) = self.nodes.remove(&ino) {
+            self.used_bytes = self.used_bytes.saturating_sub(data.len());
+        }
+    }
+
+    fn dir_entries_mut(&mut self, ino: u32) -> Result<&mut BTreeMap<String, u32>, VfsFsError> {
+        match &mut self.nodes.get_mut(&ino).ok_or(VfsFsError::NotFound)?.kind {
+            NodeKind::Dir { entries } => Ok(entries),
+            _ => Err(VfsFsError::NotDir),
+        }
+    }
+
     pub fn mkdev(&mut self, path: &str, file: Arc<dyn File>) -> Result<(), VfsFsError> {
         let (parent_ino, name) = self.split_parent(path)?;
         let _ = self.create_node(parent_ino, &name, NodeKind::Device { file })?;
@@ -200,23 +225,40 @@ impl RamFs {
     fn stat_inode(&self, ino: u32) -> Result<VfsStat, VfsFsError> {
         let node = self.nodes.get(&ino).ok_or(VfsFsError::NotFound)?;
         match &node.kind {
-            NodeKind::Dir { .. } => Ok(VfsStat {
-                inode: node.meta.inode,
-                size: 0,
-                mode: 0,
-                file_type: VFS_DT_DIR,
-            }),
+            NodeKind::Dir { entries } => {
+                // 自己的 "." 、父目录里的名字和每个子目录的 ".."
+                let subdirs = entries
+                    .values()
+                    .filter(|c| matches!(self.nodes.get(c).map(|n| &n.kind), Some(NodeKind::Dir { .. })))
+                    .count();
+                Ok(VfsStat {
+                    inode: node.meta.inode,
+                    size: 0,
+                    mode: 0,
+                    file_type: VFS_DT_DIR,
+                    nlink: 2 + subdirs as u32,
+                })
+            }
             NodeKind::File { data } => Ok(VfsStat {
                 inode: node.meta.inode,
                 size: data.len() as u64,
                 mode: 0,
                 file_type: VFS_DT_REG,
+                nlink: node.meta.nlink,
             }),
             NodeKind::Device { file } => {
                 let mut st = file.stat()?;
                 st.inode = node.meta.inode;
+                st.nlink = node.meta.nlink;
                 Ok(st)
             }
+            NodeKind::Symlink { target } => Ok(VfsStat {
+                inode: node.meta.inode,
+                size: target.len() as u64,
+                mode: 0,
+                file_type: VFS_DT_LNK,
+                nlink: node.meta.nlink,
+            }),
         }
     }
 
@@ -235,6 +277,7 @@ impl RamFs {
             let dtype = match child.kind {
                 NodeKind::Dir { .. } => VFS_DT_DIR,
                 NodeKind::File { .. } | NodeKind::Device { .. } => VFS_DT_REG,
+                NodeKind::Symlink { .. } => VFS_DT_LNK,
             };
 
             let name_bytes = name.as_bytes();
@@ -454,6 +497,8 @@ impl VfsFs for RamFs {
                 }
                 Ok(file.clone())
             }
+            // VFS 已经展开过符号链接，走到这里说明链接还指向链接
+            NodeKind::Symlink { .. } => Err(VfsFsError::Loop),
         }
     }
 
@@ -464,28 +509,133 @@ impl VfsFs for RamFs {
             NodeKind::File { .. } => self.file_truncate(ino, size as usize),
             NodeKind::Dir { .. } => Err(VfsFsError::IsDir),
             NodeKind::Device { .. } => Err(VfsFsError::NotSupported),
+            NodeKind::Symlink { .. } => Err(VfsFsError::Invalid),
         }
     }
 
     fn unlink(&mut self, path: &str) -> Result<(), VfsFsError> {
         let (parent, name) = self.split_parent(path)?;
-        let parent_node = self.nodes.get_mut(&parent).ok_or(VfsFsError::NotFound)?;
-        let NodeKind::Dir { entries } = &mut parent_node.kind else {
+        let parent_node = self.nodes.get(&parent).ok_or(VfsFsError::NotFound)?;
+        let NodeKind::Dir { entries } = &parent_node.kind else {
             return Err(VfsFsError::NotDir);
         };
-        let child = entries.remove(&name).ok_or(VfsFsError::NotFound)?;
+        let child = *entries.get(&name).ok_or(VfsFsError::NotFound)?;
         let node = self.nodes.get(&child).ok_or(VfsFsError::NotFound)?;
         if matches!(node.kind, NodeKind::Dir { .. }) {
             return Err(VfsFsError::IsDir);
         }
-        if let Some(Node { kind, .. }) = self.nodes.remove(&child) {
-            if let NodeKind::File { data } = kind {
-                self.used_bytes = self.used_bytes.saturating_sub(data.len());
+        if let Some(Node { kind: NodeKind::Dir { entries }, .. }) = self.nodes.get_mut(&parent) {
+            entries.remove(&name);
+        }
+        self.drop_link(child);
+        Ok(())
+    }
+
+    fn link(&mut self, src: &str, dest: &str) -> Result<(), VfsFsError> {
+        let ino = self.lookup_path(src)?;
+        if matches!(self.nodes.get(&ino).ok_or(VfsFsError::NotFound)?.kind, NodeKind::Dir { .. }) {
+            return Err(VfsFsError::NotPermitted);
+        }
+        let (parent, name) = self.split_parent(dest)?;
+        let parent_node = self.nodes.get_mut(&parent).ok_or(VfsFsError::NotFound)?;
+        let NodeKind::Dir { entries } = &mut parent_node.kind else {
+            return Err(VfsFsError::NotDir);
+        };
+        if entries.contains_key(&name) {
+            return Err(VfsFsError::AlreadyExists);
+        }
+        entries.insert(name, ino);
+        if let Some(node) = self.nodes.get_mut(&ino) {
+            node.meta.nlink += 1;
+        }
+        Ok(())
+    }
+
+    fn symlink(&mut self, target: &str, path: &str) -> Result<(), VfsFsError> {
+        let (parent, name) = self.split_parent(path)?;
+        let _ = self.create_node(parent, &name, NodeKind::Symlink { target: target.to_string() })?;
+        Ok(())
+    }
+
+    fn readlink(&mut self, path: &str) -> Result<String, VfsFsError> {
+        let ino = self.lookup_path(path)?;
+        match &self.nodes.get(&ino).ok_or(VfsFsError::NotFound)?.kind {
+            NodeKind::Symlink { target } => Ok(target.clone()),
+            _ => Err(VfsFsError::Invalid),
+        }
+    }
+
+    fn rmdir(&mut self, path: &str) -> Result<(), VfsFsError> {
+        let (parent, name) = self.split_parent(path)?;
+        let parent_node = self.nodes.get(&parent).ok_or(VfsFsError::NotFound)?;
+        let NodeKind::Dir { entries } = &parent_node.kind else {
+            return Err(VfsFsError::NotDir);
+        };
+        let child = *entries.get(&name).ok_or(VfsFsError::NotFound)?;
+        match &self.nodes.get(&child).ok_or(VfsFsError::NotFound)?.kind {
+            NodeKind::Dir { entries } if !entries.is_empty() => return Err(VfsFsError::NotEmpty),
+            NodeKind::Dir { .. } => {}
+            _ => return Err(VfsFsError::NotDir),
+        }
+        if let Some(Node { kind: NodeKind::Dir { entries }, .. }) = self.nodes.get_mut(&parent) {
+            entries.remove(&name);
+        }
+        self.nodes.remove(&child);
+        Ok(())
+    }
+
+    /// 把目录项从 src 的父目录挪到 dest 的父目录，inode 不变；dest 必须不存在
+    fn mv(&mut self, src: &str, dest: &str) -> Result<(), VfsFsError> {
+        self.rename2(src, dest, RenameFlags::NOREPLACE)
+    }
+
+    /// 只改父目录里的目录项：替换时 dest 原来的节点少一个名字，交换时两个目录项互换 inode
+    fn rename2(&mut self, src: &str, dest: &str, flags: RenameFlags) -> Result<(), VfsFsError> {
+        let (src_parent, src_name) = self.split_parent(src)?;
+        let (dest_parent, dest_name) = self.split_parent(dest)?;
+        let src_ino = *self.dir_entries_mut(src_parent)?.get(&src_name).ok_or(VfsFsError::NotFound)?;
+        let dest_ino = self.dir_entries_mut(dest_parent)?.get(&dest_name).copied();
+        if dest_ino == Some(src_ino) {
+            return Ok(());
+        }
+
+        if flags.contains(RenameFlags::EXCHANGE) {
+            let dest_ino = dest_ino.ok_or(VfsFsError::NotFound)?;
+            self.dir_entries_mut(src_parent)?.insert(src_name, dest_ino);
+            self.dir_entries_mut(dest_parent)?.insert(dest_name, src_ino);
+            return Ok(());
+        }
+
+        if let Some(old) = dest_ino {
+            if flags.contains(RenameFlags::NOREPLACE) {
+                return Err(VfsFsError::AlreadyExists);
             }
+            let src_is_dir = matches!(self.nodes.get(&src_ino).map(|n| &n.kind), Some(NodeKind::Dir { .. }));
+            match &self.nodes.get(&old).ok_or(VfsFsError::NotFound)?.kind {
+                NodeKind::Dir { .. } if !src_is_dir => return Err(VfsFsError::IsDir),
+                NodeKind::Dir { entries } if !entries.is_empty() => return Err(VfsFsError::NotEmpty),
+                NodeKind::Dir { .. } => {}
+                _ if src_is_dir => return Err(VfsFsError::NotDir),
+                _ => {}
+            }
+        }
+        // 先把 dest 指向 src 的节点，再去掉 src 的名字，中间不会两个都找不到
+        self.dir_entries_mut(dest_parent)?.insert(dest_name, src_ino);
+        self.dir_entries_mut(src_parent)?.remove(&src_name);
+        if let Some(old) = dest_ino {
+            self.drop_link(old);
         }
         Ok(())
     }
 
+    fn rename(&mut self, path: &str, new_name: &str) -> Result<(), VfsFsError> {
+        let new_path = match path.rfind('/') {
+            Some(0) | None => format!("/{new_name}"),
+            Some(pos) => format!("{}/{new_name}", &path[..pos]),
+        };
+        self.mv(path, &new_path)
+    }
+
     fn stat(&mut self, path: &str) -> Result<VfsStat, VfsFsError> {
         let ino = self.lookup_path(path)?;
         self.stat_inode(ino)
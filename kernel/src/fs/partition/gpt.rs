@@ -0,0 +1,183 @@
+//!GPT分区表解析 little-endian
+//! LBA0 为保护性MBR(类型0xEE)，LBA1 为主GPT头，最后一个扇区为备份GPT头
+
+use alloc::vec::Vec;
+use crate::config::SECTOR_SIZE;
+use crate::fs::partition::mbr::FsType;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const MBR_OFFSET: usize = 0x1BE;
+const PROTECTIVE_MBR_TYPE: u8 = 0xEE;
+/// 分区项数组最多读这么多扇区，防止损坏的头让我们读整块盘
+const MAX_ENTRY_SECTORS: usize = 128;
+
+///把 GUID 文本形式转换成磁盘上的字节序（前三段小端）
+const fn guid(a: u32, b: u16, c: u16, d: [u8; 8]) -> [u8; 16] {
+    let a = a.to_le_bytes();
+    let b = b.to_le_bytes();
+    let c = c.to_le_bytes();
+    [
+        a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1],
+        d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7],
+    ]
+}
+
+/// 0FC63DAF-8483-4772-8E79-3D69D8477DE4 Linux filesystem data
+const LINUX_FS_GUID: [u8; 16] = guid(0x0FC63DAF, 0x8483, 0x4772, [0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4]);
+/// EBD0A0A2-B9E5-4433-87C0-68B6B72699C7 Microsoft basic data(FAT)
+const BASIC_DATA_GUID: [u8; 16] = guid(0xEBD0A0A2, 0xB9E5, 0x4433, [0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7]);
+/// C12A7328-F81F-11D2-BA4B-00A0C93EC93B EFI System(FAT)
+const EFI_SYSTEM_GUID: [u8; 16] = guid(0xC12A7328, 0xF81F, 0x11D2, [0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B]);
+/// 0657FD6D-A4AB-43C4-84E5-0933C84B4F4F Linux swap
+const LINUX_SWAP_GUID: [u8; 16] = guid(0x0657FD6D, 0xA4AB, 0x43C4, [0x84, 0xE5, 0x09, 0x33, 0xC8, 0x4B, 0x4F, 0x4F]);
+
+pub struct gpt_entry {
+    pub partiton_type: FsType,
+    pub type_guid: [u8; 16],
+    pub start_lba: u64,
+    /// 闭区间
+    pub end_lba: u64,
+}
+
+/// CRC32(IEEE 802.3)，GPT 头和分区项数组的校验
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn le32(data: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(data[off..off + 4].try_into().unwrap())
+}
+
+fn le64(data: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(data[off..off + 8].try_into().unwrap())
+}
+
+///LBA0 是否是保护性MBR（有一项类型为0xEE）
+pub fn is_protective_mbr(data: &[u8; SECTOR_SIZE]) -> bool {
+    if data[510] != 0x55 || data[511] != 0xAA {
+        return false;
+    }
+    (0..4).any(|i| data[MBR_OFFSET + i * 16 + 4] == PROTECTIVE_MBR_TYPE)
+}
+
+///GPT 头字段
+struct GptHeader {
+    entries_lba: u64,
+    num_entries: usize,
+    entry_size: usize,
+    entries_crc: u32,
+    first_usable: u64,
+    last_usable: u64,
+}
+
+///校验并解析一个GPT头扇区，my_lba 为它所在的扇区号
+fn parse_header(data: &[u8; SECTOR_SIZE], my_lba: u64) -> Result<GptHeader, &'static str> {
+    if &data[0..8] != GPT_SIGNATURE {
+        return Err("invalid gpt signature");
+    }
+    let header_size = le32(data, 12) as usize;
+    if header_size < 92 || header_size > SECTOR_SIZE {
+        return Err("invalid gpt header size");
+    }
+    // 计算 CRC 时 HeaderCRC32 字段按 0 处理
+    let mut hdr = [0u8; SECTOR_SIZE];
+    hdr[..header_size].copy_from_slice(&data[..header_size]);
+    hdr[16..20].fill(0);
+    if crc32(&hdr[..header_size]) != le32(data, 16) {
+        return Err("gpt header crc mismatch");
+    }
+    if le64(data, 24) != my_lba {
+        return Err("gpt header lba mismatch");
+    }
+    let entry_size = le32(data, 84) as usize;
+    if entry_size < 128 || entry_size % 8 != 0 || entry_size > SECTOR_SIZE {
+        return Err("invalid gpt entry size");
+    }
+    Ok(GptHeader {
+        first_usable: le64(data, 40),
+        last_usable: le64(data, 48),
+        entries_lba: le64(data, 72),
+        num_entries: le32(data, 80) as usize,
+        entry_size,
+        entries_crc: le32(data, 88),
+    })
+}
+
+fn type_of(guid: &[u8; 16]) -> Option<FsType> {
+    match *guid {
+        LINUX_FS_GUID => Some(FsType::Linux),
+        BASIC_DATA_GUID | EFI_SYSTEM_GUID => Some(FsType::Fat32),
+        LINUX_SWAP_GUID => Some(FsType::Swap),
+        _ => None,
+    }
+}
+
+///读分区项数组并校验CRC，返回已知类型的分区（按分区项顺序，空项和未知类型跳过）
+fn parse_entries(
+    hdr: &GptHeader,
+    read_sector: &mut dyn FnMut(u64, &mut [u8; SECTOR_SIZE]) -> Result<(), &'static str>,
+) -> Result<Vec<gpt_entry>, &'static str> {
+    let bytes = hdr.num_entries.checked_mul(hdr.entry_size).ok_or("invalid gpt entry count")?;
+    let sectors = (bytes + SECTOR_SIZE - 1) / SECTOR_SIZE;
+    if sectors > MAX_ENTRY_SECTORS {
+        return Err("gpt entry array too large");
+    }
+    let mut array: Vec<u8> = Vec::with_capacity(sectors * SECTOR_SIZE);
+    let mut sector = [0u8; SECTOR_SIZE];
+    for i in 0..sectors as u64 {
+        read_sector(hdr.entries_lba + i, &mut sector)?;
+        array.extend_from_slice(&sector);
+    }
+    if crc32(&array[..bytes]) != hdr.entries_crc {
+        return Err("gpt entry array crc mismatch");
+    }
+
+    let mut partitions = Vec::new();
+    for i in 0..hdr.num_entries {
+        let e = &array[i * hdr.entry_size..(i + 1) * hdr.entry_size];
+        let type_guid: [u8; 16] = e[0..16].try_into().unwrap();
+        if type_guid == [0u8; 16] {
+            continue; // 空项
+        }
+        let start_lba = le64(e, 32);
+        let end_lba = le64(e, 40);
+        if start_lba > end_lba || start_lba < hdr.first_usable || end_lba > hdr.last_usable {
+            continue;
+        }
+        let Some(fs_type) = type_of(&type_guid) else {
+            continue;
+        };
+        partitions.push(gpt_entry {
+            partiton_type: fs_type,
+            type_guid,
+            start_lba,
+            end_lba,
+        });
+    }
+    Ok(partitions)
+}
+
+///解析GPT分区表：先用LBA1的主头，损坏时用最后一个扇区的备份头
+pub fn parsing_gpt_partition(
+    read_sector: &mut dyn FnMut(u64, &mut [u8; SECTOR_SIZE]) -> Result<(), &'static str>,
+    total_sectors: u64,
+) -> Result<Vec<gpt_entry>, &'static str> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    read_sector(1, &mut sector)?;
+    let primary = parse_header(&sector, 1).and_then(|hdr| parse_entries(&hdr, read_sector));
+    if primary.is_ok() || total_sectors < 2 {
+        return primary;
+    }
+    let backup_lba = total_sectors - 1;
+    read_sector(backup_lba, &mut sector)?;
+    let hdr = parse_header(&sector, backup_lba)?;
+    parse_entries(&hdr, read_sector)
+}
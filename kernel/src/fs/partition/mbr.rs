@@ -19,10 +19,13 @@ impl TryFrom<u8> for BootIndicator {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum FsType {
     Linux,
     Fat16,
     Fat32,
+    ///交换分区
+    Swap,
 }
 
 pub struct mbr_entry{
@@ -45,6 +48,7 @@ pub fn parsing_mbr_partition(data:[u8;SECTOR_SIZE]) -> Result<Vec<mbr_entry>, &'
         //1..3 为chs start
         let partition_type = data[base + 4];
         //0x00 = unused
+        //0x82 = Linux swap
         //0x83 = Linux（ext2/3/4 常见）
         //0x0e = FAT16 LBA
         //0x0b/0x0c = FAT32（0c 是 LBA 方式）
@@ -65,6 +69,7 @@ pub fn parsing_mbr_partition(data:[u8;SECTOR_SIZE]) -> Result<Vec<mbr_entry>, &'
         }
 
         let fs_type = match partition_type {
+            0x82 => FsType::Swap,
             0x83 => FsType::Linux, // TODO：简化，实际需要在Mount探测文件系统类型
             0x0e => FsType::Fat16,
             0x0b | 0x0c => FsType::Fat32,
@@ -1,7 +1,11 @@
-use crate::fs::partition::mbr::mbr_entry;
+use alloc::vec::Vec;
+use crate::config::SECTOR_SIZE;
+use crate::fs::partition::gpt::{gpt_entry, is_protective_mbr, parsing_gpt_partition};
+use crate::fs::partition::mbr::{FsType, mbr_entry, parsing_mbr_partition};
 
 pub enum DevicePartition {
     MBR(mbr_entry),
+    GPT(gpt_entry),
     Raw { base_lba: u64, sectors: u64 },
 }
 
@@ -9,6 +13,7 @@ impl DevicePartition {
     pub fn base_lba(&self) -> u64 {
         match self {
             DevicePartition::MBR(e) => e.start_lbn as u64,
+            DevicePartition::GPT(e) => e.start_lba,
             DevicePartition::Raw { base_lba, .. } => *base_lba,
         }
     }
@@ -16,11 +21,38 @@ impl DevicePartition {
     pub fn sectors(&self) -> u64 {
         match self {
             DevicePartition::MBR(e) => e.len as u64,
+            DevicePartition::GPT(e) => e.end_lba - e.start_lba + 1,
             DevicePartition::Raw { sectors, .. } => *sectors,
         }
     }
+
+    ///分区类型，整盘为None
+    pub fn fs_type(&self) -> Option<FsType> {
+        match self {
+            DevicePartition::MBR(e) => Some(e.partiton_type),
+            DevicePartition::GPT(e) => Some(e.partiton_type),
+            DevicePartition::Raw { .. } => None,
+        }
+    }
+}
+
+///读LBA0判断分区表格式：保护性MBR走GPT，否则按MBR解析
+/// 返回的顺序就是 /vdXN 的编号顺序
+pub fn parsing_partition_table(
+    read_sector: &mut dyn FnMut(u64, &mut [u8; SECTOR_SIZE]) -> Result<(), &'static str>,
+    total_sectors: u64,
+) -> Result<Vec<DevicePartition>, &'static str> {
+    let mut mbr = [0u8; SECTOR_SIZE];
+    read_sector(0, &mut mbr)?;
+    if is_protective_mbr(&mbr) {
+        let parts = parsing_gpt_partition(read_sector, total_sectors)?;
+        return Ok(parts.into_iter().map(DevicePartition::GPT).collect());
+    }
+    let parts = parsing_mbr_partition(mbr)?;
+    Ok(parts.into_iter().map(DevicePartition::MBR).collect())
 }
 
 
 
 pub mod mbr;
+pub mod gpt;
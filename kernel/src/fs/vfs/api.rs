@@ -5,38 +5,64 @@ use crate::alloc::string::ToString;
 use log::error;
 use spin::Mutex;
 use crate::task::{TASK_MANAER, TASK_MANAGER_INIT};
-use crate::fs::vfs::{File, KStat, MountFs, OpenFlags, ROOTFS, VfsFs, VfsFsError, VfsStat};
+use crate::fs::vfs::{File, FileCacheKey, KStat, MountFs, OpenFlags, RenameFlags, ROOTFS, VfsFs, VfsFsError, VfsStat, FILE_CACHE, VFS_DT_DIR};
 use alloc::format;
 use alloc::vec::Vec;
 
+/// 路径解析到 (文件系统, 展开符号链接后的绝对路径, 文件系统内路径)，最后一级是链接时跟随
 fn resolve_mount(path: &str) -> Result<(MountFs, String, String), VfsFsError> {
-    let abs = normalize_path(path)?;
-    let mut rootfs_guard = ROOTFS.lock();
-    let rootfs = rootfs_guard.as_mut().ok_or(VfsFsError::IO)?;
-    let (fs, sub) = rootfs
-        .resolve_mount_point(&abs)?
+    resolve_mount_follow(path, true)
+}
+
+/// 同上，最后一级是符号链接时不跟随，操作的是链接本身
+fn resolve_mount_nofollow(path: &str) -> Result<(MountFs, String, String), VfsFsError> {
+    resolve_mount_follow(path, false)
+}
+
+fn resolve_mount_follow(path: &str, follow_last: bool) -> Result<(MountFs, String, String), VfsFsError> {
+    let abs = absolute_path(path);
+    // 解析时要拿文件系统的锁读符号链接，可能睡眠等 I/O，拷一份挂载表就放掉 ROOTFS
+    let rootfs = ROOTFS.lock().clone().ok_or(VfsFsError::IO)?;
+    let (fs, abs, sub) = rootfs
+        .resolve_mount_point(&abs, follow_last)?
         .ok_or(VfsFsError::NotFound)?;
     Ok((fs, abs, sub))
 }
 
-/// 统一路径：绝对路径保持不变，相对路径以 进程打开的路径 为前缀
+/// 展开符号链接后的真实绝对路径（chdir 记录的 cwd 用它）
+pub fn vfs_realpath(path: &str) -> Result<String, VfsFsError> {
+    let (_mnt, abs, _sub) = resolve_mount(path)?;
+    Ok(abs)
+}
+
+/// 相对路径接到 cwd 后面，只去掉多余的 "/" 和 "."，".." 原样保留
+/// ".." 要等路径上的符号链接展开之后才能处理，交给 resolve_mount_point
+pub fn absolute_path(path: &str) -> String {
+    let combin = join_cwd(path);
+    let parts: Vec<&str> = combin.split('/').filter(|pa| !pa.is_empty() && *pa != ".").collect();
+    format!("/{}", parts.join("/"))
+}
+
+fn join_cwd(path: &str) -> String {
+    if path.starts_with('/') {
+        return path.to_string();
+    }
+    let tsmn_init:bool;
+    unsafe {
+         tsmn_init= TASK_MANAGER_INIT;
+    }
+    if tsmn_init {
+        let cwd = TASK_MANAER.get_current_cwd();
+        format!("{}/{}", cwd, path)
+    }else {
+        format!("/{}", path)
+    }
+}
+
+/// 统一路径：绝对路径保持不变，相对路径以 进程打开的路径 为前缀，".." 按字面折叠（不看符号链接）
 /// TASK_MANAER初始化期间只能用绝对路径，内核也不应该出现相对路径
 pub fn normalize_path(path: &str) -> Result<String, VfsFsError> {
-    let combin = if path.starts_with('/') {
-        path.to_string()
-    } else {
-        let tsmn_init:bool;
-        unsafe {
-             tsmn_init= TASK_MANAGER_INIT;
-        }
-        if tsmn_init {
-            let cwd = TASK_MANAER.get_current_cwd();
-            format!("{}/{}", cwd, path)
-        }else {
-            format!("/{}", path)
-        }
-        
-    };
+    let combin = join_cwd(path);
 
     let mut parts: Vec<&str> = Vec::new();
     for pa in combin.split('/') {
@@ -56,6 +82,54 @@ pub fn normalize_path(path: &str) -> Result<String, VfsFsError> {
     }
 }
 
+/// 打开的目录：记住打开时的绝对路径给 *at 系统调用用，其它操作原样交给文件系统的目录文件
+struct DirFile {
+    inner: Arc<dyn File>,
+    path: String,
+}
+
+impl File for DirFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, VfsFsError> {
+        self.inner.read(buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, VfsFsError> {
+        self.inner.write(buf)
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, VfsFsError> {
+        self.inner.read_at(offset, buf)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, VfsFsError> {
+        self.inner.write_at(offset, buf)
+    }
+
+    fn lseek(&self, offset: isize, whence: usize) -> Result<usize, VfsFsError> {
+        self.inner.lseek(offset, whence)
+    }
+
+    fn getdents64(&self, max_len: usize) -> Result<Vec<u8>, VfsFsError> {
+        self.inner.getdents64(max_len)
+    }
+
+    fn stat(&self) -> Result<VfsStat, VfsFsError> {
+        self.inner.stat()
+    }
+
+    fn flush(&self) -> Result<(), VfsFsError> {
+        self.inner.flush()
+    }
+
+    fn ioctl(&self, cmd: usize, data: &mut [u8]) -> Result<usize, VfsFsError> {
+        self.inner.ioctl(cmd, data)
+    }
+
+    fn path(&self) -> Option<String> {
+        Some(self.path.clone())
+    }
+}
+
 pub fn vfs_open(path: &str, flags: OpenFlags) -> Result<Arc<dyn File>, VfsFsError> {
     let (mnt, abs_path, sub_path) = resolve_mount(path)?;
     let mut guard = mnt.lock();
@@ -63,6 +137,14 @@ pub fn vfs_open(path: &str, flags: OpenFlags) -> Result<Arc<dyn File>, VfsFsErro
         error!("vfs_open failed: path={} err={:?}", abs_path, e);
         e
     })?;
+    drop(guard);
+    let is_dir = file.stat().map_or(false, |st| st.file_type == VFS_DT_DIR);
+    if is_dir {
+        return Ok(Arc::new(DirFile { inner: file, path: abs_path }));
+    }
+    if flags.contains(OpenFlags::DIRECTORY) {
+        return Err(VfsFsError::NotDir);
+    }
     Ok(file)
 }
 
@@ -100,7 +182,7 @@ pub fn vfs_fstat_kstat(file: &Arc<dyn File>) -> Result<KStat, VfsFsError> {
 
 /// mkdir：基于绝对或相对路径创建目录
 pub fn vfs_mkdir(path: &str) -> Result<(), VfsFsError> {
-    let (mnt, abs, sub) = resolve_mount(path)?;
+    let (mnt, abs, sub) = resolve_mount_nofollow(path)?;
     if abs == "/" {
         return Ok(());
     }
@@ -120,10 +202,10 @@ pub fn vfs_mkfile(path: &str) -> Result<(), VfsFsError> {
 
 /// mv：移动/重命名（高层按完整路径操作）
 pub fn vfs_mv(src: &str, dest: &str) -> Result<(), VfsFsError> {
-    let (src_mnt, _src_abs, src_sub) = resolve_mount(src)?;
-    let (dst_mnt, _dst_abs, dst_sub) = resolve_mount(dest)?;
+    let (src_mnt, _src_abs, src_sub) = resolve_mount_nofollow(src)?;
+    let (dst_mnt, _dst_abs, dst_sub) = resolve_mount_nofollow(dest)?;
     if !Arc::ptr_eq(&src_mnt, &dst_mnt) {
-        return Err(VfsFsError::NotSupported);
+        return Err(VfsFsError::CrossDevice);
     }
     let mut guard = src_mnt.lock();
     guard.mv(&src_sub, &dst_sub)
@@ -131,7 +213,7 @@ pub fn vfs_mv(src: &str, dest: &str) -> Result<(), VfsFsError> {
 
 /// rename：仅改变同一父目录下的名字（语义上等价于 mv 的子集）
 pub fn vfs_rename(path: &str, new_name: &str) -> Result<(), VfsFsError> {
-    let (mnt, abs, sub) = resolve_mount(path)?;
+    let (mnt, abs, sub) = resolve_mount_nofollow(path)?;
     if abs == "/" {
         return Err(VfsFsError::Invalid);
     }
@@ -151,23 +233,123 @@ pub fn vfs_rename(path: &str, new_name: &str) -> Result<(), VfsFsError> {
     guard.rename(&sub, new_name)
 }
 
+/// renameat2：src 改名/移动到 dest，两边都不跟随最后一级的符号链接
+/// 这里只做检查，真正的替换/交换由文件系统的 rename2 在目录项上一步完成
+pub fn vfs_rename2(src: &str, dest: &str, flags: RenameFlags) -> Result<(), VfsFsError> {
+    if flags.contains(RenameFlags::NOREPLACE | RenameFlags::EXCHANGE) {
+        return Err(VfsFsError::Invalid);
+    }
+    let (src_mnt, _src_abs, src_sub) = resolve_mount_nofollow(src)?;
+    let (dst_mnt, _dst_abs, dst_sub) = resolve_mount_nofollow(dest)?;
+    if !Arc::ptr_eq(&src_mnt, &dst_mnt) {
+        return Err(VfsFsError::CrossDevice);
+    }
+    // 根目录和挂载点本身不能挪
+    if src_sub == "/" || dst_sub == "/" {
+        return Err(VfsFsError::Busy);
+    }
+    let is_under = |path: &str, dir: &str| path.starts_with(dir) && path[dir.len()..].starts_with('/');
+
+    let mut guard = src_mnt.lock();
+    let src_st = guard.stat(&src_sub)?;
+    let dst_st = match guard.stat(&dst_sub) {
+        Ok(st) => Some(st),
+        Err(VfsFsError::NotFound) => None,
+        Err(e) => return Err(e),
+    };
+    // 同一个名字，或者是同一个文件的两个硬链接：什么都不做
+    if src_sub == dst_sub {
+        return Ok(());
+    }
+    if let Some(dst_st) = dst_st {
+        if dst_st.inode == src_st.inode && src_st.file_type != VFS_DT_DIR && src_st.nlink > 1 {
+            return Ok(());
+        }
+    }
+    // 目录不能挪进自己的子目录
+    if is_under(&dst_sub, &src_sub) {
+        return Err(VfsFsError::Invalid);
+    }
+
+    let mut stale = None;
+    if flags.contains(RenameFlags::EXCHANGE) {
+        if dst_st.is_none() {
+            return Err(VfsFsError::NotFound);
+        }
+        if is_under(&src_sub, &dst_sub) {
+            return Err(VfsFsError::Invalid);
+        }
+    } else if let Some(dst_st) = dst_st {
+        if flags.contains(RenameFlags::NOREPLACE) {
+            return Err(VfsFsError::AlreadyExists);
+        }
+        match (src_st.file_type == VFS_DT_DIR, dst_st.file_type == VFS_DT_DIR) {
+            (true, false) => return Err(VfsFsError::NotDir),
+            (false, true) => return Err(VfsFsError::IsDir),
+            _ => {}
+        }
+        if dst_st.file_type != VFS_DT_DIR && dst_st.nlink <= 1 {
+            stale = Some(dst_st.inode);
+        }
+    }
+    guard.rename2(&src_sub, &dst_sub, flags)?;
+    drop(guard);
+    // 被覆盖的文件和 vfs_unlink 一样丢弃缓存
+    if let Some(ino) = stale {
+        FILE_CACHE.lock().invalidate(FileCacheKey::new(&src_mnt, ino as u64));
+    }
+    Ok(())
+}
+
 pub fn vfs_truncate(path: &str, size: u64) -> Result<(), VfsFsError> {
     let (mnt, abs, sub) = resolve_mount(path)?;
     if abs == "/" {
         return Err(VfsFsError::Invalid);
     }
+    // 先写回脏页再截断，之后丢弃旧缓存
+    let ino = mnt.lock().stat(&sub).ok().map(|st| st.inode);
+    if let Some(ino) = ino {
+        FILE_CACHE.lock().sync_key(FileCacheKey::new(&mnt, ino as u64));
+    }
     let mut guard = mnt.lock();
-    guard.truncate(&sub, size)
+    guard.truncate(&sub, size)?;
+    drop(guard);
+    if let Some(ino) = ino {
+        FILE_CACHE.lock().invalidate(FileCacheKey::new(&mnt, ino as u64));
+    }
+    Ok(())
 }
 
-/// unlink：删除文件（不删除目录）
+/// unlink：删除文件（不删除目录），路径是符号链接时删链接本身
 pub fn vfs_unlink(path: &str) -> Result<(), VfsFsError> {
-    let (mnt, abs, sub) = resolve_mount(path)?;
+    let (mnt, abs, sub) = resolve_mount_nofollow(path)?;
     if abs == "/" {
         return Err(VfsFsError::Invalid);
     }
     let mut guard = mnt.lock();
-    guard.unlink(&sub)
+    // 删的是最后一个名字才丢缓存，还有别的硬链接时内容还在用
+    let ino = guard.stat(&sub).ok().filter(|st| st.nlink <= 1).map(|st| st.inode);
+    guard.unlink(&sub)?;
+    drop(guard);
+    // inode 号可能被复用，丢弃旧内容
+    if let Some(ino) = ino {
+        FILE_CACHE.lock().invalidate(FileCacheKey::new(&mnt, ino as u64));
+    }
+    Ok(())
+}
+
+/// rmdir：删除空目录
+pub fn vfs_rmdir(path: &str) -> Result<(), VfsFsError> {
+    let (mnt, abs, sub) = resolve_mount_nofollow(path)?;
+    if abs == "/" {
+        return Err(VfsFsError::Busy);
+    }
+    // 挂载点本身不能删
+    if sub == "/" {
+        return Err(VfsFsError::Busy);
+    }
+    let mut guard = mnt.lock();
+    guard.rmdir(&sub)
 }
 
 /// stat：获取路径的基本元数据
@@ -177,9 +359,44 @@ pub fn vfs_stat(path: &str) -> Result<VfsStat, VfsFsError> {
     guard.stat(&sub)
 }
 
+/// lstat：和 stat 一样，但最后一级是符号链接时返回链接本身
+pub fn vfs_lstat(path: &str) -> Result<VfsStat, VfsFsError> {
+    let (mnt, _abs, sub) = resolve_mount_nofollow(path)?;
+    let mut guard = mnt.lock();
+    guard.stat(&sub)
+}
+
+/// readlink：读符号链接的内容
+pub fn vfs_readlink(path: &str) -> Result<String, VfsFsError> {
+    let (mnt, _abs, sub) = resolve_mount_nofollow(path)?;
+    let mut guard = mnt.lock();
+    guard.readlink(&sub)
+}
+
+/// symlink：在 path 创建指向 target 的符号链接
+pub fn vfs_symlink(target: &str, path: &str) -> Result<(), VfsFsError> {
+    if target.is_empty() {
+        return Err(VfsFsError::NotFound);
+    }
+    let (mnt, _abs, sub) = resolve_mount_nofollow(path)?;
+    let mut guard = mnt.lock();
+    guard.symlink(target, &sub)
+}
+
+/// link：硬链接，src 和 dest 必须在同一个文件系统；follow 决定 src 是链接时链接到它指向的文件
+pub fn vfs_link(src: &str, dest: &str, follow: bool) -> Result<(), VfsFsError> {
+    let (src_mnt, _src_abs, src_sub) = resolve_mount_follow(src, follow)?;
+    let (dst_mnt, _dst_abs, dst_sub) = resolve_mount_nofollow(dest)?;
+    if !Arc::ptr_eq(&src_mnt, &dst_mnt) {
+        return Err(VfsFsError::CrossDevice);
+    }
+    let mut guard = src_mnt.lock();
+    guard.link(&src_sub, &dst_sub)
+}
+
 /// remove：删除给定路径的文件
 pub fn vfs_remove(path: &str) -> Result<(), VfsFsError> {
-    let (mnt, abs, sub) = resolve_mount(path)?;
+    let (mnt, abs, sub) = resolve_mount_nofollow(path)?;
 
     // 不允许删除根目录
     if abs == "/" {
@@ -192,7 +409,12 @@ pub fn vfs_remove(path: &str) -> Result<(), VfsFsError> {
             if st.file_type == crate::fs::vfs::VFS_DT_DIR {
                 Err(VfsFsError::NotSupported)
             } else {
-                guard.unlink(&sub)
+                guard.unlink(&sub)?;
+                drop(guard);
+                if st.nlink <= 1 {
+                    FILE_CACHE.lock().invalidate(FileCacheKey::new(&mnt, st.inode as u64));
+                }
+                Ok(())
             }
         }
         Err(e) => Err(e),
@@ -1,29 +1,342 @@
-use alloc::{collections::btree_map::BTreeMap, sync::Arc};
-use spin::Mutex;
+//! 统一页缓存：以 (挂载的文件系统, inode, 文件页号) 为键缓存文件内容
+//! FAT32/ext4 的 read_at/write_at 先走页缓存，脏页在 flush/fsync/umount/LRU 淘汰时回写
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+use lazy_static::lazy_static;
+use log::error;
 
-use crate::{fs::vfs::File, memory::FramTracker};
-pub struct FilePageNum(pub usize);
+use crate::{config::PAGE_SIZE, fs::vfs::{MountFs, VfsFsError}, memory::{FramTracker, PhysiAddr, alloc_frame}, sync::SleepLock};
+
+///缓存页数上限，超过后按 LRU 淘汰
 pub const FIELCACHE_MAX_COUNT:usize=100;
+
+lazy_static!{
+    /// 全局页缓存
+    pub static ref FILE_CACHE: SleepLock<FileCache> = SleepLock::new(FileCache::new());
+}
+
+/// 文件身份：挂载实例地址 + 该文件系统内的 inode 号
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FileCacheKey {
+    pub fs_id: usize,
+    pub ino: u64,
+}
+
+impl FileCacheKey {
+    pub fn new(mount: &MountFs, ino: u64) -> Self {
+        Self {
+            fs_id: Arc::as_ptr(mount) as *const () as usize,
+            ino,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FilePageNum(pub usize);
+
+/// 页缓存的底层读写，绕过缓存直接访问设备，由具体文件系统的 File 实现
+pub trait PageBacking: Send + Sync {
+    fn backing_read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, VfsFsError>;
+    fn backing_write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, VfsFsError>;
+    /// 当前文件长度
+    fn backing_size(&self) -> usize;
+}
+
 pub struct FileCache{
-    cache:BTreeMap<Arc<dyn File>,FileFrameCache>
+    cache:BTreeMap<FileCacheKey,FileFrameCache>,
+    /// 全部缓存页数
+    count:usize,
+    /// LRU 时钟
+    tick:u64,
 }
 
 pub struct FileFrameCache{
-    frame_cache:BTreeMap<FilePageNum,FileFrame>
+    frame_cache:BTreeMap<FilePageNum,FileFrame>,
+    /// 最近一次写入该文件的句柄，淘汰/umount 回写脏页时使用
+    /// 有脏页期间一直持有强引用，句柄关掉了也能回写；全部回写完才放掉
+    writer:Option<Arc<dyn PageBacking>>,
 }
 
-/// 后面加页脏页可以去掉这个结构体的使用
 pub struct FileFrame{
     frame:Arc<FramTracker>,
-    dirty:bool
+    dirty:bool,
+    last_use:u64,
+}
+
+/// umount 前调用：回写该挂载实例的脏页并丢弃它的全部缓存
+pub fn filecache_sync_and_drop_fs(mount: &MountFs) {
+    let fs_id = Arc::as_ptr(mount) as *const () as usize;
+    let mut cache = FILE_CACHE.lock();
+    cache.flush_fs(fs_id);
+    cache.invalidate_fs(fs_id);
+}
+
+impl FileFrame {
+    fn page(&self) -> &'static mut [u8] {
+        let pa: PhysiAddr = self.frame.ppn.into();
+        unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut u8, PAGE_SIZE) }
+    }
 }
 
+impl FileFrameCache {
+    fn new() -> Self {
+        Self {
+            frame_cache: BTreeMap::new(),
+            writer: None,
+        }
+    }
+
+    ///没有脏页了就放掉回写者
+    fn release_writer_if_clean(&mut self) {
+        if !self.frame_cache.values().any(|fr| fr.dirty) {
+            self.writer = None;
+        }
+    }
+}
 
 impl FileCache {
-    // new
-    //insert
-    // read ->framenum
-    // write -> framenum
+    pub fn new() -> Self {
+        Self {
+            cache: BTreeMap::new(),
+            count: 0,
+            tick: 0,
+        }
+    }
+
+    /// 读：缓存命中直接拷贝，未命中从底层读入整页
+    pub fn read_at(&mut self, key: FileCacheKey, backing: &dyn PageBacking, offset: usize, buf: &mut [u8]) -> Result<usize, VfsFsError> {
+        let size = backing.backing_size();
+        if offset >= size {
+            return Ok(0);
+        }
+        let end = core::cmp::min(size, offset.saturating_add(buf.len()));
+        let mut pos = offset;
+        while pos < end {
+            let page = pos / PAGE_SIZE;
+            let inner = pos % PAGE_SIZE;
+            let can = core::cmp::min(end - pos, PAGE_SIZE - inner);
+            let frame = self.get_or_load(key, backing, page)?;
+            buf[pos - offset..pos - offset + can].copy_from_slice(&frame.page()[inner..inner + can]);
+            pos += can;
+        }
+        Ok(end - offset)
+    }
+
+    /// 写：不改变文件长度的写入只写缓存并标脏；扩展文件的写入直接写穿到底层，由底层维护长度
+    pub fn write_at(&mut self, key: FileCacheKey, backing: &dyn PageBacking, writer: Arc<dyn PageBacking>, offset: usize, buf: &[u8]) -> Result<usize, VfsFsError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let end = offset.saturating_add(buf.len());
+        if end > backing.backing_size() {
+            let n = backing.backing_write_at(offset, buf)?;
+            self.update_cached(key, offset, &buf[..n]);
+            return Ok(n);
+        }
+        let mut pos = offset;
+        while pos < end {
+            let page = pos / PAGE_SIZE;
+            let inner = pos % PAGE_SIZE;
+            let can = core::cmp::min(end - pos, PAGE_SIZE - inner);
+            let frame = self.get_or_load(key, backing, page)?;
+            frame.page()[inner..inner + can].copy_from_slice(&buf[pos - offset..pos - offset + can]);
+            frame.dirty = true;
+            pos += can;
+        }
+        self.cache.get_mut(&key).expect("Kernel error").writer = Some(writer);
+        Ok(buf.len())
+    }
+
+    /// 取得文件某页对应的缓存页帧（file-backed mmap 共享同一个物理页）
+    pub fn get_frame(&mut self, key: FileCacheKey, backing: &dyn PageBacking, page: usize) -> Result<Arc<FramTracker>, VfsFsError> {
+        Ok(self.get_or_load(key, backing, page)?.frame.clone())
+    }
+
+    /// 标记脏页：数据是绕过 write_at 直接写进缓存页帧的（共享 mmap）
+    /// 缓存中该页不是 mapped 这个页帧时（已被丢弃）返回 false
+    pub fn mark_dirty(&mut self, key: FileCacheKey, page: usize, mapped: &Arc<FramTracker>, writer: Arc<dyn PageBacking>) -> bool {
+        let Some(file) = self.cache.get_mut(&key) else {
+            return false;
+        };
+        let Some(frame) = file.frame_cache.get_mut(&FilePageNum(page)) else {
+            return false;
+        };
+        if !Arc::ptr_eq(&frame.frame, mapped) {
+            return false;
+        }
+        frame.dirty = true;
+        file.writer = Some(writer);
+        true
+    }
+
+    /// 回写一个文件的全部脏页
+    pub fn flush_file(&mut self, key: FileCacheKey, backing: &dyn PageBacking) -> Result<(), VfsFsError> {
+        let Some(file) = self.cache.get_mut(&key) else {
+            return Ok(());
+        };
+        let size = backing.backing_size();
+        for (page, frame) in file.frame_cache.iter_mut() {
+            if !frame.dirty {
+                continue;
+            }
+            Self::write_back(backing, size, page.0, frame)?;
+        }
+        file.writer = None;
+        Ok(())
+    }
+
+    /// 通过登记的回写者回写一个文件的脏页（调用方手里没有该文件的句柄时使用）
+    pub fn sync_key(&mut self, key: FileCacheKey) {
+        let writer = self.cache.get(&key).and_then(|f| f.writer.clone());
+        match writer {
+            Some(w) => {
+                if let Err(e) = self.flush_file(key, &*w) {
+                    error!("filecache: flush ino={} failed err={}", key.ino, e);
+                }
+            }
+            None => {
+                if self.cache.get(&key).is_some_and(|f| f.frame_cache.values().any(|fr| fr.dirty)) {
+                    error!("filecache: ino={} has dirty pages but no writer", key.ino);
+                }
+            }
+        }
+    }
 
-    //auto mark,lru flush
-}
\ No newline at end of file
+    /// 回写某个挂载实例下所有文件的脏页，umount 前调用
+    pub fn flush_fs(&mut self, fs_id: usize) {
+        let keys: Vec<FileCacheKey> = self.cache.keys().filter(|k| k.fs_id == fs_id).copied().collect();
+        for key in keys {
+            self.sync_key(key);
+        }
+    }
+
+    /// 丢弃一个文件的全部缓存页（truncate/unlink 之后）
+    pub fn invalidate(&mut self, key: FileCacheKey) {
+        if let Some(file) = self.cache.remove(&key) {
+            self.count -= file.frame_cache.len();
+        }
+    }
+
+    /// 丢弃某个挂载实例的全部缓存页
+    pub fn invalidate_fs(&mut self, fs_id: usize) {
+        let keys: Vec<FileCacheKey> = self.cache.keys().filter(|k| k.fs_id == fs_id).copied().collect();
+        for key in keys {
+            self.invalidate(key);
+        }
+    }
+
+    ///扩展写直接写穿底层之后，同步已缓存的页
+    fn update_cached(&mut self, key: FileCacheKey, offset: usize, data: &[u8]) {
+        let Some(file) = self.cache.get_mut(&key) else {
+            return;
+        };
+        let end = offset + data.len();
+        let mut pos = offset;
+        while pos < end {
+            let page = pos / PAGE_SIZE;
+            let inner = pos % PAGE_SIZE;
+            let can = core::cmp::min(end - pos, PAGE_SIZE - inner);
+            if let Some(frame) = file.frame_cache.get_mut(&FilePageNum(page)) {
+                frame.page()[inner..inner + can].copy_from_slice(&data[pos - offset..pos - offset + can]);
+            }
+            pos += can;
+        }
+    }
+
+    fn write_back(backing: &dyn PageBacking, size: usize, page: usize, frame: &mut FileFrame) -> Result<(), VfsFsError> {
+        let off = page * PAGE_SIZE;
+        if off < size {
+            let len = core::cmp::min(PAGE_SIZE, size - off);
+            backing.backing_write_at(off, &frame.page()[..len])?;
+        }
+        frame.dirty = false;
+        Ok(())
+    }
+
+    fn get_or_load(&mut self, key: FileCacheKey, backing: &dyn PageBacking, page: usize) -> Result<&mut FileFrame, VfsFsError> {
+        self.tick += 1;
+        let tick = self.tick;
+        let hit = self
+            .cache
+            .get(&key)
+            .is_some_and(|f| f.frame_cache.contains_key(&FilePageNum(page)));
+        if !hit {
+            let frame = self.alloc_cache_frame()?;
+            let fr = FileFrame { frame, dirty: false, last_use: tick };
+            let off = page * PAGE_SIZE;
+            let size = backing.backing_size();
+            if off < size {
+                let len = core::cmp::min(PAGE_SIZE, size - off);
+                backing.backing_read_at(off, &mut fr.page()[..len])?;
+            }
+            self.cache
+                .entry(key)
+                .or_insert_with(FileFrameCache::new)
+                .frame_cache
+                .insert(FilePageNum(page), fr);
+            self.count += 1;
+        }
+        let fr = self
+            .cache
+            .get_mut(&key)
+            .and_then(|f| f.frame_cache.get_mut(&FilePageNum(page)))
+            .expect("Kernel error");
+        fr.last_use = tick;
+        Ok(fr)
+    }
+
+    ///分配缓存页帧：超过上限或物理内存不足时先淘汰
+    fn alloc_cache_frame(&mut self) -> Result<Arc<FramTracker>, VfsFsError> {
+        while self.count >= FIELCACHE_MAX_COUNT {
+            if !self.evict_one() {
+                break;
+            }
+        }
+        loop {
+            if let Some(f) = alloc_frame() {
+                return Ok(Arc::new(f));
+            }
+            if !self.evict_one() {
+                return Err(VfsFsError::IO);
+            }
+        }
+    }
+
+    /// 淘汰最久未使用的一页；被 mmap 映射着的页不淘汰，脏页先回写
+    pub fn evict_one(&mut self) -> bool {
+        let mut victim: Option<(FileCacheKey, FilePageNum, u64)> = None;
+        for (key, file) in self.cache.iter() {
+            for (page, frame) in file.frame_cache.iter() {
+                if Arc::strong_count(&frame.frame) > 1 {
+                    continue;
+                }
+                if victim.map_or(true, |(_, _, t)| frame.last_use < t) {
+                    victim = Some((*key, *page, frame.last_use));
+                }
+            }
+        }
+        let Some((key, page, _)) = victim else {
+            return false;
+        };
+        let file = self.cache.get_mut(&key).expect("Kernel error");
+        let frame = file.frame_cache.get_mut(&page).expect("Kernel error");
+        if frame.dirty {
+            match file.writer.clone() {
+                Some(writer) => {
+                    if let Err(e) = Self::write_back(&*writer, writer.backing_size(), page.0, frame) {
+                        error!("filecache: write back ino={} page={} failed err={}", key.ino, page.0, e);
+                        return false;
+                    }
+                }
+                // 有脏页就一定登记了回写者，走到这里是 bug
+                None => error!("filecache: evict dirty page ino={} page={} without writer", key.ino, page.0),
+            }
+        }
+        file.frame_cache.remove(&page);
+        file.release_writer_if_clean();
+        if file.frame_cache.is_empty() {
+            self.cache.remove(&key);
+        }
+        self.count -= 1;
+        true
+    }
+}
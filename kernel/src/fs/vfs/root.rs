@@ -2,13 +2,13 @@
 use alloc::boxed::Box;
 #[cfg(feature = "ext4")]
 use alloc::collections::btree_map::BTreeMap;
-use alloc::{string::String, sync::Arc};
+use alloc::{collections::VecDeque, string::String, sync::Arc, vec::Vec};
 use rsext4::mkfs;
-use spin::Mutex;
+use crate::sync::SleepLock;
 use crate::config::{CONSENT, MB};
 use crate::fs::vfs::{MountFs, OpenFlags, VfsFsError, vfs_open};
 #[cfg(feature = "ext4")]
-use crate::driver::VirtBlk;
+use crate::driver::{VirtBlk, probe_virtio_blk, register_virtio_blk};
 #[cfg(feature = "ext4")]
 use crate::fs::fs_backend::{Ext4BlockDevice, Ext4Fs};
 #[cfg(feature = "ext4")]
@@ -17,22 +17,25 @@ use crate::fs::fs_backend::*;
 #[cfg(feature = "ext4")]
 use crate::fs::partition::{DevicePartition};
 #[cfg(feature = "ext4")]
-use crate::fs::partition::mbr::parsing_mbr_partition;
+use crate::fs::partition::{mbr::FsType, parsing_partition_table};
 #[cfg(feature = "ext4")]
 use crate::fs::vfs::VBLOCK;
 #[cfg(feature = "ext4")]
 use crate::config::SECTOR_SIZE;
-use crate::sync::UPSafeCell;
+use crate::sync::SpinLock;
 use crate::fs::vfs::vfs::VfsFs;
 use lazy_static::lazy_static;
 use log::{error, warn};
 use crate::alloc::string::ToString;
-use crate::fs::vfs::{LinuxDirent64, VFS_DT_REG};
+use crate::fs::vfs::{LinuxDirent64, VFS_DT_LNK, VFS_DT_REG};
 use crate::fs::fs_backend::fat32::Fat32Fs;
 use crate::fs::vfs::{vfs_getdents64, vfs_mkdir, vfs_open as api_vfs_open, vfs_read_at, vfs_stat, vfs_write};
+/// 路径解析时最多展开的符号链接个数，超过返回 Loop（ELOOP）
+pub const SYMLOOP_MAX: usize = 40;
+
 /// 全局根文件系统
 lazy_static!{
-pub static ref ROOTFS: UPSafeCell<Option<RootFs>> = UPSafeCell::new(None);
+pub static ref ROOTFS: SpinLock<Option<RootFs>> = SpinLock::new(None);
 }
 
 /// 挂载点路径
@@ -57,11 +60,13 @@ impl Ord for MountPath {
 
 //全局虚拟文件系统
 #[cfg(feature = "ext4")]
+#[derive(Clone)]
 pub struct RootFs{
-    pub mount_poinr:BTreeMap<MountPath,Arc<Mutex<dyn VfsFs>>>,// 挂载点
+    pub mount_poinr:BTreeMap<MountPath,Arc<SleepLock<dyn VfsFs>>>,// 挂载点
 }
 
 #[cfg(not(feature = "ext4"))]
+#[derive(Clone)]
 pub struct RootFs{
     path:String, //当前路径
 }
@@ -113,14 +118,68 @@ impl RootFs {
         false
     }
 
-    /// 解析挂载点和剩余路径
+    /// 解析挂载点和剩余路径，逐级展开路径上的符号链接，链接可以指到别的挂载点
+    /// ".." 在它前面的链接展开之后才处理，所以 link/.. 是链接目标的父目录
+    /// follow_last 为 false 时最后一级是链接也不展开（lstat/unlink/readlink 用）
+    /// 返回 (文件系统, 展开后的绝对路径, 文件系统内的路径)
     pub fn resolve_mount_point(
         &self,
         path: &str,
-    ) -> Result<Option<(Arc<Mutex<dyn VfsFs>> , String)>, VfsFsError> {
+        follow_last: bool,
+    ) -> Result<Option<(Arc<SleepLock<dyn VfsFs>>, String, String)>, VfsFsError> {
+        let mut pending: VecDeque<String> = path
+            .split('/')
+            .filter(|c| !c.is_empty())
+            .map(|c| c.to_string())
+            .collect();
+        let mut resolved: Vec<String> = Vec::new();
+        let mut links = 0usize;
+        while let Some(comp) = pending.pop_front() {
+            if comp == "." {
+                continue;
+            }
+            if comp == ".." {
+                resolved.pop();
+                continue;
+            }
+            resolved.push(comp);
+            if pending.is_empty() && !follow_last {
+                break;
+            }
+            let cur = alloc::format!("/{}", resolved.join("/"));
+            let Some((fs, sub)) = self.lookup_mount_point(&cur) else {
+                continue;
+            };
+            // 先看类型，只有符号链接才去读内容；不存在的留给后面真正的操作报错
+            let target = {
+                let mut guard = fs.lock();
+                match guard.stat(&sub) {
+                    Ok(st) if st.file_type == VFS_DT_LNK => guard.readlink(&sub)?,
+                    _ => continue,
+                }
+            };
+            links += 1;
+            if links > SYMLOOP_MAX {
+                return Err(VfsFsError::Loop);
+            }
+            // 链接换成它的内容，相对链接相对链接所在的目录
+            resolved.pop();
+            if target.starts_with('/') {
+                resolved.clear();
+            }
+            for (i, c) in target.split('/').filter(|c| !c.is_empty()).enumerate() {
+                pending.insert(i, c.to_string());
+            }
+        }
+        let abs = alloc::format!("/{}", resolved.join("/"));
+        Ok(self.lookup_mount_point(&abs).map(|(fs, sub)| (fs, abs, sub)))
+    }
+
+    /// 只按挂载表找 path 所在的文件系统和剩余路径，不看符号链接
+    fn lookup_mount_point(&self, path: &str) -> Option<(Arc<SleepLock<dyn VfsFs>>, String)> {
         let abs = Self::normalize_abs_path(path);
 
-        let mut best: Option<(usize, Arc<Mutex<dyn VfsFs>>, String)> = None;
+        let mut best: Option<(usize, Arc<SleepLock<dyn VfsFs>>, String)> = None;
         for (mp, fs) in self.mount_poinr.iter() {
             let mps = Self::normalize_abs_path(mp.0.as_str());
             if !Self::is_component_prefix(&mps, abs.as_str()) {
@@ -143,7 +202,44 @@ impl RootFs {
             }
         }
 
-        Ok(best.map(|(_, fs, sub)| (fs, sub)))
+        best.map(|(_, fs, sub)| (fs, sub))
+    }
+
+    ///给一个块设备建立整盘节点 name 和 MBR 分区节点 name1..N，遇到交换分区顺便启用
+    #[cfg(feature = "ext4")]
+    fn build_disk_nodes(ramfs:&mut RamFs,blk:Arc<VirtBlk>,name:&str)->Result<(),VfsFsError>{
+        let total_sectors = blk.capacity_in_sectors();
+
+        let whole = Arc::new(VBLOCK::new(
+            blk.clone(),
+            DevicePartition::Raw {
+                base_lba: 0,
+                sectors: total_sectors,
+            },
+        )) as Arc<dyn crate::fs::vfs::File>;
+        ramfs.mkdev(name, whole)?;
+
+        let mut read_sector = |lba: u64, buf: &mut [u8; SECTOR_SIZE]| {
+            blk.read_block(lba as usize, buf)
+                .map_err(|_| "read sector failed")
+        };
+        let parts = parsing_partition_table(&mut read_sector, total_sectors)
+            .map_err(|_| VfsFsError::Invalid)?;
+        if parts.len() == 0{
+            // No partition
+            return Err(VfsFsError::Invalid);
+        }
+        for (idx, part) in parts.into_iter().enumerate() {
+            let is_swap = part.fs_type() == Some(FsType::Swap);
+            let dev = Arc::new(VBLOCK::new(blk.clone(), part))
+                as Arc<dyn crate::fs::vfs::File>;
+            if is_swap {
+                crate::memory::init_swap(dev.clone());
+            }
+            let path = alloc::format!("{}{}", name, idx + 1);
+            ramfs.mkdev(path.as_str(), dev)?;
+        }
+        Ok(())
     }
 
     pub fn scan_and_build_vblock_device()->Result<(),VfsFsError>{
@@ -151,8 +247,8 @@ impl RootFs {
         {
             let root = ROOTFS.lock();
             let root = root.as_ref().ok_or(VfsFsError::IO)?;
-            let (fs, sub) = root
-                .resolve_mount_point("/")?
+            let (fs, _abs, sub) = root
+                .resolve_mount_point("/", true)?
                 .ok_or(VfsFsError::NotFound)?;
             if sub != "/" {
                 return Err(VfsFsError::IO);
@@ -164,35 +260,22 @@ impl RootFs {
                 .downcast_mut::<RamFs>()
                 .ok_or(VfsFsError::NotSupported)?;
 
-            let blk = Arc::new(Mutex::new(VirtBlk::new()));
-            let total_sectors = blk.lock().capacity_in_sectors();
-
-            let whole = Arc::new(VBLOCK::new(
-                blk.clone(),
-                DevicePartition::Raw {
-                    base_lba: 0,
-                    sectors: total_sectors,
-                },
-            )) as Arc<dyn crate::fs::vfs::File>;
-            ramfs.mkdev("/vda", whole)?;
-
-            let mut mbr: [u8; SECTOR_SIZE] = [0; SECTOR_SIZE];
-            blk.lock()
-                .0
-                .lock()
-                .read_block(0, &mut mbr)
-                .map_err(|_| VfsFsError::IO)?;
-
-            let parts = parsing_mbr_partition(mbr).map_err(|_| VfsFsError::Invalid)?;
-            if parts.len() == 0{
-                // No partition
-                return Err(VfsFsError::Invalid);
+            let disks = probe_virtio_blk();
+            if disks.is_empty() {
+                error!("no virtio block device found");
+                return Err(VfsFsError::NotFound);
             }
-            for (idx, entry) in parts.into_iter().enumerate() {
-                let dev = Arc::new(VBLOCK::new(blk.clone(), DevicePartition::MBR(entry)))
-                    as Arc<dyn crate::fs::vfs::File>;
-                let path = alloc::format!("/vda{}", idx + 1);
-                ramfs.mkdev(path.as_str(), dev)?;
+            // 每个块设备依次是 /vda /vdb ...，分区是 /vdb1 /vdb2 ...
+            for (disk_idx, disk) in disks.iter().enumerate().take(26) {
+                let name = alloc::format!("/vd{}", (b'a' + disk_idx as u8) as char);
+                let blk = Arc::new(VirtBlk::new(disk));
+                register_virtio_blk(blk.clone());
+                match Self::build_disk_nodes(ramfs, blk, &name) {
+                    Ok(()) => {}
+                    // 第一块盘没有分区表时走 consent 模式（整盘 FAT32）
+                    Err(e) if disk_idx == 0 => return Err(e),
+                    Err(e) => warn!("{}: no usable partition table err={}", name, e),
+                }
             }
             Ok(())
         }
@@ -209,7 +292,7 @@ impl RootFs {
         let mut mount_point:BTreeMap<MountPath,MountFs> =BTreeMap::new(); 
         // WARN: 5MB RamFs
         let ramfs = RamFs::new(5*MB);
-        let mount_fs:MountFs = Arc::new(Mutex::new(ramfs));
+        let mount_fs:MountFs = Arc::new(SleepLock::new(ramfs));
         // Mount to /
         mount_point.insert(MountPath("/".to_string()), mount_fs);
 
@@ -247,7 +330,7 @@ impl RootFs {
                     return;
                 }
             };
-            let fat32_mnt: MountFs = Arc::new(Mutex::new(fat32));
+            let fat32_mnt: MountFs = Arc::new(SleepLock::new(fat32));
             if fat32_mnt.lock().mount().is_err() {
                 error!("consent mode: fat32 mount failed");
                 return;
@@ -266,7 +349,7 @@ impl RootFs {
             if let Ok(src) = vfs_open("/vda", OpenFlags::empty()) {
                 let rootfs_guard = ROOTFS.lock();
                 let root = rootfs_guard.as_ref().expect("root vfs not init");
-                if let Ok(Some((fs, sub))) = root.resolve_mount_point("/") {
+                if let Ok(Some((fs, _abs, sub))) = root.resolve_mount_point("/", true) {
                     let _ = sub;
                     let mut fs_guard = fs.lock();
                     if let Some(ramfs) = fs_guard.as_any_mut().downcast_mut::<RamFs>() {
@@ -291,14 +374,14 @@ impl RootFs {
 
             use crate::fs::vfs::vfs_mkdir;
             let ext4_wrapping_blockdev = Ext4BlockDevice::new(vda1);
-            let old_fs:Arc<Mutex<dyn VfsFs>>;
+            let old_fs:Arc<SleepLock<dyn VfsFs>>;
         {
-            let fs = Arc::new(Mutex::new(Ext4Fs::new(ext4_wrapping_blockdev)));
+            let fs = Arc::new(SleepLock::new(Ext4Fs::new(ext4_wrapping_blockdev)));
             fs.lock().mount().expect("ext4 mount failed");
             let mut rootfs_guard = ROOTFS.lock();
             let root_mount_point = &mut rootfs_guard.as_mut().expect("root vfs not init").mount_poinr;
             old_fs = root_mount_point.remove(&MountPath("/".to_string())).expect("Ramfs not mount at /");
-            root_mount_point.insert(MountPath("/".to_string()), fs.clone() as Arc<Mutex<dyn VfsFs>>);
+            root_mount_point.insert(MountPath("/".to_string()), fs.clone() as Arc<SleepLock<dyn VfsFs>>);
         }   
             //make dev dir
             vfs_mkdir("/dev").expect("/dev create failed!");
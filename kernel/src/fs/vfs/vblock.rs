@@ -1,5 +1,4 @@
 use alloc::sync::Arc;
-use spin::Mutex;
 use spin::Mutex as SpinMutex;
 
 use crate::driver::VirtBlk;
@@ -8,13 +7,13 @@ use crate::fs::vfs::{File, VfsFsError, VfsStat, VFS_DT_REG};
 use crate::SECTOR_SIZE;
 ///BLOCK_DEV
 pub struct VBLOCK{
-    blockdevice:Arc<Mutex<VirtBlk>>,
+    blockdevice:Arc<VirtBlk>,
     partition:DevicePartition,
     offset: SpinMutex<u64>,
 }
 
 impl VBLOCK {
-    pub fn new(blockdevice: Arc<Mutex<VirtBlk>>, partition: DevicePartition) -> Self {
+    pub fn new(blockdevice: Arc<VirtBlk>, partition: DevicePartition) -> Self {
         Self {
             blockdevice,
             partition,
@@ -71,9 +70,6 @@ impl File for VBLOCK {
 
             let mut sector: [u8; SECTOR_SIZE] = [0; SECTOR_SIZE];
             self.blockdevice
-                .lock()
-                .0
-                .lock()
                 .read_block(lba, &mut sector)
                 .map_err(|_| VfsFsError::IO)?;
 
@@ -92,6 +88,7 @@ impl File for VBLOCK {
             size: self.part_len_bytes(),
             mode: 0,
             file_type: VFS_DT_REG,
+            nlink: 1,
         })
     }
 
@@ -126,9 +123,6 @@ impl File for VBLOCK {
             let mut sector: [u8; SECTOR_SIZE] = [0; SECTOR_SIZE];
             if to_copy != SECTOR_SIZE {
                 self.blockdevice
-                    .lock()
-                    .0
-                    .lock()
                     .read_block(lba, &mut sector)
                     .map_err(|_| VfsFsError::IO)?;
             }
@@ -136,9 +130,6 @@ impl File for VBLOCK {
             sector[in_off..in_off + to_copy]
                 .copy_from_slice(&buf[read_pos..read_pos + to_copy]);
             self.blockdevice
-                .lock()
-                .0
-                .lock()
                 .write_block(lba, &sector)
                 .map_err(|_| VfsFsError::IO)?;
 
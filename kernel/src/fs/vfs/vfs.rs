@@ -3,10 +3,11 @@ use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::any::Any;
 use bitflags::bitflags;
-use spin::Mutex;
+use crate::sync::SleepLock;
 use crate::fs::vfs::vfserror::{VfsFsError};
+use crate::memory::FramTracker;
 
-pub type MountFs = Arc<Mutex<dyn VfsFs>>;
+pub type MountFs = Arc<SleepLock<dyn VfsFs>>;
 
 pub enum EntryType {
     File,
@@ -43,6 +44,15 @@ impl OpenFlags {
     }
 }
 
+bitflags! {
+    ///renameat2 的 flags
+    #[derive(Debug,Clone, Copy)]
+    pub struct RenameFlags: u32 {
+        const NOREPLACE = 1 << 0;
+        const EXCHANGE = 1 << 1;
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default)]
 pub struct VfsStat {
@@ -50,6 +60,7 @@ pub struct VfsStat {
     pub size: u64,
     pub mode: u32,
     pub file_type: u32,
+    pub nlink: u32,
 }
 
 pub const VFS_DT_UNKNOWN: u32 = 0;
@@ -57,6 +68,12 @@ pub const VFS_DT_REG: u32 = 8;
 pub const VFS_DT_DIR: u32 = 4;
 pub const VFS_DT_LNK: u32 = 10;
 
+/// st_mode 的文件类型位
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+
 #[repr(C, packed)]
 #[derive(Clone, Copy, Debug, Default)]
 pub struct LinuxDirent64 {
@@ -94,6 +111,26 @@ pub trait File: Send + Sync {
     fn flush(&self) -> Result<(), VfsFsError> {
         Ok(())
     }
+
+    /// 页缓存中文件第 page 页的页帧，file-backed mmap 直接映射它；不走页缓存的文件返回 None
+    fn cache_frame(&self, _page: usize) -> Result<Option<Arc<FramTracker>>, VfsFsError> {
+        Ok(None)
+    }
+
+    /// 共享 mmap 写过 frame 后调用：frame 正是页缓存中第 page 页时标脏并返回 true，否则返回 false 由调用者自己写回
+    fn cache_mark_dirty(&self, _page: usize, _frame: &Arc<FramTracker>) -> bool {
+        false
+    }
+
+    /// 设备控制，data 是 syscall 已经从用户态拷进来的参数，返回后按需拷回；不是终端的文件返回 NotSupported
+    fn ioctl(&self, _cmd: usize, _data: &mut [u8]) -> Result<usize, VfsFsError> {
+        Err(VfsFsError::NotSupported)
+    }
+
+    /// 目录打开时的绝对路径，*at 系统调用按它解析相对路径；不是目录的文件返回 None
+    fn path(&self) -> Option<String> {
+        None
+    }
 }
 
 #[repr(C)]
@@ -124,11 +161,21 @@ impl From<VfsStat> for KStat {
     fn from(v: VfsStat) -> Self {
         let size_i64 = core::cmp::min(v.size, i64::MAX as u64) as i64;
         let blocks = (v.size + 511) / 512;
+        // 后端没填类型位时按 file_type 补上，lstat 才分得出符号链接
+        let mode = if v.mode & S_IFMT != 0 {
+            v.mode
+        } else {
+            v.mode | match v.file_type {
+                VFS_DT_DIR => S_IFDIR,
+                VFS_DT_LNK => S_IFLNK,
+                _ => S_IFREG,
+            }
+        };
         Self {
             st_dev: 0,
             st_ino: v.inode as u64,
-            st_mode: v.mode,
-            st_nlink: 1,
+            st_mode: mode,
+            st_nlink: core::cmp::max(v.nlink, 1),
             st_uid: 0,
             st_gid: 0,
             st_rdev: 0,
@@ -170,6 +217,12 @@ pub trait VfsFs :Send + Sync{
         Err(VfsFsError::NotSupported)
     }
 
+    /// renameat2：在目录项上一步把 src 换到 dest，dest 已存在时直接替换（空目录才能被目录替换）
+    /// EXCHANGE 交换两个目录项；做不到原子完成的文件系统返回 NotSupported
+    fn rename2(&mut self, _src: &str, _dest: &str, _flags: RenameFlags) -> Result<(), VfsFsError> {
+        Err(VfsFsError::NotSupported)
+    }
+
     fn open(
         &mut self,
         _mount_fs: MountFs,
@@ -187,6 +240,26 @@ pub trait VfsFs :Send + Sync{
         Err(VfsFsError::NotSupported)
     }
 
+    /// 删除空目录
+    fn rmdir(&mut self, _path: &str) -> Result<(), VfsFsError> {
+        Err(VfsFsError::NotSupported)
+    }
+
+    /// 硬链接：给 src 再起一个名字 dest，两个路径在同一个文件系统里；不支持链接的文件系统返回 NotPermitted（EPERM）
+    fn link(&mut self, _src: &str, _dest: &str) -> Result<(), VfsFsError> {
+        Err(VfsFsError::NotPermitted)
+    }
+
+    /// 在 path 创建指向 target 的符号链接，target 原样保存，不检查是否存在
+    fn symlink(&mut self, _target: &str, _path: &str) -> Result<(), VfsFsError> {
+        Err(VfsFsError::NotPermitted)
+    }
+
+    /// 读符号链接的内容；path 不是符号链接时返回 Invalid
+    fn readlink(&mut self, _path: &str) -> Result<String, VfsFsError> {
+        Err(VfsFsError::Invalid)
+    }
+
     fn stat(&mut self, _path: &str) -> Result<VfsStat, VfsFsError> {
         Err(VfsFsError::NotSupported)
     }
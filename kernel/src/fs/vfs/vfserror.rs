@@ -16,10 +16,20 @@ pub enum VfsFsError {
     Invalid,
     BadFd,
     PermissionDenied,
+    /// 操作不被允许（如给目录建硬链接、文件系统不支持链接）
+    NotPermitted,
     NotSupported,
     Busy,
     NoSpace,
     NoDevice,
+    /// 删除的目录不是空的
+    NotEmpty,
+    /// 符号链接层数太多
+    Loop,
+    /// 跨文件系统的链接/改名
+    CrossDevice,
+    /// 阻塞时收到信号
+    Interrupted,
 }
 
 
@@ -40,10 +50,15 @@ impl Display for VfsFsError {
             Self::Invalid => write!(f, "Invalid"),
             Self::BadFd => write!(f, "BadFd"),
             Self::PermissionDenied => write!(f, "PermissionDenied"),
+            Self::NotPermitted => write!(f, "NotPermitted"),
             Self::NotSupported => write!(f, "NotSupported"),
             Self::Busy => write!(f, "Busy"),
             Self::NoSpace => write!(f, "NoSpace"),
             Self::NoDevice => write!(f, "NoDevice"),
+            Self::NotEmpty => write!(f, "NotEmpty"),
+            Self::Loop => write!(f, "Loop"),
+            Self::CrossDevice => write!(f, "CrossDevice"),
+            Self::Interrupted => write!(f, "Interrupted"),
         }
     }
 }
@@ -26,14 +26,17 @@ mod task;
 mod fs;
 
 use alloc::string::String;
-use log::{debug, error, trace, warn};
+use log::{debug, error, info, trace, warn};
 use riscv::asm;
 use crate::config::{ebss, sbss};
 use crate::driver::blktest::blktest;
+use crate::driver::fdt::{init_fdt, phys_mem_end, BOOT_INFO};
 use crate::fs::vfs::{ROOTFS, RootFs};
-use crate::task::run_first_task;
+use crate::sbi::hart_start;
+use crate::task::{run_first_task, TASK_MANAER};
 use crate::time::{ set_next_timeInterupt};
-use crate::trap::{enable_timer_interupt, rather_global_interrupt, set_kernel_trap_handler};
+use crate::driver::{init_external_interrupt, init_uart};
+use crate::trap::{enable_external_interrupt, enable_timer_interupt, rather_global_interrupt, set_kernel_trap_handler};
 extern crate alloc;
 use crate::{config::*, logger::kernel_info_debug, memory::allocator_init};
 use crate::memory::init_frame_allocator;
@@ -48,31 +51,81 @@ pub fn clear_bss() {
     }
     (sbss as usize..ebss as usize).for_each(|a| unsafe { (a as *mut u8).write_volatile(0) });
 }
-pub fn kernel_init(){
+pub fn kernel_init(dtb:usize){
     clear_bss();//清空bss
     logger::init();//日志初始化 - 必须先初始化日志才能使用 debug!
     kernel_info_debug();//打印内核日志
     allocator_init();//内核堆，分配器初始化
-    init_frame_allocator(ekernel as usize,ekernel as usize +MEMORY_SIZE);//物理内存页分配器初始化
+    init_fdt(dtb);//解析设备树：内存大小、时钟频率、设备地址
+    let mem_end = phys_mem_end();
+    if mem_end <= ekernel as usize {
+        panic!("physical memory end {:#x} below kernel end {:#x}, need more memory",mem_end,ekernel as usize);
+    }
+    init_frame_allocator(ekernel as usize,mem_end);//物理内存页分配器初始化
 }
 /// the rust entry-point of os
 #[no_mangle]
-pub fn blue_main() -> ! {//永远不会返回
-    kernel_init(); //bss，日志，分配器初始化
+/// a0:hartid a1:设备树物理地址(SBI 传入)
+pub fn blue_main(hartid:usize,dtb:usize) -> ! {//永远不会返回
+    kernel_init(dtb); //bss，日志，分配器初始化
+    if hartid >= MAX_HARTS {
+        panic!("boot hart {} out of MAX_HARTS {}",hartid,MAX_HARTS);
+    }
     set_kernel_trap_handler();//初始化陷阱入口，应该在地址空间激活前开启
     KERNEL_SPACE.lock().activate();//激活地址空间
     rather_global_interrupt();//愿意处理全局中断使能
     enable_timer_interupt();//开启全局时间中断使能
     set_next_timeInterupt();//第一次开启时钟中断
+    init_external_interrupt(hartid);//PLIC 初始化，设备中断在创建设备时使能
+    init_uart();//串口接管控制台输入输出
+    enable_external_interrupt();//开启外部中断使能
     
     debug!("stext {:#x}",__kernel_trap as usize);
     debug!("traper {:#x}",straper as usize);
     debug!("trap refume virtualaddr:{:#x}",__kernel_refume as usize - __kernel_trap as usize + TRAP_BOTTOM_ADDR);
     
     RootFs::init_rootfs();
-    
+    lazy_static::initialize(&TASK_MANAER);//先加载好任务，其它 hart 起来就能调度
+    start_secondary_harts(hartid);
+
     run_first_task();
     warn!("All right,kernel Will end\n");
     panic!("Kernel End");
 
 }
+
+///通过 SBI HSM 启动设备树里的其它 hart，从 _blue_secondary_start 进入 blue_secondary_main
+fn start_secondary_harts(boot_hartid:usize){
+    extern "C" {
+        fn _blue_secondary_start();
+    }
+    let harts = BOOT_INFO.lock().harts.clone();
+    for hart in harts {
+        if hart == boot_hartid {
+            continue;
+        }
+        if hart >= MAX_HARTS {
+            warn!("hart {} out of MAX_HARTS {}, not started",hart,MAX_HARTS);
+            continue;
+        }
+        let ret = hart_start(hart, _blue_secondary_start as usize, 0);
+        if ret != 0 {
+            warn!("hart {} start failed, sbi error {}",hart,ret);
+        }
+    }
+}
+
+/// 其它 hart 的入口，内核地址空间和任务都已经由启动 hart 准备好
+#[no_mangle]
+pub fn blue_secondary_main(hartid:usize) -> ! {
+    set_kernel_trap_handler();
+    KERNEL_SPACE.lock().activate();
+    rather_global_interrupt();
+    enable_timer_interupt();
+    set_next_timeInterupt();
+    driver::plic::init_hart(hartid);//设备中断只路由给启动 hart
+    enable_external_interrupt();
+    info!("hart {} started",hartid);
+
+    run_first_task();
+}
@@ -130,6 +130,9 @@ impl PhysiAddr{
 
 
 
+/// pte 的 rsw 保留位（bit 8），标记不合法 pte 里存的是交换槽位
+const SWAP_PTE_BIT:usize = 1 << 8;
+
 impl PageTableEntry {
     pub fn new(ppn:usize,flags:PTEFlags)->Self{
         PageTableEntry( (ppn<<10) | flags.bits()) // 页表项不持有frametracer
@@ -154,6 +157,17 @@ impl PageTableEntry {
     pub fn set_inValid(&mut self){
         self.0=0 //全部置零 
     }
+    ///换出页的 pte：V=0，rsw 最低位做标记，ppn 位置放交换槽位号
+    pub fn new_swap(slot:usize)->Self{
+        PageTableEntry((slot<<10) | SWAP_PTE_BIT)
+    }
+    ///是否是换出页留下的 swap pte
+    pub fn is_swap(&self)->bool{
+        self.0 & PTEFlags::V.bits() == 0 && self.0 & SWAP_PTE_BIT != 0
+    }
+    pub fn swap_slot(&self)->usize{
+        self.0 >> 10
+    }
     // 设置脏 刷新页表
     pub fn set_isdirty(&mut self){
         let new_flag = PTEFlags::from_bits_truncate(self.0 & 255) | PTEFlags::D;
@@ -253,6 +267,9 @@ impl PageTable {
     pub fn translate(&mut self,VDDR:VirAddr)->Option<PhysiAddr>{
         
         match self.find_pte_vpn(VDDR.into()){
+            Some(pte) if pte.is_swap()=>{
+                None //已换出，调用者需要先换入
+            }
             Some(pte)=>{
                 let ppn=pte.ppn();
                 let addr=(ppn.0*PAGE_SIZE)+VDDR.offset();//不考虑是否对齐,使用者肯定
@@ -269,6 +286,9 @@ impl PageTable {
         //使用编译器屏障，防止优化内存访问重新排序
         compiler_fence(Ordering::SeqCst);
         match self.find_pte_vpn(vpn.into()){
+            Some(pte) if pte.is_swap()=>{
+                None
+            }
             Some(pte)=>{
                 let ppn=pte.ppn();
 
@@ -329,6 +349,12 @@ impl PageTable {
         *pte=PageTableEntry::new(ppn.0,flags|PTEFlags::V); //合法
     }
 
+    ///把vpn的pte设成换出页的swap pte（不合法），记录交换槽位
+    pub fn set_swap(&mut self,vpn:VirNumber,slot:usize){
+        let pte=self.find_or_create_pte_vpn(vpn).expect("Failed When Set Swap Pte");
+        *pte=PageTableEntry::new_swap(slot);
+    }
+
     ///判断该vpn是否存在合法映射
     pub fn is_maped(&mut self,vpn:VirNumber)->bool{//判断对应vpn是否已经被映射过
         match self.find_pte_vpn(vpn){
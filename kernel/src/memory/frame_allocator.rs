@@ -1,5 +1,5 @@
 use log::{trace, warn};
-use crate::{config::{KERNEL_HEADP, KERNEL_HEAP_SIZE, MB, PAGE_SIZE}, memory::address::*,sync::UPSafeCell};
+use crate::{config::{KERNEL_HEADP, KERNEL_HEAP_SIZE, MB, PAGE_SIZE}, memory::address::*,sync::SpinLock};
 use core::cell::UnsafeCell;
 use buddy_system_allocator::LockedHeap;
 #[allow(static_mut_refs)]
@@ -59,7 +59,9 @@ impl FrameAllocatorTrait for FrameAlloctor{
             trace!("alloc frame:ppn:{}",ppn);
             Some(FramTracker::new(PhysiNumber(ppn)))
         }else{
-            panic!("no more frame!");
+            // 物理页帧耗尽，由调用者决定换出还是失败
+            warn!("no more frame!");
+            None
         }
     }
 
@@ -83,6 +85,10 @@ impl FrameAllocatorTrait for FrameAlloctor{
 }
 
 impl FrameAlloctor {
+    ///剩余可分配的物理页帧数
+    pub fn remain(&self)->usize{
+        self.recycle.len()+(self.end-self.start)
+    }
     pub fn init(&mut self,start:usize,end:usize){
         self.start=PhysiAddr(start).floor_up().0;
         self.end=PhysiAddr(end).floor_down().0;
@@ -110,9 +116,9 @@ impl FramTracker{
     }
 }
 lazy_static!{
-    pub static ref FRAME_ALLOCATOR:UPSafeCell<FrameAlloctor>= 
+    pub static ref FRAME_ALLOCATOR:SpinLock<FrameAlloctor>= 
     unsafe {
-        UPSafeCell::new(FrameAlloctor::new())
+        SpinLock::new(FrameAlloctor::new())
     };
 }
 pub fn init_frame_allocator(start:usize,end:usize){
@@ -122,6 +128,10 @@ pub fn alloc_frame()->Option<FramTracker>{
     FRAME_ALLOCATOR.lock().alloc()
 }
 
+pub fn frame_remain()->usize{
+    FRAME_ALLOCATOR.lock().remain()
+}
+
 pub fn alloc_contiguous_frames(pages: usize) -> Option<Vec<FramTracker>> {
     FRAME_ALLOCATOR.lock().alloc_contiguous(pages)
 }
@@ -1,6 +1,8 @@
 use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::btree_set::BTreeSet;
 use bitflags::bitflags;
 use alloc::vec::Vec;
+use alloc::vec;
 use alloc::sync::Arc;
 use crate::memory::memset::satp::Satp;
 use alloc::sync::Weak;
@@ -10,19 +12,23 @@ use core::arch::asm;
 use core::cell::RefMut;
 use core::hint;
     use riscv::register::satp;
-    use crate::fs::vfs::File;
+    use crate::fs::vfs::{File, VfsFsError};
     use crate::task::TaskManagerInner;
     use crate::task::getapp_kernel_sapce;
     use crate::task::{TASK_MANAER, file_loader};
 
-use crate::{config::*, memory::{address::*, alloc_frame, frame_allocator::FramTracker}};
+use crate::{config::*, memory::{address::*, alloc_frame, frame_remain, frame_allocator::FramTracker}};
+use crate::memory::swap::{ClockScan, SWAP_RESERVE_FRAMES, swap_clock_register, swap_dup, swap_enabled, swap_free, swap_out_global, swap_read_page, swap_write_page};
+use crate::sbi::remote_sfence_vma_all;
+use crate::driver::fdt::{BOOT_INFO, phys_mem_end};
 use crate::trap::no_return_start;
 use crate::trap::TrapFunction;
+use crate::syscall::Errno;
  use lazy_static::lazy_static;
- use crate::sync::UPSafeCell;
+ use crate::sync::SpinLock;
 
  lazy_static! {
-     static ref NEXT_MMAP_ID: UPSafeCell<u64> = unsafe { UPSafeCell::new(1) };
+     static ref NEXT_MMAP_ID: SpinLock<u64> = unsafe { SpinLock::new(1) };
  }
 
  fn alloc_mmap_id() -> u64 {
@@ -39,8 +45,8 @@ use crate::trap::TrapFunction;
  }
 
  lazy_static! {
-     static ref SHARED_MMAP_PAGES: UPSafeCell<BTreeMap<SharedMmapKey, Weak<FramTracker>>> =
-         unsafe { UPSafeCell::new(BTreeMap::new()) };
+     static ref SHARED_MMAP_PAGES: SpinLock<BTreeMap<SharedMmapKey, Weak<FramTracker>>> =
+         unsafe { SpinLock::new(BTreeMap::new()) };
  }
 ///开始和结束，一个范围,自动[start,end] start地址自动向下取整，end也向下取整，因为virnumrange用于代码映射，防止代码缺失, startva/PAGE =num+offset ,从num开始，endva/pagesize=endva+offset由于闭区间所以向下取整,防止多映射
 #[derive(Debug,Clone, Copy)]
@@ -107,8 +113,8 @@ pub struct VirNumRangeIter{
  }
 
  lazy_static! {
-     static ref KERNEL_STACK_ALLOCATOR: UPSafeCell<KernelStackAllocator> = unsafe {
-         UPSafeCell::new(KernelStackAllocator::new())
+     static ref KERNEL_STACK_ALLOCATOR: SpinLock<KernelStackAllocator> = unsafe {
+         SpinLock::new(KernelStackAllocator::new())
      };
  }
 
@@ -216,6 +222,81 @@ pub struct MmapInfo {
     pub offset: usize,
 }
 
+impl MmapInfo {
+    /// vpn 对应的文件页号（匿名共享映射用作页序号），offset 随 area 分割一起平移
+    pub fn page_index(&self, area_start: VirNumber, vpn: VirNumber) -> usize {
+        self.offset / PAGE_SIZE + vpn.0.saturating_sub(area_start.0)
+    }
+
+    /// area 起点后移 pages 页后的映射信息
+    fn shifted(&self, pages: usize) -> Self {
+        let mut info = self.clone();
+        info.offset += pages * PAGE_SIZE;
+        info
+    }
+}
+
+bitflags! {
+    #[derive(Debug,Clone, Copy)]
+    pub struct MsyncFlags: usize {
+        const ASYNC = 1;
+        const INVALIDATE = 2;
+        const SYNC = 4;
+    }
+}
+
+/// exec 时记录的 ELF 段来源，缺页时按需从可执行文件读取
+#[derive(Clone)]
+pub struct ElfBacking {
+    pub file: Arc<dyn File>,
+    /// 段起始虚拟地址（不要求页对齐）
+    pub vaddr: usize,
+    /// 段在文件中的偏移
+    pub offset: usize,
+    /// 段在文件中的长度，[vaddr+file_size, vaddr+mem_size) 为 BSS，缺页时保持全零
+    pub file_size: usize,
+}
+
+/// 缺页要从文件读内容的页：在地址空间的锁里分好页帧，放开锁 load 读文件，再拿锁 install_fill 装进页表
+/// 文件读写要拿 SleepLock，可能睡眠，不能拿着地址空间的 SpinLock 做
+pub struct PageFill {
+    vpn: VirNumber,
+    frame: Arc<FramTracker>,
+    source: FillSource,
+}
+
+enum FillSource {
+    /// ELF 段，相邻段共用边界页时有好几段要读
+    Elf(Vec<ElfPart>),
+    /// mmap 文件映射，id 用来确认装页表时还是同一个映射
+    Mmap { id: u64, file: Arc<dyn File>, page: usize, shared: bool },
+}
+
+/// 一个 ELF 段落在这一页里的文件内容
+struct ElfPart {
+    file: Arc<dyn File>,
+    file_off: usize,
+    page_off: usize,
+    len: usize,
+}
+
+/// 共享文件映射里要写回（或者 MS_INVALIDATE 要重新读）的一页，放开地址空间的锁之后再做文件 I/O
+#[derive(Clone)]
+pub struct FilePage {
+    file: Arc<dyn File>,
+    page: usize,
+    frame: Arc<FramTracker>,
+}
+
+/// msync 在地址空间的锁外要做的文件 I/O，按 写回 → flush → 重新读 的顺序做
+pub struct MsyncWork {
+    writeback: Vec<FilePage>,
+    /// MS_SYNC 要 flush 的文件
+    flush: Vec<Arc<dyn File>>,
+    /// MS_INVALIDATE 要从文件重新读的页
+    invalidate: Vec<FilePage>,
+}
+
 #[derive(Clone)]
 pub struct MapArea{ //通常为单次push进来，虽然粒度大，保证push粒度足够小即可
     ///虚拟页号范围,闭区间
@@ -225,6 +306,8 @@ pub struct MapArea{ //通常为单次push进来，虽然粒度大，保证push
     map_type:MapType,
     /// Some => this area is created by mmap syscall (lazy allocation / file-backed / shared semantics).
     pub mmap: Option<MmapInfo>,
+    /// Some => ELF 段，页帧在第一次访问时从可执行文件填充
+    pub elf: Option<ElfBacking>,
 }
 
 #[derive(Clone)]
@@ -233,6 +316,12 @@ pub struct MapSet{
     pub table:PageTable,
     areas:Vec<MapArea>,
     pub brk:VirAddr, //进程brk点
+    ///已经换出到交换分区的vpn，槽位号记在对应的swap pte里
+    swapped:BTreeSet<VirNumber>,
+    ///全局换出时钟在本地址空间里的位置（下一个要看的 vpn）
+    swap_hand:VirNumber,
+    ///munmap/mremap 收集的共享文件映射脏页，调用方放开锁之后 take_writeback 写回
+    writeback:Vec<FilePage>,
 }
 impl MapArea {
     
@@ -249,6 +338,7 @@ impl MapArea {
             frames:BTreeMap::new(),
             map_type,
             mmap: None,
+            elf: None,
         }
     }
     
@@ -284,6 +374,12 @@ impl MapArea {
         //debug!("Map Aread map vpn:{} -> ppn:{}",vpn.0,ppn.0);
     }
 
+    ///PROT_NONE：用户 area 没有任何 RWX 权限，页帧保留但 pte 不合法
+    pub fn is_prot_none(&self) -> bool {
+        self.flags.contains(MapAreaFlags::U)
+            && !self.flags.intersects(MapAreaFlags::R | MapAreaFlags::W | MapAreaFlags::X)
+    }
+
     pub fn map_one_with_frame(&mut self, vpn: VirNumber, frame: Arc<FramTracker>, page_table: &mut PageTable) {
         if page_table.is_maped(vpn) {
             return;
@@ -364,6 +460,149 @@ impl MapArea {
     
 }
 
+impl PageFill {
+    ///放开地址空间的锁之后调用：把文件内容读进页帧
+    /// 共享文件映射可能换成页缓存页，或者别的进程已经读好的那一页；读不出文件内容时返回 EIO
+    pub fn load(self) -> Result<Self, Errno> {
+        let PageFill { vpn, frame, source } = self;
+        let frame = match &source {
+            FillSource::Elf(parts) => {
+                let pa: PhysiAddr = frame.ppn.into();
+                let page = unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut u8, PAGE_SIZE) };
+                for part in parts {
+                    let dst = &mut page[part.page_off..part.page_off + part.len];
+                    match part.file.read_at(part.file_off, dst) {
+                        Ok(n) if n == part.len => {}
+                        Ok(n) => {
+                            error!("elf pagefault: short read off={} got={} need={}", part.file_off, n, part.len);
+                            return Err(Errno::EIO);
+                        }
+                        Err(e) => {
+                            error!("elf pagefault: read_at failed off={} err={}", part.file_off, e);
+                            return Err(Errno::EIO);
+                        }
+                    }
+                }
+                frame
+            }
+            FillSource::Mmap { file, page, shared: true, .. } => Self::shared_file_frame(file, *page, frame)?,
+            FillSource::Mmap { file, page, shared: false, .. } => {
+                Self::read_file_page(file, *page, &frame)?;
+                frame
+            }
+        };
+        Ok(PageFill { vpn, frame, source })
+    }
+
+    ///共享文件映射的一页：走页缓存的文件直接用缓存页帧，和 read/write 看到同一份数据；
+    /// 不走页缓存的按 (inode, 页号) 在 SHARED_MMAP_PAGES 里找别的进程已经读好的页，没有才读进 frame
+    fn shared_file_frame(file: &Arc<dyn File>, page: usize, frame: Arc<FramTracker>) -> Result<Arc<FramTracker>, Errno> {
+        match file.cache_frame(page) {
+            Ok(Some(cached)) => return Ok(cached),
+            Ok(None) => {}
+            Err(e) => {
+                error!("mmap shared pagefault: page cache failed err={} kill", e);
+                return Err(Errno::EIO);
+            }
+        }
+        let inode_num = match file.stat() {
+            Ok(st) => st.inode,
+            Err(_e) => {
+                error!("mmap shared pagefault: stat failed kill");
+                return Err(Errno::EIO);
+            }
+        };
+        let key = SharedMmapKey::File { inode_num, file_page: page as u64 };
+        if let Some(existing) = SHARED_MMAP_PAGES.lock().get(&key).and_then(|w| w.upgrade()) {
+            return Ok(existing);
+        }
+        Self::read_file_page(file, page, &frame)?;
+        // 读文件的时候别的进程可能已经放进去一页，大家用同一页
+        let mut shared = SHARED_MMAP_PAGES.lock();
+        if let Some(existing) = shared.get(&key).and_then(|w| w.upgrade()) {
+            return Ok(existing);
+        }
+        shared.insert(key, Arc::downgrade(&frame));
+        Ok(frame)
+    }
+
+    ///文件第 page 页读进 frame，文件末尾之后补零
+    fn read_file_page(file: &Arc<dyn File>, page: usize, frame: &Arc<FramTracker>) -> Result<(), Errno> {
+        let file_off = page.saturating_mul(PAGE_SIZE);
+        let pa: PhysiAddr = frame.ppn.into();
+        let buf = unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut u8, PAGE_SIZE) };
+        match file.read_at(file_off, buf) {
+            Ok(n) => {
+                buf[n..].fill(0);
+                Ok(())
+            }
+            Err(e) => {
+                error!("mmap pagefault: read_at failed off={} err={} kill", file_off, e);
+                Err(Errno::EIO)
+            }
+        }
+    }
+}
+
+impl FilePage {
+    ///把收集到的脏页写回文件，调用时不能拿着地址空间的锁
+    /// 页帧就是页缓存页时只标记缓存脏页，由 flush 落盘；否则直接写文件，不越过文件末尾
+    pub fn write_back(pages: Vec<FilePage>) -> Result<(), VfsFsError> {
+        let mut result = Ok(());
+        for p in pages {
+            if p.file.cache_mark_dirty(p.page, &p.frame) {
+                continue;
+            }
+            if let Err(e) = p.write_direct() {
+                error!("mmap: write back page {} failed err={}", p.page, e);
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    fn write_direct(&self) -> Result<(), VfsFsError> {
+        let file_size = self.file.stat()?.size as usize;
+        let file_off = self.page * PAGE_SIZE;
+        if file_off >= file_size {
+            return Ok(());
+        }
+        let len = core::cmp::min(PAGE_SIZE, file_size - file_off);
+        let pa: PhysiAddr = self.frame.ppn.into();
+        let buf = unsafe { core::slice::from_raw_parts(pa.0 as *const u8, len) };
+        self.file.write_at(file_off, buf)?;
+        Ok(())
+    }
+}
+
+impl MsyncWork {
+    ///放开地址空间的锁之后做 msync 的文件 I/O，失败返回 -EIO，成功返回 0
+    pub fn run(self) -> isize {
+        if FilePage::write_back(self.writeback).is_err() {
+            return -Errno::EIO;
+        }
+        for file in self.flush.iter() {
+            if let Err(e) = file.flush() {
+                error!("msync: flush failed err={}", e);
+                return -Errno::EIO;
+            }
+        }
+        // 页缓存页本身就是最新内容；其它页刚写回过，重新读一遍拿到别人经 write 写入的数据
+        for p in self.invalidate {
+            if let Ok(Some(cached)) = p.file.cache_frame(p.page) {
+                if Arc::ptr_eq(&cached, &p.frame) {
+                    continue;
+                }
+            }
+            if PageFill::read_file_page(&p.file, p.page, &p.frame).is_err() {
+                error!("msync: invalidate read failed page={}", p.page);
+                return -Errno::EIO;
+            }
+        }
+        0
+    }
+}
+
 bitflags! {
     pub struct CloneFlags:usize{
         const CSIGNAL            = 0x000000ffusize; // 低 8 位：子进程退出/停止时向父进程发送的信号（如 SIGCHLD）
@@ -441,16 +680,158 @@ impl MapSet {
         self.areas.iter().any(|area| area.mmap.is_some() && area.range.is_contain_thisvpn(vpn))
     }
 
-    ///复制Mapset 解析每一个maparea的页表，申请新页然后将数据搬过去
+    ///vpn 落在 MAP_SHARED 的 mmap 区域里，这样的页可能被别的地址空间同时映射
+    pub fn is_shared_vpn(&self, vpn: VirNumber) -> bool {
+        self.areas.iter().any(|area| {
+            area.range.is_contain_thisvpn(vpn)
+                && area.mmap.as_ref().map_or(false, |info| info.flags.contains(MmapFlags::SHARED))
+        })
+    }
+
+    ///vpn 所在 area 被 mprotect 成了 PROT_NONE
+    pub fn is_prot_none_vpn(&self, vpn: VirNumber) -> bool {
+        self.areas.iter().any(|area| area.range.is_contain_thisvpn(vpn) && area.is_prot_none())
+    }
+
+    pub fn is_elf_vpn(&self, vpn: VirNumber) -> bool {
+        self.areas.iter().any(|area| area.elf.is_some() && area.range.is_contain_thisvpn(vpn))
+    }
+
+    ///当前驻留在内存里的页数（已经分配物理页的，换出的不算），rusage 的 maxrss 用
+    pub fn resident_pages(&self) -> usize {
+        self.areas.iter().map(|area| area.frames.len()).sum()
+    }
+
+    ///内核即将通过物理地址读写用户内存[start,start+len)，先把其中还没缺页分配的 ELF/mmap 页补上
+    /// 内核访问用户内存不经过用户页表，不会触发 pagefault
+    /// 补页失败时返回错误（分配不到页帧是 ENOMEM），调用方按 EFAULT/ENOMEM 返回给系统调用
+    /// 碰到要读文件的页就停下返回它，调用方放开锁 load、install_fill 之后再从头 populate（见 with_user_range）
+    pub fn populate_range(&mut self,start:VirAddr,len:usize)->Result<Option<PageFill>,Errno>{
+        if len == 0 {
+            return Ok(None);
+        }
+        let start_vpn = start.floor_down();
+        let end_vpn = VirAddr(start.0.saturating_add(len).saturating_sub(1)).floor_down();
+        for vpn in VirNumRange(start_vpn, end_vpn) {
+            if self.table.is_maped(vpn) {
+                continue;
+            }
+            if self.is_swapped_vpn(vpn) {
+                if !self.is_prot_none_vpn(vpn) {
+                    self.swap_in(vpn).inspect_err(|_| error!("populate_range: swap in vpn:{} failed", vpn.0))?;
+                }
+                continue;
+            }
+            if (self.is_mmap_vpn(vpn) || self.is_elf_vpn(vpn)) && !self.is_prot_none_vpn(vpn) {
+                let pending = self.findarea_allocFrame_and_setPte(vpn)
+                    .inspect_err(|_| error!("populate_range: fill vpn:{} failed", vpn.0))?;
+                if pending.is_some() {
+                    return Ok(pending);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    ///ELF 段缺页：分配一个页帧，记下所有覆盖这个 vpn 的 ELF 段要读进来的文件内容
+    /// 相邻段可能共用边界页，install_fill 时同一个页帧挂到每个段下面，页表权限取并集
+    fn elf_prepare_fill(&mut self,vpn:VirNumber)->Result<PageFill,Errno>{
+        let page_va = vpn.0 * PAGE_SIZE;
+        let mut parts = Vec::new();
+        for area in self.areas.iter() {
+            if !area.range.is_contain_thisvpn(vpn) {
+                continue;
+            }
+            let Some(elf) = area.elf.as_ref() else {
+                continue;
+            };
+            // 本页与段文件内容的交集 [copy_start, copy_end)
+            let copy_start = page_va.max(elf.vaddr);
+            let copy_end = (page_va + PAGE_SIZE).min(elf.vaddr.saturating_add(elf.file_size));
+            if copy_start < copy_end {
+                parts.push(ElfPart {
+                    file: elf.file.clone(),
+                    file_off: elf.offset + (copy_start - elf.vaddr),
+                    page_off: copy_start - page_va,
+                    len: copy_end - copy_start,
+                });
+            }
+        }
+        let Some(frame) = self.alloc_user_frame() else {
+            error!("elf pagefault: out of memory vpn:{}", vpn.0);
+            return Err(Errno::ENOMEM);
+        };
+        Ok(PageFill { vpn, frame, source: FillSource::Elf(parts) })
+    }
+
+    ///把 load 好的页装进页表
+    /// 放开锁期间别的线程可能已经补上了这一页（或者又被换出），area 也可能被 munmap/mprotect 改掉了，
+    /// 这些情况丢掉这一页，调用方重新检查（缺页会再来一次）
+    pub fn install_fill(&mut self,fill:PageFill){
+        let vpn = fill.vpn;
+        if self.table.is_maped(vpn) || self.is_swapped_vpn(vpn) || self.is_prot_none_vpn(vpn) {
+            return;
+        }
+        match fill.source {
+            FillSource::Elf(_) => {
+                for area in self.areas.iter_mut() {
+                    if area.elf.is_some() && area.range.is_contain_thisvpn(vpn) {
+                        area.frames.insert(vpn, fill.frame.clone());
+                        self.table.map(vpn, fill.frame.ppn, area.flags.into());
+                    }
+                }
+            }
+            FillSource::Mmap { id, page, .. } => {
+                let area = self.areas.iter_mut().find(|area| {
+                    area.range.is_contain_thisvpn(vpn)
+                        && area.mmap.as_ref().map_or(false, |info| info.id == id && info.page_index(area.range.0, vpn) == page)
+                });
+                if let Some(area) = area {
+                    area.map_one_with_frame(vpn, fill.frame, &mut self.table);
+                }
+            }
+        }
+    }
+
+    ///缺页补一页：匿名页在锁里直接补上，要读文件的页放开锁读完再拿锁装进页表
+    pub fn fill_page(space:&SpinLock<MapSet>,vpn:VirNumber)->Result<(),Errno>{
+        let pending = space.lock().findarea_allocFrame_and_setPte(vpn)?;
+        if let Some(fill) = pending {
+            let fill = fill.load()?;
+            space.lock().install_fill(fill);
+        }
+        Ok(())
+    }
+
+    ///内核要通过物理地址访问用户内存 [start,start+len)：把页补好（write 时打破 COW），然后拿着锁执行 f
+    /// 要读文件的页放开锁读，装好之后从头再检查一遍；f 里页不会被换出
+    pub fn with_user_range<R>(space:&SpinLock<MapSet>,start:usize,len:usize,write:bool,f:impl FnOnce(&mut MapSet)->Result<R,Errno>)->Result<R,Errno>{
+        loop {
+            let mut set = space.lock();
+            let pending = if write {
+                set.cow_prepare_write(VirAddr(start), len)?
+            } else {
+                set.populate_range(VirAddr(start), len)?
+            };
+            let Some(fill) = pending else {
+                return f(&mut set);
+            };
+            drop(set);
+            let fill = fill.load()?;
+            space.lock().install_fill(fill);
+        }
+    }
+
+    ///复制Mapset 用户页采用写时复制(COW)：父子共享同一个Arc<FramTracker>，双方页表项去掉W
     pub fn clone_mapset(&mut self)->Option<Self>{
         // 目标：为 fork 复制一份“独立”的地址空间。
-        // - DEFAULT + MAPED：逐页分配新物理页帧，建立相同的页表映射，并复制页内容
+        // - 用户 MAPED 页（带 U）：不复制数据，父子共享同一个物理页帧，双方 pte 都去掉 W，
+        //   第一次写触发 StorePageFault 时由 cow_handle_fault 复制出私有页
+        // - 非用户 MAPED 页（TrapContext 等，内核通过物理地址直接读写）：逐页分配新帧并复制内容
         // - DEFAULT + INDENTICAL：建立相同的恒等映射（不分配新页帧）
-        // - MMAP：只复制虚拟地址空间的“预留信息”（MapArea 元数据），不建立页表项、不分配物理页
+        // - MMAP：已经缺页分配的页同样共享；MAP_SHARED 保留写权限，MAP_PRIVATE 走 COW
         //
-        // 注意：当前实现是“全量拷贝”（不是 COW），所以父子进程互不影响。
-
-        
+        // 页帧的释放依赖 Arc 引用计数：最后一个持有者 unmap/drop 时才真正回收。
 
         // 1) 创建一个空的 MapSet，先把 trap 映射补齐（trap 映射不是通过 MapArea 管理的）
         let mut new_set = MapSet::new_bare();
@@ -461,62 +842,290 @@ impl MapSet {
             // 复制一份 MapArea 的元信息（range/flags/map_type + mmap 元数据）
             let mut new_area = MapArea::new(area.range, area.flags, area.map_type);
             new_area.mmap = area.mmap.clone();
-
-            if area.mmap.is_some() {
-                // mmap 区域：只复制虚拟地址空间元数据，不建立页表项、不分配物理页
-            } else {
-                match area.map_type {
-                        MapType::Indentical => {
-                            // 恒等映射：直接建立相同 vpn->ppn(vpn) 映射即可
-                            // 这种映射一般用于内核空间或特殊区域（用户 MapSet 中通常较少）
-                            let start = area.range.0;
-                            let end = area.range.1;
-                            let mut vpn = start;
-                            while vpn.0 <= end.0 {
-                                // MapType::Indentical 时 ppn 就是 vpn
-                                new_set.table.map(vpn, PhysiNumber(vpn.0), area.flags.into());
+            new_area.elf = area.elf.clone();
+
+            match area.map_type {
+                    MapType::Indentical => {
+                        // 恒等映射：直接建立相同 vpn->ppn(vpn) 映射即可
+                        // 这种映射一般用于内核空间或特殊区域（用户 MapSet 中通常较少）
+                        let start = area.range.0;
+                        let end = area.range.1;
+                        let mut vpn = start;
+                        while vpn.0 <= end.0 {
+                            // MapType::Indentical 时 ppn 就是 vpn
+                            new_set.table.map(vpn, PhysiNumber(vpn.0), area.flags.into());
+                            vpn.0 += 1;
+                        }
+                    }
+                    MapType::Maped if !area.flags.contains(MapAreaFlags::U) => {
+                        // 非用户页：逐页分配新帧并复制页内容
+                        let start = area.range.0;
+                        let end = area.range.1;
+                        let mut vpn = start;
+                        while vpn.0 <= end.0 {
+                            // 如果父进程页表中该页并没有合法映射，则跳过
+                            if !self.table.is_maped(vpn) {
                                 vpn.0 += 1;
+                                continue;
                             }
-                        }
-                        MapType::Maped => {
-                            // 普通映射：逐页分配新帧并复制页内容
-                            let start = area.range.0;
-                            let end = area.range.1;
-                            let mut vpn = start;
-                            while vpn.0 <= end.0 {
-                                // 如果父进程页表中该页并没有合法映射（例如 MMAP 尚未触发缺页），则跳过
-                                if !self.table.is_maped(vpn) {
-                                    vpn.0 += 1;
-                                    continue;
-                                }
-
-
-                                let re =new_set.find_thisvpn_frame(start);
-                                // 2.1 在新地址空间中分配页帧并建立页表项
-                                // new_area.map_one 会：alloc_frame + new_area.frames.insert + new_set.table.map
-                                new_area.map_one(vpn, &mut new_set.table,re);
-
-                                // 2.2 拷贝父进程该页的内容到子进程
-                                // 这里直接按 PAGE_SIZE 全页拷贝（ELF 段尾的空洞也会被拷贝为 0/原值）
-                                let src = self.table.get_mut_byte(vpn)
-                                    .expect("clone_mapset: src page not mapped");
-                                let dst = new_set.table.get_mut_byte(vpn)
-                                    .expect("clone_mapset: dst page not mapped");
-                                dst.copy_from_slice(src);
 
-                                vpn.0 += 1;
+                            let re =new_set.find_thisvpn_frame(start);
+                            // new_area.map_one 会：alloc_frame + new_area.frames.insert + new_set.table.map
+                            new_area.map_one(vpn, &mut new_set.table,re);
+
+                            // 这里直接按 PAGE_SIZE 全页拷贝
+                            let src = self.table.get_mut_byte(vpn)
+                                .expect("clone_mapset: src page not mapped");
+                            let dst = new_set.table.get_mut_byte(vpn)
+                                .expect("clone_mapset: dst page not mapped");
+                            dst.copy_from_slice(src);
+
+                            vpn.0 += 1;
+                        }
+                    }
+                    MapType::Maped => {
+                        // 用户页：共享页帧
+                        let shared = area.mmap.as_ref()
+                            .map_or(false, |info| info.flags.contains(MmapFlags::SHARED));
+                        let prot_none = area.is_prot_none();
+                        for (vpn, frame) in area.frames.iter() {
+                            if prot_none {
+                                // PROT_NONE 页没有合法 pte，页帧照样共享，mprotect 恢复权限时再按 COW 处理
+                                new_area.frames.insert(*vpn, frame.clone());
+                                continue;
+                            }
+                            let Some(pte) = self.table.find_pte_vpn(*vpn) else {
+                                continue;
+                            };
+                            if !pte.is_valid() {
+                                continue;
+                            }
+                            let mut flags = pte.flags();
+                            if !shared {
+                                // 父进程也要去掉 W，否则父进程的写会被子进程看到
+                                flags.remove(PTEFlags::W | PTEFlags::D);
+                                pte.set_flags(PTEFlags::from_bits_truncate(flags.bits()));
                             }
+                            new_area.frames.insert(*vpn, frame.clone());
+                            new_set.table.map(*vpn, frame.ppn, flags);
                         }
-                }
+                    }
             }
 
             new_set.areas.push(new_area);
         }
 
+        // 换出的页：子进程引用同一个交换槽位，谁先换入谁得到私有副本
+        for vpn in self.swapped.iter() {
+            let Some(pte) = self.table.find_pte_vpn(*vpn) else {
+                continue;
+            };
+            if !pte.is_swap() {
+                continue;
+            }
+            let slot = pte.swap_slot();
+            swap_dup(slot);
+            new_set.table.set_swap(*vpn, slot);
+            new_set.swapped.insert(*vpn);
+        }
+
+        // 父进程页表项权限改变，刷新tlb
+        unsafe { riscv::asm::sfence_vma_all(); }
         Some(new_set)
     }
+
+    ///vpn 所在用户 area 可写、页已经分配并且不是共享映射，对它的非法写是 COW 缺页
+    pub fn is_cow_vpn(&self,vpn:VirNumber)->bool{
+        self.areas.iter().any(|area|{
+            area.range.is_contain_thisvpn(vpn)
+                && area.flags.contains(MapAreaFlags::W | MapAreaFlags::U)
+                && area.frames.contains_key(&vpn)
+                && !area.mmap.as_ref().map_or(false, |info| info.flags.contains(MmapFlags::SHARED))
+        })
+    }
+
+    ///写时复制缺页处理：vpn 所在用户 area 可写，但 pte 被 fork 去掉了 W
+    /// 共享者不止自己时复制一份新页帧替换，只剩自己时直接恢复写权限
+    /// 这不是 COW 页（真正的非法写）返回 EFAULT，复制时分配不到页帧返回 ENOMEM
+    pub fn cow_handle_fault(&mut self,vpn:VirNumber)->Result<(),Errno>{
+        if !self.is_cow_vpn(vpn) {
+            return Err(Errno::EFAULT);
+        }
+        let Some(old) = self.find_thisvpn_frame(vpn) else {
+            return Err(Errno::EFAULT);
+        };
+        // elf 段边界页可能被同一个地址空间内的多个 area 同时持有
+        let local_refs = self.areas.iter().filter(|area|{
+            area.frames.get(&vpn).map_or(false, |f| Arc::ptr_eq(f, &old))
+        }).count();
+
+        let flags = match self.table.find_pte_vpn(vpn) {
+            Some(pte) if pte.is_valid() => pte.flags() | PTEFlags::W | PTEFlags::A | PTEFlags::D,
+            _ => return Err(Errno::EFAULT),
+        };
+
+        // strong_count 里包含 old 这个临时引用
+        if Arc::strong_count(&old) - 1 <= local_refs {
+            // 只剩自己，直接恢复写权限
+            if let Some(pte) = self.table.find_pte_vpn(vpn) {
+                pte.set_flags(flags);
+            }
+        } else {
+            // old 还被引用着，不会被换出
+            let Some(new_frame) = self.alloc_user_frame() else {
+                error!("cow: out of memory vpn:{}", vpn.0);
+                return Err(Errno::ENOMEM);
+            };
+            let src_pa: PhysiAddr = old.ppn.into();
+            let dst_pa: PhysiAddr = new_frame.ppn.into();
+            unsafe {
+                core::ptr::copy_nonoverlapping(src_pa.0 as *const u8, dst_pa.0 as *mut u8, PAGE_SIZE);
+            }
+            if let Some(pte) = self.table.find_pte_vpn(vpn) {
+                *pte = PageTableEntry::new(new_frame.ppn.0, flags);
+            }
+            self.areas.iter_mut().for_each(|area|{
+                if area.frames.get(&vpn).map_or(false, |f| Arc::ptr_eq(f, &old)) {
+                    area.frames.insert(vpn, new_frame.clone());
+                }
+            });
+        }
+        unsafe { riscv::asm::sfence_vma_all(); }
+        Ok(())
+    }
+
+    ///内核即将通过物理地址写用户内存[start,start+len)，提前打破其中的 COW 共享页
+    /// 内核写不经过用户页表的W检查，不处理会把数据写进父子共享的页帧
+    /// 和 populate_range 一样，碰到要读文件的页先返回它
+    pub fn cow_prepare_write(&mut self,start:VirAddr,len:usize)->Result<Option<PageFill>,Errno>{
+        if len == 0 {
+            return Ok(None);
+        }
+        if let Some(fill) = self.populate_range(start, len)? {
+            return Ok(Some(fill));
+        }
+        let start_vpn = start.floor_down();
+        let end_vpn = VirAddr(start.0.saturating_add(len).saturating_sub(1)).floor_down();
+        for vpn in VirNumRange(start_vpn, end_vpn) {
+            let need_copy = match self.table.find_pte_vpn(vpn) {
+                Some(pte) => pte.is_valid() && !pte.flags().contains(PTEFlags::W),
+                None => false,
+            };
+            if need_copy {
+                self.cow_handle_fault(vpn)?;
+            }
+            // 内核写不经过 MMU，不会置 D 位，这里补上，共享文件映射才能被写回
+            if let Some(pte) = self.table.find_pte_vpn(vpn) {
+                if pte.is_valid() && pte.flags().contains(PTEFlags::W) {
+                    pte.set_isdirty();
+                }
+            }
+        }
+        Ok(None)
+    }
     
 
+    ///用户地址 addr 对应的物理地址，页必须已经映射并且用户可访问（带 U），读要求可读，写要求可写
+    /// 在 with_user_range 里调用，页已经补好
+    pub fn user_paddr(&mut self,addr:usize,write:bool)->Option<usize>{
+        let need = if write { PTEFlags::W } else { PTEFlags::R };
+        let ok = match self.table.find_pte_vpn(VirAddr(addr).floor_down()) {
+            Some(pte) => {
+                pte.is_valid()
+                    && pte.flags().contains(PTEFlags::U)
+                    && pte.flags().contains(need)
+            }
+            None => false,
+        };
+        if !ok {
+            return None;
+        }
+        self.table.translate(VirAddr(addr)).map(|pa| pa.0)
+    }
+
+    ///把 data 写到用户地址 addr 开始的内存，先打破 COW；范围里有不可写的页时返回 EFAULT，分配不到页帧时返回 ENOMEM
+    /// space 是要写的地址空间，调用时不能拿着它的锁
+    pub fn write_user_bytes(space:&SpinLock<MapSet>,addr:usize,data:&[u8])->Result<(),Errno>{
+        if addr.checked_add(data.len()).is_none() {
+            return Err(Errno::EFAULT);
+        }
+        Self::with_user_range(space, addr, data.len(), true, |set| {
+            let mut done = 0usize;
+            while done < data.len() {
+                let va = addr + done;
+                let Some(pa) = set.user_paddr(va, true) else {
+                    return Err(Errno::EFAULT);
+                };
+                let n = core::cmp::min(PAGE_SIZE - va % PAGE_SIZE, data.len() - done);
+                unsafe { core::ptr::copy_nonoverlapping(data[done..].as_ptr(), pa as *mut u8, n) };
+                done += n;
+            }
+            Ok(())
+        })
+    }
+
+    ///从用户地址 addr 读满 buf，范围里有不可读的页时返回 EFAULT，分配不到页帧时返回 ENOMEM
+    pub fn read_user_bytes(space:&SpinLock<MapSet>,addr:usize,buf:&mut [u8])->Result<(),Errno>{
+        if addr.checked_add(buf.len()).is_none() {
+            return Err(Errno::EFAULT);
+        }
+        Self::with_user_range(space, addr, buf.len(), false, |set| {
+            let mut done = 0usize;
+            while done < buf.len() {
+                let va = addr + done;
+                let Some(pa) = set.user_paddr(va, false) else {
+                    return Err(Errno::EFAULT);
+                };
+                let n = core::cmp::min(PAGE_SIZE - va % PAGE_SIZE, buf.len() - done);
+                unsafe { core::ptr::copy_nonoverlapping(pa as *const u8, buf[done..].as_mut_ptr(), n) };
+                done += n;
+            }
+            Ok(())
+        })
+    }
+
+    ///从用户地址 addr 读一个以 NUL 结尾的字符串（不含 NUL），最多看 max 字节
+    /// 逐页补缺页再扫描，碰到不可读的页返回 Err(false)，max 字节内没有 NUL 返回 Err(true)
+    pub fn read_user_cstr(space:&SpinLock<MapSet>,addr:usize,max:usize)->Result<Vec<u8>,bool>{
+        let mut out = Vec::new();
+        let mut va = addr;
+        while out.len() < max {
+            let n = core::cmp::min(PAGE_SIZE - va % PAGE_SIZE, max - out.len());
+            let found = Self::with_user_range(space, va, n, false, |set| {
+                let pa = set.user_paddr(va, false).ok_or(Errno::EFAULT)?;
+                let bytes = unsafe { core::slice::from_raw_parts(pa as *const u8, n) };
+                match bytes.iter().position(|&b| b == 0) {
+                    Some(pos) => {
+                        out.extend_from_slice(&bytes[..pos]);
+                        Ok(true)
+                    }
+                    None => {
+                        out.extend_from_slice(bytes);
+                        Ok(false)
+                    }
+                }
+            });
+            match found {
+                Ok(true) => return Ok(out),
+                Ok(false) => {}
+                Err(_) => return Err(false),
+            }
+            va = match va.checked_add(n) {
+                Some(v) => v,
+                None => return Err(false),
+            };
+        }
+        Err(true)
+    }
+
+    ///内核往用户地址 addr 写一个 i32（线程 tid 等）；地址不是合法的用户可写页时返回 false
+    pub fn write_user_i32(space:&SpinLock<MapSet>,addr:usize,val:i32)->bool{
+        if addr % core::mem::size_of::<i32>() != 0 {
+            return false;
+        }
+        Self::write_user_bytes(space, addr, &val.to_ne_bytes()).is_ok()
+    }
+
     ///获取当前memset的table临时借用
     pub fn get_table(&mut self)->&mut PageTable{
         &mut self.table    
@@ -524,138 +1133,59 @@ impl MapSet {
 
 
     ///查找这个vpn对应的area 给这个vpn的maparea分配物理帧，添加合法页表映射 前提是检查过确实有area包含vpn
-    pub fn findarea_allocFrame_and_setPte(&mut self,vpn:VirNumber){
+    /// 匿名页直接装好返回 None；ELF 段和文件映射的页只分好页帧返回 PageFill，调用方放开锁 load 之后 install_fill
+    /// 分配不到页帧时返回 ENOMEM，由调用方决定怎么处理（缺页时发 SIGKILL）
+    pub fn findarea_allocFrame_and_setPte(&mut self,vpn:VirNumber)->Result<Option<PageFill>,Errno>{
         let index = self.areas.iter().position(|area|{
             area.range.is_contain_thisvpn(vpn)
         }).expect("Logim ");
         let statr = self.areas[index].range.left_point();
         let re =self.find_thisvpn_frame(statr);
-        let area=&mut self.areas[index];
+        if self.areas[index].elf.is_some() {
+            return self.elf_prepare_fill(vpn).map(Some);
+        }
+        let mmap_info = self.areas[index].mmap.clone();
         debug!("Find Map Area! vpn:{} ",vpn.0);
 
-        if let Some(info) = &area.mmap {
+        if let Some(info) = &mmap_info {
             // mmap area: we do lazy allocation on page fault.
-            // - MAP_SHARED: pages may be shared across processes.
-            // - MAP_PRIVATE: pages are private to this process (no shared cache here).
-            if info.flags.contains(MmapFlags::SHARED) {
-                // Shared mapping:
-                // 1) Build a stable key identifying the backing object + page index.
-                //    - Anonymous MAP_SHARED: key is (mmap_id, page_index) so forked tasks can share.
-                //    - File-backed MAP_SHARED: key is (inode_num, file_page) so different fds/processes
-                //      mapping the same inode can reuse the same physical page.
-                // 2) Lookup a Weak<FramTracker> in global cache; if alive, reuse.
-                // 3) On cache miss, allocate a new frame.
-                //    - For file-backed: read page content from file into the freshly allocated frame.
-                //    - For anonymous: keep the frame zero-filled.
-                let page_index = vpn.0.saturating_sub(area.range.0.0) as u64;
-                let key = if info.flags.contains(MmapFlags::ANONYMOUS) {
-                    SharedMmapKey::Anon { mmap_id: info.id, page_index }
-                } else {
-                    let backing = match info.backing.as_ref() {
-                        Some(v) => v,
-                        None => {
-                            error!("mmap shared pagefault: missing backing file kill");
-                            TASK_MANAER.kail_current_task_and_run_next();
-                            return;
-                        }
-                    };
-                    let file_page = (info.offset / PAGE_SIZE) as u64 + page_index;
-                    let inode_num = match backing.stat() {
-                        Ok(st) => st.inode,
-                        Err(_e) => {
-                            error!("mmap shared pagefault: stat failed kill");
-                            TASK_MANAER.kail_current_task_and_run_next();
-                            return;
-                        }
-                    };
-                    SharedMmapKey::File {
-                        inode_num,
-                        file_page,
-                    }
-                };
-
-                let shared = SHARED_MMAP_PAGES.lock();
-                let existing = shared.get(&key).and_then(|w| w.upgrade());
-                drop(shared);
-
+            let page_index = info.page_index(self.areas[index].range.0, vpn);
+            if info.flags.contains(MmapFlags::SHARED) && info.flags.contains(MmapFlags::ANONYMOUS) {
+                // Anonymous MAP_SHARED: key is (mmap_id, page_index) so forked tasks can share.
+                // Lookup a Weak<FramTracker> in global cache; if alive, reuse, else keep a fresh zero-filled frame.
+                let key = SharedMmapKey::Anon { mmap_id: info.id, page_index: page_index as u64 };
+                let existing = SHARED_MMAP_PAGES.lock().get(&key).and_then(|w| w.upgrade());
                 let frame = match existing {
                     Some(f) => f,
                     None => {
-                        let f = Arc::new(alloc_frame().expect("Memory Alloc Failed By shared mmap"));
-                        
-                        if !info.flags.contains(MmapFlags::ANONYMOUS) {
-                            // File-backed MAP_SHARED: populate page content at first touch.
-                            // Later faults in other processes will reuse this frame via the shared cache.
-                            let file = match info.backing.as_ref() {
-                                Some(v) => v,
-                                None => {
-                                    error!("mmap shared pagefault: missing backing file kill");
-                                    TASK_MANAER.kail_current_task_and_run_next();
-                                    return;
-                                }
-                            };
-                            let file_off = info.offset.saturating_add((page_index as usize).saturating_mul(PAGE_SIZE));
-                            let pa: PhysiAddr = f.ppn.into();
-                            let buf = unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut u8, PAGE_SIZE) };
-                            match file.read_at(file_off, buf) {
-                                Ok(n) => {
-                                    if n < PAGE_SIZE {
-                                        buf[n..].fill(0);
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("mmap shared pagefault: read_at failed off={} err={} kill", file_off, e);
-                                    TASK_MANAER.kail_current_task_and_run_next();
-                                    return;
-                                }
-                            }
-                        }
-
+                        let Some(f) = self.alloc_user_frame() else {
+                            error!("mmap shared pagefault: out of memory kill");
+                            return Err(Errno::ENOMEM);
+                        };
                         // Insert as Weak to avoid keeping frames alive forever;
                         // cache entry will naturally expire when the last Arc is dropped.
-                        let mut shared = SHARED_MMAP_PAGES.lock();
-                        shared.insert(key, Arc::downgrade(&f));
+                        SHARED_MMAP_PAGES.lock().insert(key, Arc::downgrade(&f));
                         f
                     }
                 };
-
-                // Map (vpn -> ppn) and remember the Arc in this MapArea.frames.
-                area.map_one_with_frame(vpn, frame, &mut self.table);
-                return;
+                self.areas[index].map_one_with_frame(vpn, frame, &mut self.table);
+                return Ok(None);
             }
 
-            if info.flags.contains(MmapFlags::PRIVATE) && !info.flags.contains(MmapFlags::ANONYMOUS) {
-                // Private, file-backed mapping:
-                // allocate a fresh frame for this process, then read file content into it.
-                // This does NOT use the global shared cache.
-                let file = match info.backing.as_ref() {
-                    Some(v) => v,
-                    None => {
-                        error!("mmap private pagefault: missing backing file kill");
-                        TASK_MANAER.kail_current_task_and_run_next();
-                        return;
-                    }
+            if !info.flags.contains(MmapFlags::ANONYMOUS) {
+                // File-backed mapping: the file is read in PageFill::load without the address space lock.
+                // - MAP_SHARED: may end up with the page cache frame or a frame another process already read.
+                // - MAP_PRIVATE: a fresh frame for this process, not shared with anyone.
+                let Some(file) = info.backing.clone() else {
+                    error!("mmap pagefault: missing backing file kill");
+                    return Err(Errno::EIO);
                 };
-
-                let frame = Arc::new(alloc_frame().expect("Memory Alloc Failed By private mmap"));
-                let file_off = info.offset.saturating_add(((vpn.0.saturating_sub(area.range.0.0)) as usize).saturating_mul(PAGE_SIZE));
-                let pa: PhysiAddr = frame.ppn.into();
-                let buf = unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut u8, PAGE_SIZE) };
-                match file.read_at(file_off, buf) {
-                    Ok(n) => {
-                        if n < PAGE_SIZE {
-                            buf[n..].fill(0);
-                        }
-                    }
-                    Err(e) => {
-                        error!("mmap private pagefault: read_at failed off={} err={} kill", file_off, e);
-                        TASK_MANAER.kail_current_task_and_run_next();
-                        return;
-                    }
-                }
-
-                area.map_one_with_frame(vpn, frame, &mut self.table);
-                return;
+                let Some(frame) = self.alloc_user_frame() else {
+                    error!("mmap file pagefault: out of memory kill");
+                    return Err(Errno::ENOMEM);
+                };
+                let shared = info.flags.contains(MmapFlags::SHARED);
+                return Ok(Some(PageFill { vpn, frame, source: FillSource::Mmap { id: info.id, file, page: page_index, shared } }));
             }
         }
 
@@ -664,11 +1194,171 @@ impl MapSet {
         // Fallback:
         // - Anonymous MAP_PRIVATE mmap (or any other mmap area not handled above): allocate a fresh frame.
         // - Non-mmap areas: should usually already be mapped; but if we get here, keep old behavior.
-        area.map_one(vpn, &mut self.table,re);
+        if self.areas[index].map_type == MapType::Maped && !self.table.is_maped(vpn) {
+            let Some(frame) = self.alloc_user_frame() else {
+                error!("mmap pagefault: out of memory kill");
+                return Err(Errno::ENOMEM);
+            };
+            self.areas[index].map_one_with_frame(vpn, frame, &mut self.table);
+            return Ok(None);
+        }
+        self.areas[index].map_one(vpn, &mut self.table,re);
+        Ok(None)
+    }
+
+
+
+
+    pub fn is_swapped_vpn(&self, vpn: VirNumber) -> bool {
+        self.swapped.contains(&vpn)
+    }
+
+    ///分配一个用户页帧。启用交换分区时剩余页帧不多于 SWAP_RESERVE_FRAMES 就先按全局时钟换出页（可能是别的地址空间的），
+    /// 给页表、内核栈等不能换出的分配留余量
+    fn alloc_user_frame(&mut self)->Option<Arc<FramTracker>>{
+        while swap_enabled() && frame_remain() <= SWAP_RESERVE_FRAMES {
+            if !swap_out_global(self) {
+                break;
+            }
+        }
+        alloc_frame().map(Arc::new)
+    }
+
+    ///包成任务持有的地址空间，挂到全局换出时钟上
+    pub fn into_shared(self)->Arc<SpinLock<MapSet>>{
+        let space = Arc::new(SpinLock::new(self));
+        swap_clock_register(&space);
+        space
+    }
+
+    ///全局时钟在本地址空间里的一段：从 swap_hand 往后扫只被自己持有的用户私有页，
+    /// A 位为1的清掉给第二次机会，遇到 A 位为0的就换出；扫到最后也没有就把 swap_hand 拨回开头
+    pub fn clock_scan(&mut self)->ClockScan{
+        // 共享映射和 fork 共享的页（strong_count > 1）不换出
+        let mut candidates: Vec<VirNumber> = Vec::new();
+        for area in self.areas.iter() {
+            if area.map_type != MapType::Maped || !area.flags.contains(MapAreaFlags::U) || area.is_prot_none() {
+                continue;
+            }
+            if area.mmap.as_ref().map_or(false, |info| info.flags.contains(MmapFlags::SHARED)) {
+                continue;
+            }
+            for (vpn, frame) in area.frames.iter() {
+                if Arc::strong_count(frame) == 1 && vpn.0 >= self.swap_hand.0 {
+                    candidates.push(*vpn);
+                }
+            }
+        }
+        candidates.sort();
+
+        let mut victim = None;
+        for vpn in candidates {
+            let Some(pte) = self.table.find_pte_vpn(vpn) else {
+                continue;
+            };
+            if !pte.is_valid() {
+                continue;
+            }
+            if pte.flags().contains(PTEFlags::A) {
+                let mut flags = pte.flags();
+                flags.remove(PTEFlags::A);
+                pte.set_flags(flags);
+                continue;
+            }
+            victim = Some(vpn);
+            break;
+        }
+        unsafe { riscv::asm::sfence_vma_all(); }
+        let Some(vpn) = victim else {
+            self.swap_hand = VirNumber(0);
+            return ClockScan::Wrapped;
+        };
+        self.swap_hand = VirNumber(vpn.0 + 1);
+        if self.swap_out(vpn) { ClockScan::Evicted } else { ClockScan::Failed }
     }
 
+    ///把 vpn 这一页写到交换分区，pte 换成 swap pte，页帧回收
+    /// 页可能属于正在别的 hart 上运行的线程：先让 pte 失效并刷掉所有 hart 的 TLB，再拷贝页内容
+    fn swap_out(&mut self,vpn:VirNumber)->bool{
+        let Some(frame) = self.find_thisvpn_frame(vpn) else {
+            return false;
+        };
+        let Some(pte) = self.table.find_pte_vpn(vpn) else {
+            return false;
+        };
+        let flags = pte.flags();
+        pte.set_inValid();
+        remote_sfence_vma_all();
+
+        let pa: PhysiAddr = frame.ppn.into();
+        let page = unsafe { core::slice::from_raw_parts(pa.0 as *const u8, PAGE_SIZE) };
+        let Some(slot) = swap_write_page(page) else {
+            // 没换出去，恢复原来的映射
+            if let Some(pte) = self.table.find_pte_vpn(vpn) {
+                *pte = PageTableEntry::new(frame.ppn.0, flags);
+            }
+            return false;
+        };
+        self.table.set_swap(vpn, slot);
+        drop(frame);
+        self.areas.iter_mut().for_each(|area|{
+            if area.range.is_contain_thisvpn(vpn) {
+                area.frames.remove(&vpn); // 最后一个 Arc，页帧回收
+            }
+        });
+        self.swapped.insert(vpn);
+        unsafe { riscv::asm::sfence_vma_all(); }
+        debug!("swap out vpn:{} -> slot:{}", vpn.0, slot);
+        true
+    }
 
+    ///换入：vpn 是换出页时分配页帧从交换分区读回，按 area 权限重新映射并释放槽位
+    /// 不是换出页返回 EFAULT，分配不到页帧返回 ENOMEM，读交换分区失败返回 EIO
+    pub fn swap_in(&mut self,vpn:VirNumber)->Result<(),Errno>{
+        if !self.swapped.contains(&vpn) {
+            return Err(Errno::EFAULT);
+        }
+        let slot = match self.table.find_pte_vpn(vpn) {
+            Some(pte) if pte.is_swap() => pte.swap_slot(),
+            _ => return Err(Errno::EFAULT),
+        };
+        let Some(index) = self.areas.iter().position(|area| area.range.is_contain_thisvpn(vpn)) else {
+            return Err(Errno::EFAULT);
+        };
+        let Some(frame) = self.alloc_user_frame() else {
+            error!("swap in: no frame for vpn:{}", vpn.0);
+            return Err(Errno::ENOMEM);
+        };
+        let pa: PhysiAddr = frame.ppn.into();
+        let page = unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut u8, PAGE_SIZE) };
+        if let Err(e) = swap_read_page(slot, page) {
+            error!("swap in: read slot:{} failed err={}", slot, e);
+            return Err(Errno::EIO);
+        }
+        self.swapped.remove(&vpn);
+        swap_free(slot);
+        if let Some(pte) = self.table.find_pte_vpn(vpn) {
+            pte.set_inValid();
+        }
+        self.areas[index].map_one_with_frame(vpn, frame, &mut self.table);
+        unsafe { riscv::asm::sfence_vma_all(); }
+        debug!("swap in vpn:{} <- slot:{}", vpn.0, slot);
+        Ok(())
+    }
 
+    ///释放 [start,end] 内换出页的槽位，并清掉 swap pte
+    fn free_swapped_range(&mut self,start:VirNumber,end:VirNumber){
+        let vpns: Vec<VirNumber> = self.swapped.range(start..=end).copied().collect();
+        for vpn in vpns {
+            self.swapped.remove(&vpn);
+            if let Some(pte) = self.table.find_pte_vpn(vpn) {
+                if pte.is_swap() {
+                    swap_free(pte.swap_slot());
+                }
+                pte.set_inValid();
+            }
+        }
+    }
 
     fn range_is_free(&self, start: usize, len: usize) -> bool {
         if len == 0 {
@@ -689,10 +1379,10 @@ impl MapSet {
         
         // Minimal policy:
         // - start searching from a page-aligned brk
-        // - keep a gap below TRAP_CONTEXT
+        // - keep below the thread trap context slots
         let cur_align_page_viraddr:VirAddr = VirAddr(len).floor_up().into();
         let mut cur = cur_align_page_viraddr.0;
-        let upper = TRAP_CONTEXT_ADDR.saturating_sub(PAGE_SIZE);
+        let upper = THREAD_TRAP_CONTEXT_BOTTOM;
 
         while cur.saturating_add(len) <= upper {
             if self.range_is_free(cur, len) {
@@ -706,84 +1396,84 @@ impl MapSet {
 
     ///mmap系统调用，创建一个有vpnrange的maparea，没有实际映射条目和物理页帧的maparea 
     /// Linux/POSIX: mmap(addr, len, prot, flags, fd, offset)
-    /// 返回：成功返回映射起始地址；失败返回 -1
+    /// 返回：成功返回映射起始地址；失败返回负的 errno
     pub fn mmap(&mut self, addr: VirAddr, len: usize, prot: usize, flags: usize, fd: i32, offset: usize, fd_backing: Option<Arc<dyn File>>) -> isize {
         //warn!("enter memset mmap");
         if len == 0 {
-            return -1;
+            return -Errno::EINVAL;
         }
         if offset % PAGE_SIZE != 0 {
-            return -1;
+            return -Errno::EINVAL;
         }
 
         // Reject integer overflow on offset+len (file-backed) and on addr+len (fixed address).
         if offset.checked_add(len).is_none() {
-            return -1;
+            return -Errno::EOVERFLOW;
         }
 
         let prot = match MmapProt::from_bits(prot) {
             Some(v) => v,
-            None => return -1,
+            None => return -Errno::EINVAL,
         };
         let flags = match MmapFlags::from_bits(flags) {
             Some(v) => v,
-            None => return -1,
+            None => return -Errno::EINVAL,
         };
 
         let is_private = flags.contains(MmapFlags::PRIVATE);
         let is_shared = flags.contains(MmapFlags::SHARED);
         if !is_private && !is_shared {
-            return -1;
+            return -Errno::EINVAL;
         }
         if is_private && is_shared {
-            return -1;
+            return -Errno::EINVAL;
         }
 
         // Support Anonymous and file-backed mmap
         if flags.contains(MmapFlags::ANONYMOUS) {
             if fd != -1 {
-                return -1;
+                return -Errno::EINVAL;
             }
         } else {
             // file-backed
             if fd < 0 {
-                return -1;
+                return -Errno::EBADF;
             }
             if offset % PAGE_SIZE != 0 {
-                return -1;
+                return -Errno::EINVAL;
             }
     
             let _backing = match &fd_backing {
                 Some(v) => v.clone(),
-                _ => return -1,
+                _ => return -Errno::EBADF,
             };
         }
 
         let map_len = match len.checked_add(PAGE_SIZE - 1) {
             Some(v) => v & !(PAGE_SIZE - 1),
-            None => return -1,
+            None => return -Errno::ENOMEM,
         };
         let is_fixed = flags.contains(MmapFlags::FIXED);
 
-        // Keep a gap below TRAP_CONTEXT.
-        let upper = TRAP_CONTEXT_ADDR.saturating_sub(PAGE_SIZE);
+        // Keep below the thread trap context slots.
+        let upper = THREAD_TRAP_CONTEXT_BOTTOM;
 
         let map_start: usize;
         if is_fixed {
             // MAP_FIXED: force address, and on overlap we must unmap then map.
             if addr.0 % PAGE_SIZE != 0 {
-                return -1;
+                return -Errno::EINVAL;
             }
             if addr.0.checked_add(map_len).is_none() {
-                return -1;
+                return -Errno::ENOMEM;
             }
             if addr.0.saturating_add(map_len) > upper {
-                return -1;
+                return -Errno::ENOMEM;
             }
             map_start = addr.0;
             if !self.range_is_free(map_start, map_len) {
                 if self.unmap_range(VirAddr(map_start), map_len) != 0 {
-                    return -1;
+                    return -Errno::ENOMEM;
                 }
             }
         } else {
@@ -795,13 +1485,13 @@ impl MapSet {
                 } else {
                     map_start = match self.find_free_range(map_len) {
                         Some(v) => v,
-                        None => return -1,
+                        None => return -Errno::ENOMEM,
                     };
                 }
             } else {
                 map_start = match self.find_free_range(map_len) {
                     Some(v) => v,
-                    None => return -1,
+                    None => return -Errno::ENOMEM,
                 };
             }
         }
@@ -838,39 +1528,44 @@ impl MapSet {
             } else {
                 match fd_backing {
                     Some(v) => Some(v),
-                    _ => return -1,
+                    _ => return -Errno::EBADF,
                 }
             },
             offset,
         };
 
 
-        self.add_area(range, MapType::Maped, mapflags, None, Some(info));
+        if !self.add_area(range, MapType::Maped, mapflags, None, Some(info)) {
+            return -Errno::ENOMEM;
+        }
         
         map_start as isize
     }
 
     ///unmap系统调用,取消映射一个[start,end]范围的虚拟页面，并且设置对应页表项不合法
-    /// startVAR mmap起始地址 size:映射长度(会被裁剪，小于一个页取消映射一个页,不满一个页补全一个页) 返回-1代表失败 0代表成功 
+    /// startVAR mmap起始地址 size:映射长度(会被裁剪，小于一个页取消映射一个页,不满一个页补全一个页) 失败返回负的 errno，0代表成功 
     pub fn unmap_range(&mut self,startVAR:VirAddr,size:usize,)->isize{
         if size == 0 {
-            return -1;
+            return -Errno::EINVAL;
         }
         let start_vpn: VirNumber = startVAR.floor_down();
         let end_vpn: VirNumber = VirAddr(startVAR.0.saturating_add(size).saturating_sub(1)).floor_down();
         let range: VirNumRange = VirNumRange(start_vpn, end_vpn);
 
         if !self.AallArea_Iscontain_thisVpn_plus(range) {
-            return -1;
+            return -Errno::EINVAL;
         }
 
         let touches_non_mmap = self.areas.iter().any(|area| {
             area.mmap.is_none() && !area.range.is_contain_thisvpnRange(range).is_empty()
         });
         if touches_non_mmap {
-            return -1;
+            return -Errno::EINVAL;
         }
 
+        // 范围内换出的页直接释放槽位
+        self.free_swapped_range(start_vpn, end_vpn);
+
         let mut new_areas: Vec<MapArea> = Vec::with_capacity(self.areas.len());
         let mut any_touched = false; //是否有交集
 
@@ -891,8 +1586,6 @@ impl MapSet {
                 }
             };
 
-            let area_start = area.range.0;
-
             let unmap_start = if range.0.0 > area.range.0.0 { range.0 } else { area.range.0 };
             let unmap_end = if range.1.0 < area.range.1.0 { range.1 } else { area.range.1 };
             debug!("Area Before split:{:?} \n",area.range);
@@ -903,63 +1596,19 @@ impl MapSet {
                 new_areas.push(x);
             });
             let mut need_munmap = split_aread_vec.1;
+            let info = need_munmap.mmap.clone().unwrap_or(info);
 
             if info.flags.contains(MmapFlags::SHARED) && info.flags.contains(MmapFlags::ANONYMOUS) {
                 let mut shared = SHARED_MMAP_PAGES.lock();
                 for vpn in VirNumRange(unmap_start, unmap_end) {
-                    let page_index = vpn.0.saturating_sub(need_munmap.range.0.0) as u64;
+                    let page_index = info.page_index(need_munmap.range.0, vpn) as u64;
                     let key = SharedMmapKey::Anon { mmap_id: info.id, page_index };
                     shared.remove(&key);
                 }
             }
 
-            //处理有fd情况
-            if  info.flags.contains(MmapFlags::SHARED) && !info.flags.contains(MmapFlags::ANONYMOUS){
-                let file_backing = match info.backing.as_ref() {
-                    Some(v) => v.clone(),
-                    None => {
-                        panic!("This area should be have a file backend, please check mmap.Will panic every mmap will break file!");
-                    }
-                };
-                for vpn in VirNumRange(unmap_start, unmap_end) {
-                    // Only write back pages that were actually faulted-in.
-                    let Some(frame) = need_munmap.frames.get(&vpn).cloned() else {
-                        continue;
-                    };
-
-                    // Keep consistent with pagefault shared key:
-                    // file_page = (offset / PAGE_SIZE) + (vpn - area_start)
-                    let page_index = vpn.0.saturating_sub(area_start.0) as u64;
-                    let file_page = (info.offset / PAGE_SIZE) as u64 + page_index;
-                    let inode_num = match file_backing.stat() {
-                        Ok(st) => st.inode,
-                        Err(_e) => {
-                            error!("munmap: MAP_SHARED stat failed kill");
-                            TASK_MANAER.kail_current_task_and_run_next();
-                            return -1;
-                        }
-                    };
-                    let key = SharedMmapKey::File { inode_num, file_page };
-
-                    // Write the whole page back.
-                    let file_off = info.offset.saturating_add((page_index as usize).saturating_mul(PAGE_SIZE));
-                    let pa: PhysiAddr = frame.ppn.into();
-                    let buf = unsafe { core::slice::from_raw_parts(pa.0 as *const u8, PAGE_SIZE) };
-                    match file_backing.write_at(file_off, buf) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("munmap: MAP_SHARED write_back failed off={} err={} kill", file_off, e);
-                            TASK_MANAER.kail_current_task_and_run_next();
-                            return -1;
-                        }
-                    }
-
-                    // Remove cache entry for this page.
-                    let mut shared = SHARED_MMAP_PAGES.lock();
-                    shared.remove(&key);
-                }
-            }
-
+            //处理有fd情况：记下被改过的页，放开锁之后写回
+            Self::shared_file_writeback(&need_munmap, &mut self.table, unmap_start, unmap_end, &mut self.writeback);
 
             for vpn in VirNumRange(unmap_start, unmap_end) {
                 if let Some(pte) = self.table.find_pte_vpn(vpn) {
@@ -970,14 +1619,320 @@ impl MapSet {
             for vpn in VirNumRange(unmap_start, unmap_end) {
                 let _ = need_munmap.frames.remove(&vpn);
             }
+            if info.flags.contains(MmapFlags::SHARED) && !info.flags.contains(MmapFlags::ANONYMOUS) {
+                // 其它进程可能还映射着同一页，只清理已经没人用的缓存项
+                SHARED_MMAP_PAGES.lock().retain(|_, w| w.strong_count() > 0);
+            }
             // Fully covered: drop the area.
             continue;
         }
 
         self.areas = new_areas;
-        if any_touched { 0 } else { -1 }
+        if any_touched { 0 } else { -Errno::EINVAL }
+    }
+
+
+    ///共享文件映射：收集 area 中 [start,end] 内被写过（PTE D 位）的页并清掉 D 位
+    /// 这里拿着地址空间的锁，不碰文件；放开锁之后由 FilePage::write_back 写回
+    fn shared_file_writeback(area:&MapArea,table:&mut PageTable,start:VirNumber,end:VirNumber,out:&mut Vec<FilePage>){
+        let Some(info) = area.mmap.as_ref() else {
+            return;
+        };
+        if !info.flags.contains(MmapFlags::SHARED) || info.flags.contains(MmapFlags::ANONYMOUS) {
+            return;
+        }
+        let Some(file) = info.backing.as_ref() else {
+            return;
+        };
+        let mut dirty_any = false;
+        for (vpn, frame) in area.frames.range(start..=end) {
+            let Some(pte) = table.find_pte_vpn(*vpn) else {
+                continue;
+            };
+            if !pte.is_valid() || !pte.flags().contains(PTEFlags::D) {
+                continue;
+            }
+            pte.set_flags(pte.flags() - PTEFlags::D);
+            dirty_any = true;
+            out.push(FilePage { file: file.clone(), page: info.page_index(area.range.0, *vpn), frame: frame.clone() });
+        }
+        if dirty_any {
+            unsafe { asm!("sfence.vma") };
+        }
+    }
+
+    ///munmap/mremap/mmap(MAP_FIXED) 收集的要写回的页，调用方放开锁之后交给 FilePage::write_back
+    pub fn take_writeback(&mut self)->Vec<FilePage>{
+        core::mem::take(&mut self.writeback)
+    }
+
+    ///msync系统调用：收集共享文件映射要写回的页；MS_INVALIDATE 时还要重新从文件读入不走页缓存的页
+    /// 文件 I/O 由调用方放开锁之后 MsyncWork::run 完成，参数不对返回负的 errno
+    pub fn msync(&mut self,addr:VirAddr,len:usize,flags:usize)->Result<MsyncWork,isize>{
+        if addr.0 % PAGE_SIZE != 0 {
+            return Err(-Errno::EINVAL);
+        }
+        let Some(flags) = MsyncFlags::from_bits(flags) else {
+            return Err(-Errno::EINVAL);
+        };
+        if flags.contains(MsyncFlags::ASYNC) && flags.contains(MsyncFlags::SYNC) {
+            return Err(-Errno::EINVAL);
+        }
+        let mut work = MsyncWork { writeback: Vec::new(), flush: Vec::new(), invalidate: Vec::new() };
+        if len == 0 {
+            return Ok(work);
+        }
+        let start_vpn = addr.floor_down();
+        let end_vpn = VirAddr(addr.0.saturating_add(len).saturating_sub(1)).floor_down();
+        // 整个范围都必须被映射
+        for vpn in VirNumRange(start_vpn, end_vpn) {
+            if !self.areas.iter().any(|a| a.range.is_contain_thisvpn(vpn)) {
+                return Err(-Errno::ENOMEM);
+            }
+        }
+
+        for area in self.areas.iter() {
+            let inter = area.range.is_contain_thisvpnRange(VirNumRange(start_vpn, end_vpn));
+            if inter.is_empty() {
+                continue;
+            }
+            let start = VirNumber(start_vpn.0.max(area.range.0.0));
+            let end = VirNumber(end_vpn.0.min(area.range.1.0));
+            Self::shared_file_writeback(area, &mut self.table, start, end, &mut work.writeback);
+
+            let Some(info) = area.mmap.as_ref() else {
+                continue;
+            };
+            if !info.flags.contains(MmapFlags::SHARED) || info.flags.contains(MmapFlags::ANONYMOUS) {
+                continue;
+            }
+            let Some(file) = info.backing.as_ref() else {
+                continue;
+            };
+            if flags.contains(MsyncFlags::SYNC) {
+                work.flush.push(file.clone());
+            }
+            if !flags.contains(MsyncFlags::INVALIDATE) {
+                continue;
+            }
+            for (vpn, frame) in area.frames.range(start..=end) {
+                work.invalidate.push(FilePage { file: file.clone(), page: info.page_index(area.range.0, *vpn), frame: frame.clone() });
+            }
+        }
+        Ok(work)
+    }
+
+    ///mprotect系统调用：分割 area 并改写范围内已映射页的 pte 权限
+    /// 失败返回负的 errno，0代表成功
+    pub fn mprotect(&mut self,addr:VirAddr,len:usize,prot:usize)->isize{
+        if addr.0 % PAGE_SIZE != 0 {
+            return -Errno::EINVAL;
+        }
+        let Some(prot) = MmapProt::from_bits(prot) else {
+            return -Errno::EINVAL;
+        };
+        if len == 0 {
+            return 0;
+        }
+        let start_vpn = addr.floor_down();
+        let end_vpn = VirAddr(addr.0.saturating_add(len).saturating_sub(1)).floor_down();
+        // 整个范围都必须是用户 area
+        for vpn in VirNumRange(start_vpn, end_vpn) {
+            if !self.areas.iter().any(|a| a.range.is_contain_thisvpn(vpn) && a.flags.contains(MapAreaFlags::U)) {
+                return -Errno::ENOMEM;
+            }
+        }
+
+        let mut new_flags = MapAreaFlags::U;
+        if prot.contains(MmapProt::READ) {
+            new_flags |= MapAreaFlags::R;
+        }
+        if prot.contains(MmapProt::WRITE) {
+            // riscv 不允许只写不读的 pte
+            new_flags |= MapAreaFlags::R | MapAreaFlags::W;
+        }
+        if prot.contains(MmapProt::EXEC) {
+            new_flags |= MapAreaFlags::X;
+        }
+
+        let mut new_areas: Vec<MapArea> = Vec::with_capacity(self.areas.len() + 2);
+        for area in self.areas.drain(..) {
+            if area.range.is_contain_thisvpnRange(VirNumRange(start_vpn, end_vpn)).is_empty() {
+                new_areas.push(area);
+                continue;
+            }
+            let lo = VirNumber(start_vpn.0.max(area.range.0.0));
+            let hi = VirNumber(end_vpn.0.min(area.range.1.0));
+            let (rest, mut mid) = Self::split_area_by_range(area, VirNumRange(lo, hi));
+            new_areas.extend(rest);
+
+            mid.flags = new_flags;
+            if let Some(info) = mid.mmap.as_mut() {
+                info.prot = prot;
+            }
+            let shared = mid.mmap.as_ref().map_or(false, |info| info.flags.contains(MmapFlags::SHARED));
+            let prot_none = mid.is_prot_none();
+            for (vpn, frame) in mid.frames.iter() {
+                let Some(pte) = self.table.find_pte_vpn(*vpn) else {
+                    continue;
+                };
+                if prot_none {
+                    if pte.is_valid() {
+                        pte.set_inValid();
+                    }
+                    continue;
+                }
+                let mut flags: PTEFlags = new_flags.into();
+                if pte.is_valid() {
+                    flags |= pte.flags() & (PTEFlags::A | PTEFlags::D);
+                }
+                // 还和别人（fork）共享的私有页先不给 W，第一次写时走 COW
+                if flags.contains(PTEFlags::W) && !shared && Arc::strong_count(frame) > 1 {
+                    flags.remove(PTEFlags::W | PTEFlags::D);
+                }
+                *pte = PageTableEntry::new(frame.ppn.0, flags | PTEFlags::V);
+            }
+            new_areas.push(mid);
+        }
+        self.areas = new_areas;
+        unsafe { asm!("sfence.vma") };
+        0
     }
 
+    ///mremap系统调用：扩大、缩小或（MREMAP_MAYMOVE）搬移一个 mmap area，页帧和文件后备保持不变
+    /// 返回新的起始地址，失败返回负的 errno
+    pub fn mremap(&mut self,old_addr:VirAddr,old_size:usize,new_size:usize,flags:usize,new_addr:VirAddr)->isize{
+        const MREMAP_MAYMOVE: usize = 1;
+        const MREMAP_FIXED: usize = 2;
+        if old_addr.0 % PAGE_SIZE != 0 || new_size == 0 || old_size == 0 {
+            return -Errno::EINVAL;
+        }
+        if flags & !(MREMAP_MAYMOVE | MREMAP_FIXED) != 0 {
+            return -Errno::EINVAL;
+        }
+        let may_move = flags & MREMAP_MAYMOVE != 0;
+        let fixed = flags & MREMAP_FIXED != 0;
+        if fixed && (!may_move || new_addr.0 % PAGE_SIZE != 0) {
+            return -Errno::EINVAL;
+        }
+        let old_pages = (old_size + PAGE_SIZE - 1) / PAGE_SIZE;
+        let new_pages = match new_size.checked_add(PAGE_SIZE - 1) {
+            Some(v) => v / PAGE_SIZE,
+            None => return -Errno::EINVAL,
+        };
+        let start_vpn = old_addr.floor_down();
+        let old_range = VirNumRange(start_vpn, VirNumber(start_vpn.0 + old_pages - 1));
+
+        // 旧范围必须落在同一个 mmap area 里
+        let Some(index) = self.areas.iter().position(|a| {
+            a.mmap.is_some() && a.range.0.0 <= old_range.0.0 && old_range.1.0 <= a.range.1.0
+        }) else {
+            return -Errno::EFAULT;
+        };
+
+        // 原地缩小：直接 munmap 尾部
+        if !fixed && new_pages <= old_pages {
+            if new_pages < old_pages {
+                let tail = VirAddr::from(VirNumber(start_vpn.0 + new_pages));
+                if self.unmap_range(tail, (old_pages - new_pages) * PAGE_SIZE) != 0 {
+                    return -Errno::EINVAL;
+                }
+            }
+            return old_addr.0 as isize;
+        }
+
+        let upper = THREAD_TRAP_CONTEXT_BOTTOM;
+        // 原地扩大：旧范围是 area 的尾部且后面一段空闲
+        if !fixed && old_range.1.0 == self.areas[index].range.1.0 {
+            let grow_start = (old_range.1.0 + 1) * PAGE_SIZE;
+            let grow_len = (new_pages - old_pages) * PAGE_SIZE;
+            if grow_start.saturating_add(grow_len) <= upper && self.range_is_free(grow_start, grow_len) {
+                self.areas[index].range.1 = VirNumber(start_vpn.0 + new_pages - 1);
+                return old_addr.0 as isize;
+            }
+        }
+        if !may_move {
+            return -Errno::ENOMEM;
+        }
+
+        let map_len = new_pages * PAGE_SIZE;
+        let target = if fixed {
+            if new_addr.0.saturating_add(map_len) > upper {
+                return -Errno::EINVAL;
+            }
+            let new_range = VirNumRange(new_addr.floor_down(), VirNumber(new_addr.0 / PAGE_SIZE + new_pages - 1));
+            if !old_range.is_contain_thisvpnRange(new_range).is_empty() {
+                return -Errno::EINVAL;
+            }
+            if !self.range_is_free(new_addr.0, map_len) && self.unmap_range(new_addr, map_len) != 0 {
+                return -Errno::ENOMEM;
+            }
+            new_addr.0
+        } else {
+            match self.find_free_range(map_len) {
+                Some(v) => v,
+                None => return -Errno::ENOMEM,
+            }
+        };
+
+        // 把旧范围单独拆出来再整体搬走
+        let Some(index) = self.areas.iter().position(|a| a.range.is_contain_thisvpn(start_vpn)) else {
+            return -Errno::EFAULT;
+        };
+        let area = self.areas.remove(index);
+        let (rest, old) = Self::split_area_by_range(area, old_range);
+        self.areas.extend(rest);
+
+        let new_start = VirAddr(target).floor_down();
+        let mut moved = MapArea::new(VirNumRange(new_start, VirNumber(new_start.0 + new_pages - 1)), old.flags, old.map_type);
+        moved.mmap = old.mmap.clone();
+        moved.elf = old.elf.clone();
+        let keep = core::cmp::min(old_pages, new_pages);
+        // 缩小时丢掉的尾页可能是共享文件映射的脏页，记下来放开锁之后写回
+        Self::shared_file_writeback(&old, &mut self.table, old_range.0, old_range.1, &mut self.writeback);
+        for (vpn, frame) in old.frames.iter() {
+            let Some(pte) = self.table.find_pte_vpn(*vpn) else {
+                continue;
+            };
+            let valid = pte.is_valid();
+            let flags = PTEFlags::from_bits_truncate(pte.flags().bits());
+            pte.set_inValid();
+            let idx = vpn.0 - old_range.0.0;
+            if idx >= keep {
+                continue;
+            }
+            let new_vpn = VirNumber(new_start.0 + idx);
+            moved.frames.insert(new_vpn, frame.clone());
+            if valid {
+                self.table.map(new_vpn, frame.ppn, flags);
+            }
+        }
+        // 换出的页把 swap pte 搬到新位置，丢掉的尾页释放槽位
+        let swapped: Vec<VirNumber> = self.swapped.range(old_range.0..=old_range.1).copied().collect();
+        for vpn in swapped {
+            self.swapped.remove(&vpn);
+            let Some(pte) = self.table.find_pte_vpn(vpn) else {
+                continue;
+            };
+            if !pte.is_swap() {
+                continue;
+            }
+            let slot = pte.swap_slot();
+            pte.set_inValid();
+            let idx = vpn.0 - old_range.0.0;
+            if idx >= keep {
+                swap_free(slot);
+                continue;
+            }
+            let new_vpn = VirNumber(new_start.0 + idx);
+            self.table.set_swap(new_vpn, slot);
+            self.swapped.insert(new_vpn);
+        }
+        drop(old);
+        self.areas.push(moved);
+        unsafe { asm!("sfence.vma") };
+        target as isize
+    }
 
     /// 分割一个area成二/三个不同area noneed,need
     pub fn split_area_by_range(area:MapArea,mid_range:VirNumRange)->(Vec<MapArea>,MapArea){
@@ -994,19 +1949,23 @@ impl MapSet {
         let end_vpn = if mid_range.1.0 >= area.range.1.0 { area.range.1.0} else {mid_range.1.0};
 
         if start_vpn > area.range.0.0 && end_vpn < area.range.1.0{
-            let left_noneed_frametrace = area.frames.split_off(&VirNumber(start_vpn));
+            // split_off 返回 >= key 的部分：先切下右边，再切下中间，剩下的是左边
             let right_noneed_frametrace = area.frames.split_off(&VirNumber(end_vpn+1));
-            let need_frametrace = area.frames;
+            let need_frametrace = area.frames.split_off(&VirNumber(start_vpn));
+            let left_noneed_frametrace = area.frames;
             let mut re:Vec<MapArea>=Vec::new();
             let mut left = MapArea::new(VirNumRange(area.range.0,VirNumber(start_vpn-1) ), area.flags, area.map_type);
             left.frames = left_noneed_frametrace;
             left.mmap = area.mmap.clone();
+            left.elf = area.elf.clone();
             let mut mid = MapArea::new(mid_range, area.flags, area.map_type);
             mid.frames = need_frametrace;
-            mid.mmap = area.mmap.clone();
+            mid.mmap = area.mmap.as_ref().map(|m| m.shifted(start_vpn - area.range.0.0));
+            mid.elf = area.elf.clone();
             let mut right = MapArea::new(VirNumRange(VirNumber(end_vpn+1),area.range.1), area.flags, area.map_type);
             right.frames = right_noneed_frametrace;
-            right.mmap = area.mmap.clone();
+            right.mmap = area.mmap.as_ref().map(|m| m.shifted(end_vpn + 1 - area.range.0.0));
+            right.elf = area.elf.clone();
             re.push(left);
             re.push(right);
             return (re,mid);
@@ -1027,10 +1986,12 @@ impl MapSet {
             });
             let mut no_new_area = MapArea::new(life_range, area.flags, area.map_type);
             no_new_area.frames=no_munmap;
-            no_new_area.mmap=area.mmap.clone();
+            no_new_area.mmap=area.mmap.as_ref().map(|m| m.shifted(life_range.0.0 - area.range.0.0));
+            no_new_area.elf=area.elf.clone();
             let mut need_new_area = MapArea::new(mid_range, area.flags, area.map_type);
             need_new_area.frames = need_munmap;
-            need_new_area.mmap=area.mmap.clone();
+            need_new_area.mmap=area.mmap.as_ref().map(|m| m.shifted(mid_range.0.0.max(area.range.0.0) - area.range.0.0));
+            need_new_area.elf=area.elf.clone();
             re.push(no_new_area);
             return (re,need_new_area);
         }
@@ -1040,17 +2001,36 @@ impl MapSet {
 
 
 
-    ///从elf解析数据创建应用地址空间 Mapset entry user_stack,kernel_sp
-    /// elf_data: ELF 文件数据（可以从文件系统读取）
-    pub fn from_elf(elf_data:&[u8])->Option<(Self,usize,VirAddr,usize)>{ 
+    ///从elf文件创建应用地址空间 Mapset entry user_stack,kernel_sp
+    /// elf_file: 可执行文件，只读取 ELF 头和程序头表，PT_LOAD 段记录为文件后备的 area，缺页时按需读取
+    pub fn from_elf(elf_file:Arc<dyn File>)->Option<(Self,usize,VirAddr,usize)>{ 
         let mut memory_set = Self::new_bare();
-        // map program headers of elf, with U flag
-        let re = xmas_elf::ElfFile::new(elf_data);
+        // 先读 ELF 头，得到程序头表的位置，再把 [0, 程序头表结尾) 读进来交给 xmas_elf 解析
+        let mut head = vec![0u8; 64];
+        match elf_file.read_at(0, &mut head) {
+            Ok(n) if n == head.len() => {}
+            _ => {
+                warn!("Can't read elf header");
+                return None;
+            }
+        }
+        let re = xmas_elf::ElfFile::new(&head);
         if re.is_err(){
             warn!("Can't parsing this raw data to elf");
             return None;
         }
         let elf = re.expect("Kernel error");
+        let ph_end = elf.header.pt2.ph_offset() as usize
+            + elf.header.pt2.ph_entry_size() as usize * elf.header.pt2.ph_count() as usize;
+        let mut head = vec![0u8; ph_end.max(64)];
+        match elf_file.read_at(0, &mut head) {
+            Ok(n) if n == head.len() => {}
+            _ => {
+                warn!("Can't read elf program headers");
+                return None;
+            }
+        }
+        let elf = xmas_elf::ElfFile::new(&head).expect("Kernel error");
         let elf_header = elf.header;
         let magic = elf_header.pt1.magic;
         if magic != [0x7f, 0x45, 0x4c, 0x46]{
@@ -1084,13 +2064,15 @@ impl MapSet {
       
                 max_end_vpn=end_va.floor_up();
 
-                memory_set.add_area(
-                    VirNumRange::new(start_va, end_va), // bug!!!!!! 范围range权限覆盖
-                    MapType::Maped,
-                    map_perm,
-                    Some((start_va.0%PAGE_SIZE,&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize])),
-                    None
-                );//应用area默认default
+                // 只记录段信息，不分配页帧，第一次访问时由 PageFaultHandler 填充
+                let mut area = MapArea::new(VirNumRange::new(start_va, end_va), map_perm, MapType::Maped);
+                area.elf = Some(ElfBacking {
+                    file: elf_file.clone(),
+                    vaddr: start_va.0,
+                    offset: ph.offset() as usize,
+                    file_size: ph.file_size() as usize,
+                });
+                memory_set.areas.push(area);
             }
         }
         
@@ -1099,29 +2081,36 @@ impl MapSet {
         memory_set.map_traper();
         //映射上下文
         memory_set.map_trapContext();
+        if !memory_set.map_sigreturn_trampoline() {
+            return None;
+        }
         //映射普通用户栈
         let userstack_start_vpn=VirNumber(max_end_vpn.0+1);//留guradpage
         let userstack_end_vpn=VirNumber(userstack_start_vpn.0+1);
         let user_sp:VirAddr=VirAddr(userstack_end_vpn.0*PAGE_SIZE + PAGE_SIZE);//因为结尾不包含，属于下一个页面
         debug!("  Mapping user stack: vpn={:#x}, sp={:#x}", userstack_start_vpn.0, user_sp.0);
-        memory_set.add_area(
+        if !memory_set.add_area(
             VirNumRange(userstack_start_vpn,userstack_end_vpn),
             MapType::Maped,
             MapAreaFlags::W | MapAreaFlags::R | MapAreaFlags::U,
             None,
             None,
             
-        );
+        ) {
+            return None;
+        }
         //映射用户堆 初始0 通过brk生长---------------------------------------------+0
         let userheap_start_end_vpn = VirNumber(userstack_end_vpn.0+1);//无需guardpage，堆不会向下溢出
         debug!("  Mapping user heap: vpn={:#x}", userheap_start_end_vpn.0);
-        memory_set.add_area(
+        if !memory_set.add_area(
             VirNumRange(userheap_start_end_vpn, userheap_start_end_vpn),
             MapType::Maped,
             MapAreaFlags::R | MapAreaFlags::W | MapAreaFlags::U,
             None,
             None,
-        );
+        ) {
+            return None;
+        }
 
         //设置brk
         memory_set.brk = userheap_start_end_vpn.into();
@@ -1173,7 +2162,10 @@ impl MapSet {
         MapSet{
             table:PageTable::new(),
             areas:Vec::new(),
-            brk:VirAddr(0)
+            brk:VirAddr(0),
+            swapped:BTreeSet::new(),
+            swap_hand:VirNumber(0),
+            writeback:Vec::new(),
         }
     }
 
@@ -1198,6 +2190,52 @@ impl MapSet {
         );
     }
 
+    ///给新线程映射一页陷阱上下文，返回它的虚拟地址，槽位用完时返回 None
+    pub fn map_thread_trapContext(&mut self)->Option<usize>{
+        for slot in 1..=MAX_THREAD_TRAP_SLOTS {
+            let addr = USERLIB_START_RETURN_HIGNADDR - slot * PAGE_SIZE;
+            let vpn = VirAddr(addr).strict_into_virnum();
+            if self.AallArea_Iscontain_thisVpn(vpn) {
+                continue;
+            }
+            self.add_area(
+                VirNumRange(vpn, vpn),
+                MapType::Maped,
+                MapAreaFlags::R | MapAreaFlags::W,
+                None,
+                None,
+            );
+            return Some(addr);
+        }
+        None
+    }
+
+    ///线程退出后释放它的陷阱上下文页，主线程的 TRAP_CONTEXT_ADDR 跟着地址空间走，不在这里释放
+    pub fn unmap_thread_trapContext(&mut self,addr:usize){
+        if addr == TRAP_CONTEXT_ADDR {
+            return;
+        }
+        let vpn = VirAddr(addr).strict_into_virnum();
+        for mut area in self.pop_contain_range_area(VirNumRange(vpn, vpn)) {
+            area.unmap_one(&mut self.table, vpn);
+        }
+    }
+
+    ///映射信号返回跳板：处理函数返回到这里，执行 rt_sigreturn 回到被信号打断的地方
+    /// 分配不到页帧时返回 false
+    pub fn map_sigreturn_trampoline(&mut self)->bool{
+        // li a7, 139 (SYS_RT_SIGRETURN); ecall
+        const TRAMPOLINE: [u8; 8] = [0x93, 0x08, 0xb0, 0x08, 0x73, 0x00, 0x00, 0x00];
+        let vpn = VirAddr(SIGRETURN_TRAMPOLINE_ADDR).strict_into_virnum();
+        self.add_area(
+            VirNumRange(vpn, vpn),
+            MapType::Maped,
+            MapAreaFlags::R | MapAreaFlags::X | MapAreaFlags::U,
+            Some((0, &TRAMPOLINE)),
+            None,
+        )
+    }
+
     ///目前不可用
     ///映射特殊用户库没返回的情况，可以直接切换任务或者panic，保证内核稳定,目前就在TrapContext后面巴，如果后续报错，则需要特殊处理。！！！！！！！！！！！！！！！！！！！！！！
     ///只映射了处理函数一个页，可能不够 目前不能用
@@ -1240,7 +2278,8 @@ impl MapSet {
     }
 
     ///输入range，maptype和flags 自动处理maparea的映射和物理帧挂载以及对应memset的pagetable映射,处理数据的复制映射   但是映射用户栈不需要数据
-    pub fn add_area(&mut self,range:VirNumRange,map_type :MapType,flags:MapAreaFlags,data:Option<(usize,&[u8])>,mmap:Option<MmapInfo>){
+    /// 用户页分配不到页帧时撤销已经映射的页，不加入这个 area，返回 false
+    pub fn add_area(&mut self,range:VirNumRange,map_type :MapType,flags:MapAreaFlags,data:Option<(usize,&[u8])>,mmap:Option<MmapInfo>)->bool{
         let mut area=MapArea::new(range, flags, map_type);
         area.mmap = mmap;
         if area.mmap.is_none() {
@@ -1254,13 +2293,34 @@ impl MapSet {
 
 
             // 一个area只能有一个重叠目标
-            area.map_all(&mut self.table,find_re);//映射area,处理物理页帧分配逻辑
+            if map_type == MapType::Maped && flags.contains(MapAreaFlags::U) {
+                // 用户页（brk/栈等）逐页分配，物理页帧不够时可以换出
+                let mut allocated: Vec<VirNumber> = Vec::new();
+                for vpn in range {
+                    if self.table.is_maped(vpn) {
+                        area.map_one(vpn, &mut self.table, find_re.clone());
+                        continue;
+                    }
+                    let Some(frame) = self.alloc_user_frame() else {
+                        error!("add_area: out of memory vpn:{}", vpn.0);
+                        for vpn in allocated {
+                            area.unmap_one(&mut self.table, vpn);
+                        }
+                        return false;
+                    };
+                    area.map_one_with_frame(vpn, frame, &mut self.table);
+                    allocated.push(vpn);
+                }
+            } else {
+                area.map_all(&mut self.table,find_re);//映射area,处理物理页帧分配逻辑
+            }
             if let MapType::Maped = map_type{//maped方式要复制数据
                 area.copy_data(data, &mut self.table);
             }
         }
 
         self.areas.push(area);
+        true
     }
  
 
@@ -1280,6 +2340,19 @@ impl MapSet {
             None,
         );
 
+        // 设备树里不在上面硬件段内的设备 mmio 也恒等映射
+        {
+            let boot = BOOT_INFO.lock();
+            let devices = boot.virtio_mmio.iter().chain(boot.plic.iter()).chain(boot.uart.iter());
+            for dev in devices {
+                if dev.size == 0 || dev.base + dev.size <= 0x10010000 {
+                    continue;
+                }
+                let range = VirNumRange::new(VirAddr(dev.base), VirAddr(dev.base + dev.size));
+                mem_set.add_area(range, MapType::Indentical, MapAreaFlags::R | MapAreaFlags::W, None, None);
+            }
+        }
+
         //映射代码段
         let text_range = VirNumRange::new(VirAddr(stext as usize), VirAddr(etext as usize));//range封装过
         mem_set.add_area(
@@ -1327,7 +2400,7 @@ impl MapSet {
         
         // 映射物理内存(必须手动构造range区间)，phystart需要向上取整,end需要手动-1 range
         let phys_start =VirAddr(ekernel as usize).floor_up();
-        let phys_end =VirAddr(ekernel as usize + MEMORY_SIZE-PAGE_SIZE).floor_down(); //ekernel 为结束地址 end需要手动-1 range
+        let phys_end =VirAddr(phys_mem_end()-PAGE_SIZE).floor_down(); //ekernel 为结束地址 end需要手动-1 range
         let phys_range = VirNumRange(phys_start,phys_end);
         mem_set.add_area(
             phys_range,
@@ -1374,6 +2447,14 @@ impl MapSet {
 
 impl Drop for MapSet {
     fn drop(&mut self) {
+        // 释放换出页占用的交换槽位
+        for vpn in self.swapped.iter() {
+            if let Some(pte) = self.table.find_pte_vpn(*vpn) {
+                if pte.is_swap() {
+                    swap_free(pte.swap_slot());
+                }
+            }
+        }
         for area in self.areas.iter() {
             let Some(info) = area.mmap.as_ref() else {
                 continue;
@@ -1383,39 +2464,17 @@ impl Drop for MapSet {
             if info.flags.contains(MmapFlags::SHARED) && info.flags.contains(MmapFlags::ANONYMOUS) {
                 let mut shared = SHARED_MMAP_PAGES.lock();
                 for vpn in VirNumRange(area.range.0, area.range.1) {
-                    let page_index = vpn.0.saturating_sub(area.range.0.0) as u64;
+                    let page_index = info.page_index(area.range.0, vpn) as u64;
                     let key = SharedMmapKey::Anon { mmap_id: info.id, page_index };
                     shared.remove(&key);
                 }
                 continue;
             }
 
-            if info.flags.contains(MmapFlags::SHARED) && !info.flags.contains(MmapFlags::ANONYMOUS) {
-                let file_backing = match info.backing.as_ref() {
-                    Some(v) => v.clone(),
-                    None => continue,
-                };
-
-                for (vpn, frame) in area.frames.iter() {
-                    
-                    let page_index = vpn.0.saturating_sub(area.range.0.0) as u64;
-                    let file_page = (info.offset / PAGE_SIZE) as u64 + page_index;
-                    let inode_num = match file_backing.stat() {
-                        Ok(st) => st.inode,
-                        Err(_e) => {
-                            // On drop path, avoid killing task; best-effort cleanup.
-                            continue;
-                        }
-                    };
-                    let key = SharedMmapKey::File { inode_num, file_page };
-                    let file_off = info.offset.saturating_add((page_index as usize).saturating_mul(PAGE_SIZE));
-                    let pa: PhysiAddr = frame.ppn.into();
-                    let buf = unsafe { core::slice::from_raw_parts(pa.0 as *const u8, PAGE_SIZE) };
-                    let _ = file_backing.write_at(file_off, buf);
-                    let mut shared = SHARED_MMAP_PAGES.lock();
-                    shared.remove(&key);
-                }
-            }
+            // 进程退出/exec：写回共享文件映射里被改过的页，失败也只能尽力而为
+            Self::shared_file_writeback(area, &mut self.table, area.range.0, area.range.1, &mut self.writeback);
         }
+        // 地址空间已经没人引用了，锁早就放开了，直接写
+        let _ = FilePage::write_back(core::mem::take(&mut self.writeback));
     }
-}
\ No newline at end of file
+}
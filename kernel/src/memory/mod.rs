@@ -4,8 +4,10 @@ use log::trace;
 mod address;
 mod frame_allocator;
 mod memset;
+mod swap;
 
 
 pub use address::*;
 pub use frame_allocator::*;
 pub use memset::*;
+pub use swap::*;
@@ -0,0 +1,197 @@
+//! 交换分区：物理页帧不够时把用户匿名页换出到块设备分区（MBR 0x82）
+//! 换出的页在页表里留下一个不合法的 swap pte，缺页时再换入
+//! 换出哪一页由全局时钟决定，所有用户地址空间的页排成一圈，谁缺页都从同一个指针往后找
+
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use log::{error, warn};
+
+use crate::config::PAGE_SIZE;
+use crate::fs::vfs::{File, VfsFsError};
+use crate::memory::MapSet;
+use crate::sync::SpinLock;
+
+/// 启用交换分区时至少给页表、内核栈等不能换出的分配留这么多空闲页帧
+pub const SWAP_RESERVE_FRAMES: usize = 32;
+
+lazy_static! {
+    /// 全局交换设备，没有交换分区时为 None
+    pub static ref SWAP: SpinLock<Option<SwapDevice>> = SpinLock::new(None);
+    /// 全局时钟上的地址空间，按注册顺序排；一个地址空间里按 vpn 排，时钟在里面的位置是 MapSet 的 swap_hand
+    static ref SWAP_CLOCK: SpinLock<Vec<Weak<SpinLock<MapSet>>>> = SpinLock::new(Vec::new());
+}
+
+/// 全局时钟指针当前停在 SWAP_CLOCK 的第几个地址空间
+static CLOCK_SPACE: AtomicUsize = AtomicUsize::new(0);
+
+/// 地址空间里扫一段时钟的结果
+#[derive(PartialEq, Eq)]
+pub enum ClockScan {
+    /// 换出了一页
+    Evicted,
+    /// 扫到最后一页也没有 A 位为0的页，时钟转到下一个地址空间
+    Wrapped,
+    /// 交换分区满了或者写失败
+    Failed,
+}
+
+pub struct SwapDevice {
+    dev: Arc<dyn File>,
+    /// 每个槽位（一页）的引用计数，0 为空闲。fork 后父子可以共享同一个槽位
+    swap_map: Vec<u16>,
+    /// 下一次分配从这里开始找
+    next: usize,
+}
+
+impl SwapDevice {
+    fn alloc(&mut self) -> Option<usize> {
+        let total = self.swap_map.len();
+        // 第0页留给 mkswap 的头部
+        for i in 0..total {
+            let slot = (self.next + i) % total;
+            if slot != 0 && self.swap_map[slot] == 0 {
+                self.swap_map[slot] = 1;
+                self.next = slot + 1;
+                return Some(slot);
+            }
+        }
+        None
+    }
+}
+
+///用一个块设备分区初始化交换区，槽位数 = 分区大小 / PAGE_SIZE
+pub fn init_swap(dev: Arc<dyn File>) {
+    let size = match dev.stat() {
+        Ok(st) => st.size as usize,
+        Err(e) => {
+            error!("swap: stat swap partition failed err={}", e);
+            return;
+        }
+    };
+    let slots = size / PAGE_SIZE;
+    if slots < 2 {
+        warn!("swap: partition too small ({} bytes), ignored", size);
+        return;
+    }
+    let mut swap = SWAP.lock();
+    if swap.is_some() {
+        warn!("swap: already have a swap partition, ignored");
+        return;
+    }
+    warn!("swap: enable swap partition, {} pages", slots - 1);
+    *swap = Some(SwapDevice {
+        dev,
+        swap_map: vec![0; slots],
+        next: 1,
+    });
+}
+
+///分配一个槽位并把一页数据写进去
+///只在 SWAP 锁里占住槽位，写块设备之前放锁，别的 hart 换入换出不用等这次 I/O
+pub fn swap_write_page(page: &[u8]) -> Option<usize> {
+    let (dev, slot) = {
+        let mut swap = SWAP.lock();
+        let swap = swap.as_mut()?;
+        (swap.dev.clone(), swap.alloc()?)
+    };
+    match dev.write_at(slot * PAGE_SIZE, &page[..PAGE_SIZE]) {
+        Ok(n) if n == PAGE_SIZE => Some(slot),
+        Ok(n) => {
+            error!("swap: short write slot={} n={}", slot, n);
+            swap_free(slot);
+            None
+        }
+        Err(e) => {
+            error!("swap: write slot={} failed err={}", slot, e);
+            swap_free(slot);
+            None
+        }
+    }
+}
+
+///从槽位读回一页，不释放槽位；读块设备时不拿 SWAP 锁
+pub fn swap_read_page(slot: usize, page: &mut [u8]) -> Result<(), VfsFsError> {
+    let dev = SWAP.lock().as_ref().ok_or(VfsFsError::IO)?.dev.clone();
+    match dev.read_at(slot * PAGE_SIZE, &mut page[..PAGE_SIZE])? {
+        n if n == PAGE_SIZE => Ok(()),
+        _ => Err(VfsFsError::IO),
+    }
+}
+
+///fork 时子进程也引用同一个槽位
+pub fn swap_dup(slot: usize) {
+    if let Some(dev) = SWAP.lock().as_mut() {
+        if let Some(cnt) = dev.swap_map.get_mut(slot) {
+            *cnt = cnt.saturating_add(1);
+        }
+    }
+}
+
+///槽位引用计数减一，为0后可以再分配
+pub fn swap_free(slot: usize) {
+    if let Some(dev) = SWAP.lock().as_mut() {
+        if let Some(cnt) = dev.swap_map.get_mut(slot) {
+            *cnt = cnt.saturating_sub(1);
+        }
+    }
+}
+
+///是否启用了交换分区
+pub fn swap_enabled() -> bool {
+    SWAP.lock().is_some()
+}
+
+///用户地址空间挂到全局时钟上，它的页才会被换出；已经销毁的地址空间顺便摘掉
+pub fn swap_clock_register(space: &Arc<SpinLock<MapSet>>) {
+    let mut clock = SWAP_CLOCK.lock();
+    clock.retain(|w| w.strong_count() > 0);
+    clock.push(Arc::downgrade(space));
+}
+
+///全局时钟换出一页：从指针所在的地址空间开始往后扫，A 位为1的页清掉给第二次机会，换出遇到的第一个 A 位为0的页
+/// current 是调用者已经锁住的地址空间（可能还没挂到时钟上），别的地址空间只 try_lock，拿不到就跳过
+/// 返回 false 代表转了两圈也没有能换出的页，或者交换分区满了
+pub fn swap_out_global(current: &mut MapSet) -> bool {
+    let spaces: Vec<Weak<SpinLock<MapSet>>> = SWAP_CLOCK.lock().clone();
+    let n = spaces.len();
+    let start = CLOCK_SPACE.load(Ordering::Relaxed);
+    let mut saw_current = false;
+    // 起点的地址空间可能只剩后半段没扫，多转一个：第一圈清 A 位，第二圈一定能找到
+    for i in 0..2 * n + 1 {
+        let idx = (start + i) % n.max(1);
+        let Some(space) = spaces.get(idx).and_then(|w| w.upgrade()) else {
+            continue;
+        };
+        let scan = if core::ptr::eq(space.data_ptr(), current) {
+            saw_current = true;
+            current.clock_scan()
+        } else if let Some(mut set) = space.try_lock() {
+            set.clock_scan()
+        } else {
+            // 别的 hart 正拿着它的锁，这一圈跳过
+            ClockScan::Wrapped
+        };
+        match scan {
+            ClockScan::Evicted => {
+                CLOCK_SPACE.store(idx, Ordering::Relaxed);
+                return true;
+            }
+            ClockScan::Failed => return false,
+            ClockScan::Wrapped => {}
+        }
+    }
+    // 还没挂到时钟上的地址空间（exec 正在建的）只能换自己的页，同样最多扫两遍
+    if !saw_current {
+        for _ in 0..2 {
+            match current.clock_scan() {
+                ClockScan::Evicted => return true,
+                ClockScan::Failed => return false,
+                ClockScan::Wrapped => {}
+            }
+        }
+    }
+    false
+}
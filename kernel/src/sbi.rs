@@ -1,14 +1,21 @@
 use core::arch::asm;
+use alloc::vec::Vec;
 
 use log::error;
 
-use crate::fs::vfs::{ROOTFS, VfsFs};
+use crate::fs::vfs::{MountFs, ROOTFS, VfsFs, filecache_sync_and_drop_fs};
 
 
 const SET_TIMER:usize=0;
 const PUTC_CALLID:usize=1;
 const GETCHAR_CALLID:usize=2;
 const SHUTDOWN_CALLID:usize=8;
+/// HSM 扩展（SBI v0.2+），legacy 调用里没有
+const HSM_EID:usize=0x48534D;
+const HART_START_FID:usize=0;
+/// RFENCE 扩展（SBI v0.2+）
+const RFENCE_EID:usize=0x52464E43;
+const REMOTE_SFENCE_VMA_FID:usize=1;
 
 
 #[inline(always)]
@@ -36,12 +43,15 @@ pub fn get_char()->isize{//非阻塞 -1没有字符，>=0ascii码
 }
 ///关机的操作的副作用文件系统卸载
 pub fn shutdown()->!{
-    //取消文件系统挂载
-    if let Some(rootfs) = ROOTFS.lock().as_mut() {
-        rootfs.mount_poinr.iter().for_each(|fs| {
-            fs.1.lock().umount();
-        });
-    }
+    //取消文件系统挂载，回写要等块设备 I/O，先放掉 ROOTFS
+    let mounts: Vec<MountFs> = match ROOTFS.lock().as_ref() {
+        Some(rootfs) => rootfs.mount_poinr.values().cloned().collect(),
+        None => Vec::new(),
+    };
+    mounts.iter().for_each(|fs| {
+        filecache_sync_and_drop_fs(fs);
+        fs.lock().umount();
+    });
         
 
     sbi_call(SHUTDOWN_CALLID, 0, 0, 0);
@@ -56,3 +66,32 @@ pub fn set_next_timetriger(timer:usize){
     sbi_call(SET_TIMER, timer, 0, 0);
 }
 
+///SBI v0.2 之后的调用约定：a7=EID a6=FID，返回 a0=error
+#[inline(always)]
+fn sbi_ext_call(eid:usize,fid:usize,arg0:usize,arg1:usize,arg2:usize,arg3:usize)->isize{
+    let mut error;
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("x10") arg0 => error,
+            inlateout("x11") arg1 => _,
+            in("x12") arg2,
+            in("x13") arg3,
+            in("x16") fid,
+            in("x17") eid,
+        );
+    }
+    error
+}
+
+///启动一个停止状态的 hart，从 start_addr 开始执行（S 模式，关分页），a0=hartid a1=opaque
+/// 返回 SBI 错误码，0 为成功
+pub fn hart_start(hartid:usize,start_addr:usize,opaque:usize)->isize{
+    sbi_ext_call(HSM_EID, HART_START_FID, hartid, start_addr, opaque, 0)
+}
+
+///让所有 hart 刷掉整个 TLB，改了可能正在别的 hart 上运行的地址空间的页表项之后调用
+pub fn remote_sfence_vma_all(){
+    // hart_mask_base = -1 表示所有 hart，start = size = 0 表示整个地址空间
+    sbi_ext_call(RFENCE_EID, REMOTE_SFENCE_VMA_FID, 0, usize::MAX, 0, 0);
+}
@@ -1,3 +1,5 @@
-mod up;
+mod spin;
+mod sleep;
 
-pub use up::UPSafeCell;
\ No newline at end of file
+pub use spin::{SpinLock, SpinLockGuard, hart_holds_lock};
+pub use sleep::{SleepLock, SleepLockGuard};
@@ -0,0 +1,88 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::task::WaitQueue;
+
+//可以在持有期间睡眠的锁：拿不到锁的任务挂在等待队列上，不自旋
+//文件系统挂载锁、页缓存和打开文件的锁用它，持有者等块设备 I/O 时可以让出 CPU
+//拿不到锁要睡眠，调用时不能持有 SpinLock，否则 panic（见 WaitQueue::sleep_unless）
+pub struct SleepLock<T: ?Sized>{
+    locked:AtomicBool,
+    waiters:WaitQueue,
+    inner:UnsafeCell<T>
+}
+
+unsafe impl<T: ?Sized> Sync for SleepLock<T>{}
+unsafe impl<T: ?Sized> Send for SleepLock<T> {}
+
+/// SleepLock::lock 返回的守卫，drop 时释放锁并唤醒等待者
+pub struct SleepLockGuard<'a, T: ?Sized> {
+    lock: &'a SleepLock<T>,
+}
+
+impl<T: ?Sized> Deref for SleepLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.inner.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SleepLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.inner.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SleepLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        self.lock.waiters.wake_all();
+    }
+}
+
+impl<T> SleepLock<T>{
+    pub const fn new(value:T)->Self{
+        SleepLock{
+            locked:AtomicBool::new(false),
+            waiters:WaitQueue::new(),
+            inner:UnsafeCell::new(value)
+        }
+    }
+}
+
+impl<T: ?Sized> SleepLock<T>{
+    ///拿不到锁就睡眠，持有者释放时被唤醒后重新抢
+    #[track_caller]
+    pub fn lock(&self)->SleepLockGuard<'_,T>{
+        loop {
+            if let Some(g) = self.try_lock() {
+                return g;
+            }
+            // 登记之后再抢一次，抢锁和睡眠之间的释放不会丢
+            let mut acquired = false;
+            self.waiters.sleep_unless(|| {
+                acquired = self.try_acquire();
+                acquired
+            });
+            if acquired {
+                return SleepLockGuard { lock: self };
+            }
+        }
+    }
+
+    ///锁被占用时返回 None，不睡眠
+    pub fn try_lock(&self)->Option<SleepLockGuard<'_,T>>{
+        if self.try_acquire() {
+            Some(SleepLockGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    fn try_acquire(&self)->bool{
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+}
@@ -0,0 +1,109 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::config::MAX_HARTS;
+use crate::task::hart_id;
+
+/// 没有 hart 持有时的 owner
+const NO_OWNER: usize = usize::MAX;
+
+/// 每个 hart 当前持有的 SpinLock 个数
+/// 块设备 I/O 用它判断能不能让出 CPU：持有锁时切走，别的任务再拿同一把锁会一直自旋
+static HELD_LOCKS: [AtomicUsize; MAX_HARTS] = {
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    [ZERO; MAX_HARTS]
+};
+
+//多核共享数据的自旋锁，内核态不开中断，不需要关中断
+pub struct SpinLock<T>{
+    locked:AtomicBool,
+    ///持有锁的 hartid，用来发现同一个 hart 重复加锁
+    owner:AtomicUsize,
+    inner:UnsafeCell<T>
+}
+
+unsafe impl<T> Sync  for SpinLock<T>{}
+unsafe impl<T> Send for SpinLock<T> {}
+
+/// SpinLock::lock 返回的守卫，drop 时释放锁
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.inner.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.inner.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.owner.store(NO_OWNER, Ordering::Relaxed);
+        self.lock.locked.store(false, Ordering::Release);
+        HELD_LOCKS[hart_id()].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<T> SpinLock<T>{
+    pub const fn new(value:T)->Self{
+        SpinLock{
+            locked:AtomicBool::new(false),
+            owner:AtomicUsize::new(NO_OWNER),
+            inner:UnsafeCell::new(value)
+        }
+    }
+
+    ///自旋直到拿到锁，同一个 hart 重复加锁一定死锁，直接 panic
+    #[track_caller]
+    pub fn lock(&self)->SpinLockGuard<'_,T>{
+        loop {
+            if let Some(g) = self.try_lock() {
+                return g;
+            }
+            if self.held_by_current_hart() {
+                let loc = core::panic::Location::caller();
+                panic!(
+                    "SpinLock already held by hart {} at {}:{}:{}",
+                    hart_id(),
+                    loc.file(),
+                    loc.line(),
+                    loc.column()
+                );
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    ///锁被占用时返回 None，不自旋
+    pub fn try_lock(&self)->Option<SpinLockGuard<'_,T>>{
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        self.owner.store(hart_id(), Ordering::Relaxed);
+        HELD_LOCKS[hart_id()].fetch_add(1, Ordering::Relaxed);
+        Some(SpinLockGuard { lock: self })
+    }
+
+    ///不加锁拿到被保护数据的地址，只能用来判断是不是同一把锁保护的对象
+    pub fn data_ptr(&self)->*mut T{
+        self.inner.get()
+    }
+
+    ///锁是不是被当前 hart 持有，中断等不能确定调用上下文的地方用它避免重复加锁
+    pub fn held_by_current_hart(&self)->bool{
+        self.locked.load(Ordering::Relaxed) && self.owner.load(Ordering::Relaxed) == hart_id()
+    }
+}
+
+///当前 hart 是否持有任何 SpinLock
+pub fn hart_holds_lock() -> bool {
+    HELD_LOCKS[hart_id()].load(Ordering::Relaxed) != 0
+}
@@ -0,0 +1,120 @@
+use core::fmt::{Display, Formatter, Result};
+use core::ops::Neg;
+use crate::fs::vfs::VfsFsError;
+
+/// Linux errno，系统调用失败时返回它的相反数（`-Errno::ENOENT`）
+#[repr(isize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Errno {
+    EPERM = 1,
+    ENOENT = 2,
+    ESRCH = 3,
+    EINTR = 4,
+    EIO = 5,
+    ENXIO = 6,
+    E2BIG = 7,
+    ENOEXEC = 8,
+    EBADF = 9,
+    ECHILD = 10,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EACCES = 13,
+    EFAULT = 14,
+    ENOTBLK = 15,
+    EBUSY = 16,
+    EEXIST = 17,
+    EXDEV = 18,
+    ENODEV = 19,
+    ENOTDIR = 20,
+    EISDIR = 21,
+    EINVAL = 22,
+    ENFILE = 23,
+    EMFILE = 24,
+    ENOTTY = 25,
+    ETXTBSY = 26,
+    EFBIG = 27,
+    ENOSPC = 28,
+    ESPIPE = 29,
+    EROFS = 30,
+    EMLINK = 31,
+    EPIPE = 32,
+    EDOM = 33,
+    ERANGE = 34,
+    EDEADLK = 35,
+    ENAMETOOLONG = 36,
+    ENOLCK = 37,
+    ENOSYS = 38,
+    ENOTEMPTY = 39,
+    ELOOP = 40,
+    EOVERFLOW = 75,
+    EOPNOTSUPP = 95,
+    ETIMEDOUT = 110,
+    /// 下面几个只在内核里用，不会返回给用户态：被信号打断的系统调用要不要重新执行
+    /// 没有处理函数运行，或者处理函数带 SA_RESTART 时原样重新执行，否则变成 EINTR
+    ERESTARTSYS = 512,
+    /// 没有处理函数运行时重新执行，有处理函数运行时不管 SA_RESTART 都是 EINTR（sigsuspend）
+    ERESTARTNOHAND = 514,
+    /// 没有处理函数运行时经 restart_syscall 接着做剩下的（nanosleep 只睡剩下的时间），否则变成 EINTR
+    #[allow(non_camel_case_types)]
+    ERESTART_RESTARTBLOCK = 516,
+}
+
+impl Errno {
+    ///系统调用返回值
+    pub const fn ret(self) -> isize {
+        -(self as isize)
+    }
+}
+
+impl Display for Errno {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// `-Errno::EBADF` 直接得到返回给用户态的值，和 C 里的 -EBADF 一样写
+impl Neg for Errno {
+    type Output = isize;
+
+    fn neg(self) -> isize {
+        self.ret()
+    }
+}
+
+impl From<VfsFsError> for Errno {
+    fn from(err: VfsFsError) -> Self {
+        match err {
+            VfsFsError::Mounted => Errno::EBUSY,
+            VfsFsError::Unmounted => Errno::EINVAL,
+            VfsFsError::IO => Errno::EIO,
+            VfsFsError::BrokenPipe => Errno::EPIPE,
+            VfsFsError::MountFail => Errno::EINVAL,
+            VfsFsError::UnmountFail => Errno::EINVAL,
+            VfsFsError::NotFound => Errno::ENOENT,
+            VfsFsError::AlreadyExists => Errno::EEXIST,
+            VfsFsError::NotDir => Errno::ENOTDIR,
+            VfsFsError::IsDir => Errno::EISDIR,
+            VfsFsError::Invalid => Errno::EINVAL,
+            VfsFsError::BadFd => Errno::EBADF,
+            VfsFsError::PermissionDenied => Errno::EACCES,
+            VfsFsError::NotPermitted => Errno::EPERM,
+            VfsFsError::NotSupported => Errno::EOPNOTSUPP,
+            VfsFsError::Busy => Errno::EBUSY,
+            VfsFsError::NoSpace => Errno::ENOSPC,
+            VfsFsError::NoDevice => Errno::ENODEV,
+            VfsFsError::NotEmpty => Errno::ENOTEMPTY,
+            VfsFsError::Loop => Errno::ELOOP,
+            VfsFsError::CrossDevice => Errno::EXDEV,
+            VfsFsError::Interrupted => Errno::EINTR,
+        }
+    }
+}
+
+/// 文件系统错误直接当系统调用返回值用
+impl Neg for VfsFsError {
+    type Output = isize;
+
+    fn neg(self) -> isize {
+        Errno::from(self).ret()
+    }
+}
@@ -1,10 +1,16 @@
 mod syscall;
+mod errno;
+mod uaccess;
+pub use errno::Errno;
+pub use uaccess::{UserCStr, UserPtr, UserSlice};
 use log::{error, warn};
 use crate::memory::VirAddr;
 use crate::syscall::syscall::*;
 // Linux riscv64 syscall numbers (subset used by the oscomp test suite)
 pub const SYS_GETCWD: usize = 17;
+pub const SYS_IOCTL: usize = 29;
 pub const SYS_UNLINKAT: usize = 35;
+pub const SYS_SYMLINKAT: usize = 36;
 pub const SYS_LINKAT: usize = 37;
 pub const SYS_UMOUNT2: usize = 39;
 pub const SYS_MOUNT: usize = 40;
@@ -17,22 +23,47 @@ pub const SYS_GETDENTS64: usize = 61;
 pub const SYS_LSEEK: usize = 62;
 pub const SYS_READ: usize = 63;
 pub const SYS_WRITE: usize = 64;
+pub const SYS_READLINKAT: usize = 78;
 pub const SYS_NEWFSTATAT: usize = 79;
 pub const SYS_FSTAT: usize = 80;
+pub const SYS_FSYNC: usize = 82;
 pub const SYS_EXIT: usize = 93;
+pub const SYS_EXIT_GROUP: usize = 94;
+pub const SYS_WAITID: usize = 95;
+pub const SYS_SET_TID_ADDRESS: usize = 96;
+pub const SYS_FUTEX: usize = 98;
 pub const SYS_NANOSLEEP: usize = 101;
+pub const SYS_KILL: usize = 129;
+pub const SYS_TKILL: usize = 130;
+pub const SYS_TGKILL: usize = 131;
+pub const SYS_SIGALTSTACK: usize = 132;
+pub const SYS_RT_SIGSUSPEND: usize = 133;
+pub const SYS_RT_SIGACTION: usize = 134;
+pub const SYS_RT_SIGPROCMASK: usize = 135;
+pub const SYS_RT_SIGPENDING: usize = 136;
+pub const SYS_RESTART_SYSCALL: usize = 128;
+pub const SYS_RT_SIGRETURN: usize = 139;
 pub const SYS_SETPRIORITY: usize = 140;
 pub const SYS_TIMES: usize = 153;
+pub const SYS_SETPGID: usize = 154;
+pub const SYS_GETPGID: usize = 155;
+pub const SYS_GETSID: usize = 156;
+pub const SYS_SETSID: usize = 157;
 pub const SYS_UNAME: usize = 160;
 pub const SYS_GETTIMEOFDAY: usize = 169;
 pub const SYS_GETPID: usize = 172;
 pub const SYS_GETPPID: usize = 173;
+pub const SYS_GETTID: usize = 178;
 pub const SYS_BRK: usize = 214;
 pub const SYS_MUNMAP: usize = 215;
+pub const SYS_MREMAP: usize = 216;
 pub const SYS_CLONE: usize = 220;
 pub const SYS_EXECVE: usize = 221;
 pub const SYS_MMAP: usize = 222;
+pub const SYS_MPROTECT: usize = 226;
+pub const SYS_MSYNC: usize = 227;
 pub const SYS_WAIT4: usize = 260;
+pub const SYS_RENAMEAT2: usize = 276;
 pub const SYS_SCHED_YIELD: usize = 124;
 pub const SYS_DUP: usize = 23;
 pub const SYS_DUP3: usize = 24;
@@ -44,26 +75,43 @@ pub fn syscall_handler(id:usize,arg:[usize;6]) -> isize {
         SYS_WRITE => sys_write(arg[0], arg[1], arg[2]),
         SYS_READ => sys_read(arg[0], arg[1], arg[2]),
         SYS_EXIT => sys_exit(arg[0]),
+        SYS_EXIT_GROUP => sys_exit_group(arg[0]),
         SYS_SCHED_YIELD => sys_yield(),
 
         SYS_NANOSLEEP => sys_nanosleep(arg[0], arg[1]),
+        SYS_FUTEX => sys_futex(arg[0], arg[1], arg[2], arg[3], arg[4], arg[5]),
+
+        SYS_KILL => sys_kill(arg[0] as isize, arg[1]),
+        SYS_TKILL => sys_tkill(arg[0] as isize, arg[1]),
+        SYS_TGKILL => sys_tgkill(arg[0] as isize, arg[1] as isize, arg[2]),
+        SYS_RT_SIGACTION => sys_rt_sigaction(arg[0], arg[1], arg[2], arg[3]),
+        SYS_RT_SIGPROCMASK => sys_rt_sigprocmask(arg[0], arg[1], arg[2], arg[3]),
+        SYS_RT_SIGPENDING => sys_rt_sigpending(arg[0], arg[1]),
+        SYS_RT_SIGSUSPEND => sys_rt_sigsuspend(arg[0], arg[1]),
+        SYS_SIGALTSTACK => sys_sigaltstack(arg[0], arg[1]),
+        SYS_RT_SIGRETURN => sys_rt_sigreturn(),
+        SYS_RESTART_SYSCALL => sys_restart_syscall(),
 
         SYS_GETPID => sys_getpid(),
         SYS_GETPPID => sys_getppid(),
+        SYS_GETTID => sys_gettid(),
+        SYS_SETPGID => sys_setpgid(arg[0] as isize, arg[1] as isize),
+        SYS_GETPGID => sys_getpgid(arg[0] as isize),
+        SYS_GETSID => sys_getsid(arg[0] as isize),
+        SYS_SETSID => sys_setsid(),
+        SYS_SET_TID_ADDRESS => sys_set_tid_address(arg[0]),
 
         SYS_DUP => sys_dup(arg[0] as i32),
         // Linux riscv64 userspace often implements dup2 via dup3(old, new, flags=0)
         SYS_DUP3 => {
             if arg[2] != 0 {
-                -1
+                -Errno::EINVAL
             } else {
                 sys_dup2(arg[0] as i32, arg[1] as i32)
             }
         }
 
-        // NOTE: oscomp user/lib/syscall.c implements open() via openat(AT_FDCWD,...)
-        // We currently ignore dirfd/mode and reuse sys_open's semantics.
-        SYS_OPENAT => sys_open(arg[1], arg[2]),
+        SYS_OPENAT => sys_openat(arg[0] as i32 as isize, arg[1], arg[2], arg[3]),
 
         SYS_CLOSE=>{
             sys_close(arg[0])
@@ -72,21 +120,31 @@ pub fn syscall_handler(id:usize,arg:[usize;6]) -> isize {
             sys_lseek(arg[0], arg[1] as isize, arg[2])
         }
         // newfstatat(dirfd, pathname, statbuf, flags)
-        // For now we ignore dirfd/flags and reuse the existing path-based sys_stat.
-        SYS_NEWFSTATAT => sys_stat(arg[1], arg[2]),
+        SYS_NEWFSTATAT => sys_fstatat(arg[0] as i32 as isize, arg[1], arg[2], arg[3]),
         // fstat(fd, statbuf)
         SYS_FSTAT => sys_fstat(arg[0], arg[1]),
+        SYS_FSYNC => sys_fsync(arg[0]),
+        SYS_IOCTL => sys_ioctl(arg[0], arg[1], arg[2]),
         SYS_CLONE => sys_clone(arg[0], arg[1], arg[2], arg[3], arg[4]),
         SYS_EXECVE => sys_execve(arg[0], arg[1], arg[2]),
-        SYS_WAIT4 => sys_wait4(arg[0] as i32, arg[1], arg[2] as i32),
+        SYS_WAIT4 => sys_wait4(arg[0] as i32, arg[1], arg[2] as i32, arg[3]),
+        SYS_WAITID => sys_waitid(arg[0], arg[1], arg[2], arg[3] as i32, arg[4]),
 
         SYS_GETTIMEOFDAY => sys_gettimeofday(arg[0], arg[1]),
         SYS_TIMES => sys_times(arg[0]),
 
         // mkdirat(dirfd, pathname, mode)
         // oscomp user/lib/syscall.c implements mkdir() via mkdirat(AT_FDCWD,...,mode)
-        SYS_MKDIRAT => {sys_mkdirat(arg[0] as isize, arg[1], arg[2])},
-        SYS_UNLINKAT => sys_unlink(arg[1]),
+        SYS_MKDIRAT => {sys_mkdirat(arg[0] as i32 as isize, arg[1], arg[2])},
+        SYS_UNLINKAT => sys_unlinkat(arg[0] as i32 as isize, arg[1], arg[2]),
+        // linkat(olddirfd, oldpath, newdirfd, newpath, flags)
+        SYS_LINKAT => sys_linkat(arg[0] as i32 as isize, arg[1], arg[2] as i32 as isize, arg[3], arg[4]),
+        // symlinkat(target, newdirfd, linkpath)
+        SYS_SYMLINKAT => sys_symlinkat(arg[0], arg[1] as i32 as isize, arg[2]),
+        // readlinkat(dirfd, pathname, buf, bufsiz)
+        SYS_READLINKAT => sys_readlinkat(arg[0] as i32 as isize, arg[1], arg[2], arg[3]),
+        // renameat2(olddirfd, oldpath, newdirfd, newpath, flags)
+        SYS_RENAMEAT2 => sys_renameat2(arg[0] as i32 as isize, arg[1], arg[2] as i32 as isize, arg[3], arg[4]),
 
         SYS_GETDENTS64 => sys_getdents64(arg[0], arg[1], arg[2]),
         SYS_PIPE2 => sys_pipe(arg[0]),
@@ -100,19 +158,22 @@ pub fn syscall_handler(id:usize,arg:[usize;6]) -> isize {
 
         SYS_MMAP => sys_mmap(arg[0], arg[1], arg[2], arg[3], arg[4] as i32, arg[5]),
         SYS_MUNMAP => sys_munmap(arg[0], arg[1]),
+        SYS_MREMAP => sys_mremap(arg[0], arg[1], arg[2], arg[3], arg[4]),
+        SYS_MPROTECT => sys_mprotect(arg[0], arg[1], arg[2]),
+        SYS_MSYNC => sys_msync(arg[0], arg[1], arg[2]),
 
         SYS_MOUNT => sys_mount(arg[0], arg[1], arg[2], arg[3], arg[4]),
         SYS_UMOUNT2 => sys_umount2(arg[0], arg[1]),
 
         // Not implemented yet in this kernel:
-        SYS_SETPRIORITY | SYS_LINKAT => {
+        SYS_SETPRIORITY => {
             error!("Unimplemented syscall id={}", id);
-            -1
+            -Errno::ENOSYS
         }
 
         _ => {
             error!("Unknown syscall id={}", id);
-            -1
+            -Errno::ENOSYS
         }
     }
 }
\ No newline at end of file
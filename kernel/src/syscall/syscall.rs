@@ -8,27 +8,31 @@ use alloc::vec::Vec;
 use alloc::string::String;
 use log::{debug, error, warn};
 use crate::sbi::shutdown;
-use crate::sync::UPSafeCell;
-use crate::task::{INIT_PID, ProcessId, TaskControlBlock, TaskStatus};
+use crate::sync::SpinLock;
+use crate::syscall::{Errno, UserCStr, UserPtr, UserSlice};
+use crate::task::{INIT_PID, ProcessId, TaskControlBlock, TaskStatus, WaitQueue, do_futex, futex_op_has_timeout};
+use crate::task::{sigreturn, RestartBlock, Signal, SigAction, SigInfo, SignalStack, MINSIGSTKSZ, NSIG, SIG_IGN, SI_TKILL, SI_USER, SS_DISABLE, SS_ONSTACK};
+use crate::task::{send_signal, send_signal_to_process, TaskUsage, WAIT_CONTINUED, CLD_CONTINUED, CLD_EXITED, CLD_KILLED, CLD_STOPPED};
 use crate::time::get_time_tick;
-use crate::{config::PAGE_SIZE, memory::{PageTable, VirAddr, VirNumber}, task::TASK_MANAER, time::{TimeVal, get_time_ms}};
+use crate::{config::PAGE_SIZE, memory::{VirAddr, VirNumber}, task::TASK_MANAER, time::{TimeVal, get_time_ms, sleep_until_ms, ticks_to_timeval}};
 use alloc::vec;
-use crate::memory::{CloneFlags, MapSet};
-use crate::fs::vfs::{self, VfsFsError, normalize_path};
-use crate::fs::vfs::{vfs_fstat_kstat, vfs_getdents64, vfs_mkdir, vfs_open, vfs_stat, vfs_unlink, KStat, OpenFlags, VfsStat, VFS_DT_DIR};
+use crate::memory::{CloneFlags, FilePage, MapSet};
+use crate::fs::vfs::{self, VfsFsError, absolute_path, normalize_path, vfs_realpath};
+use crate::fs::vfs::{vfs_fstat_kstat, vfs_getdents64, vfs_link, vfs_lstat, vfs_mkdir, vfs_open, vfs_readlink, vfs_rename2, vfs_rmdir, vfs_stat, vfs_symlink, vfs_unlink, KStat, OpenFlags, RenameFlags, VfsStat, VFS_DT_DIR};
 use crate::fs::vfs::File;
 use crate::fs::component::pipe::pipe::{make_pipe, PipeHandle};
+use crate::fs::component::tty::tty_ioctl_arg_size;
 use crate::trap::TrapContext;
 use crate::TRAP_CONTEXT_ADDR;
 use crate::task::ProcessId_ALLOCTOR;
 use crate::task::TaskContext;
 use crate::alloc::string::ToString;
 use alloc::format;
-use crate::memory::PTEFlags;
-use crate::fs::vfs::{ROOTFS, MountPath, VfsFs};
+use crate::fs::vfs::{ROOTFS, MountPath, VfsFs, filecache_sync_and_drop_fs};
 use crate::config::SECTOR_SIZE;
+use crate::fs::partition::{mbr::FsType, parsing_partition_table};
 use crate::fs::fs_backend::fat32::Fat32Fs;
-use spin::Mutex;
+use crate::sync::SleepLock;
 use crate::task::file_loader;
 #[cfg(feature = "ext4")]
 use crate::fs::fs_backend::{Ext4BlockDevice, Ext4Fs};
@@ -57,19 +61,16 @@ struct utsname{
 
 pub fn sys_nanosleep(req_ptr: usize, rem_ptr: usize) -> isize {
     if req_ptr == 0 {
-        return -1;
+        return -Errno::EFAULT;
     }
 
-    let user_satp = TASK_MANAER.get_current_stap();
-    let mut tb = PageTable::crate_table_from_satp(user_satp);
-    let req_pa = tb.translate(VirAddr(req_ptr));
-    if req_pa.is_none() {
-        return -1;
-    }
-    let req = unsafe { &*(req_pa.unwrap().0 as *const Timespec) };
+    let req = match UserPtr::<Timespec>::new(req_ptr).read() {
+        Ok(req) => req,
+        Err(e) => return -e,
+    };
 
     if req.tv_sec < 0 || req.tv_nsec < 0 {
-        return -1;
+        return -Errno::EINVAL;
     }
 
     let ns_total = (req.tv_sec as i128)
@@ -80,25 +81,62 @@ pub fn sys_nanosleep(req_ptr: usize, rem_ptr: usize) -> isize {
     } else {
         ((ns_total + 999_999i128) / 1_000_000i128) as usize
     };
-    let start = get_time_ms();
-    let target = start.saturating_add(ms);
+    let target = get_time_ms().saturating_add(ms);
+    nanosleep_until(target, rem_ptr)
+}
 
-    while get_time_ms() < target {
-        TASK_MANAER.suspend_and_run_task();
+///挂到定时器上睡到 target，到期或者有信号时醒来
+/// 被打断时把剩下的时间写回 rem，返回 ERESTART_RESTARTBLOCK：有处理函数运行时用户看到 EINTR，
+/// 没有时经 restart_syscall 只睡剩下的时间
+fn nanosleep_until(target: usize, rem_ptr: usize) -> isize {
+    if sleep_until_ms(target) {
+        return 0;
     }
-
     if rem_ptr != 0 {
-        let rem_pa = tb.translate(VirAddr(rem_ptr));
-        if let Some(pa) = rem_pa {
-            unsafe {
-                *(pa.0 as *mut Timespec) = Timespec { tv_sec: 0, tv_nsec: 0 };
-            }
+        let left = target.saturating_sub(get_time_ms());
+        let rem = Timespec {
+            tv_sec: (left / 1000) as i64,
+            tv_nsec: ((left % 1000) * 1_000_000) as i64,
+        };
+        if let Err(e) = UserPtr::new(rem_ptr).write(&rem) {
+            return -e;
         }
     }
-    0
+    TASK_MANAER.expect_current_task().lock().restart_block = Some(RestartBlock::Nanosleep { target_ms: target, rem_ptr });
+    -Errno::ERESTART_RESTARTBLOCK
+}
+
+///SYS_RESTART_SYSCALL：接着做被信号打断的 ERESTART_RESTARTBLOCK 系统调用，由信号递送设置，用户不直接调用
+pub fn sys_restart_syscall() -> isize {
+    let block = TASK_MANAER.expect_current_task().lock().restart_block.take();
+    match block {
+        Some(RestartBlock::Nanosleep { target_ms, rem_ptr }) => nanosleep_until(target_ms, rem_ptr),
+        None => -Errno::EINTR,
+    }
+}
+
+///SYS_FUTEX系统调用 futex(uaddr, op, val, timeout/val2, uaddr2, val3)
+/// 超时按毫秒向上取整，WAIT_BITSET 的绝对时间和 gettimeofday 用同一个时钟
+pub fn sys_futex(uaddr: usize, op: usize, val: usize, timeout_ptr: usize, uaddr2: usize, val3: usize) -> isize {
+    let mut timeout_ms = None;
+    if futex_op_has_timeout(op) && timeout_ptr != 0 {
+        let ts = match UserPtr::<Timespec>::new(timeout_ptr).read() {
+            Ok(ts) => ts,
+            Err(e) => return -e,
+        };
+        if ts.tv_sec < 0 || ts.tv_nsec < 0 || ts.tv_nsec >= 1_000_000_000 {
+            return -Errno::EINVAL;
+        }
+        let ms = (ts.tv_sec as usize)
+            .saturating_mul(1000)
+            .saturating_add((ts.tv_nsec as usize + 999_999) / 1_000_000);
+        timeout_ms = Some(ms);
+    }
+    do_futex(uaddr, op, val, timeout_ms, timeout_ptr, uaddr2, val3)
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct Tms { 
     pub tms_utime: usize, // 进程用户态消耗的tick数
     pub tms_stime: usize, // 进程内核态消耗的tick数
@@ -110,60 +148,53 @@ pub struct Tms {
 ///
 /// Linux riscv64: clone(flags, stack, ptid, tls, ctid)
 ///
-/// 兼容策略（为了通过基础测试并保持语义正确）：
-/// 1) 仅支持 flags 的低 8bit（signal number），其他高位必须为 0。
-/// 2) 仅支持 stack/ptid/tls/ctid 全部为 0（不实现线程/共享地址空间等复杂语义）。
-/// 3) 在支持的情况下，行为等价于 fork：父进程返回子 pid，子进程返回 0。
+/// 1) flags 的低 8bit 是子进程退出时发给父进程的信号，不是 SIGCHLD 的子进程只有 wait 带 __WCLONE/__WALL 才等得到。
+/// 2) 支持 CLONE_VM/CLONE_FILES/CLONE_THREAD/CLONE_SETTLS 和三个 TID 标志，够 pthread_create 用；其它标志忽略。
+/// 3) 父进程返回子任务的 tid，子任务返回 0。
 pub fn sys_clone(flags: usize, stack: usize, ptid: usize, tls: usize, ctid: usize) -> isize {
     let upper = flags & !0xffusize;
     
-    // 没传信号处理
-    sys_fork(CloneFlags::from_bits_truncate(upper),stack,ptid,tls,ctid)
+    // 低 8 位是子进程退出时发给父进程的信号
+    sys_fork(CloneFlags::from_bits_truncate(upper),flags & 0xff,stack,ptid,tls,ctid)
 
     
 }
 
 pub fn sys_gettimeofday(tv_ptr: usize, _tz_ptr: usize) -> isize {
     if tv_ptr == 0 {
-        return -1;
+        return -Errno::EFAULT;
     }
     let ms = get_time_ms();
     let sec = ms / 1000;
     let usec = (ms % 1000) * 1000;
     let time_val = TimeVal { sec, usec };
-    let satp = TASK_MANAER.get_current_stap();
-    let mut tb = PageTable::crate_table_from_satp(satp);
-    let phyaddr = tb.translate(VirAddr(tv_ptr));
-    if phyaddr.is_none(){
+    if let Err(e) = UserPtr::new(tv_ptr).write(&time_val) {
         error!("[sys_gettimeofday]: invalid addr!");
-        return -1;
-    }
-    unsafe {
-        *(phyaddr.unwrap().0 as *mut TimeVal) = time_val;
+        return -e;
     }
     return 0;
 }
 
 pub fn sys_times(tms_ptr: usize) -> isize { // 返回从系统启动至今所经过的时钟滴答数
     if tms_ptr == 0 {
-        return -1;
+        return -Errno::EFAULT;
     }
     let time_tick = get_time_tick(); // 系统tick数
-    let satp = TASK_MANAER.get_current_stap();
-    let mut tb = PageTable::crate_table_from_satp(satp);
-    let phyaddr = tb.translate(VirAddr(tms_ptr));
-    if phyaddr.is_none(){
-        error!("[sys_gettimeofday]: invalid addr!");
-        return -1;
-    }
+    // 进程自己的时间和已经 wait 回收的子进程累计
+    let (usage, child_usage) = {
+        let task = TASK_MANAER.expect_current_task();
+        let t = task.lock();
+        (t.usage, t.child_usage)
+    };
     let tms_st = Tms{
-        tms_stime:time_tick,
-        tms_utime:time_tick,
-        tms_cutime:time_tick,
-        tms_cstime:time_tick,
+        tms_stime:usage.stime,
+        tms_utime:usage.utime,
+        tms_cutime:child_usage.utime,
+        tms_cstime:child_usage.stime,
     };
-    unsafe {
-        *(phyaddr.unwrap().0 as *mut Tms) = tms_st;
+    if let Err(e) = UserPtr::new(tms_ptr).write(&tms_st) {
+        error!("[sys_times]: invalid addr!");
+        return -e;
     }
     return time_tick as isize;
 }
@@ -174,11 +205,11 @@ pub fn sys_times(tms_ptr: usize) -> isize { // 返回从系统启动至今所经
 /// 1) 只支持通过 `target` 路径创建挂载点（必须是已存在目录，且不能是 `/`）。
 /// 2) `source` / `mountflags` / `data` 目前不参与实际行为（仅做参数读取/基本校验）。
 /// 3) `filesystemtype` 目前仅支持 "ext4"（在 feature=ext4 下生效）。
-/// 4) 返回值遵循 POSIX：成功返回 0，失败返回 -1。
+/// 4) 返回值遵循 POSIX：成功返回 0，失败返回负的 errno。
 pub fn sys_mount(source_ptr: usize, target_ptr: usize, fstype_ptr: usize, _flags: usize, _data_ptr: usize) -> isize {
     if target_ptr == 0 || fstype_ptr == 0 {
         error!("sys_mount: invalid args target_ptr={:#x} fstype_ptr={:#x}", target_ptr, fstype_ptr);
-        return -1;
+        return -Errno::EFAULT;
     }
 
     let source = if source_ptr == 0 {
@@ -188,7 +219,7 @@ pub fn sys_mount(source_ptr: usize, target_ptr: usize, fstype_ptr: usize, _flags
             Ok(s) => s,
             Err(e) => {
                 error!("sys_mount: invalid source ptr={:#x} err={}", source_ptr, e);
-                return -1;
+                return -e;
             }
         }
     };
@@ -197,7 +228,7 @@ pub fn sys_mount(source_ptr: usize, target_ptr: usize, fstype_ptr: usize, _flags
         Ok(s) => s,
         Err(e) => {
             error!("sys_mount: invalid target ptr={:#x} err={}", target_ptr, e);
-            return -1;
+            return -e;
         }
     };
 
@@ -205,7 +236,7 @@ pub fn sys_mount(source_ptr: usize, target_ptr: usize, fstype_ptr: usize, _flags
         Ok(s) => s,
         Err(e) => {
             error!("sys_mount: invalid fstype ptr={:#x} err={}", fstype_ptr, e);
-            return -1;
+            return -e;
         }
     };
 
@@ -216,36 +247,36 @@ pub fn sys_mount(source_ptr: usize, target_ptr: usize, fstype_ptr: usize, _flags
         Ok(p) => p,
         Err(e) => {
             error!("sys_mount: normalize target failed target={} err={:?}", target, e);
-            return -1;
+            return -e;
         }
     };
     if abs_target == "/" {
         // 不允许覆盖根挂载点
         error!("sys_mount: refuse to mount on /");
-        return -1;
+        return -Errno::EBUSY;
     }
     let st = match vfs_stat(&abs_target) {
         Ok(s) => s,
         Err(e) => {
             error!("sys_mount: target stat failed target={} err={}", abs_target, e);
-            return -1;
+            return -e;
         }
     };
     if st.file_type != VFS_DT_DIR {
         error!("sys_mount: target is not dir target={} type={}", abs_target, st.file_type);
-        return -1;
+        return -Errno::ENOTDIR;
     }
 
     let abs_source = match normalize_path(&source) {
         Ok(p) => p,
         Err(e) => {
             error!("sys_mount: normalize source failed source={} err={:?}", source, e);
-            return -1;
+            return -e;
         }
     };
     if abs_source.is_empty() {
         error!("sys_mount: empty source");
-        return -1;
+        return -Errno::ENOENT;
     }
 
     fn base_disk_path(abs_source: &str) -> Option<(&str, usize)> {
@@ -267,24 +298,30 @@ pub fn sys_mount(source_ptr: usize, target_ptr: usize, fstype_ptr: usize, _flags
         Some((&abs_source[..end], idx))
     }
 
-    fn read_partition_type(disk: &Arc<dyn File>, part_idx_1based: usize) -> Result<u8, VfsFsError> {
-        if part_idx_1based == 0 || part_idx_1based > 4 {
+    /// MBR/GPT 都按 /dev/xxxN 的编号（第N个已知类型分区）取分区类型
+    fn read_partition_type(disk: &Arc<dyn File>, part_idx_1based: usize) -> Result<FsType, VfsFsError> {
+        if part_idx_1based == 0 {
             return Err(VfsFsError::Invalid);
         }
-        let mut mbr = [0u8; SECTOR_SIZE];
-        disk.read_at(0, &mut mbr)?;
-        if mbr[510] != 0x55 || mbr[511] != 0xAA {
-            return Err(VfsFsError::Invalid);
-        }
-        let base = 0x1BE + (part_idx_1based - 1) * 16;
-        Ok(mbr[base + 4])
+        let total_sectors = disk.stat()?.size / SECTOR_SIZE as u64;
+        let mut read_sector = |lba: u64, buf: &mut [u8; SECTOR_SIZE]| {
+            match disk.read_at(lba as usize * SECTOR_SIZE, buf) {
+                Ok(n) if n == SECTOR_SIZE => Ok(()),
+                _ => Err("read sector failed"),
+            }
+        };
+        let parts = parsing_partition_table(&mut read_sector, total_sectors).map_err(|_| VfsFsError::Invalid)?;
+        parts
+            .get(part_idx_1based - 1)
+            .and_then(|p| p.fs_type())
+            .ok_or(VfsFsError::Invalid)
     }
 
     let (disk_path, part_idx) = match base_disk_path(&abs_source) {
         Some(v) => v,
         None => {
             error!("sys_mount: unsupported source path (expect /dev/xxxN) abs_source={}", abs_source);
-            return -1;
+            return -Errno::ENOTBLK;
         }
     };
 
@@ -294,23 +331,23 @@ pub fn sys_mount(source_ptr: usize, target_ptr: usize, fstype_ptr: usize, _flags
         Ok(f) => f,
         Err(e) => {
             error!("sys_mount: open disk failed disk_path={} err={}", disk_path, e);
-            return -1;
+            return -e;
         }
     };
     let ptype = match read_partition_type(&disk, part_idx) {
         Ok(t) => t,
         Err(e) => {
             error!("sys_mount: read partition type failed disk_path={} part_idx={} err={}", disk_path, part_idx, e);
-            return -1;
+            return -e;
         }
     };
 
-    debug!("sys_mount: mbr partition type=0x{:02x}", ptype);
+    debug!("sys_mount: partition type={:?}", ptype);
 
     let auto_fs = match ptype {
-        0x83 => "ext4",
-        0x0b | 0x0c => "fat32",
-        0x0e => "fat16",
+        FsType::Linux => "ext4",
+        FsType::Fat32 => "fat32",
+        FsType::Fat16 => "fat16",
         _ => "unknown",
     };
 
@@ -320,19 +357,19 @@ pub fn sys_mount(source_ptr: usize, target_ptr: usize, fstype_ptr: usize, _flags
         other => other,
     };
     let req_fs = if is_auto { auto_fs } else { explicit_fs };
-    debug!("sys_mount: auto_fs={} req_fs={} ptype=0x{:02x}", auto_fs, req_fs, ptype);
+    debug!("sys_mount: auto_fs={} req_fs={} ptype={:?}", auto_fs, req_fs, ptype);
 
     // POSIX 语义：若用户显式指定了 fstype，则按用户指定尝试挂载。
     // 只有 fstype=auto 时才依赖分区类型做自动判定。
     if is_auto {
         if req_fs == "fat16" || req_fs == "unknown" {
-            error!("sys_mount: unsupported fs req_fs={} ptype=0x{:02x}", req_fs, ptype);
-            return -1;
+            error!("sys_mount: unsupported fs req_fs={} ptype={:?}", req_fs, ptype);
+            return -Errno::ENODEV;
         }
     } else {
         if req_fs != "ext4" && req_fs != "fat32" {
-            error!("sys_mount: unsupported explicit fstype={} ptype=0x{:02x}", explicit_fs, ptype);
-            return -1;
+            error!("sys_mount: unsupported explicit fstype={} ptype={:?}", explicit_fs, ptype);
+            return -Errno::ENODEV;
         }
     }
 
@@ -340,21 +377,21 @@ pub fn sys_mount(source_ptr: usize, target_ptr: usize, fstype_ptr: usize, _flags
         Ok(f) => f,
         Err(e) => {
             error!("sys_mount: open source device failed abs_source={} err={}", abs_source, e);
-            return -1;
+            return -e;
         }
     };
 
-    let new_fs: Arc<Mutex<dyn VfsFs>> = match req_fs {
+    let new_fs: Arc<SleepLock<dyn VfsFs>> = match req_fs {
         "ext4" => {
             #[cfg(feature = "ext4")]
             {
                 let blk = Ext4BlockDevice::new(src_dev);
-                Arc::new(Mutex::new(Ext4Fs::new(blk))) as Arc<Mutex<dyn VfsFs>>
+                Arc::new(SleepLock::new(Ext4Fs::new(blk))) as Arc<SleepLock<dyn VfsFs>>
             }
             #[cfg(not(feature = "ext4"))]
             {
                 error!("sys_mount: ext4 requested but ext4 feature is disabled");
-                return -1;
+                return -Errno::ENODEV;
             }
         }
         "fat32" => {
@@ -362,17 +399,17 @@ pub fn sys_mount(source_ptr: usize, target_ptr: usize, fstype_ptr: usize, _flags
                 Ok(v) => v,
                 Err(e) => {
                     error!("sys_mount: fat32 init failed err={}", e);
-                    return -1;
+                    return -e;
                 }
             };
-            Arc::new(Mutex::new(fs)) as Arc<Mutex<dyn VfsFs>>
+            Arc::new(SleepLock::new(fs)) as Arc<SleepLock<dyn VfsFs>>
         }
-        _ => return -1,
+        _ => return -Errno::ENODEV,
     };
 
     if let Err(e) = new_fs.lock().mount() {
         error!("sys_mount: fs.mount failed req_fs={} err={}", req_fs, e);
-        return -1;
+        return -e;
     }
 
     let mut root = ROOTFS.lock();
@@ -380,13 +417,13 @@ pub fn sys_mount(source_ptr: usize, target_ptr: usize, fstype_ptr: usize, _flags
         Some(r) => r,
         None => {
             error!("sys_mount: ROOTFS not initialized");
-            return -1;
+            return -Errno::EINVAL;
         }
     };
     let key = MountPath(abs_target);
     if rootfs.mount_poinr.contains_key(&key) {
         error!("sys_mount: target already mounted target={}", key.0);
-        return -1;
+        return -Errno::EBUSY;
     }
     rootfs.mount_poinr.insert(key, new_fs);
     //debug!("sys_mount: mount success source={} target={} fstype={}", abs_source, key.0, req_fs);
@@ -398,55 +435,60 @@ pub fn sys_mount(source_ptr: usize, target_ptr: usize, fstype_ptr: usize, _flags
 /// 中文说明（当前内核的最小实现/简化点）：
 /// 1) 仅支持按 `target` 卸载挂载点；不支持 lazy/unlink 等 flags 语义（flags 暂时忽略）。
 /// 2) 不允许卸载根挂载点 `/`。
-/// 3) 返回值遵循 POSIX：成功返回 0，失败返回 -1。
+/// 3) 返回值遵循 POSIX：成功返回 0，失败返回负的 errno。
 pub fn sys_umount2(target_ptr: usize, _flags: usize) -> isize {
     if target_ptr == 0 {
-        return -1;
+        return -Errno::EFAULT;
     }
     let target = match read_c_string_from_user(target_ptr) {
         Ok(s) => s,
         Err(e) => {
             error!("sys_umount2: invalid target ptr={:#x} err={}", target_ptr, e);
-            return -1;
+            return -e;
         }
     };
     let abs_target = match normalize_path(&target) {
         Ok(p) => p,
-        Err(_) => return -1,
+        Err(e) => return -e,
     };
     if abs_target == "/" {
-        return -1;
+        return -Errno::EBUSY;
     }
 
     let mut root = ROOTFS.lock();
     let rootfs = match root.as_mut() {
         Some(r) => r,
-        None => return -1,
+        None => return -Errno::EINVAL,
     };
 
     let key = MountPath(abs_target);
 
     // 遍历进程列表确保任何进程不在挂载点路径上
-    let mp_busy = TASK_MANAER.task_que_inner.lock().task_queen.iter().any(|task|{
+    let mp_busy = TASK_MANAER.lock_inner().task_queen.iter().any(|task|{
         let tcwd = &task.lock().cwd;
         tcwd.starts_with(&key.0)
     });
 
     if mp_busy {
         error!("[sys_umount]: Vblock:{} busy!",&key.0);
-        return -1;
+        return -Errno::EBUSY;
     }
 
 
 
     let Some(fs) = rootfs.mount_poinr.remove(&key) else {
-        return -1;
+        return -Errno::EINVAL;
     };
+    // 回写和卸载要等块设备 I/O，放掉 ROOTFS 再做
+    drop(root);
+
+    // 卸载前写回页缓存里的脏页
+    filecache_sync_and_drop_fs(&fs);
 
     if let Err(e) = fs.lock().umount() {
         error!("sys_umount2: fs.umount failed err={}", e);
         // best-effort: keep entry removed to avoid inconsistent resolution
-        return -1;
+        return -e;
     }
     0
 }
@@ -459,7 +501,7 @@ impl utsname {
 ///buf:&mut utsname as *mut _ as usize
 pub fn sys_uname(buf:usize)->isize{
     if buf == 0 {
-        return -1;
+        return -Errno::EFAULT;
     }
 
     fn fill_field(dst: &mut [u8; utname_field_len], s: &str) {
@@ -470,52 +512,7 @@ pub fn sys_uname(buf:usize)->isize{
         dst[n] = 0;
     }
 
-    fn user_range_writable(satp: usize, start: usize, len: usize) -> bool {
-        if len == 0 {
-            return true;
-        }
-        let mut pt = PageTable::crate_table_from_satp(satp);
-        let start_addr = VirAddr(start);
-        let end_addr = VirAddr(start.saturating_add(len));
-        let mut addr = start_addr;
-        while addr < end_addr {
-            let vpn = addr.floor_down();
-            let Some(pte) = pt.find_pte_vpn(vpn) else {
-                return false;
-            };
-            if !pte.is_valid() {
-                return false;
-            }
-            let flags = pte.flags();
-            if !flags.contains(PTEFlags::U) || !flags.contains(PTEFlags::W) {
-                return false;
-            }
-            let next_page: VirAddr = VirNumber(vpn.0 + 1).into();
-            addr = next_page;
-        }
-        true
-    }
-
-    fn copy_to_user(satp: usize, dst: usize, src: &[u8]) -> bool {
-        let mut pt = PageTable::crate_table_from_satp(satp);
-        for (i, b) in src.iter().enumerate() {
-            let vaddr = VirAddr(dst.saturating_add(i));
-            let Some(paddr) = pt.translate(vaddr) else {
-                return false;
-            };
-            unsafe {
-                *(paddr.0 as *mut u8) = *b;
-            }
-        }
-        true
-    }
-
-    let user_satp = TASK_MANAER.get_current_stap();
     let total_len = core::mem::size_of::<utsname>();
-    if !user_range_writable(user_satp, buf, total_len) {
-        return -1;
-    }
-
     let mut u = utsname::new();
     fill_field(&mut u.sysname, "Linux");
     fill_field(&mut u.nodename, "BlueStarOS");
@@ -527,8 +524,8 @@ pub fn sys_uname(buf:usize)->isize{
     let bytes: &[u8] = unsafe {
         core::slice::from_raw_parts((&u as *const utsname) as *const u8, total_len)
     };
-    if !copy_to_user(user_satp, buf, bytes) {
-        return -1;
+    if let Err(e) = UserSlice::new(buf, total_len).write(bytes) {
+        return -e;
     }
     0
 }
@@ -539,22 +536,19 @@ pub fn sys_uname(buf:usize)->isize{
 pub fn sys_dup2(old_fd:i32,new_fd:i32) ->isize{
 
     if old_fd < 0 || new_fd < 0 {
-        return -1;
+        return -Errno::EBADF;
     }
 
-    let current_task = {
-        let inner = TASK_MANAER.task_que_inner.lock();
-        inner.task_queen[inner.current].clone()
-    };
+    let fd_table = TASK_MANAER.current_fd_table();
 
-    let mut tcb = current_task.lock();
+    let mut table = fd_table.lock();
 
     let old_idx = old_fd as usize;
-    if old_idx >= tcb.file_descriptor.len() {
-        return -1;
+    if old_idx >= table.len() {
+        return -Errno::EBADF;
     }
-    let Some(source_fd) = tcb.file_descriptor[old_idx].clone() else {
-        return -1;
+    let Some(source_fd) = table[old_idx].clone() else {
+        return -Errno::EBADF;
     };
 
     if old_fd == new_fd {
@@ -562,13 +556,14 @@ pub fn sys_dup2(old_fd:i32,new_fd:i32) ->isize{
     }
 
     let new_idx = new_fd as usize;
-    if new_idx >= tcb.file_descriptor.len() {
-        tcb.file_descriptor.resize_with(new_idx + 1, || None);
+    if new_idx >= table.len() {
+        table.resize_with(new_idx + 1, || None);
     }
 
-    // close(newfd) if it is open
-    tcb.file_descriptor[new_idx] = None;
-    tcb.file_descriptor[new_idx] = Some(source_fd);
+    // close(newfd) if it is open，放掉描述符表的锁之后再关闭
+    let old = table[new_idx].replace(source_fd);
+    drop(table);
+    drop(old);
     new_fd as isize
 }
 
@@ -576,57 +571,63 @@ pub fn sys_dup2(old_fd:i32,new_fd:i32) ->isize{
 /// 返回一个符合最小fd的结果
 /// 传入需要复制的fd
 pub fn sys_dup(old_fd:i32) ->isize{
-    let current_task = {
-        let inner = TASK_MANAER.task_que_inner.lock();
-        inner.task_queen[inner.current].clone()
-    };
+    let fd_table = TASK_MANAER.current_fd_table();
 
-    let mut tcb = current_task.lock();
+    let mut table = fd_table.lock();
 
     if old_fd < 0 {
-        return -1;
+        return -Errno::EBADF;
     }
     let old_idx = old_fd as usize;
-    if old_idx >= tcb.file_descriptor.len() {
-        return -1;
+    if old_idx >= table.len() {
+        return -Errno::EBADF;
     }
-    let Some(source_fd) = tcb.file_descriptor[old_idx].clone() else {
-        return -1;
+    let Some(source_fd) = table[old_idx].clone() else {
+        return -Errno::EBADF;
     };
 
-    if let Some((idx, _)) = tcb
-        .file_descriptor
+    if let Some((idx, _)) = table
         .iter()
         .enumerate()
         .find(|(_, slot)| slot.is_none())
     {
-        tcb.file_descriptor[idx] = Some(source_fd);
+        table[idx] = Some(source_fd);
         return idx as isize;
     }
 
     // No empty slot: grow fd table.
-    let idx = tcb.file_descriptor.len();
-    tcb.file_descriptor.push(Some(source_fd));
+    let idx = table.len();
+    table.push(Some(source_fd));
     idx as isize
 }
 
+///线程组里的线程返回同一个 pid（主线程的 tid）
 pub fn sys_getpid() -> isize {
-    let current_task = {
-        let inner = TASK_MANAER.task_que_inner.lock();
-        inner.task_queen[inner.current].clone()
-    };
+    let current_task = TASK_MANAER.expect_current_task();
+    let re = current_task.lock().tgid;
+    re as isize
+}
+
+pub fn sys_gettid() -> isize {
+    let current_task = TASK_MANAER.expect_current_task();
     let re = current_task.lock().pid.0;
     re as isize
 }
 
+///SYS_SET_TID_ADDRESS系统调用 线程退出时把 tidptr 指向的 tid 清零，返回调用者的 tid
+pub fn sys_set_tid_address(tidptr: usize) -> isize {
+    let current_task = TASK_MANAER.expect_current_task();
+    let mut tcb = current_task.lock();
+    tcb.clear_child_tid = tidptr;
+    tcb.pid.0 as isize
+}
+
 pub fn sys_getppid() -> isize {
-    let current_task = {
-        let inner = TASK_MANAER.task_que_inner.lock();
-        inner.task_queen[inner.current].clone()
-    };
-    let tcb = current_task.lock();
-    if let Some(parent) = tcb.parent.as_ref().and_then(|w| w.upgrade()) {
-        parent.lock().pid.0 as isize
+    let current_task = TASK_MANAER.expect_current_task();
+    // 不同时持有父子两把锁，回收子进程时是先锁父再锁子
+    let parent = current_task.lock().parent.as_ref().and_then(|w| w.upgrade());
+    if let Some(parent) = parent {
+        parent.lock().tgid as isize
     } else {
         0
     }
@@ -638,14 +639,11 @@ pub fn sys_getppid() -> isize {
 pub fn sys_brk(new_brk:VirAddr)->isize{ 
     let new_brkaddr = new_brk.0;
 
-    // 先取出当前 task 的 Arc，避免持有 task queue 的锁期间再 lock task。
-    let current_task = {
-        let inner = TASK_MANAER.task_que_inner.lock();
-        inner.task_queen[inner.current].clone()
-    };
+    // 线程共用地址空间，brk 记在 MapSet 上
+    let memory_set = TASK_MANAER.current_memory_set();
 
-    let mut tcb = current_task.lock();
-    let old_brk = tcb.memory_set.brk.0;
+    let mut memset = memory_set.lock();
+    let old_brk = memset.brk.0;
 
     // Linux 语义：brk(0) 只查询当前 break。
     if new_brkaddr == 0 {
@@ -654,7 +652,7 @@ pub fn sys_brk(new_brk:VirAddr)->isize{
 
     // shrink：先只更新 brk，不回收映射（最小实现，优先兼容测试）。
     if new_brkaddr <= old_brk {
-        tcb.memory_set.brk = VirAddr(new_brkaddr);
+        memset.brk = VirAddr(new_brkaddr);
         return new_brkaddr as isize;
     }
 
@@ -667,16 +665,19 @@ pub fn sys_brk(new_brk:VirAddr)->isize{
     if start_vpn.0 <= end_vpn.0 {
         // 注意：add_area 会检查区间是否与现有 MapArea 重叠，
         // 所以这里从 floor_up(old_brk) 开始，避免覆盖旧页。
-        tcb.memory_set.add_area(
+        // 物理页帧不够时 brk 不变，返回原来的 brk，用户库据此报 ENOMEM
+        if !memset.add_area(
             crate::memory::VirNumRange(start_vpn, end_vpn),
             crate::memory::MapType::Maped,
             crate::memory::MapAreaFlags::R | crate::memory::MapAreaFlags::W | crate::memory::MapAreaFlags::U,
             None,
             None,
-        );
+        ) {
+            return old_brk as isize;
+        }
     }
 
-    tcb.memory_set.brk = VirAddr(new_brkaddr);
+    memset.brk = VirAddr(new_brkaddr);
     new_brkaddr as isize
 }
 
@@ -685,87 +686,78 @@ pub fn sys_brk(new_brk:VirAddr)->isize{
 pub fn sys_execve(path_ptr: usize, argv_ptr: usize, envp_ptr: usize) -> isize {
     const MAX_ARGC: usize = 256;
 
-    // 关键：先拿到 satp，再去 lock 当前 task，避免二次借用。
-    let user_satp = TASK_MANAER.get_current_stap();
-
     debug!(
-        "sys_execve: path_ptr={:#x} argv_ptr={:#x} envp_ptr={:#x} satp={:#x}",
-        path_ptr, argv_ptr, envp_ptr, user_satp
+        "sys_execve: path_ptr={:#x} argv_ptr={:#x} envp_ptr={:#x}",
+        path_ptr, argv_ptr, envp_ptr
     );
 
     if envp_ptr != 0 {
         warn!("sys_execve: envp is ignored for now envp_ptr={:#x}", envp_ptr);
     }
 
-    let path = match read_c_string_from_user_with_satp(user_satp, path_ptr) {
+    let path = match read_c_string_from_user(path_ptr) {
         Ok(p) => p,
-        Err(_) => return -1,
+        Err(e) => return -e,
     };
 
-    let elf_data = file_loader(&path);
-    if elf_data.is_empty() {
-        return -1;
-    }
+    let elf_file = match file_loader(&path) {
+        Some(f) => f,
+        None => return -Errno::ENOENT,
+    };
 
     // 读取 argv 指针数组（NULL 结尾）
     let mut exec_argv: Vec<String> = Vec::new();
     if argv_ptr != 0 {
         for i in 0..MAX_ARGC {
-            let elem_ptr = argv_ptr + i * core::mem::size_of::<usize>();
-            let mut slices = PageTable::get_mut_slice_from_satp(
-                user_satp,
-                core::mem::size_of::<usize>(),
-                VirAddr(elem_ptr),
-            );
-            if slices.is_empty() {
-                error!("sys_execve: invalid argv element addr={:#x}", elem_ptr);
-                return -1;
-            }
-            let mut flat: Vec<u8> = Vec::with_capacity(core::mem::size_of::<usize>());
-            for s in slices.iter_mut() {
-                flat.extend_from_slice(s);
-            }
-            if flat.len() < core::mem::size_of::<usize>() {
-                error!("sys_execve: short read argv element addr={:#x}", elem_ptr);
-                return -1;
-            }
-            let ptr_bytes: [u8; core::mem::size_of::<usize>()] = flat[..core::mem::size_of::<usize>()]
-                .try_into()
-                .unwrap();
-            let cptr = usize::from_ne_bytes(ptr_bytes);
+            let elem = match UserPtr::<usize>::new(argv_ptr).add(i) {
+                Ok(elem) => elem,
+                Err(e) => return -e,
+            };
+            let cptr = match elem.read() {
+                Ok(cptr) => cptr,
+                Err(e) => {
+                    error!("sys_execve: invalid argv element addr={:#x}", elem.addr());
+                    return -e;
+                }
+            };
             if cptr == 0 {
                 break;
             }
-            match read_c_string_from_user_with_satp(user_satp, cptr) {
+            match read_c_string_from_user(cptr) {
                 Ok(s) => exec_argv.push(s),
                 Err(e) => {
                     error!(
                         "sys_execve: Can't translate argv[{}] ptr={:#x} err={}",
                         i, cptr, e
                     );
-                    return -1;
+                    return -e;
                 }
             }
         }
     }
 
     let argc = exec_argv.len();
-    let current_task = {
-        let inner = TASK_MANAER.task_que_inner.lock();
-        inner.task_queen[inner.current].clone()
+    // 旧地址空间马上要换掉，先采一次驻留页峰值
+    TASK_MANAER.update_current_rss();
+    // 建新地址空间要读 ELF 头，不能拿着 TCB 锁
+    let Some(loaded) = MapSet::from_elf(elf_file) else {
+        warn!("sys_execve: Can't create elf file");
+        return -Errno::ENOEXEC;
     };
-    {
-        let mut tcb = current_task.lock();
-        if !tcb.new_exec_task_with_elf(&path, exec_argv, argc, &elf_data) {
-            return -1;
-        }
-    }
+    let current_task = TASK_MANAER.expect_current_task();
+    let (old_memory_set, old_trap_cx_addr) = {
+        let tcb = current_task.lock();
+        (tcb.memory_set.clone(), tcb.trap_context_addr)
+    };
+    current_task.lock().new_exec_task_with_elf(&path, exec_argv, argc, loaded);
+    // 线程 exec 之后换了新的地址空间，旧地址空间里它的陷阱上下文页还给别的线程
+    old_memory_set.lock().unmap_thread_trapContext(old_trap_cx_addr);
     0
 }
 
 pub fn sys_pipe(fds_ptr: usize) -> isize {
     if fds_ptr == 0 {
-        return -1;
+        return -Errno::EFAULT;
     }
 
     let (read_end, write_end) = make_pipe();
@@ -774,35 +766,18 @@ pub fn sys_pipe(fds_ptr: usize) -> isize {
 
     let rfd:i32 = TASK_MANAER.alloc_fd_for_current(read_fd);
     if rfd < 0 {
-        return -1;
+        return -Errno::EMFILE;
     }
     let wfd:i32 = TASK_MANAER.alloc_fd_for_current(write_fd);
     if wfd < 0 {
-        return -1;
+        return -Errno::EMFILE;
     }
 
-    let user_satp = TASK_MANAER.get_current_stap();
-    let mut slices = PageTable::get_mut_slice_from_satp(
-        user_satp,
-        core::mem::size_of::<usize>() * 2,
-        VirAddr(fds_ptr),
-    );
-
-    let mut tmp: [u8; core::mem::size_of::<i32>() * 2] = [0u8; core::mem::size_of::<i32>() * 2];
-    tmp[..core::mem::size_of::<i32>()].copy_from_slice(&rfd.to_ne_bytes());
-    tmp[core::mem::size_of::<i32>()..].copy_from_slice(&wfd.to_ne_bytes());
-
-    let mut off = 0usize;
-    for s in slices.iter_mut() {
-        if off >= tmp.len() {
-            break;
-        }
-        let n = core::cmp::min(s.len(), tmp.len() - off);
-        s[..n].copy_from_slice(&tmp[off..off + n]);
-        off += n;
-    }
-    if off != tmp.len() {
-        return -1;
+    if let Err(e) = UserPtr::new(fds_ptr).write(&[rfd, wfd]) {
+        // 写不回去用户就拿不到这两个 fd，关掉
+        TASK_MANAER.close_current_fd(rfd as usize);
+        TASK_MANAER.close_current_fd(wfd as usize);
+        return -e;
     }
     0
 }
@@ -812,25 +787,26 @@ pub fn sys_chdir(path_ptr: usize) -> isize {
         Ok(p) => p,
         Err(e) => {
             error!("sys_chdir: invalid user path ptr={:#x}, err={}", path_ptr, e);
-            return -1;
+            return -e;
         }
     };
 
     
-    let abs = match normalize_path(&path) {
+    // cwd 记展开符号链接之后的真实路径
+    let abs = match vfs_realpath(&path) {
         Ok(p) => p,
-        Err(_) => return -1,
+        Err(e) => return -e,
     };
 
     let st = match vfs_stat(&abs) {
         Ok(s) => s,
         Err(e) => {
             error!("sys_chdir: vfs_stat failed: path={} err={}", abs, e);
-            return -1;
+            return -e;
         }
     };
     if st.file_type != VFS_DT_DIR {
-        return -1;
+        return -Errno::ENOTDIR;
     }
 
     TASK_MANAER.set_current_cwd(abs);
@@ -838,8 +814,8 @@ pub fn sys_chdir(path_ptr: usize) -> isize {
 }
 
 pub fn sys_getcwd(user_buf_ptr: usize, buf_len: usize) -> isize {
-    if user_buf_ptr == 0 || buf_len == 0 {
-        return 0;
+    if user_buf_ptr == 0 {
+        return -Errno::EFAULT;
     }
 
     let cwd = TASK_MANAER.get_current_cwd();
@@ -847,114 +823,242 @@ pub fn sys_getcwd(user_buf_ptr: usize, buf_len: usize) -> isize {
     tmp.extend_from_slice(cwd.as_bytes());
     tmp.push(0);
 
+    // 缓冲区放不下路径和结尾的 0
     if tmp.len() > buf_len {
-        return 0;
+        return -Errno::ERANGE;
     }
 
-    let user_satp = TASK_MANAER.get_current_stap();
-    let mut slices = PageTable::get_mut_slice_from_satp(user_satp, tmp.len(), VirAddr(user_buf_ptr));
-    let mut off = 0usize;
-    for s in slices.iter_mut() {
-        if off >= tmp.len() {
-            break;
-        }
-        let n = core::cmp::min(s.len(), tmp.len() - off);
-        s[..n].copy_from_slice(&tmp[off..off + n]);
-        off += n;
-    }
-    if off != tmp.len() {
-        return 0;
+    if let Err(e) = UserSlice::new(user_buf_ptr, buf_len).write(&tmp) {
+        return -e;
     }
     user_buf_ptr as isize
 }
 
+///*at 系统调用的 dirfd：相对路径相对当前工作目录
+pub const AT_FDCWD: isize = -100;
+///newfstatat：最后一级是符号链接时不跟随
+const AT_SYMLINK_NOFOLLOW: usize = 0x100;
+///unlinkat：删除目录
+const AT_REMOVEDIR: usize = 0x200;
+///linkat：oldpath 是符号链接时链接到它指向的文件
+const AT_SYMLINK_FOLLOW: usize = 0x400;
+///newfstatat：路径为空时对 dirfd 本身操作
+const AT_EMPTY_PATH: usize = 0x1000;
+
+///dirfd 对应的打开文件
+fn at_dir_file(dirfd: isize) -> Result<Arc<dyn File>, Errno> {
+    if dirfd < 0 {
+        return Err(Errno::EBADF);
+    }
+    match TASK_MANAER.get_current_fd(dirfd as usize) {
+        Some(Some(f)) => Ok(f),
+        _ => Err(Errno::EBADF),
+    }
+}
+
+///*at 系统调用的路径解析，返回绝对路径（".." 留给 VFS 展开符号链接之后再处理）
+/// 绝对路径忽略 dirfd；相对路径 dirfd 是 AT_FDCWD 时接在 cwd 后面，否则接在 dirfd 打开的目录后面
+fn resolve_at_path(dirfd: isize, path: &str) -> Result<String, Errno> {
+    if path.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    if path.starts_with('/') || dirfd == AT_FDCWD {
+        return Ok(absolute_path(path));
+    }
+    // 只有目录 fd 记着自己的路径
+    let base = at_dir_file(dirfd)?.path().ok_or(Errno::ENOTDIR)?;
+    Ok(absolute_path(&format!("{}/{}", base, path)))
+}
+
+///从用户态读路径并按 dirfd 解析
+fn read_at_path(dirfd: isize, path_ptr: usize) -> Result<String, Errno> {
+    let path = read_c_string_from_user(path_ptr)?;
+    resolve_at_path(dirfd, &path)
+}
+
 pub fn sys_mkdirat(dirfd: isize, path_ptr: usize, _mode: usize) -> isize {
-    // NOTE: oscomp uses mkdir() implemented via mkdirat(AT_FDCWD,...,mode).
-    // We currently ignore dirfd/mode and rely on VFS/ext4 without permission bits.
-    let _ = dirfd;
-    let path = match read_c_string_from_user(path_ptr) {
+    // 没有权限位，mode 忽略
+    let path = match read_at_path(dirfd, path_ptr) {
         Ok(p) => p,
         Err(e) => {
-            error!("sys_mkdir: invalid user path ptr={:#x}, err={}", path_ptr, e);
-            return -1;
+            error!("sys_mkdirat: bad path dirfd={} ptr={:#x}, err={}", dirfd, path_ptr, e);
+            return -e;
         }
     };
     match vfs_mkdir(&path) {
         Ok(_) => 0,
         Err(e) => {
-            error!("sys_mkdir: vfs_mkdir failed: path={} err={}", path, e);
-            -1
+            error!("sys_mkdirat: vfs_mkdir failed: path={} err={}", path, e);
+            -e
         }
     }
 }
 
 pub fn sys_mkdir(path_ptr: usize) -> isize {
-    sys_mkdirat(-100, path_ptr, 0)
+    sys_mkdirat(AT_FDCWD, path_ptr, 0)
 }
 
-pub fn sys_unlink(path_ptr: usize) -> isize {
-    let path = match read_c_string_from_user(path_ptr) {
+///unlinkat(dirfd, path, flags)，带 AT_REMOVEDIR 时删除空目录
+pub fn sys_unlinkat(dirfd: isize, path_ptr: usize, flags: usize) -> isize {
+    if flags & !AT_REMOVEDIR != 0 {
+        return -Errno::EINVAL;
+    }
+    let path = match read_at_path(dirfd, path_ptr) {
         Ok(p) => p,
         Err(e) => {
-            error!("sys_unlink: invalid user path ptr={:#x}, err={}", path_ptr, e);
-            return -1;
+            error!("sys_unlinkat: bad path dirfd={} ptr={:#x}, err={}", dirfd, path_ptr, e);
+            return -e;
         }
     };
-    match vfs_unlink(&path) {
+    let ret = if flags & AT_REMOVEDIR != 0 {
+        vfs_rmdir(&path)
+    } else {
+        vfs_unlink(&path)
+    };
+    match ret {
         Ok(_) => 0,
         Err(e) => {
-            error!("sys_unlink: vfs_unlink failed: path={} err={}", path, e);
-            -1
+            error!("sys_unlinkat: failed: path={} flags={:#x} err={}", path, flags, e);
+            -e
+        }
+    }
+}
+
+///linkat(olddirfd, oldpath, newdirfd, newpath, flags)
+/// 默认不跟随 oldpath 上的符号链接（和 Linux 一样），AT_SYMLINK_FOLLOW 时跟随
+pub fn sys_linkat(olddirfd: isize, oldpath_ptr: usize, newdirfd: isize, newpath_ptr: usize, flags: usize) -> isize {
+    if flags & !AT_SYMLINK_FOLLOW != 0 {
+        return -Errno::EINVAL;
+    }
+    let old = match read_at_path(olddirfd, oldpath_ptr) {
+        Ok(p) => p,
+        Err(e) => return -e,
+    };
+    let new = match read_at_path(newdirfd, newpath_ptr) {
+        Ok(p) => p,
+        Err(e) => return -e,
+    };
+    match vfs_link(&old, &new, flags & AT_SYMLINK_FOLLOW != 0) {
+        Ok(()) => 0,
+        Err(e) => {
+            debug!("sys_linkat: {} -> {} failed err={}", new, old, e);
+            -e
+        }
+    }
+}
+
+///renameat2(olddirfd, oldpath, newdirfd, newpath, flags)，renameat 就是 flags=0
+/// 支持 RENAME_NOREPLACE / RENAME_EXCHANGE，不认识的 flag 返回 EINVAL，跨挂载点返回 EXDEV
+pub fn sys_renameat2(olddirfd: isize, oldpath_ptr: usize, newdirfd: isize, newpath_ptr: usize, flags: usize) -> isize {
+    let flags = match RenameFlags::from_bits(flags as u32) {
+        Some(f) if flags >> 32 == 0 => f,
+        _ => return -Errno::EINVAL,
+    };
+    let old = match read_at_path(olddirfd, oldpath_ptr) {
+        Ok(p) => p,
+        Err(e) => return -e,
+    };
+    let new = match read_at_path(newdirfd, newpath_ptr) {
+        Ok(p) => p,
+        Err(e) => return -e,
+    };
+    match vfs_rename2(&old, &new, flags) {
+        Ok(()) => 0,
+        Err(e) => {
+            debug!("sys_renameat2: {} -> {} failed err={}", old, new, e);
+            -e
+        }
+    }
+}
+
+///symlinkat(target, newdirfd, linkpath)，target 原样保存，不要求存在
+pub fn sys_symlinkat(target_ptr: usize, newdirfd: isize, linkpath_ptr: usize) -> isize {
+    let target = match read_c_string_from_user(target_ptr) {
+        Ok(p) => p,
+        Err(e) => return -e,
+    };
+    let path = match read_at_path(newdirfd, linkpath_ptr) {
+        Ok(p) => p,
+        Err(e) => return -e,
+    };
+    match vfs_symlink(&target, &path) {
+        Ok(()) => 0,
+        Err(e) => {
+            debug!("sys_symlinkat: {} -> {} failed err={}", path, target, e);
+            -e
         }
     }
 }
 
-pub fn sys_stat(path_ptr: usize, stat_buf_ptr: usize) -> isize {
+///readlinkat(dirfd, path, buf, bufsiz)，返回拷贝的字节数，不补结尾的 NUL，放不下就截断
+pub fn sys_readlinkat(dirfd: isize, path_ptr: usize, buf: usize, bufsiz: usize) -> isize {
+    if bufsiz as isize <= 0 {
+        return -Errno::EINVAL;
+    }
+    let path = match read_at_path(dirfd, path_ptr) {
+        Ok(p) => p,
+        Err(e) => return -e,
+    };
+    let target = match vfs_readlink(&path) {
+        Ok(t) => t,
+        Err(e) => return -e,
+    };
+    let n = core::cmp::min(target.len(), bufsiz);
+    if let Err(e) = UserSlice::new(buf, bufsiz).write(&target.as_bytes()[..n]) {
+        return -e;
+    }
+    n as isize
+}
+
+///newfstatat(dirfd, path, statbuf, flags)
+/// 带 AT_SYMLINK_NOFOLLOW 时相当于 lstat
+pub fn sys_fstatat(dirfd: isize, path_ptr: usize, stat_buf_ptr: usize, flags: usize) -> isize {
+    if flags & !(AT_SYMLINK_NOFOLLOW | AT_EMPTY_PATH) != 0 {
+        return -Errno::EINVAL;
+    }
     let path = match read_c_string_from_user(path_ptr) {
         Ok(p) => p,
         Err(e) => {
-            error!("sys_stat: invalid user path ptr={:#x}, err={}", path_ptr, e);
-            return -1;
+            error!("sys_fstatat: invalid user path ptr={:#x}, err={}", path_ptr, e);
+            return -e;
         }
     };
 
-    let st = match vfs_stat(&path) {
+    let st = if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        // 对 dirfd 本身 stat，它可以是任何文件
+        if dirfd == AT_FDCWD {
+            vfs_stat(&TASK_MANAER.get_current_cwd())
+        } else {
+            match at_dir_file(dirfd) {
+                Ok(f) => f.stat(),
+                Err(e) => return -e,
+            }
+        }
+    } else {
+        match resolve_at_path(dirfd, &path) {
+            Ok(abs) if flags & AT_SYMLINK_NOFOLLOW != 0 => vfs_lstat(&abs),
+            Ok(abs) => vfs_stat(&abs),
+            Err(e) => return -e,
+        }
+    };
+    let st = match st {
         Ok(s) => s,
         Err(e) => {
-            error!("sys_stat: vfs_stat failed: path={} err={}", path, e);
-            return -1;
+            debug!("sys_fstatat: stat failed: dirfd={} path={} err={}", dirfd, path, e);
+            return -e;
         }
     };
 
     if stat_buf_ptr == 0 {
-        error!("sys_stat: null stat_buf_ptr for path={}", path);
-        return -1;
+        error!("sys_fstatat: null stat_buf_ptr for path={}", path);
+        return -Errno::EFAULT;
     }
 
     let kst: KStat = st.into();
 
-    let user_satp = TASK_MANAER.get_current_stap();
-    let mut slices = PageTable::get_mut_slice_from_satp(
-        user_satp,
-        core::mem::size_of::<KStat>(),
-        VirAddr(stat_buf_ptr),
-    );
-
-    let bytes: &[u8] = unsafe {
-        core::slice::from_raw_parts((&kst as *const KStat) as *const u8, core::mem::size_of::<KStat>())
-    };
-    let mut off = 0usize;
-    for s in slices.iter_mut() {
-        if off >= bytes.len() {
-            break;
-        }
-        let n = core::cmp::min(s.len(), bytes.len() - off);
-        s[..n].copy_from_slice(&bytes[off..off + n]);
-        off += n;
-    }
-    if off != bytes.len() {
-        error!("sys_stat: short copy to user: path={} copied={} need={}", path, off, bytes.len());
-        return -1;
+    if let Err(e) = UserPtr::new(stat_buf_ptr).write(&kst) {
+        error!("sys_fstatat: copy to user failed: path={}", path);
+        return -e;
     }
     0
 }
@@ -962,14 +1066,14 @@ pub fn sys_stat(path_ptr: usize, stat_buf_ptr: usize) -> isize {
 pub fn sys_fstat(fd: usize, stat_buf_ptr: usize) -> isize {
     if stat_buf_ptr == 0 {
         error!("sys_fstat: null stat_buf_ptr fd={}", fd);
-        return -1;
+        return -Errno::EFAULT;
     }
 
     let file = match TASK_MANAER.get_current_fd(fd) {
         Some(Some(f)) => f,
         _ => {
             warn!("sys_fstat: invalid fd={}", fd);
-            return -1;
+            return -Errno::EBADF;
         }
     };
 
@@ -977,32 +1081,13 @@ pub fn sys_fstat(fd: usize, stat_buf_ptr: usize) -> isize {
         Ok(s) => s,
         Err(e) => {
             error!("sys_fstat: vfs_fstat_kstat failed: fd={} err={}", fd, e);
-            return -1;
+            return -e;
         }
     };
 
-    let user_satp = TASK_MANAER.get_current_stap();
-    let mut slices = PageTable::get_mut_slice_from_satp(
-        user_satp,
-        core::mem::size_of::<KStat>(),
-        VirAddr(stat_buf_ptr),
-    );
-
-    let bytes: &[u8] = unsafe {
-        core::slice::from_raw_parts((&kst as *const KStat) as *const u8, core::mem::size_of::<KStat>())
-    };
-    let mut off = 0usize;
-    for s in slices.iter_mut() {
-        if off >= bytes.len() {
-            break;
-        }
-        let n = core::cmp::min(s.len(), bytes.len() - off);
-        s[..n].copy_from_slice(&bytes[off..off + n]);
-        off += n;
-    }
-    if off != bytes.len() {
-        error!("sys_fstat: short copy to user: fd={} copied={} need={}", fd, off, bytes.len());
-        return -1;
+    if let Err(e) = UserPtr::new(stat_buf_ptr).write(&kst) {
+        error!("sys_fstat: copy to user failed: fd={}", fd);
+        return -e;
     }
     0
 }
@@ -1010,13 +1095,13 @@ pub fn sys_fstat(fd: usize, stat_buf_ptr: usize) -> isize {
 pub fn sys_getdents64(fd: usize, user_buf_ptr: usize, len: usize) -> isize {
     if user_buf_ptr == 0 {
         warn!("sys_getdents64: null user_buf_ptr fd={} len={}", fd, len);
-        return -1;
+        return -Errno::EFAULT;
     }
     let file = match TASK_MANAER.get_current_fd(fd) {
         Some(Some(f)) => f,
         _ => {
             warn!("sys_getdents64: invalid fd={} len={}", fd, len);
-            return -1;
+            return -Errno::EBADF;
         }
     };
 
@@ -1024,44 +1109,34 @@ pub fn sys_getdents64(fd: usize, user_buf_ptr: usize, len: usize) -> isize {
         Ok(v) => v,
         Err(e) => {
             error!("sys_getdents64: vfs_getdents64 failed fd={} len={} err={}", fd, len, e);
-            return -1;
+            return -e;
         }
     };
 
-    let user_satp = TASK_MANAER.get_current_stap();
-    let mut slices = PageTable::get_mut_slice_from_satp(user_satp, data.len(), VirAddr(user_buf_ptr));
-    let mut off = 0usize;
-    for s in slices.iter_mut() {
-        if off >= data.len() {
-            break;
-        }
-        let n = core::cmp::min(s.len(), data.len() - off);
-        s[..n].copy_from_slice(&data[off..off + n]);
-        off += n;
-    }
-    if off != data.len() {
-        error!("sys_getdents64: short copy to user fd={} copied={} need={}", fd, off, data.len());
-        return -1;
+    if let Err(e) = UserSlice::new(user_buf_ptr, len).write(&data) {
+        error!("sys_getdents64: copy to user failed fd={} need={}", fd, data.len());
+        return -e;
     }
     data.len() as isize
 }
 
-pub fn sys_open(path_ptr: usize, flags_bits: usize) -> isize {
-    let path = match read_c_string_from_user(path_ptr) {
+///openat(dirfd, path, flags, mode)，没有权限位，mode 忽略
+pub fn sys_openat(dirfd: isize, path_ptr: usize, flags_bits: usize, _mode: usize) -> isize {
+    let path = match read_at_path(dirfd, path_ptr) {
         Ok(p) => p,
         Err(e) => {
-            error!("sys_open: invalid user path ptr={:#x}, err={}", path_ptr, e);
-            return -1;
+            error!("sys_openat: bad path dirfd={} ptr={:#x}, err={}", dirfd, path_ptr, e);
+            return -e;
         }
     };
 
     let acc = flags_bits & OpenFlags::ACCMODE_MASK;
     if acc > 2 {
         error!(
-            "sys_open: invalid acc bits: path={} flags_bits={:#x}",
+            "sys_openat: invalid acc bits: path={} flags_bits={:#x}",
             path, flags_bits
         );
-        return -1;
+        return -Errno::EINVAL;
     }
     let flags = OpenFlags::from_bits_truncate(flags_bits);
 
@@ -1069,22 +1144,23 @@ pub fn sys_open(path_ptr: usize, flags_bits: usize) -> isize {
         Ok(r) => r,
         Err(e) => {
             error!(
-                "sys_open: vfs_open failed: path={} flags_bits={:#x} err={}",
+                "sys_openat: vfs_open failed: path={} flags_bits={:#x} err={}",
                 path, flags_bits, e
             );
-            return -1;
+            return -e;
         }
     };
     let fd = TASK_MANAER.alloc_fd_for_current(opened);
     if fd < 0 {
-        error!("sys_open: alloc fd failed: path={} flags_bits={:#x}", path, flags_bits);
+        error!("sys_openat: alloc fd failed: path={} flags_bits={:#x}", path, flags_bits);
+        return -Errno::EMFILE;
     }
     fd as isize
 }
 
 pub fn sys_creat(path_ptr: usize) -> isize {
     let flags_bits = (1 << 6) | (1 << 9) | 1;
-    sys_open(path_ptr, flags_bits)
+    sys_openat(AT_FDCWD, path_ptr, flags_bits, 0)
 }
 
 pub fn sys_close(fd: usize) -> isize {
@@ -1095,12 +1171,30 @@ pub fn sys_close(fd: usize) -> isize {
     ret
 }
 
+/// fsync(fd)：把该文件在页缓存中的脏页写回设备
+pub fn sys_fsync(fd: usize) -> isize {
+    let file = match TASK_MANAER.get_current_fd(fd) {
+        Some(Some(f)) => f,
+        _ => {
+            warn!("sys_fsync: invalid fd={}", fd);
+            return -Errno::EBADF;
+        }
+    };
+    match file.flush() {
+        Ok(()) => 0,
+        Err(e) => {
+            error!("sys_fsync: flush failed fd={} err={}", fd, e);
+            -e
+        }
+    }
+}
+
 pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
     let file = match TASK_MANAER.get_current_fd(fd) {
         Some(Some(f)) => f,
         _ => {
             warn!("sys_lseek: invalid fd={} offset={} whence={}", fd, offset, whence);
-            return -1;
+            return -Errno::EBADF;
         }
     };
     match file.lseek(offset, whence) {
@@ -1110,176 +1204,155 @@ pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
                 "sys_lseek: failed fd={} offset={} whence={} err={}",
                 fd, offset, whence, e
             );
-            -1
+            -e
         }
     }
 }
 
 
 ///SYS_FORK系统调用
-pub fn sys_fork(mode:CloneFlags,stack: usize, ptid: usize, tls: usize, ctid: usize)->isize{
-    //warn!("forlk");
-    let mut inner = TASK_MANAER.task_que_inner.lock();
-    let current_index = inner.current;
-    let current_task = &mut inner.task_queen[current_index];
-
-    // 先从父进程深拷贝一份新的地址空间（全量复制，不是 COW）
-    // clone_mapset 目前签名是 &mut self，所以这里需要拿到父进程的可变 guard。
-    let new_memset = {
-        let mut parent = current_task.lock();
-        parent.memory_set.clone_mapset()
+/// 没有 CLONE_VM 时 COW 复制地址空间；有 CLONE_VM 时和调用者共用地址空间，只给子任务映射一页自己的陷阱上下文
+/// CLONE_THREAD 创建的线程和调用者同属一个线程组，不是调用者的子进程，退出后由调度器回收
+pub fn sys_fork(mode:CloneFlags,exit_signal: usize,stack: usize, ptid: usize, tls: usize, ctid: usize)->isize{
+    let current_task = TASK_MANAER.expect_current_task();
+    let is_thread = mode.contains(CloneFlags::CLONE_THREAD);
+    let (parent_pid, parent_tgid, memory_set, fd_table, sig_actions, parent_trap_cx_addr, grand_parent) = {
+        let t = current_task.lock();
+        (
+            t.pid.0,
+            t.tgid,
+            t.memory_set.clone(),
+            t.file_descriptor.clone(),
+            t.sig_actions.clone(),
+            t.trap_context_addr,
+            t.parent.clone(),
+        )
     };
 
+    let (child_memory_set, trap_cx_addr) = if mode.contains(CloneFlags::CLONE_VM) {
+        let Some(addr) = memory_set.lock().map_thread_trapContext() else {
+            error!("sys_fork: no trap context slot for new thread");
+            return -Errno::EAGAIN;
+        };
+        (memory_set.clone(), addr)
+    } else {
+        // COW：共享页帧，双方页表去掉写权限。clone_mapset 会修改父进程页表项，需要拿着地址空间的锁
+        // 父任务的陷阱上下文页也复制了一份，子任务沿用同一个虚拟地址
+        let Some(new_memset) = memory_set.lock().clone_mapset() else {
+            error!("Process Memset clone failed!");
+            return -Errno::ENOMEM;
+        };
+        (new_memset.into_shared(), parent_trap_cx_addr)
+    };
+    let file_descriptor = if mode.contains(CloneFlags::CLONE_FILES) {
+        fd_table
+    } else {
+        let table = fd_table.lock().clone();
+        Arc::new(SpinLock::new(table))
+    };
+    let sig_actions = if mode.contains(CloneFlags::CLONE_SIGHAND) {
+        sig_actions
+    } else {
+        let table = *sig_actions.lock();
+        Arc::new(SpinLock::new(table))
+    };
 
-    // 先把浅拷贝得到的 MapSet 用 mem::replace 取出来并 forget，避免 Drop。
-    let parent_pid = { current_task.lock().pid.0 };
-    let mut bad_task = current_task.lock().clone();//复制的是tbl本体不是arc
-
-
-    bad_task.parent = None;
-    bad_task.childrens.clear();
-
-    let new_pid = ProcessId_ALLOCTOR
+    let trap_cx_ppn = child_memory_set
         .lock()
-        .alloc_id()
-        .expect("No Process ID Can use");
-    // 不要让旧的 ProcessId(parent_pid) drop 回收 parent_pid，否则会污染 pid 池。
-    let old_pid = core::mem::replace(&mut bad_task.pid, new_pid);
-    core::mem::forget(old_pid);
-    let child_pid = bad_task.pid.0;
-    debug!("Parent:pid {} child:{}", parent_pid, child_pid);
-    let shallow = core::mem::replace(&mut bad_task.memory_set, MapSet::new_bare());
-    core::mem::forget(shallow);
-    if new_memset.is_none(){
-        error!("Process Memset clone failed!");
-        return -1;
-    }
-    bad_task.memory_set = new_memset.expect("Memset should be some");
-
-    // 为子进程分配独立的内核栈，并同步到 TaskContext/TrapContext
-    let child_kernel_sp = MapSet::alloc_kernel_stack();
-    // 子进程第一次被调度必须从 app_entry_point 起步，才能通过 __restore 使用 TrapContext 恢复用户态寄存器。
-    // 只修改 sp 会让子进程继承父进程的内核执行流，导致 fork 返回值等寄存器语义错误。
-    bad_task.task_context = TaskContext::return_trap_new(child_kernel_sp);
-
-
-    bad_task.task_statut = TaskStatus::Ready;//设置任务准备被调度
-    {
-        let trap_cx_ppn = bad_task
-        .memory_set
         .table
-        .translate_byvpn(VirAddr(TRAP_CONTEXT_ADDR).strict_into_virnum())
+        .translate_byvpn(VirAddr(trap_cx_addr).strict_into_virnum())
         .expect("trap ppn translate failed");
-        bad_task.trap_context_ppn = trap_cx_ppn.0;
-        let trap_cx_point: *mut TrapContext = (trap_cx_ppn.0 * PAGE_SIZE) as *mut TrapContext;
+    let trap_cx_point: *mut TrapContext = (trap_cx_ppn.0 * PAGE_SIZE) as *mut TrapContext;
+    if mode.contains(CloneFlags::CLONE_VM) {
+        // 新映射的陷阱上下文页是空的，从调用者复制一份（sepc 已经跳过了 ecall）
         unsafe {
-            (*trap_cx_point).kernel_sp = child_kernel_sp;
-            (*trap_cx_point).x[10] = 0;
+            core::ptr::copy_nonoverlapping(TASK_MANAER.get_current_trapcx() as *const TrapContext, trap_cx_point, 1);
+        }
+    }
 
-            // TODO THREAD define
-            // stack
-            if stack!=0{
-                (*trap_cx_point).x[2] = stack;
-            }
+    let pid = ProcessId_ALLOCTOR
+        .lock()
+        .alloc_id()
+        .expect("No Process ID Can use");
+    let child_pid = pid.0;
+    let tgid = if is_thread { parent_tgid } else { child_pid };
+    debug!("Parent:pid {} child:{} thread:{}", parent_pid, child_pid, is_thread);
 
-            debug!(
-                "fork child init: pid={} trap_ppn={} child_a0={}",
-                child_pid,
-                trap_cx_ppn.0,
-                (*trap_cx_point).x[10]
-            );
+    // 为子任务分配独立的内核栈，并同步到 TaskContext/TrapContext
+    let child_kernel_sp = MapSet::alloc_kernel_stack();
+    let mut child = current_task.lock().new_cloned(
+        pid,
+        tgid,
+        child_memory_set.clone(),
+        file_descriptor,
+        sig_actions,
+        trap_cx_ppn.0,
+        trap_cx_addr,
+        child_kernel_sp,
+    );
+    if mode.contains(CloneFlags::CLONE_CHILD_CLEARTID) {
+        child.clear_child_tid = ctid;
+    }
+    if !is_thread {
+        child.exit_signal = exit_signal;
+    }
+
+    unsafe {
+        (*trap_cx_point).kernel_sp = child_kernel_sp;
+        (*trap_cx_point).x[10] = 0;
+        if stack != 0 {
+            (*trap_cx_point).x[2] = stack;
+        }
+        if mode.contains(CloneFlags::CLONE_SETTLS) {
+            (*trap_cx_point).x[4] = tls;
         }
+        debug!(
+            "fork child init: pid={} trap_ppn={} child_a0={}",
+            child_pid,
+            trap_cx_ppn.0,
+            (*trap_cx_point).x[10]
+        );
     }
 
-    let arc_task =Arc::new(UPSafeCell::new(bad_task));
-    /* 建立父子关系 */
-    //添加child
-    current_task.lock().add_children( arc_task.clone());
-    //warn!("sys_fork: parent pid={} add child pid={} children_len={}", parent_pid, child_pid, current_task.lock().childrens.len());
-    //链接父亲
-    arc_task.lock().set_father(&*current_task);
-    drop(inner);//释放TASK_MANAER锁
+    // 子任务跑起来之前把 tid 写好，pthread_create 返回时就能看到
+    if mode.contains(CloneFlags::CLONE_PARENT_SETTID) && UserPtr::<i32>::new(ptid).write(&child_pid).is_err() {
+        warn!("sys_fork: bad parent_tid pointer {:#x}", ptid);
+    }
+    if mode.contains(CloneFlags::CLONE_CHILD_SETTID) && !MapSet::write_user_i32(&child_memory_set, ctid, child_pid) {
+        warn!("sys_fork: bad child_tid pointer {:#x}", ctid);
+    }
+
+    let arc_task =Arc::new(SpinLock::new(child));
+    if is_thread {
+        // 线程的父进程就是整个线程组的父进程
+        arc_task.lock().parent = grand_parent;
+    } else {
+        /* 建立父子关系 */
+        current_task.lock().add_children( arc_task.clone());
+        arc_task.lock().set_father(&current_task);
+    }
 
     /* 把克隆后的任务添加到任务队列 */
-    TASK_MANAER.task_que_inner.lock().task_queen.push_back(arc_task.clone());
+    TASK_MANAER.lock_inner().task_queen.push_back(arc_task);
 
     //父亲返回子pid，子返回0.
-    return child_pid as isize;
-
-
-
+    child_pid as isize
 }
 
-
-
-
-
 /// 从用户空间读取 null 结尾的 C 风格字符串
 /// 最大读取长度为 4096 字节，避免读取过长的字符串
-fn read_c_string_from_user(path_ptr: usize) -> Result<String, VfsFsError> {
-    // 获取当前任务的页表
-    let user_satp = TASK_MANAER.get_current_stap();
-    read_c_string_from_user_with_satp(user_satp, path_ptr)
-}
-
-fn read_c_string_from_user_with_satp(user_satp: usize, path_ptr: usize) -> Result<String, VfsFsError> {
+///从用户态读一个路径字符串，最长 MAX_PATH_LEN 字节（含结尾的 NUL）
+fn read_c_string_from_user(path_ptr: usize) -> Result<String, Errno> {
     const MAX_PATH_LEN: usize = 4096;
-
-    debug!(
-        "read_c_string_from_user_with_satp: satp={:#x} path_ptr={:#x}",
-        user_satp, path_ptr
-    );
-
-    // 不要一次性取 MAX_PATH_LEN 的 slice：字符串可能位于页尾，跨页会触发内核态 fault。
-    // 这里逐字节翻译虚拟地址并读取，直到遇到 '\0' 或超过最大长度。
-    let mut table = PageTable::crate_table_from_satp(user_satp);
-    let mut data: Vec<u8> = Vec::new();
-
-    for off in 0..MAX_PATH_LEN {
-        let vaddr = VirAddr(path_ptr + off);
-        let paddr = match table.translate(vaddr) {
-            Some(p) => p,
-            None => {
-                error!(
-                    "read_c_string_from_user_with_satp: translate failed: satp={:#x} path_ptr={:#x} off={} vaddr={:#x}",
-                    user_satp,
-                    path_ptr,
-                    off,
-                    vaddr.0
-                );
-                return Err(VfsFsError::Invalid);
-            }
-        };
-        let b = unsafe { *(paddr.0 as *const u8) };
-        if b == 0 {
-            debug!(
-                "read_c_string_from_user_with_satp: found NUL: len={} satp={:#x} path_ptr={:#x}",
-                data.len(),
-                user_satp,
-                path_ptr
-            );
-            let s = core::str::from_utf8(&data)
-                .map_err(|_| VfsFsError::Invalid)?
-                .to_string();
-            debug!("read_c_string_from_user_with_satp: str='{}'", s);
-            return Ok(s);
-        }
-        data.push(b);
-    }
-
-    error!(
-        "read_c_string_from_user_with_satp: no NUL within {} bytes: satp={:#x} path_ptr={:#x}",
-        MAX_PATH_LEN,
-        user_satp,
-        path_ptr
-    );
-    Err(VfsFsError::Invalid)
+    let s = UserCStr::new(path_ptr).read(MAX_PATH_LEN)?;
+    debug!("read_c_string_from_user: path_ptr={:#x} str='{}'", path_ptr, s);
+    Ok(s)
 }
 
 
 ///mmap系统调用
 /// Linux/POSIX: mmap(addr, len, prot, flags, fd, offset)
-/// 返回：成功返回映射起始地址；失败返回 -1
+/// 返回：成功返回映射起始地址；失败返回负的 errno
 ///
 /// 参数说明（Linux riscv64 ABI，用户态用 ecall 传参）：
 /// `addr`  : 映射起始虚拟地址（用户 hint）。若带 `MAP_FIXED` 则必须使用该地址。
@@ -1298,184 +1371,195 @@ fn read_c_string_from_user_with_satp(user_satp: usize, path_ptr: usize) -> Resul
 /// 当前最小实现：仅支持匿名映射（`MAP_ANONYMOUS` 且 `fd == -1`），并要求 `addr != 0`。
 pub fn sys_mmap(addr: usize, len: usize, prot: usize, flags: usize, fd: i32, offset: usize) -> isize {
     //warn!("enter mmap");
-    let inner = TASK_MANAER.task_que_inner.lock();
-    let current = inner.current;
-    drop(inner);
     let fd_backing =match TASK_MANAER.get_current_fd(fd as usize){
         Some(v)=>v,
         _=>None
     };
-    let inner = TASK_MANAER.task_que_inner.lock();
-    let mut tcb = inner.task_queen[current].lock();
+    let memory_set = TASK_MANAER.current_memory_set();
+    let mut memset = memory_set.lock();
     
      
-    tcb.memory_set.mmap(VirAddr(addr), len, prot, flags, fd, offset,fd_backing)
+    let ret = memset.mmap(VirAddr(addr), len, prot, flags, fd, offset,fd_backing);
+    // MAP_FIXED 盖掉的共享文件映射放开锁之后写回
+    let dirty = memset.take_writeback();
+    drop(memset);
+    let _ = FilePage::write_back(dirty);
+    ret
 }
 
 
 ///unmap系统调用
 /// startaddr:usize size:长度
 pub fn sys_munmap(start:usize,size:usize)->isize{
-    let inner=TASK_MANAER.task_que_inner.lock();
-    let current=inner.current;
-    drop(inner);
-    let  inner=TASK_MANAER.task_que_inner.lock();
-    let  memset=&mut inner.task_queen[current].lock().memory_set;
-    memset.unmap_range(VirAddr(start), size)
-    //inner自动销毁
-}
-
-
-
-///addr:用户传入的时间结构体地址 目前映射处理错误，因为还没有任务这个概念
-fn syscall_get_time(addr:*mut TimeVal){  //考虑是否跨页面  
-      let vpn=(addr as usize)/PAGE_SIZE;
-      let offset=VirAddr(addr as usize).offset();
-      // 获取当前页表的临时视图
-      let mut table = PageTable::get_kernel_table_layer();
-      let  frame_pointer=table.get_mut_byte(VirNumber(vpn)).expect("Big Error!");
-
-   //判断是否跨页 跨页需要特殊处理
-   let len=size_of::<TimeVal>();
-   if vpn !=(addr as usize -1 +len)/PAGE_SIZE{
-      //跨页
-      //let new_frame_pointer=table.get_mut_byte(VirNumber(vpn+1)); 不重复申请，节省内存
-      if table.is_maped(VirNumber(vpn+1)){
-         //并且存在合法映射,拼接两个页面
-        let  time_val:&mut TimeVal;
-         unsafe {
-           time_val= &mut *((frame_pointer as *mut _ as usize+offset) as *mut TimeVal);
-            *time_val=TimeVal{
-               sec:get_time_ms()/1000,
-               usec:get_time_ms()%1000
-            }
-         }
-      }else { 
-          //PageFault!!!!!! 下一个页面没有有效映射
-          panic!("InValid Memory write!!")
-      }
-      
-   }
+    let memory_set = TASK_MANAER.current_memory_set();
+    let mut memset = memory_set.lock();
+    let ret = memset.unmap_range(VirAddr(start), size);
+    let dirty = memset.take_writeback();
+    drop(memset);
+    let _ = FilePage::write_back(dirty);
+    ret
+}
 
+///mprotect系统调用 修改[start,start+size)的访问权限
+pub fn sys_mprotect(start:usize,size:usize,prot:usize)->isize{
+    let memory_set = TASK_MANAER.current_memory_set();
+    let mut memset = memory_set.lock();
+    memset.mprotect(VirAddr(start), size, prot)
+}
 
+///mremap系统调用 扩大/缩小/搬移一段mmap映射 返回新地址
+pub fn sys_mremap(old_addr:usize,old_size:usize,new_size:usize,flags:usize,new_addr:usize)->isize{
+    let memory_set = TASK_MANAER.current_memory_set();
+    let mut memset = memory_set.lock();
+    let ret = memset.mremap(VirAddr(old_addr), old_size, new_size, flags, VirAddr(new_addr));
+    let dirty = memset.take_writeback();
+    drop(memset);
+    let _ = FilePage::write_back(dirty);
+    ret
 }
+
+///msync系统调用 把共享文件映射的修改写回文件
+pub fn sys_msync(start:usize,size:usize,flags:usize)->isize{
+    let memory_set = TASK_MANAER.current_memory_set();
+    let work = memory_set.lock().msync(VirAddr(start), size, flags);
+    match work {
+        Ok(work) => work.run(),
+        Err(e) => e,
+    }
+}
+
+
+
 ///这个指针是用户空间的指针，应该解地址
 /// 使用文件描述符进行写入
 pub fn sys_write(fd_target: usize, source_buffer: usize, buffer_len: usize) -> isize {
-    // 获取当前任务的页表进行地址转换
-    let user_satp = TASK_MANAER.get_current_stap();
-    let buffer = PageTable::get_mut_slice_from_satp(user_satp, buffer_len, VirAddr(source_buffer));
-    
-    // 计算总长度并准备写入缓冲区
-    let total_len: usize = buffer.iter().map(|slic| slic.len()).sum();
-    let mut write_buffer = Vec::with_capacity(total_len);
-    
-    // 将用户空间的数据复制到内核缓冲区
-    for slice in buffer {
-        write_buffer.extend_from_slice(slice);
-    }
-
     let fd = match TASK_MANAER.get_current_fd(fd_target) {
         Some(Some(fd)) => fd,
         _ => {
             warn!("sys_write: invalid fd={} len={}", fd_target, buffer_len);
-            return -1;
+            return -Errno::EBADF;
         }
     };
 
+    // 整段拷进内核缓冲区，中间有不可读的页就是 EFAULT
+    let write_buffer = match UserSlice::new(source_buffer, buffer_len).read_to_vec() {
+        Ok(buf) => buf,
+        Err(e) => return -e,
+    };
+
     match fd.write(&write_buffer) {
         Ok(written) => written as isize,
+        // 被信号打断，交给信号递送决定要不要重启
+        Err(VfsFsError::Interrupted) => -Errno::ERESTARTSYS,
         Err(e) => {
             error!(
-                "sys_write: fd.write failed fd={} req_len={} copied_len={}  err={}",
+                "sys_write: fd.write failed fd={} len={} err={}",
                 fd_target,
                 buffer_len,
-                write_buffer.len(),
                 e
             );
-            -1
+            -e
         }
     }
 }
 ///sysread调用 traphandler栈顶
 /// 使用文件描述符进行读取
 pub fn sys_read(fd_target: usize, source_buffer: usize, buffer_len: usize) -> isize {
-    // 获取当前任务的页表进行地址转换
-    let user_satp = TASK_MANAER.get_current_stap();
-    let mut buffer = PageTable::get_mut_slice_from_satp(user_satp, buffer_len, VirAddr(source_buffer));
-    
-    // 计算总缓冲区大小
-    let total_len: usize = buffer.iter().map(|slic| slic.len()).sum();
-    let mut read_buffer = vec![0u8; total_len];
-
     let fd = match TASK_MANAER.get_current_fd(fd_target) {
         Some(Some(fd)) => fd,
         _ => {
             warn!("sys_read: invalid fd={} len={}", fd_target, buffer_len);
-            return -1;
+            return -Errno::EBADF;
         }
     };
 
+    // 先确认整段用户缓冲区可写再按长度分配内核缓冲区，读到数据之后才发现写不回去就丢了
+    let user_buf = UserSlice::new(source_buffer, buffer_len);
+    if let Err(e) = user_buf.check(true) {
+        return -e;
+    }
+    let mut read_buffer = vec![0u8; buffer_len];
+
     let read_len = match fd.read(&mut read_buffer) {
         Ok(len) => len,
+        Err(VfsFsError::Interrupted) => return -Errno::ERESTARTSYS,
         Err(e) => {
             error!("sys_read: fd.read failed fd={} len={} err={}", fd_target, buffer_len, e);
-            return -1;
+            return -e;
         }
     };
 
-    let mut offset = 0usize;
-    for slice in buffer.iter_mut() {
-        if offset >= read_len {
-            break;
-        }
-        let n = core::cmp::min(slice.len(), read_len - offset);
-        slice[..n].copy_from_slice(&read_buffer[offset..offset + n]);
-        offset += n;
+    if let Err(e) = user_buf.write(&read_buffer[..read_len]) {
+        return -e;
     }
-
     read_len as isize
 }
 
 
-///exit系统调用，一般main程序return后在这里处理退出码 任务调度型返回-1
-///注意：这个函数永不返回！要么切换到其他任务，要么关机
-pub fn sys_exit(exit_code:usize)->isize{
-    // 若把 init 标记为 Zombie，会导致系统只剩 Zombie/无 Ready 任务，从而调度器报错。
-    let (current_pid,current_task) = {
-        let inner = TASK_MANAER.task_que_inner.lock();
-        if inner.task_queen.is_empty() {
-            drop(inner);
-            (0,None)
-        } else {
-            let current = inner.current;
-            let pid = inner.task_queen[current].lock().pid.0;
-            let ts = inner.task_queen[current].clone();
-            drop(inner);
-            (pid,Some(ts))
+///ioctl系统调用，目前只支持终端的 TCGETS/TCSETS*
+/// 参数按请求号的大小拷进内核，交给文件处理后需要时再拷回用户态
+pub fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+    let file = match TASK_MANAER.get_current_fd(fd) {
+        Some(Some(f)) => f,
+        _ => {
+            warn!("sys_ioctl: invalid fd={}", fd);
+            return -Errno::EBADF;
         }
     };
+    let Some((size, copy_out)) = tty_ioctl_arg_size(cmd) else {
+        warn!("sys_ioctl: unsupported cmd={:#x} fd={}", cmd, fd);
+        return -Errno::ENOTTY;
+    };
+    if size == 0 {
+        // 不带指针参数的请求
+        return match file.ioctl(cmd, &mut []) {
+            Ok(ret) => ret as isize,
+            Err(e) => ioctl_errno(e),
+        };
+    }
+    if arg == 0 {
+        return -Errno::EFAULT;
+    }
 
-    //error!("pid :{} exit",current_pid);
+    let user_arg = UserSlice::new(arg, size);
+    if copy_out {
+        if let Err(e) = user_arg.check(true) {
+            return -e;
+        }
+    }
+    let mut data = match user_arg.read_to_vec() {
+        Ok(data) => data,
+        Err(e) => return -e,
+    };
 
-    match current_task{
-        Some(ts)=>{
-            let parent = &mut ts.lock().parent;
-            if parent.is_some(){
-                // try Wake father
-                let pa = parent.as_ref().expect("Kernel Error").upgrade().expect("Where is my father");
-                if pa.lock().task_statut == TaskStatus::Blocking {
-                    let pid = pa.lock().pid.0;
-                    TASK_MANAER.wake_task_from_blocking(pid);
-                }
-            }
+    let ret = match file.ioctl(cmd, &mut data) {
+        Ok(ret) => ret,
+        Err(e) => {
+            debug!("sys_ioctl: fd={} cmd={:#x} err={}", fd, cmd, e);
+            return ioctl_errno(e);
         }
-        None=>{
+    };
 
+    if copy_out {
+        if let Err(e) = user_arg.write(&data) {
+            return -e;
         }
     }
-    
+    ret as isize
+}
 
+///不是终端的文件不支持终端请求，Linux 返回 ENOTTY
+fn ioctl_errno(err: VfsFsError) -> isize {
+    match err {
+        VfsFsError::NotSupported => -Errno::ENOTTY,
+        e => -e,
+    }
+}
+
+///exit系统调用，一般main程序return后在这里处理退出码 任务调度型返回-1
+///注意：这个函数永不返回！要么切换到其他任务，要么关机
+pub fn sys_exit(exit_code:usize)->isize{
+    // 若把 init 标记为 Zombie，会导致系统只剩 Zombie/无 Ready 任务，从而调度器报错。
     // Linux 语义：exit 后任务进入 Zombie，保留 pid/exit_code，等待父进程 wait() 回收(reap)。
     // 父进程退出时，其子进程会被过继给 init(pid=1)。
     if exit_code == 0 {
@@ -1485,189 +1569,286 @@ pub fn sys_exit(exit_code:usize)->isize{
     }
     TASK_MANAER.reparent_current_children_to_init();
     TASK_MANAER.mark_current_zombie(exit_code as isize);
+    // 父进程可能阻塞在 wait 里，先标记 Zombie 再唤醒，别的 hart 上的父进程醒来一定能看到
+    TASK_MANAER.wake_current_parent();
     // 进入 Zombie 后必须立刻让出 CPU
     TASK_MANAER.suspend_and_run_task();
     -1
 }
 
+///exit_group系统调用，先杀掉同一线程组的其它线程再退出自己
+pub fn sys_exit_group(exit_code:usize)->isize{
+    TASK_MANAER.kill_current_thread_group();
+    sys_exit(exit_code)
+}
+
 /// wait 系统调用：等待任意子进程结束。
 ///
 /// 返回：
 /// - 成功：返回已回收(reap)的 Zombie 子进程 pid
-/// - 失败：-1（无子进程）
+/// - 失败：-Errno::ECHILD（无子进程）
 pub fn sys_wait(exit_code_ptr: usize) -> isize {
     // wait4(pid=-1, wstatus, options=1)  WNOHANG == 1   
-    sys_wait4(-1, exit_code_ptr, 1)
+    sys_wait4(-1, exit_code_ptr, WNOHANG, 0)
 }
 
-/// TODO状态
-/// wait4/waitpid 语义的最小实现。
-///
-/// - pid == -1 : 等待任意子进程
-/// - pid > 0   : 等待指定 pid 子进程
-/// - pid == 0 或 pid < -1 : 不支持，返回 -1 并输出 unsupport
-/// - options != 0 : 不支持，返回 -1 并输出 unsupport   option == 1 WNOHANG,非阻塞等待
-///
-/// wstatus 写回遵循 Linux：退出码存放在高 8 bit（status = exit_code << 8）。
-pub fn sys_wait4(pid: i32, wstatus_ptr: usize, options: i32) -> isize {
+const WNOHANG: i32 = 1;
+const WUNTRACED: i32 = 2;
+/// waitid 里的名字，和 WUNTRACED 同值
+const WSTOPPED: i32 = 2;
+const WEXITED: i32 = 4;
+const WCONTINUED: i32 = 8;
+/// 只看状态不回收，waitid 用
+const WNOWAIT: i32 = 0x0100_0000;
+const __WNOTHREAD: i32 = 0x2000_0000;
+const __WALL: i32 = 0x4000_0000;
+const __WCLONE: i32 = 0x8000_0000u32 as i32;
+
+/// waitid 的 idtype
+const P_ALL: usize = 0;
+const P_PID: usize = 1;
+const P_PGID: usize = 2;
+
+/// struct rusage，wait4/waitid 写回
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Rusage {
+    pub ru_utime: TimeVal,
+    pub ru_stime: TimeVal,
+    /// 驻留内存峰值，单位 KB
+    pub ru_maxrss: isize,
+    /// ru_ixrss 到 ru_nivcsw，没有统计，全 0
+    pub ru_rest: [isize; 13],
+}
 
-    let pid_isize = pid as isize;
-    if pid_isize == 0 || pid_isize < -1 {
-        warn!("sys_wait4: unsupport pid={}", pid_isize);
-        return -1;
+impl Rusage {
+    fn from_usage(usage: &TaskUsage) -> Self {
+        Rusage {
+            ru_utime: ticks_to_timeval(usage.utime),
+            ru_stime: ticks_to_timeval(usage.stime),
+            ru_maxrss: (usage.max_rss * PAGE_SIZE / 1024) as isize,
+            ru_rest: [0; 13],
+        }
     }
+}
 
-    let target_pid: Option<i32> = if pid_isize == -1 {
-        None
+/// wait 等哪些子进程
+#[derive(Clone, Copy)]
+enum WaitTarget {
+    Any,
+    Pid(i32),
+    Pgid(i32),
+}
+
+/// wait 等到的一次子进程状态变化
+struct WaitEvent {
+    pid: i32,
+    status: i32,
+    usage: TaskUsage,
+}
+
+///停止/继续的状态字 wait 这次要不要
+fn wait_report_wanted(status: i32, options: i32) -> bool {
+    if status == WAIT_CONTINUED {
+        options & WCONTINUED != 0
+    } else {
+        options & WUNTRACED != 0
+    }
+}
+
+///子进程是不是这次 wait 要等的：先看 pid/进程组，再看 __WCLONE/__WALL
+/// 退出时不发 SIGCHLD 的是 clone 子进程，只有 __WCLONE 或 __WALL 才等它
+fn wait_child_matches(t: &TaskControlBlock, target: WaitTarget, options: i32) -> bool {
+    let hit = match target {
+        WaitTarget::Any => true,
+        WaitTarget::Pid(pid) => t.tgid == pid,
+        WaitTarget::Pgid(pgid) => t.pgid == pgid,
+    };
+    let clone_child = t.exit_signal != Signal::SIGCHLD.signo();
+    hit && (options & __WALL != 0 || clone_child == (options & __WCLONE != 0))
+}
+
+///子进程有没有 wait 要的状态变化，睡眠前后检查用
+fn wait_child_ready(child: &Arc<SpinLock<TaskControlBlock>>, options: i32) -> bool {
+    let t = child.lock();
+    if t.task_statut == TaskStatus::Zombie {
+        options & WEXITED != 0
     } else {
-        Some(pid)
+        t.wait_report.map_or(false, |st| wait_report_wanted(st, options))
+    }
+}
+
+///取子进程的状态变化：退出的回收掉，停止/继续的取走状态字，WNOWAIT 时都原样留着
+fn wait_child_event(child: &Arc<SpinLock<TaskControlBlock>>, options: i32) -> Option<WaitEvent> {
+    let (pid, zombie) = {
+        let t = child.lock();
+        (t.tgid, t.task_statut == TaskStatus::Zombie)
     };
+    if zombie {
+        if options & WEXITED == 0 {
+            return None;
+        }
+        if options & WNOWAIT != 0 {
+            let t = child.lock();
+            return Some(WaitEvent { pid, status: t.wait_status(), usage: t.total_usage() });
+        }
+        let (status, usage) = TASK_MANAER.reap_zombie_child(pid)?;
+        return Some(WaitEvent { pid, status: status as i32, usage });
+    }
+    let mut t = child.lock();
+    let status = t.wait_report.filter(|st| wait_report_wanted(*st, options))?;
+    if options & WNOWAIT == 0 {
+        t.wait_report = None;
+    }
+    Some(WaitEvent { pid, status, usage: t.total_usage() })
+}
 
+///wait4 和 waitid 共用：在匹配的子进程里找一个已经退出、停止或者继续的
+/// 没有 WNOHANG 时一直睡到有结果或者被信号打断；Ok(None) 是 WNOHANG 下暂时没有
+fn do_wait(target: WaitTarget, options: i32) -> Result<Option<WaitEvent>, isize> {
     loop {
-        let children = {
-            let inner = TASK_MANAER.task_que_inner.lock();
-            if inner.task_queen.is_empty() {
-                drop(inner);
-                return -1;
-            }
-            let current = inner.current;
-            let current_task = inner.task_queen[current].clone();
-            drop(inner);
+        let (children, child_exit) = {
+            let current_task = TASK_MANAER.expect_current_task();
             let t = current_task.lock();
-            t.childrens.clone()
+            (t.childrens.clone(), t.child_exit.clone())
         };
+        // 先拷出子进程列表再逐个加锁，不同时持有父子两把锁
+        let matched: Vec<Arc<SpinLock<TaskControlBlock>>> = children
+            .into_iter()
+            .filter(|c| wait_child_matches(&c.lock(), target, options))
+            .collect();
+        if matched.is_empty() {
+            debug!("wait: no matching child");
+            return Err(-Errno::ECHILD);
+        }
 
-        if children.is_empty() {
-            warn!("sys_wait4: no children for current process");
-            return -1;
-        }
-
-        if let Some(tp) = target_pid {
-            let mut found = false;
-            for child in children.iter() {
-                let cpid = { child.lock().pid.0 };
-                if cpid == tp {
-                    found = true;
-                    
-                    let status = { child.lock().task_statut.clone() };
-                    if matches!(status, TaskStatus::Zombie) {
-                        let exit_code = match TASK_MANAER.reap_zombie_child(cpid) {
-                            Some(code) => code,
-                            None => return -1,
-                        };
-                        if wstatus_ptr != 0 {
-                            let st: i32 = ((exit_code as i32) & 0xff) << 8;
-                            let user_satp = TASK_MANAER.get_current_stap();
-                            let mut slices = PageTable::get_mut_slice_from_satp(
-                                user_satp,
-                                size_of::<i32>(),
-                                VirAddr(wstatus_ptr),
-                            );
-                            if slices.is_empty() {
-                                return -1;
-                            }
-                            let bytes = st.to_le_bytes();
-                            let mut written = 0usize;
-                            for s in slices.iter_mut() {
-                                let n = core::cmp::min(s.len(), bytes.len().saturating_sub(written));
-                                if n == 0 {
-                                    break;
-                                }
-                                s[..n].copy_from_slice(&bytes[written..written + n]);
-                                written += n;
-                            }
-                            if written != bytes.len() {
-                                return -1;
-                            }
-                        }
-                        return cpid as isize;
-                    }
-                    
-                }
-            }
-
-            if !found {
-                debug!("sys_wait4: target child not found pid={}", tp);
-                return -1;
+        for child in matched.iter() {
+            if let Some(event) = wait_child_event(child, options) {
+                return Ok(Some(event));
             }
+        }
 
-            //error!("Found :{} hang:{}",found,options);
-            if options==0{ // found target , but it not zombie
-                warn!("Hang parent!");
-                // Block parent task,and runing next task
-                TASK_MANAER.blocking_current_task_and_run_next();
-
-                // 继续尝试收割
-                continue;
-            }
+        // 非阻塞，posix直接返回0
+        if options & WNOHANG != 0 {
+            return Ok(None);
+        }
+        if TASK_MANAER.current_has_signal() {
+            return Err(-Errno::ERESTARTSYS);
+        }
+        // 登记到等待队列之后再确认一次，子进程在检查之后退出也能唤醒
+        child_exit.sleep_unless(|| matched.iter().any(|c| wait_child_ready(c, options)));
+        // 父亲从这里苏醒 继续尝试回收
+    }
+}
 
-            // 非阻塞，posix直接返回0
-            //warn!("Child was found but,it not zombies ,it is running(smp) or ready(signal core), when you exit your child will foster to init");
+///当前进程的进程组，pid 为 0 的 wait 用
+fn current_pgid() -> i32 {
+    TASK_MANAER.expect_current_task().lock().pgid
+}
 
-            return 0;
+///wait 状态字换成 waitid 的 si_code 和 si_status
+fn wait_status_to_cld(status: i32) -> (i32, i32) {
+    if status == WAIT_CONTINUED {
+        (CLD_CONTINUED, Signal::SIGCONT.signo() as i32)
+    } else if status & 0xff == 0x7f {
+        (CLD_STOPPED, (status >> 8) & 0xff)
+    } else if status & 0x7f != 0 {
+        (CLD_KILLED, status & 0x7f)
+    } else {
+        (CLD_EXITED, (status >> 8) & 0xff)
+    }
+}
 
-            
-        } else {
-            // pid == -1: find any zombie
-            for child in children.iter() {
-                let cpid = { child.lock().pid.0 };
-                let status = { child.lock().task_statut.clone() };
-                if matches!(status, TaskStatus::Zombie) {
-                    let exit_code = match TASK_MANAER.reap_zombie_child(cpid) {
-                        Some(code) => code,
-                        None => return -1,
-                    };
-                    if wstatus_ptr != 0 {
-                        let st: i32 = ((exit_code as i32) & 0xff) << 8;
-                        let user_satp = TASK_MANAER.get_current_stap();
-                        let mut slices = PageTable::get_mut_slice_from_satp(
-                            user_satp,
-                            size_of::<i32>(),
-                            VirAddr(wstatus_ptr),
-                        );
-                        if slices.is_empty() {
-                            return -1;
-                        }
-                        let bytes = st.to_le_bytes();
-                        let mut written = 0usize;
-                        for s in slices.iter_mut() {
-                            let n = core::cmp::min(s.len(), bytes.len().saturating_sub(written));
-                            if n == 0 {
-                                break;
-                            }
-                            s[..n].copy_from_slice(&bytes[written..written + n]);
-                            written += n;
-                        }
-                        if written != bytes.len() {
-                            return -1;
-                        }
-                    }
-                    return cpid as isize;
-                }
-            }
+/// wait4(pid, wstatus, options, rusage)
+///
+/// - pid > 0   : 等待指定 pid 子进程
+/// - pid == -1 : 等待任意子进程
+/// - pid == 0  : 等待和自己同一进程组的子进程
+/// - pid < -1  : 等待进程组 -pid 里的子进程
+///
+/// wstatus 写回遵循 Linux：退出码存放在高 8 bit（status = exit_code << 8）。
+/// 被信号杀死的子进程低 7 位是信号编号（WIFSIGNALED）。
+/// WUNTRACED/WCONTINUED 时停止和继续的子进程也会返回（WIFSTOPPED/WIFCONTINUED），每次状态变化只报告一次。
+/// 默认只等退出时发 SIGCHLD 的子进程，__WCLONE 只等其它的，__WALL 都等；子进程列表本来就是每个线程自己的，__WNOTHREAD 不用处理。
+/// rusage 是子进程连同它回收过的子进程的 CPU 时间和驻留内存峰值。
+/// 没有匹配的子进程返回 -Errno::ECHILD，WNOHANG 下子进程都还没有状态变化返回 0。
+pub fn sys_wait4(pid: i32, wstatus_ptr: usize, options: i32, rusage_ptr: usize) -> isize {
+    if options & !(WNOHANG | WUNTRACED | WCONTINUED | __WNOTHREAD | __WCLONE | __WALL) != 0 {
+        warn!("sys_wait4: unsupport options={:#x}", options);
+        return -Errno::EINVAL;
+    }
+    let target = match pid {
+        -1 => WaitTarget::Any,
+        0 => WaitTarget::Pgid(current_pgid()),
+        // -INT_MIN 溢出，Linux 返回 ESRCH
+        i32::MIN => return -Errno::ESRCH,
+        p if p < 0 => WaitTarget::Pgid(-p),
+        p => WaitTarget::Pid(p),
+    };
 
-            // 多核可用这个，单核只能单任务
-            // let has_child_run =children.iter().any(|cd|{
-            //     cd.lock().task_statut == TaskStatus::Runing
-            // });
-            
-            if options==0 {
-                //warn!("I'm, father,pid {} ",sys_getpid());
-                // Blocling current task
-                TASK_MANAER.blocking_current_task_and_run_next();
-                
-                // 父亲从这里苏醒 继续尝试回收
-                continue;
-                //warn!("I'm back! i'm father:{}",sys_getpid());
-            }
+    let event = match do_wait(target, options | WEXITED) {
+        Ok(Some(event)) => event,
+        Ok(None) => return 0,
+        Err(err) => return err,
+    };
+    if wstatus_ptr != 0 {
+        if let Err(e) = UserPtr::new(wstatus_ptr).write(&event.status) {
+            return -e;
+        }
+    }
+    if rusage_ptr != 0 {
+        if let Err(e) = UserPtr::new(rusage_ptr).write(&Rusage::from_usage(&event.usage)) {
+            return -e;
+        }
+    }
+    event.pid as isize
+}
 
-            // 非阻塞，posix直接返回0
-            return 0;
+/// waitid(idtype, id, infop, options, rusage)
+///
+/// idtype 支持 P_ALL、P_PID、P_PGID（id 为 0 时是自己的进程组），P_PIDFD 不支持。
+/// options 至少要有 WEXITED/WSTOPPED/WCONTINUED 之一，WNOWAIT 只看不回收。
+/// 成功返回 0，状态写进 infop 的 siginfo_t（si_signo=SIGCHLD，si_code 是 CLD_*）；
+/// WNOHANG 下没有结果时 siginfo 清零，用户态靠 si_pid==0 判断。
+pub fn sys_waitid(idtype: usize, id: usize, infop: usize, options: i32, rusage_ptr: usize) -> isize {
+    let valid = WNOHANG | WNOWAIT | WEXITED | WSTOPPED | WCONTINUED | __WNOTHREAD | __WCLONE | __WALL;
+    if options & !valid != 0 || options & (WEXITED | WSTOPPED | WCONTINUED) == 0 {
+        warn!("sys_waitid: unsupport options={:#x}", options);
+        return -Errno::EINVAL;
+    }
+    let target = match idtype {
+        P_ALL => WaitTarget::Any,
+        P_PID if id as i32 > 0 => WaitTarget::Pid(id as i32),
+        P_PGID if id == 0 => WaitTarget::Pgid(current_pgid()),
+        P_PGID if id as i32 > 0 => WaitTarget::Pgid(id as i32),
+        _ => {
+            warn!("sys_waitid: unsupport idtype={} id={}", idtype, id);
+            return -Errno::EINVAL;
+        }
+    };
 
+    let event = match do_wait(target, options) {
+        Ok(event) => event,
+        Err(err) => return err,
+    };
+    let (info, usage) = match &event {
+        Some(event) => {
+            let (code, status) = wait_status_to_cld(event.status);
+            (SigInfo::child(Signal::SIGCHLD.signo(), code, event.pid, status), event.usage)
+        }
+        None => (SigInfo::new(0, 0), TaskUsage::default()),
+    };
+    if infop != 0 {
+        if let Err(e) = UserPtr::new(infop).write(&info) {
+            return -e;
+        }
+    }
+    if rusage_ptr != 0 {
+        if let Err(e) = UserPtr::new(rusage_ptr).write(&Rusage::from_usage(&usage)) {
+            return -e;
         }
-        // 不需要轮询
-        //TASK_MANAER.suspend_and_run_task();
     }
+    0
 }
 
 ///主动放弃cpu
@@ -1677,3 +1858,318 @@ pub fn sys_yield()->isize{
 }
 
 
+///SYS_RT_SIGACTION系统调用 rt_sigaction(signum, act, oldact, sigsetsize)
+/// SIGKILL/SIGSTOP 不能改；设成忽略时丢掉已经待处理的这个信号
+pub fn sys_rt_sigaction(signum: usize, act_ptr: usize, oldact_ptr: usize, sigsetsize: usize) -> isize {
+    if sigsetsize != size_of::<u64>() {
+        return -Errno::EINVAL;
+    }
+    let Some(sig) = Signal::from_signo(signum) else {
+        return -Errno::EINVAL;
+    };
+    if act_ptr != 0 && Signal::unblockable().contains(sig) {
+        return -Errno::EINVAL;
+    }
+    let new_action = if act_ptr != 0 {
+        match UserPtr::<SigAction>::new(act_ptr).read() {
+            Ok(act) => Some(act),
+            Err(e) => return -e,
+        }
+    } else {
+        None
+    };
+
+    let task = TASK_MANAER.expect_current_task();
+    let sig_actions = task.lock().sig_actions.clone();
+    let old_action = {
+        let mut actions = sig_actions.lock();
+        let old = actions[signum - 1];
+        if let Some(act) = new_action {
+            actions[signum - 1] = act;
+        }
+        old
+    };
+    if new_action.map_or(false, |act| act.handler == SIG_IGN) {
+        let mut t = task.lock();
+        t.signal.remove(sig);
+        t.signal_info.remove(&signum);
+    }
+    drop(task);
+
+    if oldact_ptr != 0 {
+        if let Err(e) = UserPtr::new(oldact_ptr).write(&old_action) {
+            return -e;
+        }
+    }
+    0
+}
+
+const SIG_BLOCK: usize = 0;
+const SIG_UNBLOCK: usize = 1;
+const SIG_SETMASK: usize = 2;
+
+///SYS_RT_SIGPROCMASK系统调用 rt_sigprocmask(how, set, oldset, sigsetsize)
+/// SIGKILL/SIGSTOP 不会被屏蔽
+pub fn sys_rt_sigprocmask(how: usize, set_ptr: usize, oldset_ptr: usize, sigsetsize: usize) -> isize {
+    if sigsetsize != size_of::<u64>() {
+        return -Errno::EINVAL;
+    }
+    let set = if set_ptr != 0 {
+        match UserPtr::<u64>::new(set_ptr).read() {
+            Ok(set) => Some(Signal::from_bits_retain(set as usize)),
+            Err(e) => return -e,
+        }
+    } else {
+        None
+    };
+
+    let task = TASK_MANAER.expect_current_task();
+    let old_mask = {
+        let mut t = task.lock();
+        let old = t.signal_mask;
+        if let Some(set) = set {
+            let mask = match how {
+                SIG_BLOCK => old | set,
+                SIG_UNBLOCK => old - set,
+                SIG_SETMASK => set,
+                _ => return -Errno::EINVAL,
+            };
+            t.signal_mask = mask - Signal::unblockable();
+        }
+        old
+    };
+    drop(task);
+
+    if oldset_ptr != 0 {
+        if let Err(e) = UserPtr::new(oldset_ptr).write(&(old_mask.bits() as u64)) {
+            return -e;
+        }
+    }
+    0
+}
+
+///SYS_RT_SIGPENDING系统调用 rt_sigpending(set, sigsetsize) 返回被屏蔽着的待处理信号
+pub fn sys_rt_sigpending(set_ptr: usize, sigsetsize: usize) -> isize {
+    if sigsetsize != size_of::<u64>() {
+        return -Errno::EINVAL;
+    }
+    let pending = {
+        let task = TASK_MANAER.expect_current_task();
+        let t = task.lock();
+        t.signal & t.signal_mask
+    };
+    if let Err(e) = UserPtr::new(set_ptr).write(&(pending.bits() as u64)) {
+        return -e;
+    }
+    0
+}
+
+///SYS_RT_SIGSUSPEND系统调用 rt_sigsuspend(mask, sigsetsize)
+/// 临时换上 mask 睡眠直到有信号要递送，处理函数运行后返回 -EINTR；原来的屏蔽字在递送信号时换回去
+pub fn sys_rt_sigsuspend(mask_ptr: usize, sigsetsize: usize) -> isize {
+    if sigsetsize != size_of::<u64>() {
+        return -Errno::EINVAL;
+    }
+    let mask = match UserPtr::<u64>::new(mask_ptr).read() {
+        Ok(mask) => mask,
+        Err(e) => return -e,
+    };
+    {
+        let task = TASK_MANAER.expect_current_task();
+        let mut t = task.lock();
+        t.saved_signal_mask = Some(t.signal_mask);
+        t.signal_mask = Signal::from_bits_retain(mask as usize) - Signal::unblockable();
+    }
+    while !TASK_MANAER.current_has_signal() {
+        TASK_MANAER.blocking_current_task_and_run_next();
+    }
+    -Errno::ERESTARTNOHAND
+}
+
+///SYS_SIGALTSTACK系统调用 sigaltstack(ss, old_ss)
+/// 正在备用栈上执行处理函数时不能更换
+pub fn sys_sigaltstack(ss_ptr: usize, old_ss_ptr: usize) -> isize {
+    let user_sp = TASK_MANAER.get_current_trapcx().x[2];
+    let task = TASK_MANAER.expect_current_task();
+    let mut old = task.lock().sig_altstack;
+    let on_stack = old.ss_flags & SS_DISABLE == 0 && user_sp > old.ss_sp && user_sp <= old.ss_sp + old.ss_size;
+    if on_stack {
+        old.ss_flags |= SS_ONSTACK;
+    }
+
+    if ss_ptr != 0 {
+        let ss = match UserPtr::<SignalStack>::new(ss_ptr).read() {
+            Ok(ss) => ss,
+            Err(e) => return -e,
+        };
+        if on_stack {
+            return -Errno::EPERM;
+        }
+        let new = if ss.ss_flags == SS_DISABLE {
+            SignalStack::default()
+        } else if ss.ss_flags == 0 || ss.ss_flags == SS_ONSTACK {
+            if ss.ss_size < MINSIGSTKSZ {
+                return -Errno::ENOMEM;
+            }
+            SignalStack { ss_sp: ss.ss_sp, ss_flags: 0, _pad: 0, ss_size: ss.ss_size }
+        } else {
+            return -Errno::EINVAL;
+        };
+        task.lock().sig_altstack = new;
+    }
+    drop(task);
+
+    if old_ss_ptr != 0 {
+        if let Err(e) = UserPtr::new(old_ss_ptr).write(&old) {
+            return -e;
+        }
+    }
+    0
+}
+
+///SYS_RT_SIGRETURN系统调用，信号处理函数返回时经跳板页进来
+/// 恢复压信号帧之前的寄存器和屏蔽字，返回值就是原来的 a0
+pub fn sys_rt_sigreturn() -> isize {
+    sigreturn()
+}
+
+///SYS_KILL系统调用 kill(pid, sig)
+/// pid>0 发给这个进程；0 发给调用者所在的进程组；-1 发给除 init 和自己以外的所有进程；<-1 发给进程组 -pid
+/// sig 为 0 时只检查目标存不存在
+pub fn sys_kill(pid: isize, signum: usize) -> isize {
+    if signum > NSIG {
+        return -Errno::EINVAL;
+    }
+    let (self_tgid, self_pgid) = {
+        let task = TASK_MANAER.expect_current_task();
+        let t = task.lock();
+        (t.tgid, t.pgid)
+    };
+    let mut targets: Vec<i32> = match pid {
+        p if p > 0 => TASK_MANAER.find_tasks(|t| t.tgid == p as i32).iter().map(|task| task.lock().tgid).collect(),
+        0 => TASK_MANAER.find_tasks(|t| t.pgid == self_pgid).iter().map(|task| task.lock().tgid).collect(),
+        -1 => TASK_MANAER
+            .find_tasks(|t| t.tgid != INIT_PID && t.tgid != self_tgid)
+            .iter()
+            .map(|task| task.lock().tgid)
+            .collect(),
+        p => TASK_MANAER.find_tasks(|t| t.pgid == (-p) as i32).iter().map(|task| task.lock().tgid).collect(),
+    };
+    targets.sort_unstable();
+    targets.dedup();
+    if targets.is_empty() {
+        return -Errno::ESRCH;
+    }
+    let Some(sig) = Signal::from_signo(signum) else {
+        return 0;
+    };
+    for tgid in targets {
+        send_signal_to_process(tgid, sig, Some(SigInfo::from_sender(signum, SI_USER, self_tgid)));
+    }
+    0
+}
+
+///发给单个线程，tkill/tgkill 共用；tgid 为 None 时不检查线程组
+fn signal_thread(tgid: Option<i32>, tid: i32, signum: usize) -> isize {
+    if tid <= 0 || signum > NSIG {
+        return -Errno::EINVAL;
+    }
+    let targets = TASK_MANAER.find_tasks(|t| t.pid.0 == tid && tgid.map_or(true, |g| t.tgid == g));
+    let Some(task) = targets.first() else {
+        return -Errno::ESRCH;
+    };
+    if let Some(sig) = Signal::from_signo(signum) {
+        let sender = TASK_MANAER.expect_current_task().lock().tgid;
+        send_signal(task, sig, Some(SigInfo::from_sender(signum, SI_TKILL, sender)));
+    }
+    0
+}
+
+///SYS_TKILL系统调用 tkill(tid, sig)
+pub fn sys_tkill(tid: isize, signum: usize) -> isize {
+    signal_thread(None, tid as i32, signum)
+}
+
+///SYS_TGKILL系统调用 tgkill(tgid, tid, sig)，线程不在这个线程组里时失败
+pub fn sys_tgkill(tgid: isize, tid: isize, signum: usize) -> isize {
+    if tgid <= 0 {
+        return -Errno::EINVAL;
+    }
+    signal_thread(Some(tgid as i32), tid as i32, signum)
+}
+
+///SYS_SETPGID系统调用 setpgid(pid, pgid)
+/// pid 为 0 是调用者自己，pgid 为 0 用 pid 当进程组号；只能改自己或者自己的子进程，
+/// 会话首进程不能改，加入的进程组必须在同一个会话里
+pub fn sys_setpgid(pid: isize, pgid: isize) -> isize {
+    if pid < 0 || pgid < 0 {
+        return -Errno::EINVAL;
+    }
+    let current = TASK_MANAER.expect_current_task();
+    let (self_tgid, self_sid, children) = {
+        let t = current.lock();
+        (t.tgid, t.sid, t.childrens.clone())
+    };
+    drop(current);
+    let target = if pid == 0 { self_tgid } else { pid as i32 };
+    let pgid = if pgid == 0 { target } else { pgid as i32 };
+    if target != self_tgid && !children.iter().any(|c| c.lock().tgid == target) {
+        return -Errno::ESRCH;
+    }
+    let threads = TASK_MANAER.find_tasks(|t| t.tgid == target);
+    let Some(leader) = threads.first() else {
+        return -Errno::ESRCH;
+    };
+    let target_sid = leader.lock().sid;
+    if target_sid != self_sid || target_sid == target {
+        return -Errno::EPERM;
+    }
+    if pgid != target && TASK_MANAER.find_tasks(|t| t.pgid == pgid && t.sid == self_sid).is_empty() {
+        return -Errno::EPERM;
+    }
+    for task in threads.iter() {
+        task.lock().pgid = pgid;
+    }
+    0
+}
+
+///找到进程 pid（0 是调用者自己）读出一个字段
+fn process_field(pid: isize, field: impl Fn(&TaskControlBlock) -> i32) -> isize {
+    if pid < 0 {
+        return -Errno::ESRCH;
+    }
+    if pid == 0 {
+        let task = TASK_MANAER.expect_current_task();
+        let value = field(&task.lock());
+        return value as isize;
+    }
+    match TASK_MANAER.find_tasks(|t| t.tgid == pid as i32).first() {
+        Some(task) => field(&task.lock()) as isize,
+        None => -Errno::ESRCH,
+    }
+}
+
+///SYS_GETPGID系统调用 getpgid(pid)
+pub fn sys_getpgid(pid: isize) -> isize {
+    process_field(pid, |t| t.pgid)
+}
+
+///SYS_GETSID系统调用 getsid(pid)
+pub fn sys_getsid(pid: isize) -> isize {
+    process_field(pid, |t| t.sid)
+}
+
+///SYS_SETSID系统调用，新建会话和进程组，调用者成为首进程，没有控制终端
+/// 调用者已经是某个进程组的组长时失败
+pub fn sys_setsid() -> isize {
+    let tgid = TASK_MANAER.expect_current_task().lock().tgid;
+    if !TASK_MANAER.find_tasks(|t| t.pgid == tgid).is_empty() {
+        return -Errno::EPERM;
+    }
+    for task in TASK_MANAER.find_tasks(|t| t.tgid == tgid).iter() {
+        let mut t = task.lock();
+        t.sid = tgid;
+        t.pgid = tgid;
+    }
+    tgid as isize
+}
@@ -0,0 +1,144 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+use crate::config::PAGE_SIZE;
+use crate::memory::MapSet;
+use crate::syscall::Errno;
+use crate::task::TASK_MANAER;
+
+/// 用户态地址访问层：系统调用只通过这里读写当前任务的用户内存
+/// 统一检查 U/R/W 位，先补上 mmap/ELF 懒加载页和换出页，写之前打破 COW，跨页拷贝，访问不了是 EFAULT，分配不到页帧是 ENOMEM
+/// 调用时不能拿着当前任务的 TCB 锁和 MapSet 的锁（内部要锁 MapSet，读文件页时还要放开它）
+
+///指向用户内存里一个 T 的指针
+pub struct UserPtr<T> {
+    addr: usize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T: Copy> UserPtr<T> {
+    pub fn new(addr: usize) -> Self {
+        Self { addr, _marker: PhantomData }
+    }
+
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    ///数组里往后第 count 个元素
+    pub fn add(&self, count: usize) -> Result<Self, Errno> {
+        count
+            .checked_mul(size_of::<T>())
+            .and_then(|off| self.addr.checked_add(off))
+            .map(Self::new)
+            .ok_or(Errno::EFAULT)
+    }
+
+    pub fn read(&self) -> Result<T, Errno> {
+        let mut value = MaybeUninit::<T>::zeroed();
+        let buf = unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>()) };
+        UserSlice::new(self.addr, size_of::<T>()).read(buf)?;
+        Ok(unsafe { value.assume_init() })
+    }
+
+    pub fn write(&self, value: &T) -> Result<(), Errno> {
+        let buf = unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        UserSlice::new(self.addr, size_of::<T>()).write(buf)
+    }
+}
+
+///用户内存里的一段字节 [addr, addr+len)
+#[derive(Clone, Copy)]
+pub struct UserSlice {
+    addr: usize,
+    len: usize,
+}
+
+impl UserSlice {
+    pub fn new(addr: usize, len: usize) -> Self {
+        Self { addr, len }
+    }
+
+    fn check_bounds(&self, n: usize) -> Result<(), Errno> {
+        if n > self.len || self.addr.checked_add(self.len).is_none() {
+            return Err(Errno::EFAULT);
+        }
+        Ok(())
+    }
+
+    ///从开头读满 buf（buf 不能比这段长）
+    pub fn read(&self, buf: &mut [u8]) -> Result<(), Errno> {
+        self.check_bounds(buf.len())?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        MapSet::read_user_bytes(&TASK_MANAER.current_memory_set(), self.addr, buf)
+    }
+
+    ///整段读进内核
+    pub fn read_to_vec(&self) -> Result<Vec<u8>, Errno> {
+        self.check_bounds(self.len)?;
+        self.check(false)?;
+        let mut buf = vec![0u8; self.len];
+        self.read(&mut buf)?;
+        Ok(buf)
+    }
+
+    ///把 data 写到开头（data 不能比这段长）
+    pub fn write(&self, data: &[u8]) -> Result<(), Errno> {
+        self.check_bounds(data.len())?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        MapSet::write_user_bytes(&TASK_MANAER.current_memory_set(), self.addr, data)
+    }
+
+    ///不拷贝，只确认整段可读/可写（写时顺便打破 COW），按用户给的长度分配内核缓冲之前先调用
+    pub fn check(&self, write: bool) -> Result<(), Errno> {
+        self.check_bounds(self.len)?;
+        if self.len == 0 {
+            return Ok(());
+        }
+        MapSet::with_user_range(&TASK_MANAER.current_memory_set(), self.addr, self.len, write, |memory_set| {
+            let mut va = self.addr;
+            let end = self.addr + self.len;
+            while va < end {
+                memory_set.user_paddr(va, write).ok_or(Errno::EFAULT)?;
+                va = (va / PAGE_SIZE + 1) * PAGE_SIZE;
+            }
+            Ok(())
+        })
+    }
+}
+
+///用户内存里以 NUL 结尾的字符串
+#[derive(Clone, Copy)]
+pub struct UserCStr {
+    addr: usize,
+}
+
+impl UserCStr {
+    pub fn new(addr: usize) -> Self {
+        Self { addr }
+    }
+
+    ///读出字符串（不含 NUL），max 字节内没有 NUL 是 ENAMETOOLONG，不是 UTF-8 是 EINVAL
+    pub fn read(&self, max: usize) -> Result<String, Errno> {
+        if self.addr == 0 {
+            return Err(Errno::EFAULT);
+        }
+        let bytes = MapSet::read_user_cstr(&TASK_MANAER.current_memory_set(), self.addr, max)
+            .map_err(|too_long| if too_long { Errno::ENAMETOOLONG } else { Errno::EFAULT })?;
+        String::from_utf8(bytes).map_err(|_| Errno::EINVAL)
+    }
+}
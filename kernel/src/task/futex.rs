@@ -0,0 +1,217 @@
+//! futex：用户态的锁、条件变量和 pthread_join 在内核里睡眠的地方
+//! 私有页上的 futex 按（地址空间，虚拟地址）分组，页被换出再换进、COW 拆开都不影响
+//! MAP_SHARED 页上的按物理地址分组，不同地址空间映射同一个页时也能互相唤醒
+//! 比较 futex 的值和登记等待在同一把锁下完成，用户态改值之后再 wake 不会丢唤醒
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+use lazy_static::lazy_static;
+
+use crate::memory::{MapSet, VirAddr};
+use crate::sync::SpinLock;
+use crate::syscall::Errno;
+use crate::task::TASK_MANAER;
+use crate::time::{add_timer, get_time_ms};
+
+/// futex 操作码，低位是命令，高位是标志
+pub const FUTEX_WAIT: usize = 0;
+pub const FUTEX_WAKE: usize = 1;
+pub const FUTEX_REQUEUE: usize = 3;
+pub const FUTEX_CMP_REQUEUE: usize = 4;
+pub const FUTEX_WAIT_BITSET: usize = 9;
+pub const FUTEX_WAKE_BITSET: usize = 10;
+/// 私有 futex 总是按（地址空间，虚拟地址）分组，不去看页是不是 MAP_SHARED
+pub const FUTEX_PRIVATE_FLAG: usize = 128;
+/// 只有一个时钟，CLOCK_REALTIME 和 CLOCK_MONOTONIC 一样处理
+pub const FUTEX_CLOCK_REALTIME: usize = 256;
+pub const FUTEX_BITSET_MATCH_ANY: u32 = u32::MAX;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum FutexKey {
+    ///地址空间（MapSet 的地址）+ futex 字的虚拟地址
+    Private { mm: usize, uaddr: usize },
+    ///MAP_SHARED 页上 futex 字的物理地址，共享映射的页不会被换出，映射期间物理地址不变
+    Shared { paddr: usize },
+}
+
+struct FutexWaiter {
+    pid: i32,
+    bitset: u32,
+}
+
+lazy_static! {
+    ///futex key -> 在上面睡眠的任务，先来的排前面
+    static ref FUTEXES: SpinLock<BTreeMap<FutexKey, VecDeque<FutexWaiter>>> = SpinLock::new(BTreeMap::new());
+}
+
+///当前地址空间里用户地址 uaddr 的 futex key，private 为真时不看页是不是共享映射
+/// futex 只读 futex 字，页只需要补上，不拆 COW
+fn futex_key(uaddr: usize, private: bool) -> Result<FutexKey, Errno> {
+    let len = core::mem::size_of::<u32>();
+    if uaddr % len != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let memory_set = TASK_MANAER.current_memory_set();
+    MapSet::with_user_range(&memory_set, uaddr, len, false, |memset| {
+        let paddr = memset.user_paddr(uaddr, false).ok_or(Errno::EFAULT)?;
+        if !private && memset.is_shared_vpn(VirAddr(uaddr).floor_down()) {
+            return Ok(FutexKey::Shared { paddr });
+        }
+        Ok(FutexKey::Private { mm: Arc::as_ptr(&memory_set) as usize, uaddr })
+    })
+}
+
+///读当前地址空间里 uaddr 处 futex 字的值
+/// 拿着地址空间的锁读，读的时候页不会被换出
+fn load(uaddr: usize) -> Result<u32, Errno> {
+    MapSet::with_user_range(&TASK_MANAER.current_memory_set(), uaddr, core::mem::size_of::<u32>(), false, |memset| {
+        let paddr = memset.user_paddr(uaddr, false).ok_or(Errno::EFAULT)?;
+        Ok(unsafe { (*(paddr as *const AtomicU32)).load(Ordering::SeqCst) })
+    })
+}
+
+///从 key 的队列里摘下最多 n 个 bitset 有交集的等待者
+fn take_waiters(futexes: &mut BTreeMap<FutexKey, VecDeque<FutexWaiter>>, key: FutexKey, n: usize, bitset: u32) -> Vec<FutexWaiter> {
+    let mut taken = Vec::new();
+    let Some(queue) = futexes.get_mut(&key) else {
+        return taken;
+    };
+    let mut idx = 0;
+    while idx < queue.len() && taken.len() < n {
+        if queue[idx].bitset & bitset != 0 {
+            taken.push(queue.remove(idx).expect("Kernel Error"));
+        } else {
+            idx += 1;
+        }
+    }
+    if queue.is_empty() {
+        futexes.remove(&key);
+    }
+    taken
+}
+
+///把 pid 从所有 futex 队列上摘下来（requeue 可能把它挪到了别的地址），返回它是否还在队列上
+fn dequeue(pid: i32) -> bool {
+    let mut futexes = FUTEXES.lock();
+    let mut found = false;
+    for queue in futexes.values_mut() {
+        let before = queue.len();
+        queue.retain(|w| w.pid != pid);
+        found |= queue.len() != before;
+    }
+    futexes.retain(|_, queue| !queue.is_empty());
+    found
+}
+
+fn is_queued(pid: i32) -> bool {
+    FUTEXES.lock().values().any(|queue| queue.iter().any(|w| w.pid == pid))
+}
+
+///*uaddr 还等于 val 时睡眠，直到被唤醒、到了 deadline_ms 或者有信号
+pub fn futex_wait(uaddr: usize, val: u32, deadline_ms: Option<usize>, bitset: u32, private: bool) -> Result<usize, Errno> {
+    if bitset == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let key = futex_key(uaddr, private)?;
+    let pid = TASK_MANAER.get_current_pid().ok_or(Errno::EINVAL)?;
+    {
+        let mut futexes = FUTEXES.lock();
+        if load(uaddr)? != val {
+            return Err(Errno::EAGAIN);
+        }
+        futexes.entry(key).or_default().push_back(FutexWaiter { pid, bitset });
+    }
+    if let Some(deadline) = deadline_ms {
+        add_timer(deadline, pid);
+    }
+    loop {
+        // 唤醒者把我们从队列上摘掉之后才唤醒
+        if !is_queued(pid) {
+            return Ok(0);
+        }
+        let timeout = deadline_ms.map_or(false, |d| get_time_ms() >= d);
+        if timeout || TASK_MANAER.current_has_signal() {
+            // 摘下来之前可能刚好被唤醒，这时算作唤醒成功
+            if !dequeue(pid) {
+                return Ok(0);
+            }
+            return Err(if timeout {
+                Errno::ETIMEDOUT
+            } else if deadline_ms.is_none() {
+                Errno::ERESTARTSYS
+            } else {
+                // 带超时的重新执行会从头算时间，直接返回 EINTR
+                Errno::EINTR
+            });
+        }
+        TASK_MANAER.blocking_current_task_and_run_next();
+    }
+}
+
+///唤醒 uaddr 上最多 n 个 bitset 有交集的等待者，返回唤醒的个数
+pub fn futex_wake(uaddr: usize, n: usize, bitset: u32, private: bool) -> Result<usize, Errno> {
+    if bitset == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let key = futex_key(uaddr, private)?;
+    let woken = take_waiters(&mut FUTEXES.lock(), key, n, bitset);
+    for waiter in woken.iter() {
+        TASK_MANAER.wake_task_from_blocking(waiter.pid);
+    }
+    Ok(woken.len())
+}
+
+///唤醒 uaddr 上最多 nr_wake 个等待者，再把最多 nr_requeue 个挪到 uaddr2 上
+/// cmp 不为 None 时（FUTEX_CMP_REQUEUE）先检查 *uaddr 还等于它
+pub fn futex_requeue(uaddr: usize, nr_wake: usize, nr_requeue: usize, uaddr2: usize, cmp: Option<u32>, private: bool) -> Result<usize, Errno> {
+    let key = futex_key(uaddr, private)?;
+    let key2 = futex_key(uaddr2, private)?;
+    let (woken, requeued) = {
+        let mut futexes = FUTEXES.lock();
+        if let Some(val) = cmp {
+            if load(uaddr)? != val {
+                return Err(Errno::EAGAIN);
+            }
+        }
+        let woken = take_waiters(&mut futexes, key, nr_wake, FUTEX_BITSET_MATCH_ANY);
+        let moved = take_waiters(&mut futexes, key, nr_requeue, FUTEX_BITSET_MATCH_ANY);
+        let requeued = moved.len();
+        if requeued > 0 {
+            futexes.entry(key2).or_default().extend(moved);
+        }
+        (woken, requeued)
+    };
+    for waiter in woken.iter() {
+        TASK_MANAER.wake_task_from_blocking(waiter.pid);
+    }
+    Ok(woken.len() + requeued)
+}
+
+///两种 wait 的第四个参数是超时 timespec，其余操作里它是整数 val2
+pub fn futex_op_has_timeout(op: usize) -> bool {
+    matches!(op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME), FUTEX_WAIT | FUTEX_WAIT_BITSET)
+}
+
+///futex 系统调用的分发，timeout_ms 是已经从用户态读出来的超时（毫秒）
+/// FUTEX_WAIT 的超时是相对时间，FUTEX_WAIT_BITSET 的是绝对时间；失败返回负的错误码
+pub fn do_futex(uaddr: usize, op: usize, val: usize, timeout_ms: Option<usize>, val2: usize, uaddr2: usize, val3: usize) -> isize {
+    let private = op & FUTEX_PRIVATE_FLAG != 0;
+    let ret = match op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME) {
+        FUTEX_WAIT => {
+            let deadline = timeout_ms.map(|ms| get_time_ms().saturating_add(ms));
+            futex_wait(uaddr, val as u32, deadline, FUTEX_BITSET_MATCH_ANY, private)
+        }
+        FUTEX_WAIT_BITSET => futex_wait(uaddr, val as u32, timeout_ms, val3 as u32, private),
+        FUTEX_WAKE => futex_wake(uaddr, val, FUTEX_BITSET_MATCH_ANY, private),
+        FUTEX_WAKE_BITSET => futex_wake(uaddr, val, val3 as u32, private),
+        FUTEX_REQUEUE => futex_requeue(uaddr, val, val2, uaddr2, None, private),
+        FUTEX_CMP_REQUEUE => futex_requeue(uaddr, val, val2, uaddr2, Some(val3 as u32), private),
+        _ => Err(Errno::ENOSYS),
+    };
+    match ret {
+        Ok(n) => n as isize,
+        Err(e) => -e,
+    }
+}
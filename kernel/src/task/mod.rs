@@ -1,8 +1,20 @@
 mod task;
 mod process;
-use crate::fs::vfs::{OpenFlags, vfs_open};
-use alloc::vec::{Vec};
-use alloc::vec;
+mod wait_queue;
+mod futex;
+mod signal;
+pub use wait_queue::WaitQueue;
+pub use futex::{do_futex, futex_op_has_timeout, futex_wake, FUTEX_BITSET_MATCH_ANY};
+pub use signal::{
+    exec_sig_actions, handle_signals, RestartBlock, has_deliverable_signal, new_sig_actions, notify_parent, send_signal,
+    send_signal_to_process, sigreturn, SigAction, SigActionFlags, SigActions, SigInfo, SignalStack,
+    CLD_CONTINUED, CLD_DUMPED, CLD_EXITED, CLD_KILLED, CLD_STOPPED, MINSIGSTKSZ, NSIG, SI_KERNEL,
+    SI_TKILL, SI_USER, SIG_DFL, SIG_IGN, SS_DISABLE, SS_ONSTACK,
+    force_signal, current_ignores_or_blocks, wait_stopped, WAIT_CONTINUED, BUS_ADRALN, BUS_ADRERR, ILL_ILLOPC, SEGV_ACCERR, SEGV_MAPERR, TRAP_BRKPT,
+};
+pub use process::{hart_id, current_processer, Processer};
+use crate::fs::vfs::{File, OpenFlags, VfsFsError, VfsStat, VFS_DT_REG, vfs_open};
+use alloc::sync::Arc;
 use log::{debug, error, info, warn};
 use bitflags::bitflags;
 use crate::config::{app_end, app_start};
@@ -55,10 +67,41 @@ pub fn have_elf_header(data:[u8;4])->bool{
     }
 }
 
-/// 文件加载器，根据 app_id 从文件系统 /test 目录加载对应的 ELF 文件
-/// app_id 从 0 开始
-/// 自动elf过滤
-pub fn file_loader(file_path: &str) -> Vec<u8> {
+/// 内联进内核镜像的 init 程序，包装成只读 File，和文件系统里的程序走同一条按需分页路径
+struct EmbeddedApp(&'static [u8]);
+
+impl File for EmbeddedApp {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, VfsFsError> {
+        Err(VfsFsError::NotSupported)
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize, VfsFsError> {
+        Err(VfsFsError::PermissionDenied)
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, VfsFsError> {
+        if offset >= self.0.len() {
+            return Ok(0);
+        }
+        let n = core::cmp::min(buf.len(), self.0.len() - offset);
+        buf[..n].copy_from_slice(&self.0[offset..offset + n]);
+        Ok(n)
+    }
+
+    fn stat(&self) -> Result<VfsStat, VfsFsError> {
+        Ok(VfsStat {
+            inode: 0,
+            size: self.0.len() as u64,
+            mode: 0,
+            file_type: VFS_DT_REG,
+            nlink: 1,
+        })
+    }
+}
+
+/// 文件加载器，打开可执行文件并校验 elf 头
+/// 不再把整个 ELF 读进内核堆，返回的 File 由 from_elf 记录到各个段，缺页时按需读取
+pub fn file_loader(file_path: &str) -> Option<Arc<dyn File>> {
     debug!("Eter in loader");
 
     // 比赛内联init
@@ -66,10 +109,10 @@ pub fn file_loader(file_path: &str) -> Vec<u8> {
         let start = app_start as usize;
         let end = app_end as usize;
         if end <= start {
-            return vec![];
+            return None;
         }
         let bytes = unsafe { core::slice::from_raw_parts(start as *const u8, end - start) };
-        return bytes.to_vec();
+        return Some(Arc::new(EmbeddedApp(bytes)));
     }
 
     let fd =match vfs_open(file_path, OpenFlags::empty()){
@@ -78,46 +121,31 @@ pub fn file_loader(file_path: &str) -> Vec<u8> {
         }
         Err(_)=>{
             warn!("Open Application faild,can't open it");
-            return vec![];//提前结束
+            return None;//提前结束
         }
     };
     debug!("open file success");
 
-    let mut out: Vec<u8> = Vec::new();
-
-
     // elf校验
-    let mut tmp = [0u8; 512];
-    let n = match fd.read(&mut tmp) {
-            Ok(n) => n,
-            Err(e) => {
-                error!("file_loader: fd.read failed: path={} err={:?}", file_path, e);
-                return vec![];
-            }
-        };
-        
-    if !have_elf_header([tmp[0],tmp[1],tmp[2],tmp[3]]) {
+    let mut magic = [0u8; 4];
+    match fd.read_at(0, &mut magic) {
+        Ok(4) => {}
+        Ok(_) => {
+            warn!("file_loader: file too short: path={}", file_path);
+            return None;
+        }
+        Err(e) => {
+            error!("file_loader: fd.read_at failed: path={} err={:?}", file_path, e);
+            return None;
+        }
+    }
+    if !have_elf_header(magic) {
         warn!("Valid elf file");
-        return vec![]
+        return None;
     }
 
-        out.extend_from_slice(&tmp[..n]);
-
-    loop {
-        let n = match fd.read(&mut tmp) {
-            Ok(n) => n,
-            Err(e) => {
-                error!("file_loader: fd.read failed: path={} err={:?}", file_path, e);
-                return vec![];
-            }
-        };
-        if n == 0 {
-            break;
-        }
-        out.extend_from_slice(&tmp[..n]);
-    }
     info!("Load app for {} success!", file_path);
-    out
+    Some(fd)
 }
 
 pub use task::*;
\ No newline at end of file
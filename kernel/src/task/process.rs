@@ -1,18 +1,66 @@
 ///
 /// 进程管理调度
+/// 每个 hart 一个 Processer，记录正在运行的任务和 idle 循环的上下文
 
 
 use alloc::sync::Arc;
-use crate::task::TaskControlBlock;
-
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use crate::config::MAX_HARTS;
+use crate::sync::SpinLock;
+use crate::task::{TaskContext, TaskControlBlock};
 
+///当前 hartid，内核里 tp 一直保存 hartid（entry.asm 设置，陷入时从 TrapContext 恢复）
+#[inline(always)]
+pub fn hart_id()->usize{
+    let id: usize;
+    unsafe {
+        asm!("mv {}, tp", out(reg) id);
+    }
+    id
+}
 
 
 
  /**
   * 进程管理器
   */
-struct Processer{
-    task:Arc<TaskControlBlock>,
-    current_task:usize
-}
\ No newline at end of file
+pub struct Processer{
+    ///本 hart 上正在运行的任务，idle 循环时为 None
+    current:Option<Arc<SpinLock<TaskControlBlock>>>,
+    ///idle 循环的任务上下文，没有任务可运行时切回这里
+    idle_task_cx:TaskContext,
+}
+
+impl Processer {
+    const fn new()->Self{
+        Processer { current: None, idle_task_cx: TaskContext::zero_init() }
+    }
+
+    pub fn current(&self)->Option<Arc<SpinLock<TaskControlBlock>>>{
+        self.current.clone()
+    }
+
+    pub fn set_current(&mut self,task:Option<Arc<SpinLock<TaskControlBlock>>>){
+        self.current = task;
+    }
+
+    pub fn idle_task_cx_ptr(&mut self)->*mut TaskContext{
+        &mut self.idle_task_cx as *mut TaskContext
+    }
+}
+
+///只被自己的 hart 访问，不需要锁
+struct ProcesserCell(UnsafeCell<Processer>);
+
+unsafe impl Sync for ProcesserCell {}
+
+static PROCESSERS:[ProcesserCell;MAX_HARTS] = {
+    const EMPTY:ProcesserCell = ProcesserCell(UnsafeCell::new(Processer::new()));
+    [EMPTY;MAX_HARTS]
+};
+
+///当前 hart 的 Processer
+pub fn current_processer()->&'static mut Processer{
+    unsafe { &mut *PROCESSERS[hart_id()].0.get() }
+}
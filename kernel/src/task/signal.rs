@@ -0,0 +1,579 @@
+//! 信号：每个线程有自己的待处理集合和屏蔽字，处理函数表由 CLONE_SIGHAND 的线程共用
+//! 每次从内核返回用户态之前递送：默认动作在内核里做，用户处理函数通过在用户栈上压一个信号帧进入，
+//! 处理函数返回到跳板页上的 rt_sigreturn，再从帧里恢复被打断时的寄存器
+
+use alloc::sync::Arc;
+use bitflags::bitflags;
+use log::{debug, warn};
+
+use crate::config::SIGRETURN_TRAMPOLINE_ADDR;
+use crate::sync::SpinLock;
+use crate::syscall::{Errno, UserSlice, SYS_RESTART_SYSCALL};
+use crate::task::{Signal, TaskControlBlock, TaskStatus, INIT_PID, TASK_MANAER};
+
+/// 信号个数，编号 1..=64
+pub const NSIG: usize = 64;
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+
+/// sigaltstack 的 ss_flags
+pub const SS_ONSTACK: i32 = 1;
+pub const SS_DISABLE: i32 = 2;
+pub const MINSIGSTKSZ: usize = 2048;
+
+/// siginfo 的 si_code
+pub const SI_USER: i32 = 0;
+pub const SI_KERNEL: i32 = 0x80;
+pub const SI_TKILL: i32 = -6;
+/// 硬件错误的 si_code
+pub const ILL_ILLOPC: i32 = 1;
+pub const SEGV_MAPERR: i32 = 1;
+pub const SEGV_ACCERR: i32 = 2;
+pub const BUS_ADRALN: i32 = 1;
+pub const BUS_ADRERR: i32 = 2;
+pub const TRAP_BRKPT: i32 = 1;
+/// SIGCHLD 的 si_code
+pub const CLD_EXITED: i32 = 1;
+pub const CLD_KILLED: i32 = 2;
+pub const CLD_DUMPED: i32 = 3;
+pub const CLD_STOPPED: i32 = 5;
+pub const CLD_CONTINUED: i32 = 6;
+
+bitflags! {
+    /// sigaction 的 sa_flags
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct SigActionFlags: usize {
+        const SA_NOCLDSTOP = 0x0000_0001;
+        const SA_NOCLDWAIT = 0x0000_0002;
+        const SA_SIGINFO   = 0x0000_0004;
+        const SA_RESTORER  = 0x0400_0000;
+        const SA_ONSTACK   = 0x0800_0000;
+        const SA_RESTART   = 0x1000_0000;
+        const SA_NODEFER   = 0x4000_0000;
+        const SA_RESETHAND = 0x8000_0000;
+    }
+}
+
+/// riscv64 内核 ABI 的 struct sigaction（没有 sa_restorer）
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub mask: u64,
+}
+
+/// 每个信号的处理方式，下标是编号-1
+pub type SigActions = [SigAction; NSIG];
+
+/// stack_t
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SignalStack {
+    pub ss_sp: usize,
+    pub ss_flags: i32,
+    pub _pad: i32,
+    pub ss_size: usize,
+}
+
+impl Default for SignalStack {
+    fn default() -> Self {
+        SignalStack { ss_sp: 0, ss_flags: SS_DISABLE, _pad: 0, ss_size: 0 }
+    }
+}
+
+impl SignalStack {
+    fn contains(&self, sp: usize) -> bool {
+        self.ss_flags & SS_DISABLE == 0 && sp > self.ss_sp && sp <= self.ss_sp + self.ss_size
+    }
+}
+
+/// siginfo_t，128字节；kill 用 fields[0] 放 si_pid/si_uid，硬件错误用它放 si_addr
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SigInfo {
+    pub si_signo: i32,
+    pub si_errno: i32,
+    pub si_code: i32,
+    pub _pad: i32,
+    pub fields: [usize; 14],
+}
+
+impl SigInfo {
+    pub fn new(signo: usize, code: i32) -> Self {
+        SigInfo { si_signo: signo as i32, si_errno: 0, si_code: code, _pad: 0, fields: [0; 14] }
+    }
+
+    ///kill/tkill 发的信号：si_pid 是发送者的 tgid，si_uid 为 0
+    pub fn from_sender(signo: usize, code: i32, pid: i32) -> Self {
+        let mut info = SigInfo::new(signo, code);
+        info.fields[0] = pid as u32 as usize;
+        info
+    }
+
+    ///SIGCHLD 和 waitid：si_pid、si_uid 之后是 si_status
+    pub fn child(signo: usize, code: i32, pid: i32, status: i32) -> Self {
+        let mut info = SigInfo::from_sender(signo, code, pid);
+        info.fields[1] = status as u32 as usize;
+        info
+    }
+}
+
+/// mcontext_t：gregs[0] 是 pc，其余是 x1..x31；浮点状态内核不保存，留空
+#[repr(C, align(16))]
+#[derive(Clone, Copy)]
+struct MContext {
+    gregs: [usize; 32],
+    fpregs: [u64; 66],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UContext {
+    uc_flags: usize,
+    uc_link: usize,
+    uc_stack: SignalStack,
+    uc_sigmask: u64,
+    /// sigset_t 按 1024 位留的空间
+    _unused: [u8; 120],
+    uc_mcontext: MContext,
+}
+
+/// 压在用户栈上的信号帧，处理函数拿到的 siginfo/ucontext 指针都指向这里
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SignalFrame {
+    info: SigInfo,
+    uc: UContext,
+}
+
+/// 默认动作
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum DefaultAction {
+    Terminate,
+    Core,
+    Stop,
+    Ignore,
+    Continue,
+}
+
+impl Signal {
+    ///信号编号对应的一位，编号不在 1..=64 时为 None
+    pub fn from_signo(signo: usize) -> Option<Signal> {
+        if (1..=NSIG).contains(&signo) {
+            Some(Signal::from_bits_retain(1 << (signo - 1)))
+        } else {
+            None
+        }
+    }
+
+    ///只有一位时的信号编号
+    pub fn signo(&self) -> usize {
+        self.bits().trailing_zeros() as usize + 1
+    }
+
+    ///集合里编号最小的信号
+    pub fn lowest(&self) -> Option<Signal> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(Signal::from_bits_retain(1 << self.bits().trailing_zeros()))
+        }
+    }
+
+    ///不能屏蔽、捕获和忽略的信号
+    pub fn unblockable() -> Signal {
+        Signal::SIGKILL | Signal::SIGSTOP
+    }
+
+    ///让任务停下来的信号
+    pub fn stop_signals() -> Signal {
+        Signal::SIGSTOP | Signal::SIGTSTP | Signal::SIGTTIN | Signal::SIGTTOU
+    }
+
+    fn default_action(&self) -> DefaultAction {
+        let sig = *self;
+        if sig == Signal::SIGCONT {
+            DefaultAction::Continue
+        } else if Signal::stop_signals().contains(sig) {
+            DefaultAction::Stop
+        } else if (Signal::SIGCHLD | Signal::SIGURG | Signal::SIGWINCH).contains(sig) {
+            DefaultAction::Ignore
+        } else if (Signal::SIGQUIT
+            | Signal::SIGILL
+            | Signal::SIGTRAP
+            | Signal::SIGABRT
+            | Signal::SIGBUS
+            | Signal::SIGFPE
+            | Signal::SIGSEGV
+            | Signal::SIGXCPU
+            | Signal::SIGXFSZ
+            | Signal::SIGSYS)
+            .contains(sig)
+        {
+            DefaultAction::Core
+        } else {
+            DefaultAction::Terminate
+        }
+    }
+}
+
+///新进程的信号处理表，全部是默认动作
+pub fn new_sig_actions() -> Arc<SpinLock<SigActions>> {
+    Arc::new(SpinLock::new([SigAction::default(); NSIG]))
+}
+
+///exec 之后捕获的信号恢复默认动作，忽略的保持忽略
+pub fn exec_sig_actions(actions: &SigActions) -> Arc<SpinLock<SigActions>> {
+    let mut new = [SigAction::default(); NSIG];
+    for (idx, action) in actions.iter().enumerate() {
+        if action.handler == SIG_IGN {
+            new[idx].handler = SIG_IGN;
+        }
+    }
+    Arc::new(SpinLock::new(new))
+}
+
+///产生信号时就能丢掉：处理方式是忽略（SIGCONT 被忽略也要让停下的任务继续，不能丢）
+fn is_ignored(actions: &SigActions, sig: Signal) -> bool {
+    if Signal::unblockable().contains(sig) || sig == Signal::SIGCONT {
+        return false;
+    }
+    match actions[sig.signo() - 1].handler {
+        SIG_IGN => true,
+        SIG_DFL => sig.default_action() == DefaultAction::Ignore,
+        _ => false,
+    }
+}
+
+///给任务发一个信号，info 为 None 时递送时按 SI_USER 填
+/// 没被屏蔽的信号会唤醒阻塞的任务，阻塞的系统调用返回 -EINTR
+/// 调用方不能持有这个任务的 TCB 锁和任务队列锁
+pub fn send_signal(task: &Arc<SpinLock<TaskControlBlock>>, sig: Signal, info: Option<SigInfo>) {
+    let wake = {
+        let mut t = task.lock();
+        if t.task_statut == TaskStatus::Zombie {
+            return;
+        }
+        if is_ignored(&t.sig_actions.lock(), sig) {
+            return;
+        }
+        // init 只收它自己装了处理函数的信号，^C 和 kill -9 1 不会把系统带走
+        if t.tgid == INIT_PID && t.sig_actions.lock()[sig.signo() - 1].handler == SIG_DFL {
+            return;
+        }
+        // SIGCONT 和停止信号互相抵消
+        if sig == Signal::SIGCONT {
+            t.signal.remove(Signal::stop_signals());
+        } else if Signal::stop_signals().contains(sig) {
+            t.signal.remove(Signal::SIGCONT);
+        }
+        t.signal.insert(sig);
+        if let Some(info) = info {
+            t.signal_info.insert(sig.signo(), info);
+        }
+        // 停下来的任务只有 SIGCONT/SIGKILL 能叫醒
+        let deliverable = sig == Signal::SIGCONT
+            || Signal::unblockable().contains(sig)
+            || !t.signal_mask.contains(sig);
+        deliverable.then(|| t.pid.0)
+    };
+    if let Some(pid) = wake {
+        TASK_MANAER.wake_task_from_blocking(pid);
+    }
+}
+
+///发给进程（线程组）的信号：交给组里第一个没屏蔽它的线程，都屏蔽了就挂在主线程上
+/// 进程不存在时返回 false
+pub fn send_signal_to_process(tgid: i32, sig: Signal, info: Option<SigInfo>) -> bool {
+    let threads = TASK_MANAER.find_tasks(|t| t.tgid == tgid);
+    let target = threads
+        .iter()
+        .find(|task| !task.lock().signal_mask.contains(sig))
+        .or_else(|| threads.iter().find(|task| task.lock().pid.0 == tgid))
+        .or_else(|| threads.first());
+    match target {
+        Some(task) => {
+            send_signal(task, sig, info);
+            true
+        }
+        None => false,
+    }
+}
+
+///子进程状态变化时通知父进程：发 SIGCHLD（退出时是 clone 指定的退出信号）并唤醒在 wait 里等的父进程
+/// 父进程对 SIGCHLD 设了 SA_NOCLDSTOP 时，停止和继续不发信号
+pub fn notify_parent(child: &Arc<SpinLock<TaskControlBlock>>, code: i32, status: i32) {
+    let (parent, pid, exit_signal) = {
+        let t = child.lock();
+        (t.parent.as_ref().and_then(|p| p.upgrade()), t.tgid, t.exit_signal)
+    };
+    let Some(parent) = parent else {
+        return;
+    };
+    let (sig_actions, child_exit) = {
+        let p = parent.lock();
+        (p.sig_actions.clone(), p.child_exit.clone())
+    };
+    let signo = if code == CLD_EXITED || code == CLD_KILLED || code == CLD_DUMPED {
+        exit_signal
+    } else {
+        let flags = SigActionFlags::from_bits_retain(sig_actions.lock()[Signal::SIGCHLD.signo() - 1].flags);
+        if flags.contains(SigActionFlags::SA_NOCLDSTOP) { 0 } else { Signal::SIGCHLD.signo() }
+    };
+    if let Some(sig) = Signal::from_signo(signo) {
+        send_signal(&parent, sig, Some(SigInfo::child(signo, code, pid, status)));
+    }
+    child_exit.wake_all();
+}
+
+///硬件错误产生的信号，发给当前线程，si_addr 是出错的地址
+/// 信号被屏蔽或者忽略时恢复成默认动作：错误指令返回后还会再错，只能杀掉
+pub fn force_signal(sig: Signal, code: i32, addr: usize) {
+    let task = TASK_MANAER.expect_current_task();
+    let mut t = task.lock();
+    {
+        let mut actions = t.sig_actions.lock();
+        let action = &mut actions[sig.signo() - 1];
+        if action.handler == SIG_IGN || t.signal_mask.contains(sig) {
+            *action = SigAction::default();
+        }
+    }
+    t.signal_mask.remove(sig);
+    t.signal.insert(sig);
+    let mut info = SigInfo::new(sig.signo(), code);
+    info.fields[0] = addr;
+    t.signal_info.insert(sig.signo(), info);
+}
+
+///当前线程会不会忽略或者屏蔽这个信号，终端作业控制据此决定发不发 SIGTTIN/SIGTTOU
+pub fn current_ignores_or_blocks(sig: Signal) -> bool {
+    let task = TASK_MANAER.expect_current_task();
+    let t = task.lock();
+    let blocked = t.signal_mask.contains(sig);
+    blocked || t.sig_actions.lock()[sig.signo() - 1].handler == SIG_IGN
+}
+
+///任务有没有没被屏蔽的待处理信号
+pub fn has_deliverable_signal(t: &TaskControlBlock) -> bool {
+    !(t.signal & !(t.signal_mask - Signal::unblockable())).is_empty()
+}
+
+/// wait 看到的停止/继续状态字（WIFSTOPPED/WIFCONTINUED）
+pub const WAIT_CONTINUED: i32 = 0xffff;
+pub fn wait_stopped(signo: usize) -> i32 {
+    ((signo as i32) << 8) | 0x7f
+}
+
+///停在这里直到收到 SIGCONT 或 SIGKILL，停下和继续时都通知父进程，状态留给 wait4 的 WUNTRACED/WCONTINUED
+fn stop_current_task(sig: Signal) {
+    debug!("task stopped by signal {}", sig.signo());
+    let task = TASK_MANAER.expect_current_task();
+    task.lock().wait_report = Some(wait_stopped(sig.signo()));
+    notify_parent(&task, CLD_STOPPED, sig.signo() as i32);
+    drop(task);
+    loop {
+        let task = TASK_MANAER.expect_current_task();
+        let (continued, killed) = {
+            let t = task.lock();
+            (t.signal.contains(Signal::SIGCONT), t.signal.contains(Signal::SIGKILL))
+        };
+        if continued {
+            task.lock().wait_report = Some(WAIT_CONTINUED);
+            notify_parent(&task, CLD_CONTINUED, Signal::SIGCONT.signo() as i32);
+        }
+        if continued || killed {
+            return;
+        }
+        drop(task);
+        TASK_MANAER.blocking_current_task_and_run_next();
+    }
+}
+
+///ERESTART_RESTARTBLOCK 的系统调用被打断时记下怎么接着做，restart_syscall 用
+#[derive(Clone, Copy, Debug)]
+pub enum RestartBlock {
+    ///接着睡到 target_ms（绝对时间），再被打断时剩下的时间写回 rem_ptr
+    Nanosleep { target_ms: usize, rem_ptr: usize },
+}
+
+///返回用户态之前递送当前任务的信号
+/// interrupted：这次陷入是系统调用并且返回了 -ERESTART* 时，是 (原来的第一个参数, 返回值)
+pub fn handle_signals(interrupted: Option<(usize, isize)>) {
+    loop {
+        let Some(task) = TASK_MANAER.current_task() else {
+            return;
+        };
+        let (sig, info, action) = {
+            let mut t = task.lock();
+            let deliverable = t.signal & !(t.signal_mask - Signal::unblockable());
+            let Some(sig) = deliverable.lowest() else {
+                // sigsuspend 临时换的屏蔽字，没有处理函数运行时在这里换回去
+                if let Some(mask) = t.saved_signal_mask.take() {
+                    t.signal_mask = mask;
+                }
+                break;
+            };
+            t.signal.remove(sig);
+            let info = t
+                .signal_info
+                .remove(&sig.signo())
+                .unwrap_or_else(|| SigInfo::new(sig.signo(), SI_USER));
+            let action = t.sig_actions.lock()[sig.signo() - 1];
+            (sig, info, action)
+        };
+        drop(task);
+
+        let handler = if Signal::unblockable().contains(sig) { SIG_DFL } else { action.handler };
+        match handler {
+            SIG_IGN => continue,
+            SIG_DFL => match sig.default_action() {
+                DefaultAction::Ignore | DefaultAction::Continue => continue,
+                DefaultAction::Stop => {
+                    stop_current_task(sig);
+                    continue;
+                }
+                DefaultAction::Terminate | DefaultAction::Core => {
+                    debug!("signal {} terminates the task", sig.signo());
+                    TASK_MANAER.kill_current_thread_group();
+                    TASK_MANAER.exit_current_by_signal(sig.signo());
+                    return;
+                }
+            },
+            _ => {
+                let flags = SigActionFlags::from_bits_retain(action.flags);
+                // 处理函数要运行：只有 ERESTARTSYS 加 SA_RESTART 才重新执行，其余返回 EINTR
+                if let Some((a0, ret)) = interrupted {
+                    if ret == -Errno::ERESTARTSYS && flags.contains(SigActionFlags::SA_RESTART) {
+                        restart_syscall(a0);
+                    } else {
+                        interrupt_syscall();
+                    }
+                }
+                if !setup_signal_frame(sig, info, action) {
+                    warn!("can't push signal frame for signal {}, killed", sig.signo());
+                    TASK_MANAER.kill_current_thread_group();
+                    TASK_MANAER.exit_current_by_signal(Signal::SIGSEGV.signo());
+                }
+                // 一次只压一个帧，剩下的信号在 rt_sigreturn 之后递送
+                return;
+            }
+        }
+    }
+    // 没有运行处理函数（信号被忽略、停止/继续），被打断的系统调用接着做
+    match interrupted {
+        Some((_, ret)) if ret == -Errno::ERESTART_RESTARTBLOCK => {
+            let trap_cx = TASK_MANAER.get_current_trapcx();
+            trap_cx.sepc_entry_point -= 4;
+            trap_cx.x[17] = SYS_RESTART_SYSCALL;
+        }
+        Some((a0, _)) => restart_syscall(a0),
+        None => {}
+    }
+}
+
+///回到 ecall 重新执行系统调用
+fn restart_syscall(a0: usize) {
+    let trap_cx = TASK_MANAER.get_current_trapcx();
+    trap_cx.sepc_entry_point -= 4;
+    trap_cx.x[10] = a0;
+}
+
+///不重新执行：用户态看到的返回值换成 EINTR，restart_syscall 记下的状态也不要了
+fn interrupt_syscall() {
+    TASK_MANAER.expect_current_task().lock().restart_block = None;
+    TASK_MANAER.get_current_trapcx().x[10] = -Errno::EINTR as usize;
+}
+
+///在用户栈（或者 sigaltstack）上压信号帧，把返回地址改成处理函数
+fn setup_signal_frame(sig: Signal, info: SigInfo, action: SigAction) -> bool {
+    let flags = SigActionFlags::from_bits_retain(action.flags);
+    let task = TASK_MANAER.expect_current_task();
+    let (old_mask, altstack) = {
+        let mut t = task.lock();
+        let old_mask = t.saved_signal_mask.take().unwrap_or(t.signal_mask);
+        let mut mask = t.signal_mask | Signal::from_bits_retain(action.mask as usize);
+        if !flags.contains(SigActionFlags::SA_NODEFER) {
+            mask |= sig;
+        }
+        t.signal_mask = mask - Signal::unblockable();
+        if flags.contains(SigActionFlags::SA_RESETHAND) {
+            t.sig_actions.lock()[sig.signo() - 1] = SigAction::default();
+        }
+        (old_mask, t.sig_altstack)
+    };
+    drop(task);
+
+    let trap_cx = TASK_MANAER.get_current_trapcx();
+    let user_sp = trap_cx.x[2];
+    let on_altstack = altstack.contains(user_sp);
+    let mut sp = if flags.contains(SigActionFlags::SA_ONSTACK)
+        && altstack.ss_flags & SS_DISABLE == 0
+        && !on_altstack
+    {
+        altstack.ss_sp + altstack.ss_size
+    } else {
+        user_sp
+    };
+    sp = sp.wrapping_sub(core::mem::size_of::<SignalFrame>()) & !15;
+
+    let mut gregs = [0usize; 32];
+    gregs[0] = trap_cx.sepc_entry_point;
+    gregs[1..].copy_from_slice(&trap_cx.x[1..]);
+    let mut uc_stack = altstack;
+    if altstack.contains(sp) {
+        uc_stack.ss_flags |= SS_ONSTACK;
+    }
+    let frame = SignalFrame {
+        info,
+        uc: UContext {
+            uc_flags: 0,
+            uc_link: 0,
+            uc_stack,
+            uc_sigmask: old_mask.bits() as u64,
+            _unused: [0; 120],
+            uc_mcontext: MContext { gregs, fpregs: [0; 66] },
+        },
+    };
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&frame as *const SignalFrame as *const u8, core::mem::size_of::<SignalFrame>())
+    };
+    if UserSlice::new(sp, bytes.len()).write(bytes).is_err() {
+        return false;
+    }
+
+    trap_cx.x[2] = sp;
+    trap_cx.x[10] = sig.signo();
+    trap_cx.x[11] = sp;
+    trap_cx.x[12] = sp + core::mem::offset_of!(SignalFrame, uc);
+    trap_cx.x[1] = SIGRETURN_TRAMPOLINE_ADDR;
+    trap_cx.sepc_entry_point = action.handler;
+    true
+}
+
+///rt_sigreturn：从用户栈上的信号帧恢复寄存器和屏蔽字，返回恢复出来的 a0
+/// 帧读不出来时杀掉任务
+pub fn sigreturn() -> isize {
+    let trap_cx = TASK_MANAER.get_current_trapcx();
+    let sp = trap_cx.x[2];
+    let mut frame = core::mem::MaybeUninit::<SignalFrame>::zeroed();
+    let buf = unsafe {
+        core::slice::from_raw_parts_mut(frame.as_mut_ptr() as *mut u8, core::mem::size_of::<SignalFrame>())
+    };
+    if UserSlice::new(sp, buf.len()).read(buf).is_err() {
+        warn!("rt_sigreturn: bad signal frame at {:#x}, killed", sp);
+        TASK_MANAER.kill_current_thread_group();
+        TASK_MANAER.exit_current_by_signal(Signal::SIGSEGV.signo());
+        return -1;
+    }
+    let frame = unsafe { frame.assume_init() };
+    trap_cx.sepc_entry_point = frame.uc.uc_mcontext.gregs[0];
+    trap_cx.x[1..].copy_from_slice(&frame.uc.uc_mcontext.gregs[1..]);
+    {
+        let task = TASK_MANAER.expect_current_task();
+        let mut t = task.lock();
+        t.signal_mask = Signal::from_bits_retain(frame.uc.uc_sigmask as usize) - Signal::unblockable();
+    }
+    trap_cx.x[10] as isize
+}
@@ -1,7 +1,9 @@
-use core::arch::global_asm;
+use core::arch::{asm, global_asm};
+use core::ops::{Deref, DerefMut};
 use core::panicking::panic;
 
 use alloc::collections::vec_deque::VecDeque;
+use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::string::ToString;
 use alloc::sync::Arc;
@@ -12,6 +14,7 @@ use lazy_static::lazy_static;
 use log::error;
 use log::trace;
 use log::warn;
+use riscv::register::sip;
 use riscv::register::sstatus;
 use riscv::register::sstatus::SPP;
 use rsext4::OpenFile;
@@ -26,10 +29,18 @@ use crate::fs::vfs::vfs_open;
 use crate::fs::vfs::VFS_DT_REG;
 use crate::memory::*;
 use crate::sbi::shutdown;
+use crate::task::{current_processer, hart_id};
+use crate::sync::{SpinLockGuard, hart_holds_lock};
+use crate::time::{check_timers, get_time_tick, set_next_timeInterupt};
 use crate::task::Signal;
+use crate::task::{exec_sig_actions, has_deliverable_signal, new_sig_actions, notify_parent, send_signal, send_signal_to_process, RestartBlock, SigActions, SigInfo, SignalStack, CLD_EXITED, CLD_KILLED};
+use crate::task::WaitQueue;
+use crate::syscall::Errno;
+use crate::task::{futex_wake, FUTEX_BITSET_MATCH_ANY};
 use crate::task::file_loader;
 use log::debug;
 use crate::fs::component::stdio::stdio::{stdin_file, stdout_file, stderr_file};
+use crate::fs::component::tty::tty_release_session;
 use crate::trap::{app_entry_point, kernel_trap_handler};
 ///init进程PID
 pub const INIT_PID:i32=1;
@@ -37,7 +48,7 @@ pub const INIT_PID:i32=1;
 pub static mut TASK_MANAGER_INIT:bool = false;
 
 ///任务上下文
-use crate::{ sync::UPSafeCell, trap::TrapContext};
+use crate::{ sync::SpinLock, trap::TrapContext};
 global_asm!(include_str!("_switch.S"));
 
 #[repr(C)]
@@ -46,7 +57,9 @@ pub struct TaskContext{
      ra:usize, //offset 0
      pub sp:usize, //offser 8
      ///s0-s11 被调用者保存寄存器 switch保存
-     calleed_register:[usize;12]//offset 16-..
+     calleed_register:[usize;12],//offset 16-..
+     ///任务还在某个 hart 上执行（含切换途中），调度时置1，__switch 保存完上下文后清0
+     on_cpu:usize //offset 14*8
 }
 
 #[derive(Clone,PartialEq,Debug)]
@@ -70,22 +83,43 @@ pub struct ProcessIdAlloctor{
     id_pool:Vec<ProcessId>
 }
 
-#[derive(Clone)]
+///文件描述符表，CLONE_FILES 创建的线程共用一张
+pub type FdTable = Vec<Option<Arc<dyn File>>>;
+
 pub struct TaskControlBlock{
-        pub signal:Vec<Signal>,
-        pub pid:ProcessId,                              //进程id
-        pub memory_set:MapSet,                          //程序地址空间
+        pub signal:Signal,                              //待处理的信号
+        pub signal_mask:Signal,                         //屏蔽字
+        pub saved_signal_mask:Option<Signal>,           //sigsuspend 期间原来的屏蔽字
+        pub restart_block:Option<RestartBlock>,         //被打断的 nanosleep 接着睡用的状态
+        pub sig_actions:Arc<SpinLock<SigActions>>,      //信号处理表，CLONE_SIGHAND 的线程共用
+        pub sig_altstack:SignalStack,                   //sigaltstack
+        pub signal_info:BTreeMap<usize,SigInfo>,        //待处理信号附带的 siginfo，按编号
+        pub pid:ProcessId,                              //进程id，线程里就是 tid
+        pub tgid:i32,                                   //线程组id，主线程的 pid，getpid 返回它
+        pub pgid:i32,                                   //进程组id，kill 按它给一组进程发信号
+        pub sid:i32,                                    //会话id，控制终端属于一个会话
+        pub wait_report:Option<i32>,                    //停止/继续之后还没被 wait 取走的状态字
+        pub exit_signal:usize,                          //退出时发给父进程的信号（clone 标志的低 8 位），0 不发
+        pub memory_set:Arc<SpinLock<MapSet>>,           //程序地址空间，CLONE_VM 的线程共用
         pub task_statut:TaskStatus,                         //程序运行状态
         pub exit_code:isize,
+        pub term_signal:usize,                          //被信号杀死时的信号编号，0 表示自己退出
+        pub usage:TaskUsage,                            //自己用掉的 CPU 时间和驻留页峰值
+        pub child_usage:TaskUsage,                      //已经 wait 回收的子进程累计
+        pub last_tick:usize,                            //上次记账的时间点
+        pub wakeup_pending:bool,                            //阻塞之前就被唤醒了，下次阻塞直接返回
+        pub child_exit:Arc<WaitQueue>,                      //在 wait 里等子进程退出
         pub task_context:TaskContext,                       //任务上下文
         pub trap_context_ppn:usize,                         //陷阱上下文物理帧
+        pub trap_context_addr:usize,                        //陷阱上下文在用户地址空间的虚拟地址，线程各有一页
+        pub clear_child_tid:usize,                          //退出时清零的用户地址（CLONE_CHILD_CLEARTID/set_tid_address）
         pass:usize,                                     //行程
         stride:usize,                                   //步长
         ticket:usize,                                   //权重
-        pub file_descriptor:Vec<Option<Arc<dyn File>>>,       //文件描述符表
+        pub file_descriptor:Arc<SpinLock<FdTable>>,       //文件描述符表
         pub cwd:String,         //进程工作的路径 默认/
-        pub parent:Option<Weak<UPSafeCell<TaskControlBlock>>>,                  //父进程弱引用
-        pub childrens:Vec<Arc<UPSafeCell<TaskControlBlock>>>            //子进程强引用
+        pub parent:Option<Weak<SpinLock<TaskControlBlock>>>,                  //父进程弱引用
+        pub childrens:Vec<Arc<SpinLock<TaskControlBlock>>>            //子进程强引用
 }
 
 
@@ -95,16 +129,67 @@ pub struct TaskControlBlock{
 
 
 
+/// 任务的资源统计，时间单位是 tick，max_rss 单位是页
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TaskUsage{
+    pub utime:usize,
+    pub stime:usize,
+    pub max_rss:usize,
+}
+
+impl TaskUsage {
+    ///把回收的子进程记到父进程名下：时间累加，峰值取大
+    pub fn accumulate(&mut self, other: &TaskUsage) {
+        self.utime += other.utime;
+        self.stime += other.stime;
+        self.max_rss = self.max_rss.max(other.max_rss);
+    }
+}
+
 pub struct TaskManagerInner{
-    pub task_queen:VecDeque<Arc<UPSafeCell<TaskControlBlock>>>,// Ready任务队列
-    pub task_blocking:VecDeque<Arc<UPSafeCell<TaskControlBlock>>>, // Blocking 任务队列，不参与调度
-    pub current:usize//当前任务
+    pub task_queen:VecDeque<Arc<SpinLock<TaskControlBlock>>>,// Ready任务队列
+    pub task_blocking:VecDeque<Arc<SpinLock<TaskControlBlock>>>, // Blocking 任务队列，不参与调度
+}
+
+///任务管理器 所有 hart 共用一个队列，各 hart 正在运行的任务记录在自己的 Processer 里
+pub struct TaskManager{
+    ///注意释放时机，加锁走 lock_inner
+   pub task_que_inner:SpinLock<TaskManagerInner>,//内部可变性 
+}
+
+/// 每个 hart 持有任务队列锁期间推迟的唤醒（pid），放锁时由 TaskQueueGuard 补上
+static PENDING_WAKES: [SpinLock<Vec<i32>>; MAX_HARTS] = {
+    const EMPTY: SpinLock<Vec<i32>> = SpinLock::new(Vec::new());
+    [EMPTY; MAX_HARTS]
+};
+
+/// TaskManager::lock_inner 返回的守卫，放掉任务队列锁之后补上持锁期间推迟的唤醒
+pub struct TaskQueueGuard<'a> {
+    manager: &'a TaskManager,
+    guard: Option<SpinLockGuard<'a, TaskManagerInner>>,
+}
+
+impl Deref for TaskQueueGuard<'_> {
+    type Target = TaskManagerInner;
+    fn deref(&self) -> &TaskManagerInner {
+        self.guard.as_ref().expect("Kernel Error")
+    }
+}
+
+impl DerefMut for TaskQueueGuard<'_> {
+    fn deref_mut(&mut self) -> &mut TaskManagerInner {
+        self.guard.as_mut().expect("Kernel Error")
+    }
 }
 
-///任务管理器
-pub struct TaskManager{//单核环境目前无竞争
-    ///注意释放时机
-   pub task_que_inner:UPSafeCell<TaskManagerInner>,//内部可变性 
+impl Drop for TaskQueueGuard<'_> {
+    fn drop(&mut self) {
+        drop(self.guard.take());
+        let pending = core::mem::take(&mut *PENDING_WAKES[hart_id()].lock());
+        for pid in pending {
+            self.manager.wake_task_from_blocking(pid);
+        }
+    }
 }
 
 impl  ProcessIdAlloctor{
@@ -145,11 +230,16 @@ impl TaskContext {
     /// 注意：kernel_sp 是内核栈指针，不是用户栈！
     /// app_entry_point 是内核函数，需要内核栈来执行
     pub fn return_trap_new(kernel_sp: usize) -> Self {
-       TaskContext { ra: app_entry_point as usize, sp: kernel_sp, calleed_register: [0;12] }
+       TaskContext { ra: app_entry_point as usize, sp: kernel_sp, calleed_register: [0;12], on_cpu: 0 }
     }
 ///零初始化
-    pub fn zero_init()->Self{
-        TaskContext { ra: 0, sp: 0, calleed_register: [0;12] }
+    pub const fn zero_init()->Self{
+        TaskContext { ra: 0, sp: 0, calleed_register: [0;12], on_cpu: 0 }
+    }
+
+    ///是否还在某个 hart 上（__switch 在别的 hart 上异步清零）
+    pub fn is_on_cpu(&self)->bool{
+        unsafe { core::ptr::read_volatile(&self.on_cpu) != 0 }
     }
 }
 
@@ -195,12 +285,12 @@ impl TaskControlBlock {
     }
 
     ///设置父亲进程引用
-    pub fn set_father(&mut self,father:&Arc<UPSafeCell<TaskControlBlock>>){
+    pub fn set_father(&mut self,father:&Arc<SpinLock<TaskControlBlock>>){
         self.parent = Some(Arc::downgrade(&father));
     }
 
     ///添加子进程引用
-    pub fn add_children(&mut self,tlb:Arc<UPSafeCell<TaskControlBlock>>){
+    pub fn add_children(&mut self,tlb:Arc<SpinLock<TaskControlBlock>>){
         self.childrens.push(tlb);
     }
 
@@ -213,34 +303,16 @@ impl TaskControlBlock {
     }
 
     ///exec换血
-    /// path:可执行文件位置
-    /// 不应该返回
-    pub fn new_exec_task(&mut self,path:&str,argv:Vec<String>,argc:usize) -> bool {
-        //按照new函数来换血，换内核栈，换地址空间
-        debug!("exec: replacing current task image with {}  <----ptah\n", path);
-    
-        let elf_data = file_loader(path);
-        if elf_data.is_empty() {
-            //加载错误,直接返回
-            return false;
-        }
-        self.new_exec_task_with_elf(path, argv, argc, &elf_data)
-    }
-
+    /// loaded: MapSet::from_elf 建好的新地址空间，要读文件，调用方在拿 TCB 锁之前建好
     pub fn new_exec_task_with_elf(
         &mut self,
         path: &str,
         argv: Vec<String>,
         argc: usize,
-        elf_data: &[u8],
-    ) -> bool {
-        debug!("Load success");
-        let re = MapSet::from_elf(elf_data);
-        if re.is_none() {
-            warn!("Can't create elf file");
-            return false;
-        }
-        let (mut memset, elf_entry, user_sp, kernel_sp) = re.expect("Kernel error");
+        loaded: (MapSet, usize, VirAddr, usize),
+    ) {
+        debug!("exec: replacing current task image with {}  <----ptah\n", path);
+        let (mut memset, elf_entry, user_sp, kernel_sp) = loaded;
         let task_cx = TaskContext::return_trap_new(kernel_sp);
         let kernel_satp = KERNEL_SPACE.lock().table.satp_token();
         let user_satp = memset.table.satp_token();
@@ -255,10 +327,16 @@ impl TaskControlBlock {
 
 
 
-        self.memory_set = memset;//释放旧的全复制地址空间
+        // 和别的线程共用的旧地址空间由调用方在放掉 TCB 锁之后释放
+        self.memory_set = memset.into_shared();
    
         self.task_context = task_cx;
         self.trap_context_ppn = trap_cx_ppn.0;
+        self.trap_context_addr = TRAP_CONTEXT_ADDR;
+        // 捕获的信号恢复默认动作，处理函数已经不在新的地址空间里了
+        let sig_actions = exec_sig_actions(&self.sig_actions.lock());
+        self.sig_actions = sig_actions;
+        self.sig_altstack = SignalStack::default();
 
         let trap_cx_point: *mut TrapContext = (trap_cx_ppn.0 * PAGE_SIZE) as *mut TrapContext;
         unsafe {
@@ -270,16 +348,15 @@ impl TaskControlBlock {
                 new_user_sp,
             );
         }
-        true
     }
     
 
     /// 创建新任务
-    fn new(app_path: &str, _kernel_stack_id: usize,father:Option<Weak<UPSafeCell<TaskControlBlock>>>) -> Option<Self> {
+    fn new(app_path: &str, _kernel_stack_id: usize,father:Option<Weak<SpinLock<TaskControlBlock>>>) -> Option<Self> {
         debug!("Creating task for app_path: {}, kernel_stack_id: {}", app_path, _kernel_stack_id);
         
-        let elf_data = file_loader(app_path);
-        let re = MapSet::from_elf(&elf_data);
+        let elf_file = file_loader(app_path)?;
+        let re = MapSet::from_elf(elf_file);
         if re.is_none() {
             warn!("Can't create elf file");
             return None;
@@ -296,23 +373,43 @@ impl TaskControlBlock {
         let new_user_sp = Self::push_args_to_user_stack(user_satp, user_sp.0, &argv);
         
         // 初始化文件描述符表：0=stdin, 1=stdout, 2=stderr
-        let mut file_descriptor_table: Vec<Option<Arc<dyn File>>> = Vec::new();
+        let mut file_descriptor_table: FdTable = Vec::new();
         file_descriptor_table.push(Some(stdin_file()));
         file_descriptor_table.push(Some(stdout_file()));
         file_descriptor_table.push(Some(stderr_file()));
         
+        let pid = ProcessId_ALLOCTOR.lock().alloc_id().expect("No Process ID Can use");
         let task_control_block = TaskControlBlock {
-            signal:Vec::new(),
-            pid:ProcessId_ALLOCTOR.lock().alloc_id().expect("No Process ID Can use"),
-            memory_set: memset,
+            signal:Signal::empty(),
+            signal_mask:Signal::empty(),
+            saved_signal_mask:None,
+            restart_block:None,
+            sig_actions:new_sig_actions(),
+            sig_altstack:SignalStack::default(),
+            signal_info:BTreeMap::new(),
+            tgid: pid.0,
+            pgid: pid.0,
+            sid: pid.0,
+            wait_report: None,
+            exit_signal: 0,
+            pid,
+            memory_set: memset.into_shared(),
             task_statut: TaskStatus::Ready,
             exit_code: 0,
+            term_signal: 0,
+            usage: TaskUsage::default(),
+            child_usage: TaskUsage::default(),
+            last_tick: get_time_tick(),
+            wakeup_pending: false,
+            child_exit: Arc::new(WaitQueue::new()),
             task_context: task_cx,
             trap_context_ppn: trap_cx_ppn.0,
+            trap_context_addr: TRAP_CONTEXT_ADDR,
+            clear_child_tid: 0,
             pass: 0,
             stride: BIG_INT / TASK_TICKET,
             ticket: TASK_TICKET,
-            file_descriptor: file_descriptor_table,
+            file_descriptor: Arc::new(SpinLock::new(file_descriptor_table)),
             cwd:"/".to_string(),
             parent:father,
             childrens:Vec::new()
@@ -342,19 +439,131 @@ impl Drop for TaskControlBlock {
             c.parent = None;
         }
         self.childrens.clear();
+        // 线程的陷阱上下文页在共用的地址空间里，随 TCB 一起释放
+        if self.trap_context_addr != TRAP_CONTEXT_ADDR {
+            self.memory_set.lock().unmap_thread_trapContext(self.trap_context_addr);
+        }
+    }
+}
+
+impl TaskControlBlock {
+    ///clone 出子任务的 TCB，地址空间、描述符表、信号处理表和陷阱上下文由 sys_fork 准备好
+    /// 调度参数、cwd、信号屏蔽字和 sigaltstack 继承自 self，父子关系由调用方建立
+    pub fn new_cloned(
+        &self,
+        pid: ProcessId,
+        tgid: i32,
+        memory_set: Arc<SpinLock<MapSet>>,
+        file_descriptor: Arc<SpinLock<FdTable>>,
+        sig_actions: Arc<SpinLock<SigActions>>,
+        trap_context_ppn: usize,
+        trap_context_addr: usize,
+        kernel_sp: usize,
+    ) -> Self {
+        TaskControlBlock {
+            signal: Signal::empty(),
+            signal_mask: self.signal_mask,
+            saved_signal_mask: None,
+            restart_block: None,
+            sig_actions,
+            sig_altstack: self.sig_altstack,
+            signal_info: BTreeMap::new(),
+            pid,
+            tgid,
+            pgid: self.pgid,
+            sid: self.sid,
+            wait_report: None,
+            exit_signal: 0,
+            memory_set,
+            task_statut: TaskStatus::Ready,
+            exit_code: 0,
+            term_signal: 0,
+            usage: TaskUsage::default(),
+            child_usage: TaskUsage::default(),
+            last_tick: get_time_tick(),
+            wakeup_pending: false,
+            child_exit: Arc::new(WaitQueue::new()),
+            // 第一次被调度从 app_entry_point 起步，经 __kernel_refume 用 TrapContext 回到用户态
+            task_context: TaskContext::return_trap_new(kernel_sp),
+            trap_context_ppn,
+            trap_context_addr,
+            clear_child_tid: 0,
+            pass: self.pass,
+            stride: self.stride,
+            ticket: self.ticket,
+            file_descriptor,
+            cwd: self.cwd.clone(),
+            parent: None,
+            childrens: Vec::new(),
+        }
+    }
+
+    ///wait 拿到的状态字：正常退出是 退出码<<8，被信号杀死是信号编号（WIFSIGNALED）
+    pub fn wait_status(&self)->i32{
+        if self.term_signal != 0 {
+            (self.term_signal & 0x7f) as i32
+        } else {
+            ((self.exit_code as i32) & 0xff) << 8
+        }
+    }
+
+    ///自己加上已回收子进程的资源统计，wait4 的 rusage 用
+    pub fn total_usage(&self)->TaskUsage{
+        let mut usage = self.usage;
+        usage.accumulate(&self.child_usage);
+        usage
+    }
+
+    ///把上次记账到现在的时间记到用户态或者内核态
+    pub fn account_time(&mut self, user: bool){
+        let now = get_time_tick();
+        let delta = now.saturating_sub(self.last_tick);
+        if user {
+            self.usage.utime += delta;
+        } else {
+            self.usage.stime += delta;
+        }
+        self.last_tick = now;
+    }
+
+    ///是不是线程组里的非主线程，这种线程退出后不留给父进程 wait，由调度器回收
+    pub fn is_thread(&self)->bool{
+        self.pid.0 != self.tgid
     }
 }
 
 
 impl TaskManager {//全局唯一
 
+    ///当前 hart 上正在运行的任务，idle 循环里为 None
+    pub fn current_task(&self)->Option<Arc<SpinLock<TaskControlBlock>>>{
+        current_processer().current()
+    }
+
+    ///当前任务，只能在任务的内核执行流里调用
+    pub fn expect_current_task(&self)->Arc<SpinLock<TaskControlBlock>>{
+        self.current_task().expect("No task running on this hart")
+    }
 
     /// 把指定blocking的任务放进准备队列
-    pub fn wake_task_from_blocking(&self,pid:i32){
-        let mut inner = self.task_que_inner.lock();
-        if inner.task_queen.is_empty() {
-            return;
+    /// 任务还没来得及阻塞（别的 hart 上正准备睡眠）时记下 wakeup_pending，它阻塞时直接返回
+    ///拿任务队列锁，放锁时补上持锁期间推迟的唤醒
+    #[track_caller]
+    pub fn lock_inner(&self)->TaskQueueGuard<'_>{
+        TaskQueueGuard { manager: self, guard: Some(self.task_que_inner.lock()) }
+    }
+
+    ///唤醒 pid；本 hart 正持有任务队列锁（比如持锁时释放了最后一个管道端）时重复加锁会死锁，先记下来，放锁时再唤醒
+    pub fn wake_task_or_defer(&self,pid:i32){
+        if self.task_que_inner.held_by_current_hart() {
+            PENDING_WAKES[hart_id()].lock().push(pid);
+        } else {
+            self.wake_task_from_blocking(pid);
         }
+    }
+
+    pub fn wake_task_from_blocking(&self,pid:i32){
+        let mut inner = self.lock_inner();
         // 在blocing 队列找
         let target_index = inner.task_blocking.iter().position(|task|{
             task.lock().pid.0 == pid
@@ -374,24 +583,35 @@ impl TaskManager {//全局唯一
                 inner.task_queen.push_back(weak_task);
             }
             None=>{
-                return;
+                if let Some(task) = inner.task_queen.iter().find(|task| task.lock().pid.0 == pid) {
+                    let mut t = task.lock();
+                    if t.task_statut != TaskStatus::Zombie {
+                        t.wakeup_pending = true;
+                    }
+                }
             }
         }
 
     }
 
     /// 阻塞当前任务 调度下一个任务
+    /// 调用方醒来后要重新检查等待的条件（可能是提前到达的唤醒）
     pub fn blocking_current_task_and_run_next(&self){
-        let mut inner = self.task_que_inner.lock();
-        if inner.task_queen.is_empty() {
+        let Some(current) = self.current_task() else {
             return;
+        };
+        let mut inner = self.lock_inner();
+        {
+            let mut t = current.lock();
+            if t.wakeup_pending {
+                t.wakeup_pending = false;
+                return;
+            }
         }
-        let current = inner.current;
-        let task =  inner.task_queen.remove(current);
-        if task.is_none(){
+        let Some(index) = inner.task_queen.iter().position(|t| Arc::ptr_eq(t, &current)) else {
             return;
-        }
-        let task = task.expect("Kernel Error");
+        };
+        let task = inner.task_queen.remove(index).expect("Kernel Error");
         task.lock().task_statut = TaskStatus::Blocking;
 
         let swap_out ={
@@ -400,101 +620,146 @@ impl TaskManager {//全局唯一
         };
 
         // 放进阻塞队列
-        inner.task_blocking.push_back(task.clone());
+        inner.task_blocking.push_back(task);
+        let next = Self::take_next_task_inner(&inner, None);
         drop(inner);
-        let ts = self.stride_select_task();
-        if ts.is_none() {
-            return;
-        }
-        let mut inner = self.task_que_inner.lock(); 
+        drop(current);
+        // 调度下一个任务
+        Self::switch_to(swap_out, next);
+    }
 
-        let new_ts  =ts.expect("Kernel Error").0;
-        inner.current = new_ts;
-        let swap_in = {
-            let out = &mut inner.task_queen[new_ts].lock().task_context;
-            out  as *mut _ as  *mut TaskContext
-        };
 
-        
-        drop(inner);
-        // 调度下一个任务
-        unsafe {
-            __switch(swap_out, swap_in);
+    ///当前任务有没有没被屏蔽的待处理信号，阻塞的系统调用据此返回 -EINTR
+    pub fn current_has_signal(&self)->bool{
+        match self.current_task() {
+            Some(task) => has_deliverable_signal(&task.lock()),
+            None => false,
         }
-
     }
 
+    ///找出满足条件的任务（Zombie 除外），给它们发信号之前先放掉任务队列的锁
+    pub fn find_tasks(&self, pred: impl Fn(&TaskControlBlock) -> bool) -> Vec<Arc<SpinLock<TaskControlBlock>>> {
+        let inner = self.lock_inner();
+        inner
+            .task_queen
+            .iter()
+            .chain(inner.task_blocking.iter())
+            .filter(|task| {
+                let t = task.lock();
+                t.task_statut != TaskStatus::Zombie && pred(&t)
+            })
+            .cloned()
+            .collect()
+    }
 
-    /// 处理当前task的signal 返回是否超过
-    pub fn resolve_current_task_signal(&self){
-        let inner = self.task_que_inner.lock();
-        if inner.task_queen.is_empty() {
-            drop(inner);
-            return;
+    ///给进程组里的每个进程发信号，终端的 ^C/^Z 发给前台进程组。没有这个进程组时返回 false
+    pub fn signal_process_group(&self,pgid:i32,sig:Signal)->bool{
+        let mut tgids: Vec<i32> = self
+            .find_tasks(|t| t.pgid == pgid)
+            .iter()
+            .map(|task| task.lock().tgid)
+            .collect();
+        tgids.sort_unstable();
+        tgids.dedup();
+        for tgid in tgids.iter() {
+            send_signal_to_process(*tgid, sig, None);
         }
-        let current = inner.current;
-        if current >= inner.task_queen.len() {
-            drop(inner);
+        !tgids.is_empty()
+    }
+
+    ///给父进程发退出信号并唤醒在 wait 里阻塞的父进程，需要在标记 Zombie 之后调用
+    pub fn wake_current_parent(&self){
+        let Some(current_task) = self.current_task() else {
             return;
-        }
-        let current_task = inner.task_queen[current].clone();
-        drop(inner);
-        let signal = &mut current_task.lock().signal;
-        if signal.is_empty() {
+        };
+        let (is_thread, exit_code, term_signal) = {
+            let t = current_task.lock();
+            (t.is_thread(), t.exit_code, t.term_signal)
+        };
+        if is_thread {
             return;
         }
-
-        for sig in signal.drain(..) {
-            match sig{
-                Signal::SIGKILL=>{
-                    TASK_MANAER.kail_current_task_and_run_next();
-                }
-                _=>{
-                    //空操作
-                    warn!("Unsupport signal!");
-                }
-            }
+        if term_signal != 0 {
+            notify_parent(&current_task, CLD_KILLED, term_signal as i32);
+        } else {
+            notify_parent(&current_task, CLD_EXITED, (exit_code & 0xff) as i32);
         }
-        debug!("Process pid:{} signal resolved",current_task.lock().pid.0);
     }
 
+    ///标记为 Zombie 并放掉文件描述符表，最后一个用它的线程退出时关闭所有文件（管道另一端能看到 EOF）
+    /// 会话首进程退出时前台进程组收到 SIGHUP
+    /// 设置了 clear_child_tid 时把用户态的 tid 清零并在上面做一次 futex 唤醒，pthread_join 在那里等
     pub fn mark_current_zombie(&self, exit_code: isize) {
-        let inner = self.task_que_inner.lock();
-        if inner.task_queen.is_empty() {
-            drop(inner);
-            panic!("Task Queen is empty!");
-        }
-        let current = inner.current;
-        if current >= inner.task_queen.len() {
-            drop(inner);
-            panic!("TaskManager current index out of range");
-        }
-        {
-            let mut t = inner.task_queen[current].lock();
+        let current_task = self.expect_current_task();
+        let (files, clear_child_tid, memory_set, session_leader) = {
+            let mut t = current_task.lock();
             t.task_statut = TaskStatus::Zombie;
             t.exit_code = exit_code;
+            let files = core::mem::replace(&mut t.file_descriptor, Arc::new(SpinLock::new(Vec::new())));
+            let session_leader = (!t.is_thread() && t.tgid == t.sid).then_some(t.sid);
+            (files, core::mem::take(&mut t.clear_child_tid), t.memory_set.clone(), session_leader)
+        };
+        // 地址空间还在，最后采一次驻留页
+        let rss = memory_set.lock().resident_pages();
+        {
+            let mut t = current_task.lock();
+            t.usage.max_rss = t.usage.max_rss.max(rss);
+        }
+        // 会话首进程退出，控制终端和会话脱离
+        if let Some(sid) = session_leader {
+            tty_release_session(sid);
+        }
+        // 关闭文件可能唤醒别的任务，不能拿着 TCB 锁
+        drop(files);
+        if clear_child_tid != 0 && MapSet::write_user_i32(&memory_set, clear_child_tid, 0) {
+            let _ = futex_wake(clear_child_tid, 1, FUTEX_BITSET_MATCH_ANY, false);
         }
-        drop(inner);
     }
 
-    pub fn reparent_current_children_to_init(&self) {
-        let inner = self.task_que_inner.lock();
-        if inner.task_queen.is_empty() {
-            drop(inner);
-            return;
+    ///当前任务记一次时间，user 为真表示刚从用户态陷入
+    pub fn account_current_time(&self, user: bool) {
+        if let Some(task) = self.current_task() {
+            task.lock().account_time(user);
         }
-        let current = inner.current;
-        if current >= inner.task_queen.len() {
-            drop(inner);
+    }
+
+    ///采样当前任务的驻留页数，更新峰值（时钟中断和 exec 换掉地址空间之前调用）
+    pub fn update_current_rss(&self) {
+        let Some(task) = self.current_task() else {
             return;
+        };
+        let memory_set = task.lock().memory_set.clone();
+        let rss = memory_set.lock().resident_pages();
+        let mut t = task.lock();
+        t.usage.max_rss = t.usage.max_rss.max(rss);
+    }
+
+    ///给线程组里除自己以外的线程发 SIGKILL，exit_group 和致命信号用
+    pub fn kill_current_thread_group(&self) {
+        let current_task = self.expect_current_task();
+        let (pid, tgid) = {
+            let t = current_task.lock();
+            (t.pid.0, t.tgid)
+        };
+        drop(current_task);
+        for task in self.find_tasks(|t| t.tgid == tgid && t.pid.0 != pid).iter() {
+            send_signal(task, Signal::SIGKILL, None);
         }
-        let current_task = inner.task_queen[current].clone();
-        let init_task = inner
-            .task_queen
-            .iter()
-            .find(|t| t.lock().pid.0 == INIT_PID)
-            .cloned();
-        drop(inner);
+    }
+
+    pub fn reparent_current_children_to_init(&self) {
+        let Some(current_task) = self.current_task() else {
+            return;
+        };
+        let init_task = {
+            let inner = self.lock_inner();
+            inner
+                .task_queen
+                .iter()
+                .chain(inner.task_blocking.iter())
+                .find(|t| t.lock().pid.0 == INIT_PID)
+                .cloned()
+        };
 
         let Some(init_task) = init_task else {
             return;
@@ -523,57 +788,40 @@ impl TaskManager {//全局唯一
         }
     }
 
-    pub fn reap_zombie_child(&self, child_pid: i32) -> Option<isize> {
-        let mut inner = self.task_que_inner.lock();
-        let current = inner.current;
-        if inner.task_queen.is_empty() || current >= inner.task_queen.len() {
-            drop(inner);
-            return None;
-        }
-        let current_task = inner.task_queen[current].clone();
+    ///回收一个 Zombie 子进程，返回它的 wait 状态字
+    /// 返回状态字和子进程（连同它回收过的子进程）的资源统计，统计同时记到当前进程的 child_usage
+    pub fn reap_zombie_child(&self, child_pid: i32) -> Option<(isize, TaskUsage)> {
+        let current_task = self.current_task()?;
 
         // 只允许回收当前进程的子进程，防止误回收其他任务的 Zombie。
-        let is_child_of_current = {
-            let parent = current_task.lock();
-            parent.childrens.iter().any(|c| c.lock().pid.0 == child_pid)
+        // 先拷出子进程列表再逐个加锁，不同时持有父子两把锁
+        let children = current_task.lock().childrens.clone();
+        let child = children.into_iter().find(|c| c.lock().pid.0 == child_pid)?;
+        let (exit_code, usage) = {
+            let t = child.lock();
+            if !matches!(t.task_statut, TaskStatus::Zombie) {
+                return None;
+            }
+            (t.wait_status() as isize, t.total_usage())
         };
-        if !is_child_of_current {
-            drop(inner);
-            return None;
-        }
 
-        let mut zombie_index: Option<usize> = None;
-        let mut exit_code: Option<isize> = None;
-        for (idx, cell) in inner.task_queen.iter().enumerate() {
-            let t = cell.lock();
-            if t.pid.0 == child_pid {
-                if matches!(t.task_statut, TaskStatus::Zombie) {
-                    zombie_index = Some(idx);
-                    exit_code = Some(t.exit_code);
-                }
-                break;
-            }
+        // 子进程可能刚在别的 hart 上退出，等它切走以后才能释放它的内核栈
+        while child.lock().task_context.is_on_cpu() {
+            core::hint::spin_loop();
         }
-        let Some(idx) = zombie_index else {
-            drop(inner);
-            return None;
-        };
 
-        inner.task_queen.remove(idx);
-        if !inner.task_queen.is_empty() {
-            if inner.current > idx {
-                inner.current -= 1;
-            } else if inner.current >= inner.task_queen.len() {
-                inner.current = 0;
-            }
+        {
+            let mut inner = self.lock_inner();
+            let idx = inner.task_queen.iter().position(|t| Arc::ptr_eq(t, &child))?;
+            inner.task_queen.remove(idx);
         }
-        drop(inner);
 
         {
             let mut parent = current_task.lock();
-            parent.childrens.retain(|c| c.lock().pid.0 != child_pid);
+            parent.childrens.retain(|c| !Arc::ptr_eq(c, &child));
+            parent.child_usage.accumulate(&usage);
         }
-        exit_code
+        Some((exit_code, usage))
     }
 
     ///TODO:根据传入路径加载并且new新的taskblock然后add_task进队列
@@ -582,33 +830,37 @@ impl TaskManager {//全局唯一
     }
 
     ///添加任务队列或者归队
-    pub fn add_task(self,task:Arc<UPSafeCell<TaskControlBlock>>){
-        self.task_que_inner.lock().task_queen.push_back(task);
+    pub fn add_task(self,task:Arc<SpinLock<TaskControlBlock>>){
+        self.lock_inner().task_queen.push_back(task);
     }
 
     ///根据stride选择一个任务 (index, pass)
     ///
     ///注意：这是一个对外包装，会持锁一次。若调用方已经持有 inner 锁，必须使用
-    ///`stride_select_task_inner`，否则会触发 `RefCell already borrowed`。
+    ///`stride_select_task_inner`，否则同一个 hart 重复加锁会 panic。
     pub fn stride_select_task(&self)->Option<(usize, usize)>{
-        let inner  = self.task_que_inner.lock();
-        Self::stride_select_task_inner(&inner)
+        let inner  = self.lock_inner();
+        Self::stride_select_task_inner(&inner, self.current_task().as_ref())
     }
 
     ///在已持有 TaskManagerInner 锁的情况下选择任务（不会再次 lock）。
-    fn stride_select_task_inner(inner: &TaskManagerInner) -> Option<(usize, usize)> {
-        let current = inner.current;
+    /// 还在别的 hart 上的任务不选；current 和别的任务行程相同时优先别的
+    fn stride_select_task_inner(inner: &TaskManagerInner, current: Option<&Arc<SpinLock<TaskControlBlock>>>) -> Option<(usize, usize)> {
+        let is_current = |cell: &Arc<SpinLock<TaskControlBlock>>| current.map_or(false, |c| Arc::ptr_eq(c, cell));
         let mut selected: Option<(usize, usize)> = None; // (index, pass)
         for (idx, cell) in inner.task_queen.iter().enumerate() {
             let t = cell.lock();
             if let TaskStatus::Ready = t.task_statut {
+                if t.task_context.is_on_cpu() && !is_current(cell) {
+                    continue;
+                }
                 let pass = t.pass;
                 match selected {
                     Some((best_idx, best_pass)) => {
                         if pass < best_pass {
                             selected = Some((idx, pass));
                         } else if pass == best_pass {
-                            if best_idx == current && idx != current {
+                            if is_current(&inner.task_queen[best_idx]) && !is_current(cell) {
                                 selected = Some((idx, pass));
                             }
                         }
@@ -620,187 +872,98 @@ impl TaskManager {//全局唯一
         selected
     }
 
-    ///TODO 内核栈释放
-    ///从队列移除当前任务,应该由aplication的exit系统调用来执行 之后必须执行下一个任务 bug修复：应该同时移动指针到任意一个ready的任务
-    pub fn remove_current_task(&self){
-        let mut inner=self.task_que_inner.lock();
-        
-        // 先保存要删除的任务索引
-        let task_to_remove = inner.current;
-        debug!("Removing task at index: {}, queue length before removal: {}", task_to_remove, inner.task_queen.len());
-
-        let removed_task = inner.task_queen[task_to_remove].clone();
-        let init_task = inner
-            .task_queen
-            .iter()
-            .find(|t| t.lock().pid.0 == INIT_PID)
-            .cloned();
-        
-        // 删除任务
-        inner.task_queen.remove(task_to_remove).expect("Remove Task Control Block Failed!");
-        
-        // 删除后更新current指针
-        // VecDeque.remove(i) 会删除索引i的元素，后面的元素索引都会减1
-        // 删除后，如果还有任务，我们需要将current设置为一个有效的任务索引
-        if !inner.task_queen.is_empty() {
-            // 如果删除的是最后一个任务（task_to_remove == 原队列长度-1）
-            // 则删除后 task_to_remove >= 新队列长度，需要回绕到开头
-            if task_to_remove > inner.task_queen.len() {
-                panic!("Task Remove Faild , kernel try to remove a task that index over taskqueen")
-            }
-            if task_to_remove == inner.task_queen.len() {
-                //并且选择一个已经ready的任务，防止执行流错误。
-                let select_task = Self::stride_select_task_inner(&inner);
-                if select_task.is_none(){
-                    error!("After remove,No task can select");
-                    shutdown();
-                }else {
-                    inner.current = select_task.unwrap().0; //current
-                }
-                
+    ///从队列里摘下已经退出并且切走了的线程，调用方放掉队列锁以后再 drop 它们
+    fn take_exited_threads_inner(inner: &mut TaskManagerInner) -> Vec<Arc<SpinLock<TaskControlBlock>>> {
+        let mut exited = Vec::new();
+        let mut idx = 0;
+        while idx < inner.task_queen.len() {
+            let done = {
+                let t = inner.task_queen[idx].lock();
+                t.is_thread() && t.task_statut == TaskStatus::Zombie && !t.task_context.is_on_cpu()
+            };
+            if done {
+                exited.push(inner.task_queen.remove(idx).expect("Kernel Error"));
             } else {
-                // 否则，保持current在原位置
-                // 此时current指向的是原来task_to_remove+1位置的任务
-                inner.current = task_to_remove; //防御延迟到调度函数
+                idx += 1;
             }
-            debug!("After removal: current set to {}, queue length: {}", inner.current, inner.task_queen.len());
         }
-        
-        drop(inner);
+        exited
+    }
+
+    ///选出下一个任务并标记为运行（on_cpu=1，别的 hart 不会再选它），没有可运行的任务时为 None
+    fn take_next_task_inner(inner: &TaskManagerInner, current: Option<&Arc<SpinLock<TaskControlBlock>>>) -> Option<Arc<SpinLock<TaskControlBlock>>> {
+        let (index, _) = Self::stride_select_task_inner(inner, current)?;
+        let task = inner.task_queen[index].clone();
+        {
+            let mut t = task.lock();
+            t.task_statut = TaskStatus::Runing;
+            t.task_context.on_cpu = 1;
+        }
+        Some(task)
+    }
 
-        if let Some(init_task) = init_task {
-            if removed_task.lock().pid.0 != INIT_PID {
-                let children = {
-                    let mut parent = removed_task.lock();
-                    core::mem::take(&mut parent.childrens)
+    ///从 swap_out 切到 next，next 为 None 时回到本 hart 的 idle 循环
+    /// 调用前必须释放所有锁，也不能在栈上留着任务的 Arc（退出的任务不会再回来 drop 它）
+    fn switch_to(swap_out: *mut TaskContext, next: Option<Arc<SpinLock<TaskControlBlock>>>) {
+        let processer = current_processer();
+        let swap_in = match next {
+            Some(task) => {
+                let cx = {
+                    let mut t = task.lock();
+                    // 不在 CPU 上的时间不记账
+                    t.last_tick = get_time_tick();
+                    &mut t.task_context as *mut TaskContext
                 };
-                if !children.is_empty() {
-                    let init_weak = Arc::downgrade(&init_task);
-                    {
-                        let mut init = init_task.lock();
-                        for child in children.iter() {
-                            init.childrens.push(child.clone());
-                        }
-                    }
-                    for child in children {
-                        let mut c = child.lock();
-                        c.parent = Some(init_weak.clone());
-                    }
-                }
+                processer.set_current(Some(task));
+                cx
             }
-        } else {
-            error!("Can't find init process!,these child process will become foster process");
-            let children = {
-                let mut parent = removed_task.lock();
-                core::mem::take(&mut parent.childrens)
-            };
-            for child in children {
-                let mut c = child.lock();
-                c.parent = None;
+            None => {
+                processer.set_current(None);
+                processer.idle_task_cx_ptr()
             }
-        }
-
-        if self.task_queen_is_empty() {
-            error!("The last task(should be init) exit or be removed,shutdown");
-            shutdown();
+        };
+        unsafe {
+            // 新任务的内核栈可能是别的 hart 刚映射进内核地址空间的
+            asm!("sfence.vma");
+            __switch(swap_out, swap_in);
         }
     }
-    ///根据Stride挑选下个要运行的READY任务,挂起当前任务,把current设置为下个任务的index,然后运行下一个任务 Stride算法：增加运行任务的步长
-    pub fn suspend_and_run_task(&self){ //首先应该检查任务是否为空
 
-        //任务列表是否为空?
-        if self.task_queen_is_empty(){
-                panic!("Task Queen is empty!");
-        }
-
-       
+    ///根据Stride挑选下个要运行的READY任务,挂起当前任务,然后运行下一个任务 Stride算法：增加运行任务的步长
+    /// 没有别的任务可运行时：当前任务还能运行就继续，否则回到 idle 循环
+    pub fn suspend_and_run_task(&self){
+        // 内核态不响应外部中断，调度时顺便收取块设备完成的请求，唤醒等待的任务
+        crate::driver::virtio_blk_poll();
 
-        let mut inner  =self.task_que_inner.lock();
-        if inner.task_queen.is_empty() {
-            drop(inner);
-            panic!("Task Queen is empty!");
-        }
-        let current = inner.current;
-        if current >= inner.task_queen.len() {
-            drop(inner);
-            panic!("TaskManager current index out of range");
-        }
-        // warn!("Current task queen :\nReady {:?}\nBlocking:{:?}",inner.task_queen.len(),inner.task_blocking.len());
-        // for t in inner.task_queen.iter().enumerate() {
-        //     let idx = t.1.lock().pid.0;
-        //     warn!("task queen,idx:{} pid:{} task_status:{:?}",t.0,idx,t.1.lock().task_statut);
-        // }
-        {
-            let mut cur = inner.task_queen[current].lock();
+        let Some(current) = self.current_task() else {
+            return;
+        };
+        // 释放退出线程的资源要拿地址空间的锁，持有别的锁时留给下一次调度
+        let can_reap = !hart_holds_lock();
+        let mut inner  =self.lock_inner();
+        let exited = if can_reap { Self::take_exited_threads_inner(&mut inner) } else { Vec::new() };
+        let swaped_task_cx = {
+            let mut cur = current.lock();
             if !matches!(cur.task_statut, TaskStatus::Zombie) {
                 cur.task_statut = TaskStatus::Ready;
                 cur.pass += cur.stride;
             }
-        }
+            &mut cur.task_context as *mut TaskContext
+        };
 
         // 选择 stride 最小的 READY 任务（注意：这里已持有 inner 锁，不能再次 lock）
-        let selected: Option<(usize, usize)> = Self::stride_select_task_inner(&inner);
-        let task_index = match selected {
-            Some((idx, _)) => idx,
-            None => {
-                warn!("No task can select");
-                shutdown();
-            }
-        };
-        
-        if task_index >= inner.task_queen.len() {
-            drop(inner);
-            panic!("Selected task index out of range");
-        }
-        {
-            let task_status;
-            {
-                let t = inner.task_queen[task_index].lock();
-                task_status= t.task_statut.clone();
-               // error!("Select pid:{} idx:{} status:{:?}",t.pid.0,task_index,task_status);
-            }
-            
-            match task_status {
-                TaskStatus::Ready => {}
-                _ => {
-                    drop(inner);
-                    panic!("Selected task is not Ready");
-                }
-            }
-        }
-        
-        debug!("current:{} Next task:{}",inner.current,task_index);
-        
+        let next = Self::take_next_task_inner(&inner, Some(&current));
+        drop(inner);
+        drop(exited);
+
         //如果切换到同一个任务，直接返回 _switch耗费上下文资源
         //这可以防止在持有用户态锁时发生任务切换导致的死锁问题（全局锁）
-        if current == task_index {
-            {
-                let mut t = inner.task_queen[task_index].lock();
-                t.task_statut = TaskStatus::Runing;
-            }
-            drop(inner);
+        if next.as_ref().map_or(false, |n| Arc::ptr_eq(n, &current)) {
             debug!("Same task, skip __switch");
             return;
         }
-        
-        // 准备切换：先拿到上下文指针，更新状态，然后释放所有锁再 __switch
-        let swaped_task_cx = {
-            let mut cur = inner.task_queen[current].lock();
-            &mut cur.task_context as *mut TaskContext
-        };
-
-        let need_swap_in = {
-            let mut next = inner.task_queen[task_index].lock();
-            next.task_statut = TaskStatus::Runing;
-            &mut next.task_context as *mut TaskContext
-        };
-
-        inner.current = task_index;
-        drop(inner);
-        unsafe {
-            __switch(swaped_task_cx, need_swap_in);
-        }
-
+        drop(current);
+        Self::switch_to(swaped_task_cx, next);
 
         //任务从这里返回
 
@@ -809,144 +972,118 @@ impl TaskManager {//全局唯一
     }
 
     pub fn task_queen_is_empty(&self)->bool{
-        let inner=self.task_que_inner.lock();
+        let inner=self.lock_inner();
         let result= inner.task_queen.is_empty();
         drop(inner);
         debug!("task queen empty?:{}",result);
         result
     }
 
+    ///所有任务都退出了（队列里只剩 Zombie，也没有阻塞的任务）
+    fn all_task_exited(&self)->bool{
+        let inner=self.lock_inner();
+        inner.task_blocking.is_empty()
+            && inner.task_queen.iter().all(|t| t.lock().task_statut == TaskStatus::Zombie)
+    }
 
-
-    ///运行第一个任务
-    pub fn run_first_task(&self) -> ! {
-      let inner=self.task_que_inner.lock();//记得drop
-      let curren_task_index=inner.current;
-      let task_cx_ptr = {
-        let mut task = inner.task_queen[curren_task_index].lock();
-        // 标记为 running
-        task.task_statut = TaskStatus::Runing;
-        // 增加步长
-        task.pass += task.stride;
-        &mut task.task_context as *mut TaskContext
-      };
-      let kernel_task_cx=TaskContext::zero_init();
-      drop(inner);//越早越好
-      // 调用 __switch 切换到第一个任务
-      // __switch 会：
-      // 1. 保存 _unused 的上下文（虽然我们不会再用到）
-      // 2. 恢复 next_task_cx_ptr 指向的上下文
-      // 3. 跳转到 task.task_context.ra，即 app_entry_point
-      unsafe {
-        __switch(&kernel_task_cx as *const TaskContext, task_cx_ptr);
-      }
-      
-      panic!("unreachable in run_first_task!");
-    }
-
-    /// 在删除当前任务后，直接切换到当前 inner.current 指向的任务。
-    ///
-    /// 关键点：此时 CPU 仍在“已删除任务”的内核栈/执行流上，不能把当前寄存器保存到
-    /// 新任务的 `task_context` 里，否则会污染新任务上下文。
-    pub fn run_current_task(&self) -> ! {
-        let inner = self.task_que_inner.lock();
-        if inner.task_queen.is_empty() {
-            drop(inner);
-            panic!("Task Queen is empty!");
-        }
-        let current = inner.current;
-        if current >= inner.task_queen.len() {
-            drop(inner);
-            panic!("TaskManager current index out of range");
-        }
-        let task_cx_ptr = {
-            let mut task = inner.task_queen[current].lock();
-            task.task_statut = TaskStatus::Runing;
-            task.pass += task.stride;
-            &mut task.task_context as *mut TaskContext
-        };
-        let dummy = TaskContext::zero_init();
-        drop(inner);
-        unsafe {
-            __switch(&dummy as *const TaskContext, task_cx_ptr);
+    ///每个 hart 的 idle 循环：挑一个可运行的任务切过去，任务让出 CPU 且没有别的任务时切回这里
+    /// 没有任务可运行时 wfi 等中断，所有任务都退出后关机
+    pub fn run_tasks(&self) -> ! {
+        loop {
+            crate::driver::virtio_blk_poll();
+            let (next, exited) = {
+                let mut inner = self.lock_inner();
+                let exited = Self::take_exited_threads_inner(&mut inner);
+                (Self::take_next_task_inner(&inner, None), exited)
+            };
+            drop(exited);
+            match next {
+                Some(task) => {
+                    let idle_cx = current_processer().idle_task_cx_ptr();
+                    Self::switch_to(idle_cx, Some(task));
+                }
+                None => {
+                    if self.all_task_exited() {
+                        error!("The last task(should be init) exit or be removed,shutdown");
+                        shutdown();
+                    }
+                    wait_for_interrupt();
+                }
+            }
         }
-        panic!("unreachable in run_current_task!");
+    }
+
+    ///获取当前任务的pid，idle 循环里为 None
+    pub fn get_current_pid(&self)->Option<i32>{
+        let task = self.current_task()?;
+        let pid = task.lock().pid.0;
+        Some(pid)
+    }
+
+    ///当前任务的地址空间
+    /// 先放掉 TCB 锁再锁地址空间：换入页时的块设备唤醒会去锁别的 TCB
+    pub fn current_memory_set(&self)->Arc<SpinLock<MapSet>>{
+        let task = self.expect_current_task();
+        let memory_set = task.lock().memory_set.clone();
+        memory_set
+    }
+
+    ///当前任务的文件描述符表
+    pub fn current_fd_table(&self)->Arc<SpinLock<FdTable>>{
+        let task = self.expect_current_task();
+        let table = task.lock().file_descriptor.clone();
+        table
     }
 
     ///获取当前任务的页表stap
     pub fn get_current_stap(&self)->usize{
-        let inner= self.task_que_inner.lock();
-        let current_task:usize=inner.current;
-        let stap = {
-            let mut task = inner.task_queen[current_task].lock();
-            task.memory_set.get_table().satp_token()
-        };
-        drop(inner);
+        let stap = self.current_memory_set().lock().get_table().satp_token();
         stap
     }
 
+    ///当前任务陷阱上下文的用户虚拟地址，返回用户态时交给 __kernel_refume
+    pub fn get_current_trapcx_addr(&self)->usize{
+        let addr = self.expect_current_task().lock().trap_context_addr;
+        addr
+    }
+
     ///获取当前任务的陷阱上下文可变引用
     pub fn get_current_trapcx(&self)->&mut TrapContext{
-        let inner =self.task_que_inner.lock();
-        let curren_task_index=inner.current;
-        let task_trap_ppn = {
-            let task = inner.task_queen[curren_task_index].lock();
-            task.trap_context_ppn
-        };
+        let task_trap_ppn = self.expect_current_task().lock().trap_context_ppn;
         let origin_phyaddr =( task_trap_ppn*PAGE_SIZE) as *mut TrapContext;
         let trap_context =unsafe {
             &mut *origin_phyaddr
         };
-        drop(inner);
         trap_context
     }
 
     ///获取当前任务的文件描述符
     pub fn get_current_fd(&self, fd: usize) -> Option<Option<Arc<dyn File>>> {
-        let inner = self.task_que_inner.lock();
-        let current_task = inner.current;
-        let result = {
-            let task = inner.task_queen[current_task].lock();
-            task.file_descriptor.get(fd).cloned()
-        };
-        drop(inner);
+        let result = self.current_fd_table().lock().get(fd).cloned();
         result
     }
 
     pub fn get_current_cwd(&self) -> String {
-        if self.task_que_inner.lock().task_queen.is_empty(){
-            return "/".to_string();
+        match self.current_task() {
+            Some(task) => task.lock().get_cwd().to_string(),
+            None => "/".to_string(),
         }
-        let inner = self.task_que_inner.lock();
-        let current_task = inner.current;
-        let cwd = {
-            let task = inner.task_queen[current_task].lock();
-            task.get_cwd().to_string()
-        };
-        drop(inner);
-        cwd
     }
 
     pub fn set_current_cwd(&self, cwd: String) {
-        let inner = self.task_que_inner.lock();
-        let current_task = inner.current;
-        {
-            let mut task = inner.task_queen[current_task].lock();
-            task.set_cwd(cwd);
-        }
-        drop(inner);
+        let task = self.expect_current_task();
+        task.lock().set_cwd(cwd);
     }
 
     pub fn alloc_fd_for_current(&self, new_fd: Arc<dyn File>) -> i32 {
-        let inner = self.task_que_inner.lock();
-        let current_task = inner.current;
-        let mut task = inner.task_queen[current_task].lock();
-        if task.file_descriptor.len() < 2 {
-            while task.file_descriptor.len() < 2 {
-                task.file_descriptor.push(None);
+        let fd_table = self.current_fd_table();
+        let mut table = fd_table.lock();
+        if table.len() < 2 {
+            while table.len() < 2 {
+                table.push(None);
             }
         }
-        for (i, slot) in task.file_descriptor.iter_mut().enumerate() {
+        for (i, slot) in table.iter_mut().enumerate() {
             if i < 2 {
                 continue;
             }
@@ -955,54 +1092,74 @@ impl TaskManager {//全局唯一
                 return i as i32;
             }
         }
-        task.file_descriptor.push(Some(new_fd));
-        (task.file_descriptor.len() - 1) as i32
+        table.push(Some(new_fd));
+        (table.len() - 1) as i32
     }
 
     pub fn close_current_fd(&self, fd: usize) -> isize {
-        let inner = self.task_que_inner.lock();
-        let current_task = inner.current;
-        let mut task = inner.task_queen[current_task].lock();
-        if fd >= task.file_descriptor.len() {
-            return -1;
+        let fd_table = self.current_fd_table();
+        let mut table = fd_table.lock();
+        if fd >= table.len() {
+            return -Errno::EBADF;
         }
-        if task.file_descriptor[fd].is_none() {
-            return -1;
-        }
-        task.file_descriptor[fd].take();
-        if task.file_descriptor[fd].is_none() {
-            return 0;
-        }else {
-            error!("Close fd :{} failed!",fd);
-            return -1;    
+        if table[fd].is_none() {
+            return -Errno::EBADF;
         }
+        let file = table[fd].take();
+        // 最后一个引用关闭时可能唤醒别的任务（管道），放掉描述符表的锁再 drop
+        drop(table);
+        drop(file);
+        0
     }
 
 
     ///kail当前任务，内核有权调用 调用栈顶必须为TrapHandler! 调用它的地方考虑是否直接return
+    /// 父进程看到的是被 SIGKILL 杀死
     pub fn kail_current_task_and_run_next(&self){
+        self.exit_current_by_signal(Signal::SIGKILL.signo());
+        error!("Task Kailed!");
+    }
+
+    ///当前任务被信号杀死：父进程 wait 拿到 WIFSIGNALED 的状态
+    pub fn exit_current_by_signal(&self, signo: usize){
         self.reparent_current_children_to_init();
-        self.mark_current_zombie(-1);
+        self.expect_current_task().lock().term_signal = signo;
+        self.mark_current_zombie(0);
+        self.wake_current_parent();
         self.suspend_and_run_task();//调度下一个stride最小的任务
-        error!("Task Kailed!");
     }
 
 
 
 }
 
+///idle 时等中断：内核态 SIE=0，wfi 只是醒来，挂起的中断在这里手动处理
+fn wait_for_interrupt() {
+    unsafe {
+        riscv::asm::wfi();
+    }
+    let pending = sip::read();
+    if pending.stimer() {
+        set_next_timeInterupt();
+        check_timers();
+    }
+    if pending.sext() {
+        crate::driver::handle_external_interrupt();
+    }
+}
+
 
 impl TaskContext {
     ///ra设置为trap refume地址，sp为用户栈指针，callee_register初始化0
     pub fn trapnew_init(sp:usize)->Self{
-       TaskContext { ra: __kernel_refume as usize, sp: sp, calleed_register: [0;12] }
+       TaskContext { ra: __kernel_refume as usize, sp: sp, calleed_register: [0;12], on_cpu: 0 }
     }
 }
 
 
 //全局进程id分配器
 lazy_static!{
-    pub static ref ProcessId_ALLOCTOR:UPSafeCell<ProcessIdAlloctor>=UPSafeCell::new(ProcessIdAlloctor::initial_processid_alloctor(1, 10_000_000));
+    pub static ref ProcessId_ALLOCTOR:SpinLock<ProcessIdAlloctor>=SpinLock::new(ProcessIdAlloctor::initial_processid_alloctor(1, 10_000_000));
 }
 
 // 全局任务管理器，加载init程序
@@ -1016,12 +1173,11 @@ lazy_static! {
             let init_task = TaskControlBlock::new("/cinit", 1, None);
             if init_task.is_some() {
                 let task = init_task.expect("Kernel error");
-                task_deque.push_back(Arc::new(UPSafeCell::new(task)));
+                task_deque.push_back(Arc::new(SpinLock::new(task)));
                 return TaskManager {
-                    task_que_inner: UPSafeCell::new(TaskManagerInner {
+                    task_que_inner: SpinLock::new(TaskManagerInner {
                         task_queen: task_deque,
                         task_blocking: VecDeque::new(),
-                        current: 0
                     })
                 };
             }
@@ -1072,7 +1228,7 @@ lazy_static! {
             for name in names {
                 let path = alloc::format!("/sd/{}", name);
                 if let Some(task) = TaskControlBlock::new(&path, kid, None) {
-                    task_deque.push_back(Arc::new(UPSafeCell::new(task)));
+                    task_deque.push_back(Arc::new(SpinLock::new(task)));
                     kid += 1;
                 }
             }
@@ -1082,10 +1238,9 @@ lazy_static! {
             }
 
             TaskManager {
-                task_que_inner: UPSafeCell::new(TaskManagerInner {
+                task_que_inner: SpinLock::new(TaskManagerInner {
                     task_queen: task_deque,
                     task_blocking: VecDeque::new(),
-                    current: 0  // 初始化为第一个任务
                 })
             }
             
@@ -1096,7 +1251,7 @@ lazy_static! {
                 // app_id 从 0 开始，kernel_stack_id 从 1 开始
                 let task = TaskControlBlock::new("/test/init",1,None).expect("Can't load init elf");
                 //task.task_statut=TaskStatus::Ready; 在new已经设置为ready
-                task_deque.push_back(Arc::new(UPSafeCell::new(task)));
+                task_deque.push_back(Arc::new(SpinLock::new(task)));
                 debug!("Application init {} loaded successfully", 0);
             
             unsafe {
@@ -1104,10 +1259,9 @@ lazy_static! {
             }
             
             TaskManager {
-                task_que_inner: UPSafeCell::new(TaskManagerInner {
+                task_que_inner: SpinLock::new(TaskManagerInner {
                     task_queen: task_deque,
                     task_blocking: VecDeque::new(),
-                    current: 0  // 初始化为第一个任务
                 })
             }
         }
@@ -1122,6 +1276,7 @@ pub fn getapp_kernel_sapce()->usize{
 }
 
 
+///当前 hart 进入 idle 循环开始调度任务，每个 hart 都调用
 pub fn run_first_task()->!{
-    TASK_MANAER.run_first_task();
+    TASK_MANAER.run_tasks();
 }
\ No newline at end of file
@@ -0,0 +1,79 @@
+//! 等待队列：任务在上面睡眠，事件（管道数据、子进程退出、终端输入）发生时唤醒
+//! 队列里记的是 pid，唤醒走 TaskManager::wake_task_from_blocking；
+//! 登记之后再检查条件，检查和睡眠之间来的唤醒由 wakeup_pending 兜住，不会丢
+
+use alloc::collections::VecDeque;
+
+use crate::sync::{SpinLock, hart_holds_lock};
+use crate::task::{TASK_MANAER, hart_id};
+
+pub struct WaitQueue {
+    waiters: SpinLock<VecDeque<i32>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: SpinLock::new(VecDeque::new()),
+        }
+    }
+
+    ///把当前任务登记到队列上
+    fn prepare_wait(&self) {
+        let Some(pid) = TASK_MANAER.get_current_pid() else {
+            return;
+        };
+        let mut waiters = self.waiters.lock();
+        if !waiters.contains(&pid) {
+            waiters.push_back(pid);
+        }
+    }
+
+    ///把当前任务从队列上摘下来（条件已经满足或者被别的原因唤醒）
+    fn finish_wait(&self) {
+        let Some(pid) = TASK_MANAER.get_current_pid() else {
+            return;
+        };
+        self.waiters.lock().retain(|p| *p != pid);
+    }
+
+    ///登记后检查 cond，不成立就睡眠，醒来（可能是被信号等提前唤醒）后返回，调用方重新检查条件
+    /// 睡眠会切到别的任务，不能拿着任何 SpinLock：别的任务再拿同一把锁会在这个 hart 上一直自旋，直接 panic
+    #[track_caller]
+    pub fn sleep_unless(&self, cond: impl FnOnce() -> bool) {
+        self.prepare_wait();
+        if cond() {
+            self.finish_wait();
+            return;
+        }
+        if hart_holds_lock() {
+            let loc = core::panic::Location::caller();
+            panic!(
+                "sleep while holding a SpinLock on hart {} at {}:{}:{}",
+                hart_id(),
+                loc.file(),
+                loc.line(),
+                loc.column()
+            );
+        }
+        TASK_MANAER.blocking_current_task_and_run_next();
+        self.finish_wait();
+    }
+
+    ///睡眠直到 cond 成立
+    #[track_caller]
+    pub fn wait_until(&self, mut cond: impl FnMut() -> bool) {
+        while !cond() {
+            self.sleep_unless(&mut cond);
+        }
+    }
+
+    ///唤醒队列上所有任务
+    /// 本 hart 正持有任务队列锁时推迟到放锁的时候唤醒（见 TaskManager::wake_task_or_defer）
+    pub fn wake_all(&self) {
+        let waiters = core::mem::take(&mut *self.waiters.lock());
+        for pid in waiters {
+            TASK_MANAER.wake_task_or_defer(pid);
+        }
+    }
+}
@@ -1,8 +1,14 @@
 const  MSEC:usize=1000;
 use riscv::register::time;
 use crate::sbi::set_next_timetriger;
-use crate::config::{CPU_CIRCLE, TIME_FREQUENT};
+use crate::config::TIME_FREQUENT;
+use crate::driver::fdt::timebase_frequency;
 use log::debug;
+use alloc::collections::BinaryHeap;
+use core::cmp::Reverse;
+use lazy_static::lazy_static;
+use crate::sync::SpinLock;
+use crate::task::TASK_MANAER;
 
 
 #[repr(C)]
@@ -22,24 +28,79 @@ pub fn get_time_tick()->usize{
 
 ///返回毫秒数
 pub fn get_time_ms()->usize{
-    let current=(time::read()*MSEC)/CPU_CIRCLE;//先×再除防止精度丢失
+    let current=(time::read()*MSEC)/timebase_frequency();//先×再除防止精度丢失
     current
 }
 
 
+///tick 数换算成 timeval，rusage 用
+pub fn ticks_to_timeval(ticks:usize)->TimeVal{
+    let usec=ticks*1_000_000/timebase_frequency();
+    TimeVal { sec: usec/1_000_000, usec: usec%1_000_000 }
+}
+
 ///设置下一次时钟中断(不带中断检查，太耗时间，所有耗时操作其实都不应该出现在这里)，mtimecmp使用原始tick计数
 pub fn set_next_timeInterupt(){
     //需要考虑调用误差，即使错过也没事，只是提前触发中断(mtime < mtimecmp)
-    let next_time=get_time_tick() + CPU_CIRCLE/TIME_FREQUENT;
+    let next_time=get_time_tick() + timebase_frequency()/TIME_FREQUENT;
     set_next_timetriger(next_time);
 }
 
 ///内核sleep函数,传入毫秒数 阻塞式  目前不能使用，buged
 pub fn kernel_sleep(time_ms:usize){
-let target =time::read()+CPU_CIRCLE/MSEC*time_ms;
+let target =time::read()+timebase_frequency()/MSEC*time_ms;
     while time::read()<= target {
       //  debug!("current :{} targer :{}",time::read(),target)
       core::hint::spin_loop();
     }
 
 }
+
+
+lazy_static!{
+    ///睡眠任务的定时器，按到期时间(ms)排序，堆顶最早到期
+    static ref TIMERS:SpinLock<BinaryHeap<Reverse<(usize,i32)>>>=SpinLock::new(BinaryHeap::new());
+}
+
+///登记一个定时器：deadline_ms 到期时唤醒 pid
+pub fn add_timer(deadline_ms:usize,pid:i32){
+    TIMERS.lock().push(Reverse((deadline_ms,pid)));
+}
+
+///唤醒所有到期的定时器，每次时钟中断调用
+pub fn check_timers(){
+    let now=get_time_ms();
+    let mut expired=alloc::vec::Vec::new();
+    {
+        let mut timers=TIMERS.lock();
+        while let Some(Reverse((deadline,pid)))=timers.peek().copied() {
+            if deadline>now {
+                break;
+            }
+            timers.pop();
+            expired.push(pid);
+        }
+    }
+    for pid in expired {
+        TASK_MANAER.wake_task_from_blocking(pid);
+    }
+}
+
+///当前任务睡眠到 deadline_ms，睡眠期间不占 CPU
+/// 被信号打断时提前返回 false
+pub fn sleep_until_ms(deadline_ms:usize)->bool{
+    loop {
+        if get_time_ms()>=deadline_ms {
+            return true;
+        }
+        if TASK_MANAER.current_has_signal() {
+            return false;
+        }
+        let Some(pid)=TASK_MANAER.get_current_pid() else {
+            kernel_sleep(deadline_ms-get_time_ms());
+            return true;
+        };
+        add_timer(deadline_ms,pid);
+        TASK_MANAER.blocking_current_task_and_run_next();
+    }
+}
@@ -1,6 +1,6 @@
 
 use core::{arch::global_asm, panic, panicking::panic};
-use crate::{config::*, task::TASK_MANAER, time::set_next_timeInterupt, trap::pagefaultHandler::PageFaultHandler};
+use crate::{config::*, task::{TASK_MANAER, hart_id, handle_signals, force_signal, Signal, BUS_ADRALN, ILL_ILLOPC, SEGV_ACCERR, TRAP_BRKPT}, time::{check_timers, set_next_timeInterupt}, trap::pagefaultHandler::PageFaultHandler};
 use log::{debug, error, };
 use riscv::register::{scause::{self, Exception, Trap}, sie::Sie, sscratch, sstatus::{self, SPP, Sstatus}, stval, stvec, utvec::TrapMode};
 use crate::syscall::*;//系统调用
@@ -33,6 +33,8 @@ pub struct TrapContext{
      pub kernel_sp:usize,//35*8(sp)
      ///陷阱处理程序
      pub trap_handler:usize,//36*8(sp)
+     ///返回用户态时所在的 hartid，陷入时恢复到 tp
+     pub hartid:usize,//37*8(sp)
 }
 
 
@@ -60,6 +62,7 @@ impl TrapContext {
             kernel_satp,              // 内核页表
             kernel_sp,                // 内核栈指针
             trap_handler,             // trap 处理函数
+            hartid: 0,                // app_entry_point 返回用户态前填写
         }
     }
 }
@@ -118,7 +121,11 @@ pub fn set_kernel_forbid(){
 #[no_mangle]
 pub extern "C" fn app_entry_point() {
     set_kernel_trap_handler();
+    // 用户态会改 tp，陷入时从这里恢复
+    TASK_MANAER.get_current_trapcx().hartid = hart_id();
     let user_satp = TASK_MANAER.get_current_stap();
+    // 同一地址空间里的线程各有一页陷阱上下文
+    let trap_cx_va = TASK_MANAER.get_current_trapcx_addr();
     let restore_va = __kernel_refume as usize - __kernel_trap as usize + TRAP_BOTTOM_ADDR;
     //error!("Resrore_va:{:#x}",restore_va);
    // let restore_va = __kernel_refume as usize;
@@ -129,7 +136,7 @@ pub extern "C" fn app_entry_point() {
             "fence.i",
             "jr {restore_va}",         // jump to new addr of __restore asm function
             restore_va = in(reg) restore_va,
-            in("a0") TRAP_CONTEXT_ADDR,      // a0 = virt addr of Trap Context
+            in("a0") trap_cx_va,      // a0 = virt addr of Trap Context
             in("a1") user_satp,        // a1 = phy addr of usr page table
             options(noreturn)
         );
@@ -142,6 +149,8 @@ use riscv::register::sepc;
 ///handler必须返回到trap里面去
 pub extern "C" fn kernel_trap_handler(){//内核专属trap（目前不应该被调用）
     set_kernel_forbid();
+    // 从用户态陷入，之前的时间记为用户态时间
+    TASK_MANAER.account_current_time(true);
     let scauses = scause::read();
     let sepc_val = sepc::read();
     let stval_val = stval::read();
@@ -158,6 +167,8 @@ pub extern "C" fn kernel_trap_handler(){//内核专属trap（目前不应该被
         ];
         (id, args)
     };
+    // 系统调用被信号打断时记下原来的 a0 和返回值，递送信号时决定要不要重新执行
+    let mut interrupted = None;
         match scauses.cause(){
         Trap::Exception(Exception::UserEnvCall)=>{
             {
@@ -172,41 +183,65 @@ pub extern "C" fn kernel_trap_handler(){//内核专属trap（目前不应该被
                 debug!("lat sepc:{:#x}",current_trapcx.sepc_entry_point);
                 current_trapcx.x[10] = ret as usize;
             }
+            // 被信号打断的系统调用，递送时按返回值和 SA_RESTART 决定要不要重新执行
+            if (ret == -Errno::ERESTARTSYS || ret == -Errno::ERESTARTNOHAND || ret == -Errno::ERESTART_RESTARTBLOCK)
+                && sys_id != SYS_RT_SIGRETURN
+            {
+                interrupted = Some((sys_args[0], ret));
+            }
         }
         Trap::Exception(Exception::IllegalInstruction)=>{
             error!("User IllegalInstruction at {:#x}", sepc_val);
-            TASK_MANAER.kail_current_task_and_run_next();
-            //panic!("User IllegalInstruction at {:#x}", sepc_val)
+            force_signal(Signal::SIGILL, ILL_ILLOPC, sepc_val);
         }
-        Trap::Exception(Exception::InstructionPageFault)=>{
-            error!("User InstructionPageFault at {:#x}, accessing {:#x}", sepc_val, stval_val);
-            PageFaultHandler(VirAddr(stval_val),scauses);
+        Trap::Exception(Exception::Breakpoint)=>{
+            debug!("User Breakpoint at {:#x}", sepc_val);
+            force_signal(Signal::SIGTRAP, TRAP_BRKPT, sepc_val);
         }
-        Trap::Exception(Exception::LoadPageFault)=>{
-            warn!("User LoadPageFault at {:#x}, accessing {:#x}", sepc_val, stval_val);
-            PageFaultHandler(VirAddr(stval_val),scauses);
+        Trap::Exception(Exception::InstructionMisaligned) | Trap::Exception(Exception::StoreMisaligned)=>{
+            warn!("User misaligned access at {:#x}, accessing {:#x}", sepc_val, stval_val);
+            force_signal(Signal::SIGBUS, BUS_ADRALN, stval_val);
         }
-        Trap::Exception(Exception::StorePageFault)=>{
-            error!("User StorePageFault at {:#x}, accessing {:#x}", sepc_val, stval_val);
-            PageFaultHandler(VirAddr(stval_val),scauses);
+        Trap::Exception(Exception::InstructionFault) | Trap::Exception(Exception::LoadFault) | Trap::Exception(Exception::StoreFault)=>{
+            warn!("User access fault at {:#x}, accessing {:#x}", sepc_val, stval_val);
+            force_signal(Signal::SIGSEGV, SEGV_ACCERR, stval_val);
+        }
+        Trap::Exception(Exception::InstructionPageFault)
+        | Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::StorePageFault)=>{
+            debug!("User {:?} at {:#x}, accessing {:#x}", scauses.cause(), sepc_val, stval_val);
+            if let Err((sig, code)) = PageFaultHandler(VirAddr(stval_val),scauses) {
+                force_signal(sig, code, stval_val);
+            }
         }
         Trap::Interrupt(Interrupt::SupervisorTimer)=>{
-
-            // 处理进程信号
-            TASK_MANAER.resolve_current_task_signal();
-           
             set_next_timeInterupt();
+            // 唤醒到期的睡眠任务
+            check_timers();
+            TASK_MANAER.update_current_rss();
 
             TASK_MANAER.suspend_and_run_task();
         }
         Trap::Interrupt(Interrupt::SupervisorExternal)=>{
-            //外部中断，键盘等
-            panic!("externnal interrupt,but rust sbi make complete abtract!");
+            //外部中断，经 PLIC 分发给块设备等
+            crate::driver::handle_external_interrupt();
+        }
+        Trap::Exception(_)=>{
+            // LoadMisaligned(4) 在这个版本的 riscv crate 里是 Unknown
+            if scauses.code() == 4 {
+                force_signal(Signal::SIGBUS, BUS_ADRALN, stval_val);
+            } else {
+                warn!("Unknown exception from user: code {} at {:#x}", scauses.code(), sepc_val);
+                force_signal(Signal::SIGILL, ILL_ILLOPC, sepc_val);
+            }
         }
-        _=>{
-            panic!("Unknown trap from user: {:?}", scauses.cause())
+        Trap::Interrupt(_)=>{
+            warn!("Unexpected interrupt from user: {:?}", scauses.cause());
         }
     }
+    // 回用户态之前递送信号
+    handle_signals(interrupted);
+    TASK_MANAER.account_current_time(false);
     app_entry_point();//传入特定参数，返回回去
 }
 
@@ -1,13 +1,14 @@
 use log::{debug, error, warn};
 
-use crate::{memory::{PTEFlags, PageTable, VirAddr, VirNumRange, VirNumber}, task::TASK_MANAER};
+use crate::{memory::{MapSet, PTEFlags, PageTable, VirAddr, VirNumRange, VirNumber}, syscall::Errno, task::{Signal, TASK_MANAER, BUS_ADRERR, SEGV_ACCERR, SEGV_MAPERR, SI_KERNEL}};
 use riscv::register::{scause::{self, Exception, Trap}, sie::Sie, sscratch, sstatus::{self, SPP, Sstatus}, stval, stvec, utvec::TrapMode};
 use riscv::register::scause::Scause;
 
 ///专门处理非虚拟化环境下的PAGEFAULT exception
 ///faultVAddr发生fault时被操作的addr
 ///pagefault触发时的环境可能为内核，可能为用户态 内核态可能是在帮用户处理程序->合法,User态->合法
-pub fn PageFaultHandler(faultVAddr:VirAddr,cause:Scause){
+/// 非法访问返回要发给任务的信号和 si_code：没有映射 SEGV_MAPERR，权限不够 SEGV_ACCERR，读不出文件内容 SIGBUS，分配不到页帧 SIGKILL
+pub fn PageFaultHandler(faultVAddr:VirAddr,cause:Scause)->Result<(),(Signal,i32)>{
     debug!("Handle Fault Virtual Address:{:#x}",faultVAddr.0);
     let contain_vpn:VirNumber=faultVAddr.floor_down();
     let tsak_satp=TASK_MANAER.get_current_stap();
@@ -25,25 +26,48 @@ pub fn PageFaultHandler(faultVAddr:VirAddr,cause:Scause){
              
 
             // cpu硬件有权选择不维护 页表 A（access） D(dirty)，需要通知操作系统 
-            if pte.is_valid() && pte.flags().contains(PTEFlags::W) && Trap::Exception(Exception::StorePageFault)==cause.cause(){
+            // 用户态碰到没有 U 位的页（陷阱上下文等）不是 A/D 位的问题，落到下面的非法分支
+            let user_page = pte.flags().contains(PTEFlags::U);
+            if user_page && pte.is_valid() && pte.flags().contains(PTEFlags::W) && Trap::Exception(Exception::StorePageFault)==cause.cause(){
                 // 更新pte的ad位
                 (*pte).set_isaccess();
                 (*pte).set_isdirty();
                 unsafe { riscv::asm::sfence_vma(0, 0) };
                 warn!("Update pte access and dirty flags");
-                return;
-            }else if pte.is_valid() && pte.flags().contains(PTEFlags::R) && (Trap::Exception(Exception::LoadPageFault)==cause.cause() || Trap::Exception(Exception::InstructionPageFault)==cause.cause()) {
+                return Ok(());
+            }else if user_page && pte.is_valid() && (
+                (pte.flags().contains(PTEFlags::R) && Trap::Exception(Exception::LoadPageFault)==cause.cause())
+                || (pte.flags().contains(PTEFlags::X) && Trap::Exception(Exception::InstructionPageFault)==cause.cause())
+            ) {
                 // 更新pte的a位
                 (*pte).set_isaccess();
                 unsafe { riscv::asm::sfence_vma(0, 0) };
                 warn!("Update pte access flags");
-                return;
+                return Ok(());
             }
             else if !pte.is_valid(){
                 // 继续pagefault路程
+            }else if Trap::Exception(Exception::StorePageFault)==cause.cause() && pte.flags().contains(PTEFlags::U) {
+                // 合法页但不可写：可能是 fork 共享出来的 COW 页
+                let memory_set=TASK_MANAER.current_memory_set();
+                let (is_cow, handled) = {
+                    let mut memset=memory_set.lock();
+                    let is_cow = memset.is_cow_vpn(contain_vpn);
+                    (is_cow, if is_cow { memset.cow_handle_fault(contain_vpn) } else { Ok(()) })
+                };
+                drop(memory_set);
+                if !is_cow {
+                    error!("Store to read-only page! Addr: {:#x}", faultVAddr.0);
+                    return Err((Signal::SIGSEGV, SEGV_ACCERR));
+                }
+                if let Err(e) = handled {
+                    error!("cow fault failed! Addr: {:#x}", faultVAddr.0);
+                    return Err(fill_failed_signal(e));
+                }
+                return Ok(());
             }else {
-                //非法!,kail进程
-                error!("PageFault Unhandled! Killed.");
+                //非法!,发 SIGSEGV
+                error!("PageFault Unhandled!");
                 error!("  Addr: {:#x}", faultVAddr.0);
                 error!("  Cause: {:?}", cause.cause());
                 error!("  PTE Flags: {:?}", pte.flags());
@@ -51,8 +75,7 @@ pub fn PageFaultHandler(faultVAddr:VirAddr,cause:Scause){
                 error!("  - Readable: {}", pte.flags().contains(PTEFlags::R));
                 error!("  - Writable: {}", pte.flags().contains(PTEFlags::W));
                 error!("  - Dirty: {}", pte.flags().contains(PTEFlags::D));
-                TASK_MANAER.kail_current_task_and_run_next();
-                return;
+                return Err((Signal::SIGSEGV, SEGV_ACCERR));
             }
         }
         None=>{ // 路不通
@@ -62,41 +85,62 @@ pub fn PageFaultHandler(faultVAddr:VirAddr,cause:Scause){
 
 
     //是否有对应area
-    let inner=TASK_MANAER.task_que_inner.lock();
-    let current=inner.current;
-    drop(inner);
-    let  inner=TASK_MANAER.task_que_inner.lock();
-    // 必须有 area 包含该 vpn，且该 area 是 mmap 区域（memset.areas 中 MapArea.mmap.is_some()）。
-    let will_kill :bool={
-        let memset=&mut inner.task_queen[current].lock().memory_set;
-        !memset.is_mmap_vpn(contain_vpn)
+    // 只拿地址空间的锁：换入时块设备的唤醒会去锁 TCB
+    let memory_set=TASK_MANAER.current_memory_set();
+    // 换出到交换分区的页：换入后直接返回
+    let swapped :Option<Result<(),Errno>>={
+        let memset=&mut *memory_set.lock();
+        if memset.is_swapped_vpn(contain_vpn) && !memset.is_prot_none_vpn(contain_vpn) {
+            Some(memset.swap_in(contain_vpn))
+        }else {
+            None
+        }
+    };
+    match swapped {
+        Some(Ok(()))=>{
+            drop(memory_set);
+            return Ok(());
+        }
+        Some(Err(e))=>{
+            error!("swap in failed! Addr: {:#x}", faultVAddr.0);
+            drop(memory_set);
+            return Err(fill_failed_signal(e));
+        }
+        None=>{}
+    }
+    // 必须有 area 包含该 vpn，且该 area 是 mmap 区域或按需加载的 ELF 段（MapArea.mmap / MapArea.elf is_some()）。
+    // mprotect(PROT_NONE) 的页也不能缺页分配
+    let (no_area, prot_none) ={
+        let memset=&mut *memory_set.lock();
+        (!memset.is_mmap_vpn(contain_vpn) && !memset.is_elf_vpn(contain_vpn), memset.is_prot_none_vpn(contain_vpn))
     }; 
-    if will_kill {
+    if no_area || prot_none {
 
-        //没有area包含mmap的地址，杀掉
-        error!("area not contain mmap addr kill!");
-        drop(inner);//杀任务的话提前drop了
-        TASK_MANAER.kail_current_task_and_run_next();
-        return;
+        //没有area包含mmap/elf的地址，发 SIGSEGV
+        error!("area not contain mmap/elf addr: {:#x}", faultVAddr.0);
+        drop(memory_set);
+        return Err((Signal::SIGSEGV, if no_area { SEGV_MAPERR } else { SEGV_ACCERR }));
     }
     
     debug!("[PageFaultHandler]:ligel!");
 
     
-    {
-        //重新拿锁
-        let memset=&mut inner.task_queen[current].lock().memory_set;
+    //合法，然后
+    //2.分配物理页帧挂载到对应的maparea下面，要读文件的页放开锁读
+    //3.设置合法页表项
+    let filled = MapSet::fill_page(&memory_set, contain_vpn);
 
-        //合法，然后
-        //2.分配物理页帧挂载到对应的maparea下面
-        //3.设置合法页表项
-        //一部到位
-        
-        memset.findarea_allocFrame_and_setPte(contain_vpn);
-    }
+    //返回 释放当前任务的引用
+    drop(memory_set);
+    filled.map_err(fill_failed_signal)
 
-    
-    //返回 释放inner
-    drop(inner);
+}
 
+///缺页补页失败时发的信号：没有页帧了直接杀掉（SIGKILL 不能被捕获），其它是读不出内容，发 SIGBUS
+fn fill_failed_signal(e:Errno)->(Signal,i32){
+    if e == Errno::ENOMEM {
+        (Signal::SIGKILL, SI_KERNEL)
+    } else {
+        (Signal::SIGBUS, BUS_ADRERR)
+    }
 }   
\ No newline at end of file
@@ -12,6 +12,8 @@
 #     pub kernel_sp:usize,//35*8(sp)
 #     ///陷阱处理程序
 #     pub trap_handler:usize,//36*8(sp)
+#     ///所在的hartid
+#     pub hartid:usize,//37*8(sp)
 ######################################################
 
 #针对内核trap处理，先不使用trapcontext的结构体
@@ -53,6 +55,8 @@ sd t2,2*8(sp)#保存usersp
 ld t0,34*8(sp)
 #traphand;er
 ld t1,36*8(sp)
+#用户态的tp已经保存，换回hartid
+ld tp,37*8(sp)
 #app kernel sp
 ld sp,35*8(sp)
 
@@ -0,0 +1,94 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{print, println};
+use user_lib::syscall::{
+    sys_exit, sys_fork, sys_mmap, sys_unmap, sys_waitpid, wexitstatus, wifexited, MmapFlags, MmapProt,
+};
+extern crate user_lib;
+
+/// 写时复制 fork：父子进程一开始共享页帧，谁先写谁拿到自己的副本，另一方看到的内容不变
+/// 数据段（ELF 页）和匿名 mmap 页都要覆盖到
+const PAGE: usize = 4096;
+const PAGES: usize = 16;
+
+static mut DATA: [usize; 1024] = [0; 1024];
+
+fn fill(base: *mut usize, words: usize, seed: usize) {
+    for i in 0..words {
+        unsafe { base.add(i).write_volatile(i ^ seed) };
+    }
+}
+
+fn check(base: *const usize, words: usize, seed: usize) -> bool {
+    (0..words).all(|i| unsafe { base.add(i).read_volatile() } == i ^ seed)
+}
+
+///子进程：先看到 fork 前的内容，改写后看到自己的内容
+fn child(anon: *mut usize, anon_words: usize, data: *mut usize) -> usize {
+    if !check(anon, anon_words, 0x1111) || !check(data, 1024, 0x2222) {
+        println!("[FAIL] child does not see parent data");
+        return 1;
+    }
+    fill(anon, anon_words, 0x3333);
+    fill(data, 1024, 0x4444);
+    if !check(anon, anon_words, 0x3333) || !check(data, 1024, 0x4444) {
+        println!("[FAIL] child writes lost");
+        return 1;
+    }
+    0
+}
+
+#[no_mangle]
+pub fn main() -> usize {
+    let bytes = PAGES * PAGE;
+    let anon = sys_mmap(
+        0,
+        bytes,
+        (MmapProt::READ | MmapProt::WRITE).bits(),
+        (MmapFlags::PRIVATE | MmapFlags::ANONYMOUS).bits(),
+        -1,
+        0,
+    );
+    if anon < 0 {
+        println!("[FAIL] mmap ret={}", anon);
+        return 1;
+    }
+    let anon = anon as *mut usize;
+    let anon_words = bytes / core::mem::size_of::<usize>();
+    let data = unsafe { core::ptr::addr_of_mut!(DATA) as *mut usize };
+    fill(anon, anon_words, 0x1111);
+    fill(data, 1024, 0x2222);
+
+    let mut fail = 0usize;
+    let pid = sys_fork();
+    if pid == 0 {
+        sys_exit(child(anon, anon_words, data));
+    }
+    if pid < 0 {
+        println!("[FAIL] fork ret={}", pid);
+        return 1;
+    }
+    let mut status: isize = 0;
+    let ret = sys_waitpid(&mut status as *mut isize, pid as i32, 0);
+    let status = status as i32;
+    if ret != pid || !wifexited(status) || wexitstatus(status) != 0 {
+        println!("[FAIL] child {} ret={} status={:#x}", pid, ret, status);
+        fail += 1;
+    }
+    // 子进程的写不能漏到父进程
+    if !check(anon, anon_words, 0x1111) || !check(data, 1024, 0x2222) {
+        println!("[FAIL] child writes leaked into parent");
+        fail += 1;
+    }
+    // 子进程退出后父进程独占这些页，照样能写
+    fill(anon, anon_words, 0x5555);
+    if !check(anon, anon_words, 0x5555) {
+        println!("[FAIL] parent write after child exit");
+        fail += 1;
+    }
+    sys_unmap(anon as usize, bytes);
+
+    println!("==== cow test done: fail={} ====", fail);
+    if fail == 0 { 0 } else { 1 }
+}
@@ -0,0 +1,162 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicI32, Ordering};
+
+use user_lib::{print, println};
+use user_lib::syscall::{
+    sys_exit, sys_fork, sys_futex, sys_mmap, sys_unmap, sys_waitpid, sys_yield, wexitstatus, wifexited, MmapFlags,
+    MmapProt, TimeSpec, FUTEX_CMP_REQUEUE, FUTEX_PRIVATE_FLAG, FUTEX_WAIT, FUTEX_WAIT_BITSET, FUTEX_WAKE,
+    FUTEX_WAKE_BITSET,
+};
+extern crate user_lib;
+
+/// futex：值不符返回 EAGAIN，超时返回 ETIMEDOUT，没人等时 wake 返回 0；
+/// 两个子进程在 MAP_SHARED 页上的 A 等待，父进程 CMP_REQUEUE 到 B 再唤醒 B，两个都要醒来退出
+const PAGE: usize = 4096;
+const EAGAIN: isize = 11;
+const ETIMEDOUT: isize = 110;
+const WAITERS: usize = 2;
+const RETRIES: usize = 100_000;
+
+static PRIVATE_WORD: AtomicI32 = AtomicI32::new(0);
+
+struct Shared {
+    a: AtomicI32,
+    b: AtomicI32,
+    ready: AtomicI32,
+}
+
+fn private_cases() -> usize {
+    let mut fail = 0;
+    let word = &PRIVATE_WORD as *const AtomicI32 as *const i32;
+    let null = core::ptr::null();
+
+    PRIVATE_WORD.store(1, Ordering::Relaxed);
+    let ret = sys_futex(word, FUTEX_WAIT | FUTEX_PRIVATE_FLAG, 0, 0, null, 0);
+    if ret != -EAGAIN {
+        println!("[FAIL] wait on changed value ret={} expect EAGAIN", ret);
+        fail += 1;
+    }
+
+    PRIVATE_WORD.store(0, Ordering::Relaxed);
+    let ts = TimeSpec::from_ms(30);
+    let ret = sys_futex(word, FUTEX_WAIT | FUTEX_PRIVATE_FLAG, 0, &ts as *const TimeSpec as usize, null, 0);
+    if ret != -ETIMEDOUT {
+        println!("[FAIL] timed wait ret={} expect ETIMEDOUT", ret);
+        fail += 1;
+    }
+    // WAIT_BITSET 的超时是绝对时间，0 秒早就过了
+    let ts = TimeSpec::default();
+    let ret = sys_futex(word, FUTEX_WAIT_BITSET | FUTEX_PRIVATE_FLAG, 0, &ts as *const TimeSpec as usize, null, usize::MAX);
+    if ret != -ETIMEDOUT {
+        println!("[FAIL] bitset wait with past deadline ret={} expect ETIMEDOUT", ret);
+        fail += 1;
+    }
+
+    let ret = sys_futex(word, FUTEX_WAKE | FUTEX_PRIVATE_FLAG, 1, 0, null, 0);
+    let ret2 = sys_futex(word, FUTEX_WAKE_BITSET | FUTEX_PRIVATE_FLAG, 1, 0, null, usize::MAX);
+    if ret != 0 || ret2 != 0 {
+        println!("[FAIL] wake with no waiters ret={} bitset ret={}", ret, ret2);
+        fail += 1;
+    }
+    fail
+}
+
+///子进程：A 为 0 时一直等
+fn waiter(shared: &Shared) -> ! {
+    shared.ready.fetch_add(1, Ordering::SeqCst);
+    let a = &shared.a as *const AtomicI32 as *const i32;
+    while shared.a.load(Ordering::SeqCst) == 0 {
+        sys_futex(a, FUTEX_WAIT, 0, 0, core::ptr::null(), 0);
+    }
+    sys_exit(0);
+}
+
+///反复做 op 直到累计结果到 want，等待者可能还没进内核
+fn repeat_until(want: isize, mut op: impl FnMut() -> isize) -> isize {
+    let mut total = 0;
+    for _ in 0..RETRIES {
+        let ret = op();
+        if ret < 0 {
+            return ret;
+        }
+        total += ret;
+        if total >= want {
+            break;
+        }
+        sys_yield();
+    }
+    total
+}
+
+fn shared_requeue_case() -> usize {
+    let page = sys_mmap(
+        0,
+        PAGE,
+        (MmapProt::READ | MmapProt::WRITE).bits(),
+        (MmapFlags::SHARED | MmapFlags::ANONYMOUS).bits(),
+        -1,
+        0,
+    );
+    if page < 0 {
+        println!("[FAIL] mmap shared ret={}", page);
+        return 1;
+    }
+    let shared = unsafe { &*(page as *const Shared) };
+    let a = &shared.a as *const AtomicI32 as *const i32;
+    let b = &shared.b as *const AtomicI32 as *const i32;
+    let mut fail = 0;
+
+    let mut pids = [0isize; WAITERS];
+    for pid in pids.iter_mut() {
+        *pid = sys_fork();
+        if *pid == 0 {
+            waiter(shared);
+        }
+    }
+    while shared.ready.load(Ordering::SeqCst) < WAITERS as i32 {
+        sys_yield();
+    }
+
+    // 不唤醒，全部挪到 B 上；A 的值不是 0 时 CMP_REQUEUE 要返回 EAGAIN
+    let moved = repeat_until(WAITERS as isize, || sys_futex(a, FUTEX_CMP_REQUEUE, 0, WAITERS, b, 0));
+    if moved != WAITERS as isize {
+        println!("[FAIL] requeue moved {} expect {}", moved, WAITERS);
+        fail += 1;
+    }
+    if sys_futex(a, FUTEX_CMP_REQUEUE, 0, WAITERS, b, 1) != -EAGAIN {
+        println!("[FAIL] CMP_REQUEUE with stale value should be EAGAIN");
+        fail += 1;
+    }
+    // 挪走之后唤醒 A 没有人
+    if sys_futex(a, FUTEX_WAKE, WAITERS, 0, core::ptr::null(), 0) != 0 {
+        println!("[FAIL] waiters still queued on A after requeue");
+        fail += 1;
+    }
+    shared.a.store(1, Ordering::SeqCst);
+    let woken = repeat_until(WAITERS as isize, || sys_futex(b, FUTEX_WAKE, WAITERS, 0, core::ptr::null(), 0));
+    if woken != WAITERS as isize {
+        println!("[FAIL] wake on B woke {} expect {}", woken, WAITERS);
+        fail += 1;
+    }
+
+    for &pid in pids.iter() {
+        let mut status: isize = 0;
+        let ret = sys_waitpid(&mut status as *mut isize, pid as i32, 0);
+        let status = status as i32;
+        if pid < 0 || ret != pid || !wifexited(status) || wexitstatus(status) != 0 {
+            println!("[FAIL] waiter pid={} ret={} status={:#x}", pid, ret, status);
+            fail += 1;
+        }
+    }
+    sys_unmap(page as usize, PAGE);
+    fail
+}
+
+#[no_mangle]
+pub fn main() -> usize {
+    let fail = private_cases() + shared_requeue_case();
+    println!("==== futex test done: fail={} ====", fail);
+    if fail == 0 { 0 } else { 1 }
+}
@@ -3,9 +3,9 @@
 
 use core::usize;
 extern crate alloc;
-use user_lib::{String, getchar, print, println,sys_waitpid};
+use user_lib::{String, print, println,sys_waitpid};
 use crate::alloc::string::ToString;
-use user_lib::sys_wait;
+use user_lib::{sys_signal, SIG_IGN, SIGINT, SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU};
 extern crate user_lib;
 
 
@@ -24,171 +24,173 @@ mod ui {
 }
 
 mod console {
-    use alloc::{string::ToString, vec::Vec};
-    use user_lib::{String, getchar, print};
-
-    static mut COMMAND_BUFFER: Option<Vec<String>> = None;
-    static mut HISTORY_CURSOR: usize = 0;
-    static mut TAB_TRIGGERED: bool = false;
-
-    fn ensure_history() {
-        unsafe {
-            if COMMAND_BUFFER.is_none() {
-                COMMAND_BUFFER = Some(Vec::new());
-                HISTORY_CURSOR = 0;
-            }
-        }
-    }
-
-    pub fn history_push(cmd: String) {
-        ensure_history();
-        let cmd = cmd.trim().to_string();
-        if cmd.is_empty() {
-            return;
-        }
-        unsafe {
-            if let Some(buf) = COMMAND_BUFFER.as_mut() {
-                buf.push(cmd);
-                HISTORY_CURSOR = buf.len();
-            }
-        }
-    }
-
-    fn history_set_cursor_to_end() {
-        ensure_history();
-        unsafe {
-            if let Some(buf) = COMMAND_BUFFER.as_ref() {
-                HISTORY_CURSOR = buf.len();
-            }
-        }
-    }
+    use alloc::vec::Vec;
+    use user_lib::{String, readline};
 
-    fn history_select_prev() -> Option<String> {
-        ensure_history();
-        unsafe {
-            let buf = COMMAND_BUFFER.as_ref()?;
-            if buf.is_empty() {
-                return None;
+    /// 从终端读一行，回显、退格和 ^C 由内核 TTY 行规程处理
+    /// 返回 None 表示行首按了 ^D（文件结束）
+    pub fn read_line() -> Option<String> {
+        let mut line: Vec<u8> = Vec::new();
+        let mut buf = [0u8; 128];
+        loop {
+            let n = readline(buf.as_mut_ptr() as usize, buf.len());
+            if n <= 0 {
+                // ^D 或者被信号打断，已经读到的部分当作一行
+                if line.is_empty() {
+                    return None;
+                }
+                break;
             }
-            if HISTORY_CURSOR == 0 {
-                return Some(buf[0].clone());
+            line.extend_from_slice(&buf[..n as usize]);
+            if line.last() == Some(&b'\n') {
+                line.pop();
+                break;
             }
-            HISTORY_CURSOR -= 1;
-            Some(buf[HISTORY_CURSOR].clone())
         }
+        Some(String::from_utf8_lossy(&line).into_owned())
     }
+}
 
-    fn history_select_next() -> Option<String> {
-        ensure_history();
-        unsafe {
-            let buf = COMMAND_BUFFER.as_ref()?;
-            if buf.is_empty() {
-                return None;
-            }
-            if HISTORY_CURSOR >= buf.len() {
-                return Some(String::new());
-            }
-            HISTORY_CURSOR += 1;
-            if HISTORY_CURSOR >= buf.len() {
-                Some(String::new())
-            } else {
-                Some(buf[HISTORY_CURSOR].clone())
-            }
-        }
+/// 作业控制：每个外部命令放进自己的进程组，前台作业占着终端，^Z 停下来的和 & 启动的记在作业表里
+mod jobs {
+    use alloc::vec::Vec;
+    use spin::Mutex;
+    use user_lib::{
+        String, println, sys_getpgid, sys_kill, sys_waitpid, tcsetpgrp, wifcontinued, wifexited, wifsignaled,
+        wifstopped, wtermsig, SIGCONT, WCONTINUED, WNOHANG, WUNTRACED,
+    };
+
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum JobState {
+        Running,
+        Stopped,
     }
 
-    fn is_eol(c: u8) -> bool {
-        c == b'\r' || c == b'\n'
+    pub struct Job {
+        pub id: usize,
+        /// 单个命令的作业，进程组号就是它的 pid
+        pub pgid: isize,
+        pub state: JobState,
+        pub cmd: String,
     }
 
-    fn is_backspace(c: u8) -> bool {
-        c == 8u8 || c == 127u8
-    }
+    static JOBS: Mutex<Vec<Job>> = Mutex::new(Vec::new());
 
-    fn is_tab(c: u8) -> bool {
-        c == b'\t'
+    ///记一个作业，编号取最小的空闲号
+    pub fn add(pgid: isize, state: JobState, cmd: &str) -> usize {
+        let mut jobs = JOBS.lock();
+        let mut id = 1;
+        while jobs.iter().any(|j| j.id == id) {
+            id += 1;
+        }
+        jobs.push(Job { id, pgid, state, cmd: String::from(cmd) });
+        id
     }
 
-    pub fn take_tab_triggered() -> bool {
-        unsafe {
-            let v = TAB_TRIGGERED;
-            TAB_TRIGGERED = false;
-            v
-        }
+    ///按编号（%n 或 n）取出作业，不给编号时取最近的一个
+    fn take(arg: Option<&str>) -> Option<Job> {
+        let mut jobs = JOBS.lock();
+        let idx = match arg {
+            Some(a) => {
+                let id: usize = a.trim_start_matches('%').parse().ok()?;
+                jobs.iter().position(|j| j.id == id)?
+            }
+            None => jobs.len().checked_sub(1)?,
+        };
+        Some(jobs.remove(idx))
     }
 
-    fn clear_current_input(len: usize) {
-        for _ in 0..len {
-            print!("\x08 \x08");
+    fn print_signaled(status: i32) {
+        let sig = wtermsig(status);
+        // ^C 杀掉的前台作业不用再提示
+        if sig != user_lib::SIGINT as i32 {
+            println!("Terminated by signal {}", sig);
         }
     }
 
-    fn try_read_arrow(c: u8) -> Option<u8> {
-        if c != 0x1b {
-            return None;
+    ///前台运行：把终端交给作业的进程组，等它退出或者停下，再把终端要回来
+    pub fn wait_foreground(pgid: isize, cmd: &str) {
+        let shell_pgid = sys_getpgid(0);
+        tcsetpgrp(0, pgid);
+        let mut status: isize = 0;
+        let waited = sys_waitpid(&mut status as *mut isize, pgid as i32, WUNTRACED);
+        tcsetpgrp(0, shell_pgid);
+        if waited < 0 {
+            println!("wait failed, ret={}", waited);
+            return;
         }
-        let c1 = getchar() as u8;
-        if c1 != b'[' {
-            return None;
+        let status = status as i32;
+        if wifstopped(status) {
+            let id = add(pgid, JobState::Stopped, cmd);
+            println!("[{}]+  Stopped                 {}", id, cmd);
+        } else if wifsignaled(status) {
+            print_signaled(status);
         }
-        let c2 = getchar() as u8;
-        Some(c2)
     }
 
-    pub fn read_line() -> String {
-        history_set_cursor_to_end();
-        let mut line = String::new();
+    ///不阻塞地回收已经结束的子进程，更新作业状态；过继给 init 的遗孤也在这里回收
+    pub fn reap() {
         loop {
-            let c = getchar() as u8;
-            if is_tab(c) {
-                unsafe {
-                    TAB_TRIGGERED = true;
-                }
+            let mut status: isize = 0;
+            let pid = sys_waitpid(&mut status as *mut isize, -1, WNOHANG | WUNTRACED | WCONTINUED);
+            if pid <= 0 {
                 break;
             }
-            if let Some(code) = try_read_arrow(c) {
-                if code == b'A' {
-                    if let Some(sel) = history_select_prev() {
-                        clear_current_input(line.len());
-                        line = sel;
-                        print!("{}", line);
-                    }
-                    continue;
-                }
-                if code == b'B' {
-                    if let Some(sel) = history_select_next() {
-                        clear_current_input(line.len());
-                        line = sel;
-                        print!("{}", line);
-                    }
-                    continue;
-                }
-            }
-            if is_eol(c) {
-                print!("\n");
-                break;
-            }
-
-            if is_backspace(c) {
-                if !line.is_empty() {
-                    line.pop();
-                    print!("\x08 \x08");
-                }
+            let status = status as i32;
+            let mut jobs = JOBS.lock();
+            let Some(idx) = jobs.iter().position(|j| j.pgid == pid) else {
                 continue;
+            };
+            if wifstopped(status) {
+                jobs[idx].state = JobState::Stopped;
+            } else if wifcontinued(status) {
+                jobs[idx].state = JobState::Running;
+            } else if wifexited(status) || wifsignaled(status) {
+                let job = jobs.remove(idx);
+                println!("[{}]   Done                    {}", job.id, job.cmd);
             }
+        }
+    }
 
-            if c.is_ascii_graphic() || c == b' ' {
-                line.push(c as char);
-                print!("{}", c as char);
-            }
+    pub fn list() {
+        for job in JOBS.lock().iter() {
+            let state = match job.state {
+                JobState::Running => "Running",
+                JobState::Stopped => "Stopped",
+            };
+            println!("[{}]   {:<24}{}", job.id, state, job.cmd);
         }
-        line
+    }
+
+    ///fg [%n]：继续作业并放到前台
+    pub fn fg(arg: Option<&str>) {
+        let Some(job) = take(arg) else {
+            println!("fg: no such job");
+            return;
+        };
+        println!("{}", job.cmd);
+        tcsetpgrp(0, job.pgid);
+        sys_kill(-job.pgid, SIGCONT);
+        wait_foreground(job.pgid, &job.cmd);
+    }
+
+    ///bg [%n]：让停下来的作业在后台继续
+    pub fn bg(arg: Option<&str>) {
+        let Some(mut job) = take(arg) else {
+            println!("bg: no such job");
+            return;
+        };
+        sys_kill(-job.pgid, SIGCONT);
+        job.state = JobState::Running;
+        println!("[{}]+ {} &", job.id, job.cmd);
+        JOBS.lock().push(job);
     }
 }
 
 mod command {
-    use user_lib::{String, chdir, getcwd, print, println, sys_exec_args, sys_exit, sys_fork, sys_wait, sys_waitpid};
+    use user_lib::{String, chdir, getcwd, print, println, sys_exec_args, sys_exit, sys_fork, sys_setpgid, sys_signal, SIG_DFL, SIGINT, SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU};
     use alloc::vec::Vec;
+    use crate::jobs::{self, JobState};
     fn clear_screen() {
         // ANSI: clear screen + move cursor to home
         print!("\x1b[2J\x1b[H");
@@ -205,10 +207,14 @@ mod command {
         println!("  mkdir       Run /test/mkdir");
         println!("  rm          Run /test/rm");
         println!("  cat         Run /test/cat");
+        println!("  <cmd> &     Run <cmd> in the background");
+        println!("  jobs        List background and stopped jobs");
+        println!("  fg [%n]     Continue job n in the foreground");
+        println!("  bg [%n]     Continue stopped job n in the background");
         println!("  exit        Exit init");
     }
 
-    fn run_bin(path: &str, argv0: &str, args: &[&str]) {
+    fn run_bin(path: &str, argv0: &str, args: &[&str], background: bool) {
         let mut path = String::from(path);
         path.push('\0');
 
@@ -228,6 +234,11 @@ mod command {
 
         let pid = sys_fork();
         if pid == 0 {
+            // 自己一个进程组，shell 忽略的作业控制信号恢复默认
+            sys_setpgid(0, 0);
+            for sig in [SIGINT, SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU] {
+                sys_signal(sig, SIG_DFL);
+            }
             let ret = sys_exec_args(&path, argv_ptrs.as_ptr());
             println!("exec failed, ret={};", ret);
             sys_exit(1);
@@ -236,19 +247,25 @@ mod command {
             println!("fork failed, ret={}", pid);
             return;
         }
-        let mut code: isize = 0;
-        //println!("I will wait ");
-        let waited = sys_waitpid(&mut code as *mut isize,pid as i32,0);
-        //println!("wait after");
-        if waited < 0 {
-            println!("wait failed, ret={}", waited);
+        // 父子都设一次，谁先跑都不影响后面的 tcsetpgrp
+        sys_setpgid(pid, pid);
+        let mut cmdline = String::from(argv0);
+        for a in args.iter() {
+            cmdline.push(' ');
+            cmdline.push_str(a);
+        }
+        if background {
+            let id = jobs::add(pid, JobState::Running, &cmdline);
+            println!("[{}] {}", id, pid);
+        } else {
+            jobs::wait_foreground(pid, &cmdline);
         }
     }
 
-    fn run_test_bin(name: &str, args: &[&str]) {
+    fn run_test_bin(name: &str, args: &[&str], background: bool) {
         let mut path = String::from("/test/");
         path.push_str(name);
-        run_bin(&path, name, args);
+        run_bin(&path, name, args, background);
     }
 
     pub fn handle_line(line: String) {
@@ -262,22 +279,49 @@ mod command {
             Some(c) => c,
             None => return,
         };
-        let rest: Vec<&str> = parts.collect();
+        let mut rest: Vec<&str> = parts.collect();
+        // 结尾的 & 放到后台运行
+        let mut cmd = cmd;
+        let background = if rest.last() == Some(&"&") {
+            rest.pop();
+            true
+        } else if let Some(c) = cmd.strip_suffix('&').filter(|_| rest.is_empty()) {
+            cmd = c;
+            true
+        } else {
+            false
+        };
 
         if cmd == "ls" {
-            run_test_bin("ls", &rest);
+            run_test_bin("ls", &rest, background);
             return;
         }
         if cmd == "mkdir" {
-            run_test_bin("mkdir", &rest);
+            run_test_bin("mkdir", &rest, background);
             return;
         }
         if cmd == "rm" {
-            run_test_bin("rm", &rest);
+            run_test_bin("rm", &rest, background);
             return;
         }
         if cmd == "cat" {
-            run_test_bin("cat", &rest);
+            run_test_bin("cat", &rest, background);
+            return;
+        }
+
+        if cmd == "jobs" {
+            jobs::reap();
+            jobs::list();
+            return;
+        }
+
+        if cmd == "fg" {
+            jobs::fg(rest.first().copied());
+            return;
+        }
+
+        if cmd == "bg" {
+            jobs::bg(rest.first().copied());
             return;
         }
 
@@ -343,35 +387,30 @@ mod command {
                 path.push('/');
             }
             path.push_str(prog);
-            run_bin(&path, prog, &rest);
+            run_bin(&path, prog, &rest, background);
             return;
         }
 
-        run_test_bin(cmd, &rest);
-    }
-
-    pub fn handle_tab(_line: String) {
-        //ls获取当前目录获取所有条目然后分割成vec
-        //分割当前已经输入的命令，以最后一个为准，使用withprefix匹配条目并且补全。如果有多个匹配就选取最后一个
+        run_test_bin(cmd, &rest, background);
     }
 }
 
 
 #[no_mangle]
 pub fn main(){
-    let mut exit_code = -1;
     ui::banner();
+    // shell 自己不被 ^C/^Z 打断，把终端交还给自己时也不会因为在后台收到 SIGTTOU
+    for sig in [SIGINT, SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU] {
+        sys_signal(sig, SIG_IGN);
+    }
     loop {
         // 回收fork后的遗孤 不要再现4小时修复僵尸错位的时序Bug了😭
-        sys_wait(&mut exit_code);
+        jobs::reap();
         
         ui::prompt();
-        let line = console::read_line();
-        if console::take_tab_triggered() {
-            command::handle_tab(line);
-            continue;
+        match console::read_line() {
+            Some(line) => command::handle_line(line),
+            None => println!(""),
         }
-        console::history_push(line.clone());
-        command::handle_line(line);
     }
 }
@@ -0,0 +1,125 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use user_lib::{print, println};
+use user_lib::syscall::{
+    sys_close, sys_exit, sys_fork, sys_mmap, sys_open, sys_read, sys_unlink, sys_unmap, sys_waitpid, sys_write,
+    sys_yield, wexitstatus, wifexited, MmapFlags, MmapProt, O_CREAT, O_RDONLY, O_TRUNC, O_WRONLY,
+};
+extern crate user_lib;
+
+/// 块设备读写期间别的任务要能运行：在单 hart 下（make run）跑才有意义
+/// 子进程在共享页上不停计数，父进程一次 read 读一个比页缓存大得多的磁盘文件，
+/// read 返回时计数必须涨过，说明父进程等 I/O 时让出了 CPU 而不是在内核里轮询
+const PAGE: usize = 4096;
+const FILE_PATH: &str = "/io_sleep_test.dat";
+const FILE_BYTES: usize = 4 * 1024 * 1024;
+const READ_BYTES: usize = 2 * 1024 * 1024;
+const CHUNK: usize = 64 * 1024;
+
+fn map_anon(bytes: usize, shared: bool) -> isize {
+    let share = if shared { MmapFlags::SHARED } else { MmapFlags::PRIVATE };
+    sys_mmap(
+        0,
+        bytes,
+        (MmapProt::READ | MmapProt::WRITE).bits(),
+        (share | MmapFlags::ANONYMOUS).bits(),
+        -1,
+        0,
+    )
+}
+
+///写一个 FILE_BYTES 的文件，成功返回 true
+fn make_file(buf: &mut [u8]) -> bool {
+    let fd = sys_open(FILE_PATH, O_WRONLY | O_CREAT | O_TRUNC);
+    if fd < 0 {
+        println!("[FAIL] open {} ret={}", FILE_PATH, fd);
+        return false;
+    }
+    let mut ok = true;
+    for i in 0..FILE_BYTES / CHUNK {
+        buf[..CHUNK].fill(i as u8);
+        let ret = sys_write(fd as usize, buf.as_ptr() as usize, CHUNK);
+        if ret != CHUNK as isize {
+            println!("[FAIL] write chunk {} ret={}", i, ret);
+            ok = false;
+            break;
+        }
+    }
+    sys_close(fd as usize);
+    ok
+}
+
+#[no_mangle]
+pub fn main() -> usize {
+    let shared = map_anon(PAGE, true);
+    let data = map_anon(READ_BYTES, false);
+    if shared < 0 || data < 0 {
+        println!("[FAIL] mmap shared={} data={}", shared, data);
+        return 1;
+    }
+    let counter = unsafe { &*(shared as *const AtomicUsize) };
+    let stop = unsafe { &*((shared as usize + 64) as *const AtomicUsize) };
+    let buf = unsafe { core::slice::from_raw_parts_mut(data as *mut u8, READ_BYTES) };
+
+    if !make_file(buf) {
+        return 1;
+    }
+
+    let pid = sys_fork();
+    if pid == 0 {
+        while stop.load(Ordering::Relaxed) == 0 {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        sys_exit(0);
+    }
+    if pid < 0 {
+        println!("[FAIL] fork ret={}", pid);
+        return 1;
+    }
+    // 等子进程真正跑起来
+    while counter.load(Ordering::Relaxed) == 0 {
+        sys_yield();
+    }
+
+    let mut fail = 0usize;
+    let fd = sys_open(FILE_PATH, O_RDONLY);
+    if fd < 0 {
+        println!("[FAIL] reopen ret={}", fd);
+        fail += 1;
+    } else {
+        let before = counter.load(Ordering::Relaxed);
+        let ret = sys_read(fd as usize, buf.as_mut_ptr() as usize, READ_BYTES);
+        let after = counter.load(Ordering::Relaxed);
+        sys_close(fd as usize);
+        if ret != READ_BYTES as isize {
+            println!("[FAIL] read ret={}", ret);
+            fail += 1;
+        } else if (0..READ_BYTES).step_by(CHUNK).any(|off| buf[off] != (off / CHUNK) as u8) {
+            println!("[FAIL] read back wrong data");
+            fail += 1;
+        } else if after == before {
+            println!("[FAIL] child made no progress during read");
+            fail += 1;
+        } else {
+            println!("[PASS] child counted {} during a {} KiB read", after - before, READ_BYTES >> 10);
+        }
+    }
+
+    stop.store(1, Ordering::Relaxed);
+    let mut status: isize = 0;
+    let ret = sys_waitpid(&mut status as *mut isize, pid as i32, 0);
+    let status = status as i32;
+    if ret != pid || !wifexited(status) || wexitstatus(status) != 0 {
+        println!("[FAIL] child {} ret={} status={:#x}", pid, ret, status);
+        fail += 1;
+    }
+    sys_unlink(FILE_PATH);
+    sys_unmap(data as usize, READ_BYTES);
+    sys_unmap(shared as usize, PAGE);
+
+    println!("==== io sleep test done: fail={} ====", fail);
+    if fail == 0 { 0 } else { 1 }
+}
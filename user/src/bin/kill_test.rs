@@ -0,0 +1,165 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+
+use user_lib::{print, println};
+use user_lib::syscall::{
+    sys_exit, sys_fork, sys_getpid, sys_gettid, sys_kill, sys_setpgid, sys_sigaction, sys_tgkill, sys_tkill,
+    sys_waitpid, sys_yield, wexitstatus, wifexited, wifsignaled, wtermsig, SA_RESTART, SA_SIGINFO, SIGCHLD, SIGKILL,
+    SIGTERM, SIGUSR1, SIG_DFL,
+};
+extern crate user_lib;
+
+/// kill/tkill/tgkill：SIGTERM 杀死子进程，wait 看到 WIFSIGNALED；子进程退出时父进程收到带 si_pid 的 SIGCHLD；
+/// kill(-pgid) 杀掉整个进程组；tkill/tgkill 发给自己的线程会运行处理函数，线程组不对返回 ESRCH
+const ESRCH: isize = 3;
+const CLD_EXITED: i32 = 1;
+const GROUP: usize = 2;
+/// 不会有这么大的 pid
+const NO_SUCH_PID: isize = 0x7fff_0000;
+
+static CHLD_COUNT: AtomicUsize = AtomicUsize::new(0);
+static CHLD_PID: AtomicI32 = AtomicI32::new(0);
+static CHLD_CODE: AtomicI32 = AtomicI32::new(0);
+static USR1_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn on_sigchld(_sig: usize, info: *const i32, _uc: usize) {
+    // si_code 在第 3 个 int，si_pid 在 16 字节处
+    unsafe {
+        CHLD_CODE.store(info.add(2).read(), Ordering::SeqCst);
+        CHLD_PID.store(info.add(4).read(), Ordering::SeqCst);
+    }
+    CHLD_COUNT.fetch_add(1, Ordering::SeqCst);
+}
+
+extern "C" fn on_sigusr1(_sig: usize) {
+    USR1_COUNT.fetch_add(1, Ordering::SeqCst);
+}
+
+fn spin_forever() -> ! {
+    loop {
+        sys_yield();
+    }
+}
+
+///等 pid 结束，返回 wait 的状态字
+fn reap(pid: isize) -> Option<i32> {
+    let mut status: isize = 0;
+    let ret = sys_waitpid(&mut status as *mut isize, pid as i32, 0);
+    if pid < 0 || ret != pid { None } else { Some(status as i32) }
+}
+
+fn expect_killed_by(tag: &str, pid: isize, sig: usize) -> bool {
+    match reap(pid) {
+        Some(status) if wifsignaled(status) && wtermsig(status) == sig as i32 => true,
+        status => {
+            println!("[FAIL] {}: pid={} status={:?}", tag, pid, status);
+            false
+        }
+    }
+}
+
+fn sigterm_case() -> usize {
+    let pid = sys_fork();
+    if pid == 0 {
+        spin_forever();
+    }
+    if sys_kill(pid, SIGTERM) != 0 || !expect_killed_by("SIGTERM child", pid, SIGTERM) {
+        return 1;
+    }
+    0
+}
+
+///子进程正常退出，父进程的 SIGCHLD 处理函数看到它的 pid；wait4 照样回收
+fn sigchld_case() -> usize {
+    let mut fail = 0;
+    sys_sigaction(SIGCHLD, on_sigchld as usize, SA_SIGINFO | SA_RESTART, 0);
+    let pid = sys_fork();
+    if pid == 0 {
+        sys_exit(7);
+    }
+    match reap(pid) {
+        Some(status) if wifexited(status) && wexitstatus(status) == 7 => {}
+        status => {
+            println!("[FAIL] SIGCHLD child pid={} status={:?}", pid, status);
+            fail += 1;
+        }
+    }
+    if CHLD_COUNT.load(Ordering::SeqCst) != 1
+        || CHLD_PID.load(Ordering::SeqCst) as isize != pid
+        || CHLD_CODE.load(Ordering::SeqCst) != CLD_EXITED
+    {
+        println!(
+            "[FAIL] SIGCHLD count={} si_pid={} si_code={} expect pid={}",
+            CHLD_COUNT.load(Ordering::SeqCst),
+            CHLD_PID.load(Ordering::SeqCst),
+            CHLD_CODE.load(Ordering::SeqCst),
+            pid
+        );
+        fail += 1;
+    }
+    sys_sigaction(SIGCHLD, SIG_DFL, 0, 0);
+    fail
+}
+
+///两个子进程放进以第一个子进程为组长的进程组，kill(-pgid) 一起杀掉，父进程不受影响
+fn group_case() -> usize {
+    let mut fail = 0;
+    let mut pids = [0isize; GROUP];
+    for pid in pids.iter_mut() {
+        *pid = sys_fork();
+        if *pid == 0 {
+            spin_forever();
+        }
+    }
+    let pgid = pids[0];
+    for &pid in pids.iter() {
+        if sys_setpgid(pid, pgid) != 0 {
+            println!("[FAIL] setpgid({}, {})", pid, pgid);
+            fail += 1;
+        }
+    }
+    if sys_kill(-pgid, SIGKILL) != 0 {
+        println!("[FAIL] kill(-{}) failed", pgid);
+        fail += 1;
+    }
+    for &pid in pids.iter() {
+        if !expect_killed_by("process group member", pid, SIGKILL) {
+            fail += 1;
+        }
+    }
+    if sys_kill(-pgid, SIGKILL) != -ESRCH {
+        println!("[FAIL] kill on empty process group should be ESRCH");
+        fail += 1;
+    }
+    fail
+}
+
+fn thread_cases() -> usize {
+    let mut fail = 0;
+    let (pid, tid) = (sys_getpid(), sys_gettid());
+    sys_sigaction(SIGUSR1, on_sigusr1 as usize, 0, 0);
+    if sys_tgkill(pid, tid, SIGUSR1) != 0 || sys_tkill(tid, SIGUSR1) != 0 || USR1_COUNT.load(Ordering::SeqCst) != 2 {
+        println!("[FAIL] tkill/tgkill to self handled {} times", USR1_COUNT.load(Ordering::SeqCst));
+        fail += 1;
+    }
+    if sys_tgkill(NO_SUCH_PID, tid, SIGUSR1) != -ESRCH || USR1_COUNT.load(Ordering::SeqCst) != 2 {
+        println!("[FAIL] tgkill with wrong tgid should be ESRCH");
+        fail += 1;
+    }
+    // 信号 0 只检查目标在不在
+    if sys_kill(pid, 0) != 0 || sys_kill(NO_SUCH_PID, 0) != -ESRCH {
+        println!("[FAIL] kill with signal 0");
+        fail += 1;
+    }
+    sys_sigaction(SIGUSR1, SIG_DFL, 0, 0);
+    fail
+}
+
+#[no_mangle]
+pub fn main() -> usize {
+    let fail = sigterm_case() + sigchld_case() + group_case() + thread_cases();
+    println!("==== kill test done: fail={} ====", fail);
+    if fail == 0 { 0 } else { 1 }
+}
@@ -0,0 +1,133 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{print, println};
+use user_lib::syscall::{
+    sys_close, sys_link, sys_lstat, sys_mkdir, sys_open, sys_read, sys_readlink, sys_rmdir, sys_stat, sys_symlink,
+    sys_unlink, sys_write, KStat, O_CREAT, O_RDONLY, O_TRUNC, O_WRONLY,
+};
+extern crate user_lib;
+
+/// 硬链接和符号链接：link 之后两个名字是同一个 inode，st_nlink 跟着增减，删掉一个名字另一个照常读；
+/// 符号链接能 readlink、能跟随（包括相对路径和中间一级是链接的目录），lstat 看到 S_IFLNK；
+/// 互相指向的链接返回 ELOOP，给目录建硬链接返回 EPERM
+const EPERM: isize = 1;
+const ELOOP: isize = 40;
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+const CONTENT: &[u8] = b"link test";
+
+fn write_file(path: &str, data: &[u8]) -> bool {
+    let fd = sys_open(path, O_WRONLY | O_CREAT | O_TRUNC);
+    if fd < 0 {
+        return false;
+    }
+    let n = sys_write(fd as usize, data.as_ptr() as usize, data.len());
+    sys_close(fd as usize);
+    n == data.len() as isize
+}
+
+///读出来和 CONTENT 一样；打开失败时返回 open 的错误码
+fn read_matches(path: &str) -> Result<bool, isize> {
+    let fd = sys_open(path, O_RDONLY);
+    if fd < 0 {
+        return Err(fd);
+    }
+    let mut buf = [0u8; 32];
+    let n = sys_read(fd as usize, buf.as_mut_ptr() as usize, buf.len());
+    sys_close(fd as usize);
+    Ok(n == CONTENT.len() as isize && &buf[..CONTENT.len()] == CONTENT)
+}
+
+fn stat(path: &str) -> Option<KStat> {
+    let mut st = KStat::default();
+    if sys_stat(path, &mut st) < 0 { None } else { Some(st) }
+}
+
+fn hard_link_cases() -> usize {
+    let mut fail = 0;
+    if !write_file("/lt_a", CONTENT) || sys_link("/lt_a", "/lt_b") != 0 {
+        println!("[FAIL] create /lt_a and link it to /lt_b");
+        return 1;
+    }
+    match (stat("/lt_a"), stat("/lt_b")) {
+        (Some(a), Some(b)) if a.st_ino == b.st_ino && a.st_nlink == 2 && b.st_nlink == 2 => {}
+        (a, b) => {
+            println!("[FAIL] after link: {:?} / {:?}", a.map(|s| (s.st_ino, s.st_nlink)), b.map(|s| (s.st_ino, s.st_nlink)));
+            fail += 1;
+        }
+    }
+    if read_matches("/lt_b") != Ok(true) {
+        println!("[FAIL] read through the second name");
+        fail += 1;
+    }
+    // 删掉原来的名字，数据还在
+    sys_unlink("/lt_a");
+    if stat("/lt_a").is_some() || stat("/lt_b").map(|s| s.st_nlink) != Some(1) || read_matches("/lt_b") != Ok(true) {
+        println!("[FAIL] /lt_b after unlinking /lt_a");
+        fail += 1;
+    }
+    fail
+}
+
+fn symlink_cases() -> usize {
+    let mut fail = 0;
+    if sys_symlink("/lt_b", "/lt_sym") != 0 {
+        println!("[FAIL] symlink /lt_sym");
+        return 1;
+    }
+    let mut buf = [0u8; 32];
+    let n = sys_readlink("/lt_sym", &mut buf);
+    if n != 5 || &buf[..5] != b"/lt_b" {
+        println!("[FAIL] readlink ret={}", n);
+        fail += 1;
+    }
+    if read_matches("/lt_sym") != Ok(true) {
+        println!("[FAIL] open through symlink");
+        fail += 1;
+    }
+    let mut st = KStat::default();
+    if sys_lstat("/lt_sym", &mut st) != 0 || st.st_mode & S_IFMT != S_IFLNK {
+        println!("[FAIL] lstat mode={:#o} expect S_IFLNK", st.st_mode);
+        fail += 1;
+    }
+    if stat("/lt_sym").map(|s| s.st_mode & S_IFMT) != Some(S_IFREG) {
+        println!("[FAIL] stat through symlink is not a regular file");
+        fail += 1;
+    }
+
+    // 相对路径的目标相对链接所在的目录解析，中间一级是链接也要跟随
+    if sys_mkdir("/lt_dir") != 0 || !write_file("/lt_dir/f", CONTENT) || sys_symlink("lt_dir", "/lt_dsym") != 0 {
+        println!("[FAIL] create /lt_dir and /lt_dsym");
+        fail += 1;
+    } else if read_matches("/lt_dsym/f") != Ok(true) {
+        println!("[FAIL] open through a symlinked directory");
+        fail += 1;
+    }
+
+    sys_symlink("/lt_loop2", "/lt_loop1");
+    sys_symlink("/lt_loop1", "/lt_loop2");
+    let ret = read_matches("/lt_loop1");
+    if ret != Err(-ELOOP) {
+        println!("[FAIL] symlink loop {:?} expect ELOOP", ret);
+        fail += 1;
+    }
+    let ret = sys_link("/lt_dir", "/lt_dir2");
+    if ret != -EPERM {
+        println!("[FAIL] link on a directory ret={} expect EPERM", ret);
+        fail += 1;
+    }
+    fail
+}
+
+#[no_mangle]
+pub fn main() -> usize {
+    let fail = hard_link_cases() + symlink_cases();
+    for path in ["/lt_a", "/lt_b", "/lt_sym", "/lt_dsym", "/lt_loop1", "/lt_loop2", "/lt_dir/f"] {
+        sys_unlink(path);
+    }
+    sys_rmdir("/lt_dir");
+    println!("==== link test done: fail={} ====", fail);
+    if fail == 0 { 0 } else { 1 }
+}
@@ -0,0 +1,157 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{print, println};
+use user_lib::syscall::{
+    sys_exit, sys_fork, sys_mmap, sys_mprotect, sys_mremap, sys_unmap, sys_waitpid, wexitstatus, wifexited,
+    wifsignaled, wtermsig, MmapFlags, MmapProt, MREMAP_MAYMOVE,
+};
+extern crate user_lib;
+
+/// mprotect 拆分 area 改权限：只读页写入、PROT_NONE 页读取都要 SIGSEGV，改回来之后照常读写；
+/// 相邻的页权限不变，父进程照常读写；mremap 扩大（可搬移）和缩小之后原有的数据不变
+const PAGE: usize = 4096;
+const SIGSEGV: i32 = 11;
+const ENOMEM: isize = 12;
+
+fn page_ptr(base: usize, page: usize) -> *mut usize {
+    (base + page * PAGE) as *mut usize
+}
+
+fn fill_pages(base: usize, pages: core::ops::Range<usize>) {
+    for page in pages {
+        unsafe { page_ptr(base, page).write_volatile(page + 1) };
+    }
+}
+
+///返回第一个内容不对的页
+fn check_pages(base: usize, pages: core::ops::Range<usize>) -> Option<usize> {
+    pages.into_iter().find(|&page| unsafe { page_ptr(base, page).read_volatile() } != page + 1)
+}
+
+///在子进程里访问 addr，期望被 SIGSEGV 杀死
+fn expect_segv(tag: &str, addr: *mut usize, write: bool) -> bool {
+    let pid = sys_fork();
+    if pid == 0 {
+        unsafe {
+            if write {
+                addr.write_volatile(0xdead);
+            } else {
+                let _ = addr.read_volatile();
+            }
+        }
+        sys_exit(0);
+    }
+    let mut status: isize = 0;
+    let ret = sys_waitpid(&mut status as *mut isize, pid as i32, 0);
+    let status = status as i32;
+    if pid < 0 || ret != pid || !wifsignaled(status) || wtermsig(status) != SIGSEGV {
+        println!(
+            "[FAIL] {}: pid={} ret={} exited={} status={:#x}",
+            tag,
+            pid,
+            ret,
+            wifexited(status) && wexitstatus(status) == 0,
+            status
+        );
+        return false;
+    }
+    true
+}
+
+#[no_mangle]
+pub fn main() -> usize {
+    let rw = (MmapProt::READ | MmapProt::WRITE).bits();
+    let base = sys_mmap(0, 4 * PAGE, rw, (MmapFlags::PRIVATE | MmapFlags::ANONYMOUS).bits(), -1, 0);
+    if base < 0 {
+        println!("[FAIL] mmap ret={}", base);
+        return 1;
+    }
+    let base = base as usize;
+    fill_pages(base, 0..4);
+    let mut fail = 0usize;
+
+    // 中间一页改成只读：能读，写会 SIGSEGV
+    if sys_mprotect(base + PAGE, PAGE, MmapProt::READ.bits()) != 0 {
+        println!("[FAIL] mprotect READ");
+        fail += 1;
+    }
+    if check_pages(base, 0..4).is_some() {
+        println!("[FAIL] data changed after mprotect");
+        fail += 1;
+    }
+    if !expect_segv("write to read-only page", page_ptr(base, 1), true) {
+        fail += 1;
+    }
+    // 父进程自己写相邻的页：权限只能改在第 1 页上，写错页会在这里 SIGSEGV
+    fill_pages(base, 0..1);
+    fill_pages(base, 2..4);
+    if check_pages(base, 0..4).is_some() {
+        println!("[FAIL] neighbours of the read-only page");
+        fail += 1;
+    }
+    // PROT_NONE：读也不行
+    if sys_mprotect(base + 2 * PAGE, PAGE, 0) != 0 || !expect_segv("read PROT_NONE page", page_ptr(base, 2), false) {
+        fail += 1;
+    }
+    fill_pages(base, 0..1);
+    fill_pages(base, 3..4);
+    if unsafe { page_ptr(base, 1).read_volatile() } != 2 || check_pages(base, 3..4).is_some() {
+        println!("[FAIL] neighbours of the PROT_NONE page");
+        fail += 1;
+    }
+    // 改回读写，被拆开的几段照常使用
+    if sys_mprotect(base, 4 * PAGE, rw) != 0 {
+        println!("[FAIL] mprotect back to RW");
+        fail += 1;
+    }
+    fill_pages(base, 0..4);
+    if let Some(page) = check_pages(base, 0..4) {
+        println!("[FAIL] page {} wrong after restoring RW", page);
+        fail += 1;
+    }
+    if sys_mprotect(base + 64 * PAGE, PAGE, rw) != -ENOMEM {
+        println!("[FAIL] mprotect on unmapped range should be ENOMEM");
+        fail += 1;
+    }
+
+    // 扩大到 8 页，允许搬移
+    let grown = sys_mremap(base, 4 * PAGE, 8 * PAGE, MREMAP_MAYMOVE, 0);
+    let mut cur = base;
+    let mut cur_len = 4 * PAGE;
+    if grown < 0 {
+        println!("[FAIL] mremap grow ret={}", grown);
+        fail += 1;
+    } else {
+        cur = grown as usize;
+        cur_len = 8 * PAGE;
+        if let Some(page) = check_pages(cur, 0..4) {
+            println!("[FAIL] page {} lost after mremap grow", page);
+            fail += 1;
+        }
+        fill_pages(cur, 4..8);
+        if let Some(page) = check_pages(cur, 0..8) {
+            println!("[FAIL] page {} wrong in grown mapping", page);
+            fail += 1;
+        }
+        // 缩小到 2 页原地完成
+        let shrunk = sys_mremap(cur, 8 * PAGE, 2 * PAGE, 0, 0);
+        if shrunk != cur as isize {
+            println!("[FAIL] mremap shrink ret={:#x} expect {:#x}", shrunk, cur);
+            fail += 1;
+        } else {
+            cur_len = 2 * PAGE;
+            if check_pages(cur, 0..2).is_some() {
+                println!("[FAIL] data lost after mremap shrink");
+                fail += 1;
+            }
+            if !expect_segv("access past shrunk mapping", page_ptr(cur, 3), false) {
+                fail += 1;
+            }
+        }
+    }
+    sys_unmap(cur, cur_len);
+
+    println!("==== mprotect/mremap test done: fail={} ====", fail);
+    if fail == 0 { 0 } else { 1 }
+}
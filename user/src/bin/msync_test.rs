@@ -0,0 +1,118 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{print, println};
+use user_lib::syscall::{
+    sys_close, sys_exit, sys_fork, sys_lseek, sys_mmap, sys_msync, sys_open, sys_read, sys_unlink, sys_unmap, sys_waitpid,
+    sys_write, wexitstatus, wifexited, MmapFlags, MmapProt, MS_ASYNC, MS_SYNC, O_CREAT, O_RDONLY, O_RDWR, O_TRUNC,
+    O_WRONLY, SEEK_SET,
+};
+extern crate user_lib;
+
+/// MAP_SHARED 文件映射：两个进程映射同一个文件互相看得到写入，
+/// msync 和 munmap 之后用 read 读文件能读到映射里写的内容
+const PAGE: usize = 4096;
+const FILE_PATH: &str = "/msync_test.dat";
+
+///从文件 off 处读一个字节
+fn file_byte(off: usize) -> Option<u8> {
+    let fd = sys_open(FILE_PATH, O_RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let mut byte = 0u8;
+    sys_lseek(fd as usize, off as isize, SEEK_SET);
+    let n = sys_read(fd as usize, &mut byte as *mut u8 as usize, 1);
+    sys_close(fd as usize);
+    if n != 1 {
+        return None;
+    }
+    Some(byte)
+}
+
+fn make_file() -> bool {
+    let fd = sys_open(FILE_PATH, O_WRONLY | O_CREAT | O_TRUNC);
+    if fd < 0 {
+        println!("[FAIL] create {} ret={}", FILE_PATH, fd);
+        return false;
+    }
+    // 用户栈只有两页，分块写
+    let buf = [b'a'; 512];
+    for _ in 0..2 * PAGE / buf.len() {
+        let n = sys_write(fd as usize, buf.as_ptr() as usize, buf.len());
+        if n != buf.len() as isize {
+            println!("[FAIL] write ret={}", n);
+            sys_close(fd as usize);
+            return false;
+        }
+    }
+    sys_close(fd as usize);
+    true
+}
+
+#[no_mangle]
+pub fn main() -> usize {
+    if !make_file() {
+        return 1;
+    }
+    let fd = sys_open(FILE_PATH, O_RDWR);
+    if fd < 0 {
+        println!("[FAIL] open ret={}", fd);
+        return 1;
+    }
+    let map = sys_mmap(
+        0,
+        2 * PAGE,
+        (MmapProt::READ | MmapProt::WRITE).bits(),
+        MmapFlags::SHARED.bits(),
+        fd,
+        0,
+    );
+    sys_close(fd as usize);
+    if map < 0 {
+        println!("[FAIL] mmap ret={}", map);
+        return 1;
+    }
+    let map = map as *mut u8;
+    let mut fail = 0usize;
+
+    // 子进程写第一页并 msync
+    let pid = sys_fork();
+    if pid == 0 {
+        unsafe { core::slice::from_raw_parts_mut(map, PAGE).fill(b'c') };
+        let ret = sys_msync(map as usize, PAGE, MS_SYNC);
+        sys_exit(if ret == 0 { 0 } else { 1 });
+    }
+    let mut status: isize = 0;
+    let ret = sys_waitpid(&mut status as *mut isize, pid as i32, 0);
+    let status = status as i32;
+    if pid < 0 || ret != pid || !wifexited(status) || wexitstatus(status) != 0 {
+        println!("[FAIL] child pid={} ret={} status={:#x}", pid, ret, status);
+        fail += 1;
+    }
+    if unsafe { map.read_volatile() } != b'c' {
+        println!("[FAIL] parent mapping does not see child write");
+        fail += 1;
+    }
+    if file_byte(0) != Some(b'c') {
+        println!("[FAIL] msync did not reach the file");
+        fail += 1;
+    }
+
+    if sys_msync(map as usize, PAGE, MS_ASYNC | MS_SYNC) != -22 {
+        println!("[FAIL] msync ASYNC|SYNC should be EINVAL");
+        fail += 1;
+    }
+
+    // 父进程写第二页，munmap 时写回
+    unsafe { core::slice::from_raw_parts_mut(map.add(PAGE), PAGE).fill(b'p') };
+    sys_unmap(map as usize, 2 * PAGE);
+    if file_byte(PAGE) != Some(b'p') {
+        println!("[FAIL] munmap did not write back");
+        fail += 1;
+    }
+    sys_unlink(FILE_PATH);
+
+    println!("==== msync test done: fail={} ====", fail);
+    if fail == 0 { 0 } else { 1 }
+}
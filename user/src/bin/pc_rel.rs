@@ -83,7 +83,7 @@ pub fn main() -> usize {
     // After all children are reaped, wait should return -1 (ECHILD in Linux).
     let mut st: isize = 0;
     let waited = sys_wait(&mut st as *mut isize);
-    if waited != -1 {
+    if waited >= 0 {
         println!("pc_rel: expected wait=-1 after reaping all children, got waited={} status={}", waited, st);
         return 1;
     }
@@ -0,0 +1,133 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{print, println};
+use user_lib::syscall::{
+    sys_close, sys_link, sys_mkdir, sys_open, sys_read, sys_renameat2, sys_rmdir, sys_stat, sys_unlink, sys_write,
+    KStat, O_CREAT, O_RDONLY, O_TRUNC, O_WRONLY, RENAME_EXCHANGE, RENAME_NOREPLACE,
+};
+extern crate user_lib;
+
+/// renameat2：NOREPLACE 碰到已有的名字返回 EEXIST，EXCHANGE 交换两个名字，普通 rename 覆盖目标、旧名字消失，
+/// 同一个 inode 的两个硬链接之间 rename 什么都不做；目录能挪到别的目录下，不能挪进自己的子目录；
+/// rmdir 非空目录返回 ENOTEMPTY；跨挂载点返回 EXDEV
+const ENOENT: isize = 2;
+const EEXIST: isize = 17;
+const EXDEV: isize = 18;
+const EINVAL: isize = 22;
+const ENOTEMPTY: isize = 39;
+
+fn write_file(path: &str, data: &[u8]) -> bool {
+    let fd = sys_open(path, O_WRONLY | O_CREAT | O_TRUNC);
+    if fd < 0 {
+        return false;
+    }
+    let n = sys_write(fd as usize, data.as_ptr() as usize, data.len());
+    sys_close(fd as usize);
+    n == data.len() as isize
+}
+
+fn content_is(path: &str, want: &[u8]) -> bool {
+    let fd = sys_open(path, O_RDONLY);
+    if fd < 0 {
+        return false;
+    }
+    let mut buf = [0u8; 16];
+    let n = sys_read(fd as usize, buf.as_mut_ptr() as usize, buf.len());
+    sys_close(fd as usize);
+    n == want.len() as isize && &buf[..want.len()] == want
+}
+
+fn exists(path: &str) -> bool {
+    let mut st = KStat::default();
+    sys_stat(path, &mut st) == 0
+}
+
+fn file_cases() -> usize {
+    let mut fail = 0;
+    if !write_file("/rt_a", b"aaaa") || !write_file("/rt_b", b"bbbb") {
+        println!("[FAIL] create /rt_a and /rt_b");
+        return 1;
+    }
+    let ret = sys_renameat2("/rt_a", "/rt_b", RENAME_NOREPLACE);
+    if ret != -EEXIST || !content_is("/rt_a", b"aaaa") || !content_is("/rt_b", b"bbbb") {
+        println!("[FAIL] NOREPLACE onto existing file ret={} expect EEXIST", ret);
+        fail += 1;
+    }
+    let ret = sys_renameat2("/rt_a", "/rt_b", RENAME_EXCHANGE);
+    if ret != 0 || !content_is("/rt_a", b"bbbb") || !content_is("/rt_b", b"aaaa") {
+        println!("[FAIL] EXCHANGE ret={}", ret);
+        fail += 1;
+    }
+    // 覆盖已有的目标，旧名字消失
+    let ret = sys_renameat2("/rt_a", "/rt_b", 0);
+    if ret != 0 || exists("/rt_a") || !content_is("/rt_b", b"bbbb") {
+        println!("[FAIL] rename over /rt_b ret={}", ret);
+        fail += 1;
+    }
+    if sys_renameat2("/rt_a", "/rt_c", 0) != -ENOENT {
+        println!("[FAIL] rename of a missing file should be ENOENT");
+        fail += 1;
+    }
+    // 同一个文件的两个硬链接：两个名字都留着
+    if sys_link("/rt_b", "/rt_l") != 0 || sys_renameat2("/rt_b", "/rt_l", 0) != 0 || !exists("/rt_b") || !exists("/rt_l") {
+        println!("[FAIL] rename between hard links of one inode is not a no-op");
+        fail += 1;
+    }
+    // 根文件系统和 /dev 不是同一个挂载时不能跨过去；根是 ramfs 时 /dev 只是个目录，挪回来
+    let ret = sys_renameat2("/rt_b", "/dev/rt_b", 0);
+    if ret == 0 {
+        sys_renameat2("/dev/rt_b", "/rt_b", 0);
+    } else if ret != -EXDEV {
+        println!("[FAIL] rename across mounts ret={} expect EXDEV", ret);
+        fail += 1;
+    }
+    fail
+}
+
+fn dir_cases() -> usize {
+    let mut fail = 0;
+    if sys_mkdir("/rt_d1") != 0 || sys_mkdir("/rt_d2") != 0 || sys_mkdir("/rt_d1/sub") != 0
+        || !write_file("/rt_d1/sub/f", b"dir")
+    {
+        println!("[FAIL] create directories");
+        return 1;
+    }
+    let ret = sys_renameat2("/rt_d1/sub", "/rt_d2/sub", 0);
+    if ret != 0 || exists("/rt_d1/sub") || !content_is("/rt_d2/sub/f", b"dir") {
+        println!("[FAIL] move directory across directories ret={}", ret);
+        fail += 1;
+    }
+    if !write_file("/rt_d2/sub/g", b"new") || !content_is("/rt_d2/sub/g", b"new") {
+        println!("[FAIL] create a file in the moved directory");
+        fail += 1;
+    }
+    let ret = sys_renameat2("/rt_d2", "/rt_d2/sub/inner", 0);
+    if ret != -EINVAL {
+        println!("[FAIL] move directory into itself ret={} expect EINVAL", ret);
+        fail += 1;
+    }
+    let ret = sys_rmdir("/rt_d2");
+    if ret != -ENOTEMPTY {
+        println!("[FAIL] rmdir non-empty ret={} expect ENOTEMPTY", ret);
+        fail += 1;
+    }
+    sys_unlink("/rt_d2/sub/f");
+    sys_unlink("/rt_d2/sub/g");
+    if sys_rmdir("/rt_d2/sub") != 0 || sys_rmdir("/rt_d2") != 0 || exists("/rt_d2") {
+        println!("[FAIL] rmdir after emptying");
+        fail += 1;
+    }
+    fail
+}
+
+#[no_mangle]
+pub fn main() -> usize {
+    let fail = file_cases() + dir_cases();
+    for path in ["/rt_a", "/rt_b", "/rt_c", "/rt_l"] {
+        sys_unlink(path);
+    }
+    sys_rmdir("/rt_d1");
+    println!("==== rename test done: fail={} ====", fail);
+    if fail == 0 { 0 } else { 1 }
+}
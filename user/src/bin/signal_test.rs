@@ -0,0 +1,172 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+
+use user_lib::{print, println};
+use user_lib::syscall::{
+    get_time_ms, sigmask, sys_close, sys_exit, sys_fork, sys_getpid, sys_kill, sys_nanosleep, sys_pipe, sys_read,
+    sys_sigaction, sys_sigpending, sys_sigprocmask, sys_waitpid, sys_write, wexitstatus, wifexited, wifstopped,
+    TimeSpec, SA_RESTART, SA_SIGINFO, SIGCONT, SIGSTOP, SIGUSR1, SIGUSR2, SIG_BLOCK, SIG_DFL, SIG_UNBLOCK, WUNTRACED,
+};
+extern crate user_lib;
+
+/// 信号：处理函数运行完经 sigreturn 回到原处；SA_SIGINFO 拿到 siginfo；被屏蔽的信号挂在 sigpending 里，
+/// 解除屏蔽后递送；阻塞的 read 被打断时有 SA_RESTART 重新开始，没有返回 EINTR；
+/// nanosleep 被处理函数打断返回 EINTR 并写回剩余时间，被停止再继续则接着睡完剩下的时间
+const EINTR: isize = 4;
+const SLEEP_MS: usize = 400;
+
+static HANDLED: AtomicUsize = AtomicUsize::new(0);
+static INFO_SIGNO: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn on_signal(_sig: usize) {
+    HANDLED.fetch_add(1, Ordering::SeqCst);
+}
+
+extern "C" fn on_siginfo(_sig: usize, info: *const i32, _uc: usize) {
+    // siginfo 开头是 si_signo
+    INFO_SIGNO.store(unsafe { info.read() }, Ordering::SeqCst);
+}
+
+fn sleep_ms(ms: usize) {
+    sys_nanosleep(&TimeSpec::from_ms(ms), core::ptr::null_mut());
+}
+
+///等子进程退出，退出码不是 0 算失败
+fn wait_child(tag: &str, pid: isize) -> bool {
+    let mut status: isize = 0;
+    let ret = sys_waitpid(&mut status as *mut isize, pid as i32, 0);
+    let status = status as i32;
+    if pid < 0 || ret != pid || !wifexited(status) || wexitstatus(status) != 0 {
+        println!("[FAIL] {}: pid={} ret={} status={:#x}", tag, pid, ret, status);
+        return false;
+    }
+    true
+}
+
+fn handler_cases() -> usize {
+    let mut fail = 0;
+    let me = sys_getpid();
+    sys_sigaction(SIGUSR1, on_signal as usize, 0, 0);
+    // 处理函数返回之后这里的局部变量还在
+    let marker = 0x5a5a_a5a5usize;
+    sys_kill(me, SIGUSR1);
+    if HANDLED.load(Ordering::SeqCst) != 1 || core::hint::black_box(marker) != 0x5a5a_a5a5 {
+        println!("[FAIL] SIGUSR1 handler ran {} times", HANDLED.load(Ordering::SeqCst));
+        fail += 1;
+    }
+
+    sys_sigaction(SIGUSR2, on_siginfo as usize, SA_SIGINFO, 0);
+    sys_kill(me, SIGUSR2);
+    if INFO_SIGNO.load(Ordering::SeqCst) != SIGUSR2 as i32 {
+        println!("[FAIL] SA_SIGINFO handler saw si_signo={}", INFO_SIGNO.load(Ordering::SeqCst));
+        fail += 1;
+    }
+
+    // 屏蔽期间只挂起，解除屏蔽时递送
+    sys_sigprocmask(SIG_BLOCK, sigmask(SIGUSR1)).ok();
+    sys_kill(me, SIGUSR1);
+    let pending = sys_sigpending().unwrap_or(0);
+    if HANDLED.load(Ordering::SeqCst) != 1 || pending & sigmask(SIGUSR1) == 0 {
+        println!("[FAIL] blocked SIGUSR1 handled={} pending={:#x}", HANDLED.load(Ordering::SeqCst), pending);
+        fail += 1;
+    }
+    sys_sigprocmask(SIG_UNBLOCK, sigmask(SIGUSR1)).ok();
+    if HANDLED.load(Ordering::SeqCst) != 2 || sys_sigpending().unwrap_or(0) & sigmask(SIGUSR1) != 0 {
+        println!("[FAIL] SIGUSR1 not delivered after unblock");
+        fail += 1;
+    }
+    sys_sigaction(SIGUSR2, SIG_DFL, 0, 0);
+    fail
+}
+
+///子进程在空管道上 read，父进程先发 SIGUSR1 再写一个字节
+fn read_case(flags: usize) -> usize {
+    let mut fds = [0i32; 2];
+    if sys_pipe(fds.as_mut_ptr()) < 0 {
+        println!("[FAIL] pipe");
+        return 1;
+    }
+    let (rfd, wfd) = (fds[0] as usize, fds[1] as usize);
+    // fork 之前装好，子进程继承
+    sys_sigaction(SIGUSR1, on_signal as usize, flags, 0);
+    HANDLED.store(0, Ordering::SeqCst);
+    let pid = sys_fork();
+    if pid == 0 {
+        let mut byte = 0u8;
+        let n = sys_read(rfd, &mut byte as *mut u8 as usize, 1);
+        let want = if flags & SA_RESTART != 0 { 1 } else { -EINTR };
+        if n != want || HANDLED.load(Ordering::SeqCst) != 1 {
+            println!("[FAIL] read flags={:#x} ret={} expect {} handled={}", flags, n, want, HANDLED.load(Ordering::SeqCst));
+            sys_exit(1);
+        }
+        sys_exit(0);
+    }
+    sleep_ms(50);
+    sys_kill(pid, SIGUSR1);
+    sleep_ms(50);
+    // 无论子进程有没有重新 read 都写一个字节，免得卡住
+    sys_write(wfd, b"x".as_ptr() as usize, 1);
+    let ok = wait_child("interrupted read", pid);
+    sys_close(rfd);
+    sys_close(wfd);
+    if ok { 0 } else { 1 }
+}
+
+fn nanosleep_cases() -> usize {
+    let mut fail = 0;
+    // 处理函数打断：EINTR，rem 是还没睡的时间
+    sys_sigaction(SIGUSR1, on_signal as usize, SA_RESTART, 0);
+    let pid = sys_fork();
+    if pid == 0 {
+        let mut rem = TimeSpec::default();
+        let ret = sys_nanosleep(&TimeSpec::from_ms(SLEEP_MS), &mut rem);
+        let left = rem.as_ms();
+        if ret != -EINTR || left == 0 || left >= SLEEP_MS {
+            println!("[FAIL] interrupted nanosleep ret={} rem={}ms", ret, left);
+            sys_exit(1);
+        }
+        sys_exit(0);
+    }
+    sleep_ms(100);
+    sys_kill(pid, SIGUSR1);
+    if !wait_child("nanosleep EINTR", pid) {
+        fail += 1;
+    }
+
+    // 睡到一半被停下再继续：不报 EINTR，只睡完剩下的时间，不从头再睡一遍
+    let pid = sys_fork();
+    if pid == 0 {
+        let start = get_time_ms();
+        let ret = sys_nanosleep(&TimeSpec::from_ms(SLEEP_MS), core::ptr::null_mut());
+        let elapsed = get_time_ms() - start;
+        if ret != 0 || elapsed + 10 < SLEEP_MS || elapsed > SLEEP_MS + SLEEP_MS / 2 {
+            println!("[FAIL] nanosleep across stop/continue ret={} elapsed={}ms", ret, elapsed);
+            sys_exit(1);
+        }
+        sys_exit(0);
+    }
+    sleep_ms(SLEEP_MS / 2);
+    sys_kill(pid, SIGSTOP);
+    let mut status: isize = 0;
+    let ret = sys_waitpid(&mut status as *mut isize, pid as i32, WUNTRACED);
+    if ret != pid || !wifstopped(status as i32) {
+        println!("[FAIL] child not stopped ret={} status={:#x}", ret, status);
+        fail += 1;
+    }
+    sleep_ms(SLEEP_MS / 4);
+    sys_kill(pid, SIGCONT);
+    if !wait_child("nanosleep restart", pid) {
+        fail += 1;
+    }
+    sys_sigaction(SIGUSR1, SIG_DFL, 0, 0);
+    fail
+}
+
+#[no_mangle]
+pub fn main() -> usize {
+    let fail = handler_cases() + read_case(SA_RESTART) + read_case(0) + nanosleep_cases();
+    println!("==== signal test done: fail={} ====", fail);
+    if fail == 0 { 0 } else { 1 }
+}
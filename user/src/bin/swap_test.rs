@@ -0,0 +1,117 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{print, println};
+use user_lib::syscall::{
+    sys_exit, sys_fork, sys_mmap, sys_unmap, sys_waitpid, wexitstatus, wifexited, MmapFlags, MmapProt,
+};
+extern crate user_lib;
+
+/// 需要交换分区，在 -m 64M 下运行（kernel 目录 make run-swap）：
+/// 一个进程写满比物理内存还大的匿名映射再逐页读回检查；
+/// 两个子进程同时各写一大块，一个缺页时要换出另一个的页，读回的数据也必须正确
+const PAGE: usize = 4096;
+const SINGLE_BYTES: usize = 96 * 1024 * 1024;
+const CHILD_BYTES: usize = 40 * 1024 * 1024;
+
+///每页开头写页号和种子，中间和结尾各写一个校验值
+fn fill(base: usize, bytes: usize, seed: usize) {
+    for page in 0..bytes / PAGE {
+        let p = (base + page * PAGE) as *mut usize;
+        unsafe {
+            p.write_volatile(page);
+            p.add(1).write_volatile(seed);
+            p.add(PAGE / 16).write_volatile(page ^ seed);
+            p.add(PAGE / 8 - 1).write_volatile(!page);
+        }
+    }
+}
+
+///返回第一个内容不对的页号
+fn verify(base: usize, bytes: usize, seed: usize) -> Option<usize> {
+    for page in 0..bytes / PAGE {
+        let p = (base + page * PAGE) as *const usize;
+        let ok = unsafe {
+            p.read_volatile() == page
+                && p.add(1).read_volatile() == seed
+                && p.add(PAGE / 16).read_volatile() == page ^ seed
+                && p.add(PAGE / 8 - 1).read_volatile() == !page
+        };
+        if !ok {
+            return Some(page);
+        }
+    }
+    None
+}
+
+fn map_anon(bytes: usize) -> isize {
+    sys_mmap(
+        0,
+        bytes,
+        (MmapProt::READ | MmapProt::WRITE).bits(),
+        (MmapFlags::PRIVATE | MmapFlags::ANONYMOUS).bits(),
+        -1,
+        0,
+    )
+}
+
+///映射 bytes 字节，写两遍再读回检查，成功返回 true
+fn touch_and_check(tag: &str, bytes: usize, seed: usize) -> bool {
+    let base = map_anon(bytes);
+    if base < 0 {
+        println!("[FAIL] {} mmap {} MiB ret={}", tag, bytes >> 20, base);
+        return false;
+    }
+    let base = base as usize;
+    fill(base, bytes, seed);
+    // 第二遍换一个种子，换出过的页还要能被改写
+    fill(base, bytes, seed + 1);
+    let bad = verify(base, bytes, seed + 1);
+    // 放掉交换槽位，后面的子进程才有地方换出
+    sys_unmap(base, bytes);
+    match bad {
+        None => {
+            println!("[PASS] {} {} MiB read back", tag, bytes >> 20);
+            true
+        }
+        Some(page) => {
+            println!("[FAIL] {} page {} corrupted", tag, page);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub fn main() -> usize {
+    let mut fail = 0usize;
+
+    if !touch_and_check("single process", SINGLE_BYTES, 0x5a5a_0000) {
+        fail += 1;
+    }
+
+    let mut pids = [0isize; 2];
+    for (i, pid) in pids.iter_mut().enumerate() {
+        let ret = sys_fork();
+        if ret == 0 {
+            let ok = touch_and_check("child", CHILD_BYTES, 0x1000 * (i + 1));
+            sys_exit(if ok { 0 } else { 1 });
+        }
+        if ret < 0 {
+            println!("[FAIL] fork ret={}", ret);
+            fail += 1;
+        }
+        *pid = ret;
+    }
+    for pid in pids.iter().filter(|&&pid| pid > 0) {
+        let mut status: isize = 0;
+        let ret = sys_waitpid(&mut status as *mut isize, *pid as i32, 0);
+        let status = status as i32;
+        if ret != *pid || !wifexited(status) || wexitstatus(status) != 0 {
+            println!("[FAIL] child {} ret={} status={:#x}", pid, ret, status);
+            fail += 1;
+        }
+    }
+
+    println!("==== swap test done: fail={} ====", fail);
+    if fail == 0 { 0 } else { 1 }
+}
@@ -0,0 +1,198 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+
+use user_lib::{print, println};
+use user_lib::syscall::{
+    sys_exit, sys_exit_group, sys_fork, sys_getpid, sys_gettid, sys_mmap, sys_set_tid_address, sys_unmap,
+    sys_waitpid, sys_yield, wexitstatus, wifexited, MmapFlags, MmapProt, CLONE_CHILD_CLEARTID, CLONE_FILES,
+    CLONE_FS, CLONE_PARENT_SETTID, CLONE_SETTLS, CLONE_SIGHAND, CLONE_SYSVSEM, CLONE_THREAD, CLONE_VM, SYS_CLONE,
+};
+extern crate user_lib;
+
+/// 线程：CLONE_VM|CLONE_THREAD 创建的线程共用地址空间和线程组 id，有自己的 tid 和 tp，
+/// PARENT_SETTID 写入 tid，线程退出时 CHILD_CLEARTID 把 tid 清零；exit_group 结束整个线程组
+const THREADS: usize = 2;
+const ROUNDS: usize = 10000;
+const STACK_SIZE: usize = 4 * 4096;
+/// musl pthread_create 用的标志
+const THREAD_FLAGS: usize = CLONE_VM
+    | CLONE_FS
+    | CLONE_FILES
+    | CLONE_SIGHAND
+    | CLONE_THREAD
+    | CLONE_SYSVSEM
+    | CLONE_SETTLS
+    | CLONE_PARENT_SETTID
+    | CLONE_CHILD_CLEARTID;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+static PTID: [AtomicI32; THREADS] = [AtomicI32::new(0), AtomicI32::new(0)];
+static CTID: [AtomicI32; THREADS] = [AtomicI32::new(0), AtomicI32::new(0)];
+static SEEN_TID: [AtomicUsize; THREADS] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+static SEEN_PID: [AtomicUsize; THREADS] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+static SEEN_TP: [AtomicUsize; THREADS] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+static TLS_BLOCK: [[usize; 8]; THREADS] = [[0; 8]; THREADS];
+
+fn read_tp() -> usize {
+    let tp: usize;
+    unsafe { core::arch::asm!("mv {}, tp", out(reg) tp) };
+    tp
+}
+
+///clone 出一个线程在 stack_top 上运行 entry(arg)，返回新线程的 tid
+/// 子线程里 a0 以外的寄存器和父线程相同，entry 和 arg 放在 t0/t1 带过去
+unsafe fn spawn(flags: usize, stack_top: usize, tls: usize, ptid: *const AtomicI32, ctid: *const AtomicI32,
+    entry: extern "C" fn(usize) -> !, arg: usize) -> isize {
+    let ret: isize;
+    core::arch::asm!(
+        "ecall",
+        "bnez a0, 1f",
+        "mv a0, t1",
+        "jalr t0",
+        "1:",
+        inlateout("a0") flags as isize => ret,
+        in("a1") stack_top,
+        in("a2") ptid,
+        in("a3") tls,
+        in("a4") ctid,
+        in("a7") SYS_CLONE,
+        in("t0") entry,
+        in("t1") arg,
+    );
+    ret
+}
+
+extern "C" fn worker(idx: usize) -> ! {
+    SEEN_TID[idx].store(sys_gettid() as usize, Ordering::Relaxed);
+    SEEN_PID[idx].store(sys_getpid() as usize, Ordering::Relaxed);
+    SEEN_TP[idx].store(read_tp(), Ordering::Relaxed);
+    for _ in 0..ROUNDS {
+        COUNTER.fetch_add(1, Ordering::Relaxed);
+        sys_yield();
+    }
+    // 只结束本线程
+    sys_exit(0);
+}
+
+extern "C" fn spinner(_arg: usize) -> ! {
+    loop {
+        sys_yield();
+    }
+}
+
+fn alloc_stack() -> usize {
+    let ret = sys_mmap(
+        0,
+        STACK_SIZE,
+        (MmapProt::READ | MmapProt::WRITE).bits(),
+        (MmapFlags::PRIVATE | MmapFlags::ANONYMOUS).bits(),
+        -1,
+        0,
+    );
+    if ret < 0 { 0 } else { ret as usize }
+}
+
+///等线程退出：CHILD_CLEARTID 把 ctid 清零
+fn join(idx: usize) -> bool {
+    for _ in 0..1_000_000 {
+        if CTID[idx].load(Ordering::Acquire) == 0 {
+            return true;
+        }
+        sys_yield();
+    }
+    false
+}
+
+///子进程里起一个永远不退出的线程再 exit_group，整个进程要带着退出码结束
+fn exit_group_case() -> bool {
+    let pid = sys_fork();
+    if pid == 0 {
+        let stack = alloc_stack();
+        let ctid = AtomicI32::new(0);
+        let tid = unsafe {
+            spawn(THREAD_FLAGS & !CLONE_SETTLS, stack + STACK_SIZE, 0, &ctid, &ctid, spinner, 0)
+        };
+        if stack == 0 || tid <= 0 {
+            sys_exit(1);
+        }
+        sys_exit_group(5);
+    }
+    let mut status: isize = 0;
+    let ret = sys_waitpid(&mut status as *mut isize, pid as i32, 0);
+    let status = status as i32;
+    if pid < 0 || ret != pid || !wifexited(status) || wexitstatus(status) != 5 {
+        println!("[FAIL] exit_group child pid={} ret={} status={:#x}", pid, ret, status);
+        return false;
+    }
+    true
+}
+
+#[no_mangle]
+pub fn main() -> usize {
+    let mut fail = 0usize;
+    let pid = sys_getpid() as usize;
+    let main_tid = sys_gettid();
+    let mut dummy = 0i32;
+    if main_tid as usize != pid || sys_set_tid_address(&mut dummy) != main_tid {
+        println!("[FAIL] main thread tid={} pid={}", main_tid, pid);
+        fail += 1;
+    }
+
+    let mut stacks = [0usize; THREADS];
+    let mut tids = [0isize; THREADS];
+    for i in 0..THREADS {
+        stacks[i] = alloc_stack();
+        if stacks[i] == 0 {
+            println!("[FAIL] stack mmap");
+            return 1;
+        }
+        CTID[i].store(-1, Ordering::Relaxed);
+        let tls = TLS_BLOCK[i].as_ptr() as usize;
+        tids[i] = unsafe { spawn(THREAD_FLAGS, stacks[i] + STACK_SIZE, tls, &PTID[i], &CTID[i], worker, i) };
+        if tids[i] <= 0 {
+            println!("[FAIL] clone thread {} ret={}", i, tids[i]);
+            return 1;
+        }
+        if PTID[i].load(Ordering::Relaxed) as isize != tids[i] {
+            println!("[FAIL] PARENT_SETTID wrote {} expect {}", PTID[i].load(Ordering::Relaxed), tids[i]);
+            fail += 1;
+        }
+    }
+
+    for i in 0..THREADS {
+        if !join(i) {
+            println!("[FAIL] thread {} never cleared its tid", i);
+            return 1;
+        }
+        let tls = TLS_BLOCK[i].as_ptr() as usize;
+        if SEEN_TID[i].load(Ordering::Relaxed) as isize != tids[i]
+            || SEEN_PID[i].load(Ordering::Relaxed) != pid
+            || SEEN_TP[i].load(Ordering::Relaxed) != tls
+        {
+            println!(
+                "[FAIL] thread {} saw tid={} pid={} tp={:#x}, expect tid={} pid={} tp={:#x}",
+                i,
+                SEEN_TID[i].load(Ordering::Relaxed),
+                SEEN_PID[i].load(Ordering::Relaxed),
+                SEEN_TP[i].load(Ordering::Relaxed),
+                tids[i],
+                pid,
+                tls
+            );
+            fail += 1;
+        }
+        sys_unmap(stacks[i], STACK_SIZE);
+    }
+    if COUNTER.load(Ordering::Relaxed) != THREADS * ROUNDS {
+        println!("[FAIL] counter={} expect {}", COUNTER.load(Ordering::Relaxed), THREADS * ROUNDS);
+        fail += 1;
+    }
+    if !exit_group_case() {
+        fail += 1;
+    }
+
+    println!("==== thread test done: fail={} ====", fail);
+    if fail == 0 { 0 } else { 1 }
+}
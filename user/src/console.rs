@@ -45,7 +45,7 @@ impl Write for STDBUFFER {
         for byte in s.as_bytes().iter() {
             self.0.push_back(*byte);
             if self.0.len() == BUFFER_SIZE || *byte==b'\n'{
-                if self.flush()==-1{
+                if self.flush() < 0{
                     return Err(core::fmt::Error);
                 }
             }
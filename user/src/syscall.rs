@@ -4,7 +4,9 @@ use bitflags::bitflags;
 
 // Linux riscv64 syscall numbers (subset)
 pub const SYS_GETCWD: usize = 17;
+pub const SYS_IOCTL: usize = 29;
 pub const SYS_UNLINKAT: usize = 35;
+pub const SYS_SYMLINKAT: usize = 36;
 pub const SYS_LINKAT: usize = 37;
 pub const SYS_UMOUNT2: usize = 39;
 pub const SYS_MOUNT: usize = 40;
@@ -17,31 +19,95 @@ pub const SYS_GETDENTS64: usize = 61;
 pub const SYS_LSEEK: usize = 62;
 pub const SYS_READ: usize = 63;
 pub const SYS_WRITE: usize = 64;
+pub const SYS_READLINKAT: usize = 78;
 pub const SYS_NEWFSTATAT: usize = 79;
 pub const SYS_FSTAT: usize = 80;
 pub const SYS_EXIT: usize = 93;
+pub const SYS_EXIT_GROUP: usize = 94;
+pub const SYS_SET_TID_ADDRESS: usize = 96;
+pub const SYS_FUTEX: usize = 98;
 pub const SYS_NANOSLEEP: usize = 101;
+pub const SYS_KILL: usize = 129;
+pub const SYS_TKILL: usize = 130;
+pub const SYS_TGKILL: usize = 131;
+pub const SYS_RT_SIGACTION: usize = 134;
+pub const SYS_RT_SIGPROCMASK: usize = 135;
+pub const SYS_RT_SIGPENDING: usize = 136;
 pub const SYS_SETPRIORITY: usize = 140;
 pub const SYS_TIMES: usize = 153;
+pub const SYS_SETPGID: usize = 154;
+pub const SYS_GETPGID: usize = 155;
+pub const SYS_SETSID: usize = 157;
 pub const SYS_UNAME: usize = 160;
 pub const SYS_GETTIMEOFDAY: usize = 169;
 pub const SYS_GETPID: usize = 172;
 pub const SYS_GETPPID: usize = 173;
+pub const SYS_GETTID: usize = 178;
 pub const SYS_BRK: usize = 214;
 pub const SYS_MUNMAP: usize = 215;
+pub const SYS_MREMAP: usize = 216;
 pub const SYS_CLONE: usize = 220;
 pub const SYS_EXECVE: usize = 221;
 pub const SYS_MMAP: usize = 222;
+pub const SYS_MPROTECT: usize = 226;
+pub const SYS_MSYNC: usize = 227;
 pub const SYS_WAIT4: usize = 260;
+pub const SYS_RENAMEAT2: usize = 276;
 pub const SYS_SCHED_YIELD: usize = 124;
 pub const SYS_DUP: usize = 23;
 pub const SYS_DUP3: usize = 24;
 
 pub const AT_FDCWD: isize = -100;
+pub const AT_SYMLINK_NOFOLLOW: usize = 0x100;
+pub const AT_REMOVEDIR: usize = 0x200;
 
 
 // Not Hang parent option
 pub const WNOHANG:i32 = 1;
+// 停止的子进程也返回
+pub const WUNTRACED:i32 = 2;
+// 被 SIGCONT 继续的子进程也返回
+pub const WCONTINUED:i32 = 8;
+
+///wait 状态字解析
+pub fn wifexited(status: i32) -> bool { status & 0x7f == 0 }
+pub fn wexitstatus(status: i32) -> i32 { (status >> 8) & 0xff }
+pub fn wifsignaled(status: i32) -> bool { ((status & 0x7f) + 1) as i8 >= 2 }
+pub fn wtermsig(status: i32) -> i32 { status & 0x7f }
+pub fn wifstopped(status: i32) -> bool { status & 0xff == 0x7f }
+pub fn wstopsig(status: i32) -> i32 { (status >> 8) & 0xff }
+pub fn wifcontinued(status: i32) -> bool { status == 0xffff }
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGUSR2: usize = 12;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+pub const SA_SIGINFO: usize = 0x0000_0004;
+pub const SA_RESTART: usize = 0x1000_0000;
+pub const SA_NODEFER: usize = 0x4000_0000;
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+///信号集里 sig 对应的位
+pub fn sigmask(sig: usize) -> u64 { 1 << (sig - 1) }
+
+pub const TIOCGPGRP: usize = 0x540F;
+pub const TIOCSPGRP: usize = 0x5410;
 
 
 /// syscall 封装：Linux ABI 版本（最多 6 个参数）
@@ -135,6 +201,28 @@ pub fn sys_unmap(startAddr:usize,len:usize)->isize{
     sys_call(SYS_MUNMAP,[startAddr,len,0,0,0,0])
 }
 
+pub const MS_ASYNC: usize = 1;
+pub const MS_INVALIDATE: usize = 2;
+pub const MS_SYNC: usize = 4;
+
+///改 [addr, addr+len) 的访问权限，prot 是 MmapProt 的位
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> isize {
+    sys_call(SYS_MPROTECT, [addr, len, prot, 0, 0, 0])
+}
+
+pub const MREMAP_MAYMOVE: usize = 1;
+pub const MREMAP_FIXED: usize = 2;
+
+///扩大、缩小或搬移一段映射，返回新地址
+pub fn sys_mremap(old_addr: usize, old_len: usize, new_len: usize, flags: usize, new_addr: usize) -> isize {
+    sys_call(SYS_MREMAP, [old_addr, old_len, new_len, flags, new_addr, 0])
+}
+
+///把共享文件映射的脏页写回文件
+pub fn sys_msync(addr: usize, len: usize, flags: usize) -> isize {
+    sys_call(SYS_MSYNC, [addr, len, flags, 0, 0, 0])
+}
+
 pub fn sys_read(fd:usize,buffer_ptr:usize,buffer_len:usize)->isize{
     sys_call(SYS_READ, [fd,buffer_ptr,buffer_len,0,0,0])
 }
@@ -223,6 +311,62 @@ pub fn sys_unlink(path: &str) -> isize {
     )
 }
 
+///删除空目录
+pub fn sys_rmdir(path: &str) -> isize {
+    let mut st = String::from(path);
+    st.push('\0');
+    sys_call(SYS_UNLINKAT, [AT_FDCWD as usize, st.as_ptr() as usize, AT_REMOVEDIR, 0, 0, 0])
+}
+
+///硬链接：new 和 old 指向同一个 inode
+pub fn sys_link(old: &str, new: &str) -> isize {
+    let mut old_st = String::from(old);
+    old_st.push('\0');
+    let mut new_st = String::from(new);
+    new_st.push('\0');
+    sys_call(
+        SYS_LINKAT,
+        [AT_FDCWD as usize, old_st.as_ptr() as usize, AT_FDCWD as usize, new_st.as_ptr() as usize, 0, 0],
+    )
+}
+
+///在 linkpath 创建指向 target 的符号链接
+pub fn sys_symlink(target: &str, linkpath: &str) -> isize {
+    let mut target_st = String::from(target);
+    target_st.push('\0');
+    let mut link_st = String::from(linkpath);
+    link_st.push('\0');
+    sys_call(
+        SYS_SYMLINKAT,
+        [target_st.as_ptr() as usize, AT_FDCWD as usize, link_st.as_ptr() as usize, 0, 0, 0],
+    )
+}
+
+pub const RENAME_NOREPLACE: usize = 1;
+pub const RENAME_EXCHANGE: usize = 2;
+
+///flags 为 0 时就是 rename
+pub fn sys_renameat2(old: &str, new: &str, flags: usize) -> isize {
+    let mut old_st = String::from(old);
+    old_st.push('\0');
+    let mut new_st = String::from(new);
+    new_st.push('\0');
+    sys_call(
+        SYS_RENAMEAT2,
+        [AT_FDCWD as usize, old_st.as_ptr() as usize, AT_FDCWD as usize, new_st.as_ptr() as usize, flags, 0],
+    )
+}
+
+///读符号链接的内容，不补 \0，返回写入的字节数
+pub fn sys_readlink(path: &str, buf: &mut [u8]) -> isize {
+    let mut st = String::from(path);
+    st.push('\0');
+    sys_call(
+        SYS_READLINKAT,
+        [AT_FDCWD as usize, st.as_ptr() as usize, buf.as_mut_ptr() as usize, buf.len(), 0, 0],
+    )
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default)]
 pub struct KStat {
@@ -265,6 +409,16 @@ pub fn sys_stat(path: &str, stat_buf: *mut KStat) -> isize {
     
 }
 
+///不跟随最后一级符号链接的 stat
+pub fn sys_lstat(path: &str, stat_buf: *mut KStat) -> isize {
+    let mut st = String::from(path);
+    st.push('\0');
+    sys_call(
+        SYS_NEWFSTATAT,
+        [AT_FDCWD as usize, st.as_ptr() as usize, stat_buf as usize, AT_SYMLINK_NOFOLLOW, 0, 0],
+    )
+}
+
 pub fn sys_brl(new_addr:usize) -> isize{
     sys_call(SYS_BRK, [new_addr,0,0,0,0,0])
 }
@@ -286,12 +440,88 @@ pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
 }
 
 pub fn sys_fork()->isize{
-    sys_call(SYS_CLONE, [0, 0, 0, 0, 0, 0])
+    // 和 fork 一样退出时给父进程发 SIGCHLD，不然 wait 不带 __WALL 等不到它
+    sys_call(SYS_CLONE, [SIGCHLD, 0, 0, 0, 0, 0])
 }
 
 pub fn sys_clone(flags: usize, stack: usize, ptid: usize, tls: usize, ctid: usize) -> isize {
     sys_call(SYS_CLONE, [flags, stack, ptid, tls, ctid, 0])
 }
+
+pub const CLONE_VM: usize = 0x0000_0100;
+pub const CLONE_FS: usize = 0x0000_0200;
+pub const CLONE_FILES: usize = 0x0000_0400;
+pub const CLONE_SIGHAND: usize = 0x0000_0800;
+pub const CLONE_THREAD: usize = 0x0001_0000;
+pub const CLONE_SYSVSEM: usize = 0x0004_0000;
+pub const CLONE_SETTLS: usize = 0x0008_0000;
+pub const CLONE_PARENT_SETTID: usize = 0x0010_0000;
+pub const CLONE_CHILD_CLEARTID: usize = 0x0020_0000;
+pub const CLONE_CHILD_SETTID: usize = 0x0100_0000;
+
+pub fn sys_gettid() -> isize {
+    sys_call(SYS_GETTID, [0, 0, 0, 0, 0, 0])
+}
+
+///线程退出时内核把 tidptr 指向的 tid 清零并 futex 唤醒，返回调用者的 tid
+pub fn sys_set_tid_address(tidptr: *mut i32) -> isize {
+    sys_call(SYS_SET_TID_ADDRESS, [tidptr as usize, 0, 0, 0, 0, 0])
+}
+
+///结束整个线程组
+pub fn sys_exit_group(exit_code: usize) -> ! {
+    sys_call(SYS_EXIT_GROUP, [exit_code, 0, 0, 0, 0, 0]);
+    unreachable!()
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimeSpec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+impl TimeSpec {
+    pub fn from_ms(ms: usize) -> Self {
+        Self { tv_sec: (ms / 1000) as i64, tv_nsec: ((ms % 1000) * 1_000_000) as i64 }
+    }
+
+    pub fn as_ms(&self) -> usize {
+        self.tv_sec as usize * 1000 + self.tv_nsec as usize / 1_000_000
+    }
+}
+
+///睡 req，被信号打断时剩下的时间写进 rem
+pub fn sys_nanosleep(req: &TimeSpec, rem: *mut TimeSpec) -> isize {
+    sys_call(SYS_NANOSLEEP, [req as *const TimeSpec as usize, rem as usize, 0, 0, 0, 0])
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct TimeVal {
+    sec: usize,
+    usec: usize,
+}
+
+///开机以来的毫秒数
+pub fn get_time_ms() -> usize {
+    let mut tv = TimeVal::default();
+    sys_call(SYS_GETTIMEOFDAY, [&mut tv as *mut TimeVal as usize, 0, 0, 0, 0, 0]);
+    tv.sec * 1000 + tv.usec / 1000
+}
+
+pub const FUTEX_WAIT: usize = 0;
+pub const FUTEX_WAKE: usize = 1;
+pub const FUTEX_REQUEUE: usize = 3;
+pub const FUTEX_CMP_REQUEUE: usize = 4;
+pub const FUTEX_WAIT_BITSET: usize = 9;
+pub const FUTEX_WAKE_BITSET: usize = 10;
+pub const FUTEX_PRIVATE_FLAG: usize = 128;
+
+///futex(uaddr, op, val, timeout 或 val2, uaddr2, val3)
+pub fn sys_futex(uaddr: *const i32, op: usize, val: usize, timeout_or_val2: usize, uaddr2: *const i32, val3: usize) -> isize {
+    sys_call(SYS_FUTEX, [uaddr as usize, op, val, timeout_or_val2, uaddr2 as usize, val3])
+}
 pub fn sys_exec(path:&str)->isize{
     sys_execve(path, core::ptr::null(), core::ptr::null())
 }
@@ -349,3 +579,88 @@ pub fn sys_yield(){
     sys_call(SYS_SCHED_YIELD, [0,0,0,0,0,0]);
 }
 
+///发信号，pid<0 时发给进程组 -pid
+pub fn sys_kill(pid: isize, sig: usize) -> isize {
+    sys_call(SYS_KILL, [pid as usize, sig, 0, 0, 0, 0])
+}
+
+///发给单个线程
+pub fn sys_tkill(tid: isize, sig: usize) -> isize {
+    sys_call(SYS_TKILL, [tid as usize, sig, 0, 0, 0, 0])
+}
+
+///发给线程组 tgid 里的线程 tid，不在这个线程组里返回 ESRCH
+pub fn sys_tgkill(tgid: isize, tid: isize, sig: usize) -> isize {
+    sys_call(SYS_TGKILL, [tgid as usize, tid as usize, sig, 0, 0, 0])
+}
+
+///内核 ABI 的 struct sigaction
+#[repr(C)]
+struct SigAction {
+    handler: usize,
+    flags: usize,
+    mask: u64,
+}
+
+///设置信号处理方式（SIG_DFL/SIG_IGN/处理函数地址），返回原来的
+pub fn sys_signal(sig: usize, handler: usize) -> isize {
+    let act = SigAction { handler, flags: 0, mask: 0 };
+    let mut old = SigAction { handler: 0, flags: 0, mask: 0 };
+    let ret = sys_call(
+        SYS_RT_SIGACTION,
+        [sig, &act as *const _ as usize, &mut old as *mut _ as usize, 8, 0, 0],
+    );
+    if ret < 0 { ret } else { old.handler as isize }
+}
+
+///带 flags 和处理期间屏蔽字的 sigaction
+pub fn sys_sigaction(sig: usize, handler: usize, flags: usize, mask: u64) -> isize {
+    let act = SigAction { handler, flags, mask };
+    sys_call(SYS_RT_SIGACTION, [sig, &act as *const _ as usize, 0, 8, 0, 0])
+}
+
+///how 是 SIG_BLOCK/SIG_UNBLOCK/SIG_SETMASK，返回原来的屏蔽字
+pub fn sys_sigprocmask(how: usize, set: u64) -> Result<u64, isize> {
+    let mut old: u64 = 0;
+    let ret = sys_call(
+        SYS_RT_SIGPROCMASK,
+        [how, &set as *const u64 as usize, &mut old as *mut u64 as usize, 8, 0, 0],
+    );
+    if ret < 0 { Err(ret) } else { Ok(old) }
+}
+
+///被屏蔽着等待递送的信号
+pub fn sys_sigpending() -> Result<u64, isize> {
+    let mut set: u64 = 0;
+    let ret = sys_call(SYS_RT_SIGPENDING, [&mut set as *mut u64 as usize, 8, 0, 0, 0, 0]);
+    if ret < 0 { Err(ret) } else { Ok(set) }
+}
+
+pub fn sys_setpgid(pid: isize, pgid: isize) -> isize {
+    sys_call(SYS_SETPGID, [pid as usize, pgid as usize, 0, 0, 0, 0])
+}
+
+pub fn sys_getpgid(pid: isize) -> isize {
+    sys_call(SYS_GETPGID, [pid as usize, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_setsid() -> isize {
+    sys_call(SYS_SETSID, [0, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+    sys_call(SYS_IOCTL, [fd, cmd, arg, 0, 0, 0])
+}
+
+///终端的前台进程组
+pub fn tcgetpgrp(fd: usize) -> isize {
+    let mut pgrp: i32 = 0;
+    let ret = sys_ioctl(fd, TIOCGPGRP, &mut pgrp as *mut i32 as usize);
+    if ret < 0 { ret } else { pgrp as isize }
+}
+
+///把进程组切到终端前台
+pub fn tcsetpgrp(fd: usize, pgrp: isize) -> isize {
+    let pgrp = pgrp as i32;
+    sys_ioctl(fd, TIOCSPGRP, &pgrp as *const i32 as usize)
+}